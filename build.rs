@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the bundled protoc so builds don't need one installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+
+    // The messages are always needed for the prpc endpoints, the service
+    // only with the gRPC server
+    #[cfg(feature = "grpc")]
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/worker.proto"], &["proto"])?;
+    #[cfg(not(feature = "grpc"))]
+    prost_build::compile_protos(&["proto/worker.proto"], &["proto"])?;
+    Ok(())
+}
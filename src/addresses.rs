@@ -0,0 +1,101 @@
+use local_ip_address::list_afinet_netifas;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+/// An address the worker can be reached at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Address {
+    pub ip: String,
+    pub family: AddressFamily,
+    /// Network interface the address belongs to, for interface addresses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    pub source: AddressSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSource {
+    /// Pinned with `advertise_address`.
+    Configured,
+    /// Assigned to a local network interface.
+    Interface,
+    /// Found by public IP discovery.
+    Public,
+}
+
+impl Address {
+    pub fn new(ip: IpAddr, interface: Option<String>, source: AddressSource) -> Self {
+        Self {
+            ip: ip.to_string(),
+            family: match ip {
+                IpAddr::V4(_) => AddressFamily::Ipv4,
+                IpAddr::V6(_) => AddressFamily::Ipv6,
+            },
+            interface,
+            source,
+        }
+    }
+}
+
+/// Lists the IPv4 and IPv6 addresses of all network interfaces, skipping
+/// loopback and link-local addresses, which aren't reachable from outside.
+pub fn interface_addresses() -> Vec<Address> {
+    let interfaces = match list_afinet_netifas() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            error!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let addresses: Vec<Address> = interfaces
+        .into_iter()
+        .filter(|(_, ip)| !ip.is_loopback() && !ip.is_unspecified() && !is_link_local(ip))
+        .map(|(name, ip)| Address::new(ip, Some(name), AddressSource::Interface))
+        .collect();
+    for address in &addresses {
+        info!(
+            "Detected address {} on {}",
+            address.ip,
+            address.interface.as_deref().unwrap_or("unknown interface")
+        );
+    }
+    addresses
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// All known addresses, the pinned one first, without duplicates.
+pub fn collect(
+    advertised: Option<IpAddr>,
+    interfaces: &[Address],
+    public_ip: Option<IpAddr>,
+) -> Vec<Address> {
+    let mut addresses: Vec<Address> = Vec::new();
+    let candidates = advertised
+        .map(|ip| Address::new(ip, None, AddressSource::Configured))
+        .into_iter()
+        .chain(interfaces.iter().cloned())
+        .chain(public_ip.map(|ip| Address::new(ip, None, AddressSource::Public)));
+    for address in candidates {
+        if !addresses.iter().any(|known| known.ip == address.ip) {
+            addresses.push(address);
+        }
+    }
+    addresses
+}
@@ -0,0 +1,108 @@
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use crate::gpu_changes::GpuChange;
+use crate::health::{BackendInfo, DephyWorkerRespondedStatus};
+use crate::server::AppState;
+
+/// Sends a NIP-17 private message (NIP-44 encrypted, gift wrapped) to the
+/// admin whenever the worker status flips between states or GPUs appear,
+/// disappear or change `is_free`.
+pub async fn run_status_alerts(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    admin: PublicKey,
+) {
+    let mut changes = state.poller.subscribe();
+    let mut gpu_changes = state.gpu_changes.subscribe();
+    let mut last_status = state.poller.latest().map(|info| info.status);
+
+    info!("Sending status transition alerts to {}", admin);
+
+    loop {
+        let message = tokio::select! {
+            info = changes.recv() => {
+                let info = match info {
+                    Ok(info) => info,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Status alerts lagged, skipped {} updates", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let previous = last_status.replace(info.status);
+                let Some(previous) = previous.filter(|previous| *previous != info.status) else {
+                    continue;
+                };
+                transition_message(&state, previous, &info)
+            }
+            changes = gpu_changes.recv() => match changes {
+                Ok(changes) => gpu_changes_message(&state, &changes),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Status alerts lagged, skipped {} GPU changes", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        match EventBuilder::private_msg(&signer, admin, message, []).await {
+            Ok(event) => match client.send_event(event).await {
+                Ok(output) => {
+                    state.relay_stats.record(&output.success, &output.failed);
+                    info!("Sent alert to admin")
+                }
+                Err(e) => error!("Failed to publish alert: {}", e),
+            },
+            Err(e) => error!("Failed to build alert: {}", e),
+        }
+    }
+}
+
+fn gpu_changes_message(state: &AppState, changes: &[GpuChange]) -> String {
+    let mut message = format!(
+        "dstack worker {} ({}) GPU inventory changed\nTime: {}",
+        state.nostr_pubkey,
+        state.node_type,
+        Timestamp::now().to_human_datetime()
+    );
+    for change in changes {
+        message.push_str(&format!(
+            "\n{} {} ({})",
+            change.slot, change.kind, change.description
+        ));
+    }
+    message
+}
+
+fn transition_message(
+    state: &AppState,
+    previous: DephyWorkerRespondedStatus,
+    info: &BackendInfo,
+) -> String {
+    let mut message = format!(
+        "dstack worker {} ({}) changed status: {} -> {}\nTime: {}",
+        state.nostr_pubkey,
+        state.node_type,
+        previous,
+        info.status,
+        Timestamp::now().to_human_datetime()
+    );
+
+    let error = info.metadata.as_ref().and_then(|metadata| {
+        metadata
+            .to_structured(&state.node_type)
+            .errors
+            .into_iter()
+            .next()
+    });
+    if let Some(error) = error {
+        message.push_str(&format!("\nError: {}", error));
+    }
+
+    message
+}
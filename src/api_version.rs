@@ -0,0 +1,106 @@
+use axum::extract::Request;
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+
+use crate::error::ApiError;
+use crate::health::BackendInfo;
+
+/// Prefix of the versioned routes.
+pub const API_V1_PREFIX: &str = "/api/v1";
+
+/// Request header selecting a version on the unversioned routes, echoed on
+/// every versioned response.
+pub const API_VERSION_HEADER: &str = "api-version";
+
+/// Versions served under `/api/<version>`, oldest first.
+const API_VERSIONS: &[&str] = &["v1"];
+
+/// The response schema a request is served with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Unversioned routes such as `/health`, shaped the way existing DePHY
+    /// workers expect, including `LEGACY_METADATA`.
+    Legacy,
+    /// `/api/v1`: metadata is always a nested object.
+    V1,
+}
+
+impl ApiVersion {
+    fn from_header(value: &str) -> Option<Self> {
+        match value.trim() {
+            "1" | "v1" => Some(Self::V1),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Legacy => None,
+            Self::V1 => Some("v1"),
+        }
+    }
+
+    /// Shapes a health snapshot for this version. Only legacy responses
+    /// carry the stringified metadata, and only with `LEGACY_METADATA`.
+    pub fn present(self, info: BackendInfo, legacy_metadata: bool) -> BackendInfo {
+        match self {
+            Self::Legacy if legacy_metadata => info.with_legacy_metadata(),
+            _ => info,
+        }
+    }
+}
+
+/// Middleware picking the [`ApiVersion`] of each request: from the path
+/// under `/api/v1`, otherwise from an optional `API-Version` header.
+/// Unsupported versions are rejected rather than silently served as
+/// legacy.
+pub async fn negotiate_version(mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let path = request.uri().path();
+    let version = if path == API_V1_PREFIX || path.starts_with("/api/v1/") {
+        ApiVersion::V1
+    } else {
+        match request.headers().get(API_VERSION_HEADER) {
+            None => ApiVersion::Legacy,
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(ApiVersion::from_header)
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "unsupported_api_version",
+                        format!(
+                            "Unsupported API version, supported: {}",
+                            API_VERSIONS.join(", ")
+                        ),
+                    )
+                })?,
+        }
+    };
+
+    request.extensions_mut().insert(version);
+    let mut response = next.run(request).await;
+    if let Some(name) = version.name() {
+        response
+            .headers_mut()
+            .insert(API_VERSION_HEADER, HeaderValue::from_static(name));
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiVersions {
+    versions: &'static [&'static str],
+    latest: &'static str,
+}
+
+/// `GET /api`: the supported API versions.
+pub async fn api_versions_handler() -> Json<ApiVersions> {
+    Json(ApiVersions {
+        versions: API_VERSIONS,
+        latest: API_VERSIONS[API_VERSIONS.len() - 1],
+    })
+}
@@ -0,0 +1,61 @@
+use alloy::hex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::dstack::DStackClient;
+
+/// TDX report data is 64 bytes; the worker's 32-byte Nostr public key fills
+/// the first half and the rest is zero.
+pub const REPORT_DATA_LEN: usize = 64;
+
+#[derive(Debug, Deserialize)]
+struct GetQuoteResponse {
+    quote: String,
+    #[serde(default)]
+    event_log: Option<String>,
+}
+
+/// A TDX quote binding the worker's Nostr identity to the hardware.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Attestation {
+    pub nostr_pubkey: String,
+    pub report_data: String,
+    pub quote: String,
+    pub event_log: Option<String>,
+}
+
+/// Builds the report data for a hex-encoded Nostr public key.
+pub fn report_data_for_pubkey(nostr_pubkey: &str) -> Result<[u8; REPORT_DATA_LEN], String> {
+    let pubkey = hex::decode(nostr_pubkey).map_err(|e| format!("Invalid public key: {}", e))?;
+    if pubkey.len() != 32 {
+        return Err(format!("Invalid public key length: {}", pubkey.len()));
+    }
+
+    let mut report_data = [0u8; REPORT_DATA_LEN];
+    report_data[..32].copy_from_slice(&pubkey);
+    Ok(report_data)
+}
+
+/// Requests a TDX quote from the dstack guest agent with the public key
+/// embedded in the report data.
+pub async fn get_quote(
+    guest_agent: &dyn DStackClient,
+    nostr_pubkey: &str,
+) -> Result<Attestation, String> {
+    let report_data = hex::encode(report_data_for_pubkey(nostr_pubkey)?);
+
+    let response: GetQuoteResponse = guest_agent
+        .request(
+            "/GetQuote",
+            Some(&serde_json::json!({ "report_data": report_data })),
+        )
+        .await
+        .map_err(|e| format!("Guest agent quote request failed: {}", e))?;
+
+    Ok(Attestation {
+        nostr_pubkey: nostr_pubkey.to_string(),
+        report_data,
+        quote: response.quote,
+        event_log: response.event_log,
+    })
+}
@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::error::ApiError;
+use crate::server::AppState;
+
+/// Bearer tokens accepted on admin routes. Admin routes are disabled when
+/// there are none.
+#[derive(Debug, Clone, Default)]
+pub struct AdminAuth {
+    tokens: Vec<String>,
+}
+
+impl AdminAuth {
+    pub fn new(tokens: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            tokens: tokens.into_iter().collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Checks the `Authorization: Bearer <token>` header against every
+    /// accepted token.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        if !self.is_enabled() {
+            return Err(ApiError::forbidden(
+                "Admin endpoints are disabled, set ADMIN_TOKEN to enable them",
+            ));
+        }
+
+        let provided = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::unauthorized("Missing bearer token"))?;
+
+        // Compare against all tokens so timing doesn't reveal which one matched
+        let matched = self.tokens.iter().fold(false, |matched, token| {
+            constant_time_eq(provided.as_bytes(), token.as_bytes()) | matched
+        });
+        if matched {
+            Ok(())
+        } else {
+            Err(ApiError::unauthorized("Invalid bearer token"))
+        }
+    }
+}
+
+/// Middleware rejecting requests without a valid admin bearer token: `401`
+/// for a missing or wrong token, `403` when admin routes are disabled.
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    state.admin_auth.check(request.headers())?;
+    Ok(next.run(request).await)
+}
+
+/// Derives a worker's admin token from a shared secret, so a coordinator can
+/// compute the token for every worker without distributing them: the hex
+/// HMAC-SHA256 of the worker's Nostr public key (hex).
+pub fn derive_worker_token(secret: &str, nostr_pubkey: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(nostr_pubkey.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
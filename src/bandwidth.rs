@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+
+/// Periodic measurement of the worker's throughput and latency to a test
+/// endpoint. Bandwidth matters for scheduling GPU workloads but dstack
+/// doesn't report it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    pub enabled: bool,
+    /// URL whose response body is downloaded to measure download speed and
+    /// latency.
+    pub download_url: String,
+    /// URL `upload_bytes` are POSTed to, to measure upload speed.
+    pub upload_url: String,
+    /// Size of the upload; `0` skips the upload test.
+    pub upload_bytes: usize,
+    /// How often the test runs.
+    pub interval_secs: u64,
+    /// Time allowed for each of the download and upload tests.
+    pub timeout_secs: u64,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            download_url: "https://speed.cloudflare.com/__down?bytes=25000000".to_string(),
+            upload_url: "https://speed.cloudflare.com/__up".to_string(),
+            upload_bytes: 10_000_000,
+            interval_secs: 21_600,
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// Outcome of the latest bandwidth test.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BandwidthResult {
+    pub measured_at: u64,
+    /// Time until the download response headers arrived.
+    pub latency_ms: u64,
+    pub download_mbps: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_mbps: Option<f64>,
+}
+
+/// The most recent successful measurement.
+#[derive(Debug, Default)]
+pub struct BandwidthCache {
+    current: RwLock<Option<BandwidthResult>>,
+}
+
+impl BandwidthCache {
+    pub fn get(&self) -> Option<BandwidthResult> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn set(&self, result: BandwidthResult) {
+        *self.current.write().unwrap() = Some(result);
+    }
+}
+
+fn mbps(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 * 8.0 / elapsed.as_secs_f64().max(f64::EPSILON) / 1_000_000.0
+}
+
+/// Runs the download and, if configured, upload test once.
+pub async fn measure(config: &BandwidthConfig) -> Result<BandwidthResult, String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let mut response = client
+        .get(&config.download_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("download failed: {}", e))?;
+    let latency = started.elapsed();
+    let mut downloaded = 0;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("download failed: {}", e))?
+    {
+        downloaded += chunk.len();
+    }
+    let download_mbps = mbps(downloaded, started.elapsed() - latency);
+
+    let upload_mbps = if config.upload_bytes == 0 {
+        None
+    } else {
+        let started = Instant::now();
+        client
+            .post(&config.upload_url)
+            .body(vec![0u8; config.upload_bytes])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("upload failed: {}", e))?;
+        Some(mbps(config.upload_bytes, started.elapsed()))
+    };
+
+    Ok(BandwidthResult {
+        measured_at: unix_timestamp(),
+        latency_ms: latency.as_millis() as u64,
+        download_mbps,
+        upload_mbps,
+    })
+}
+
+/// Runs the test at startup and then every `interval_secs`. A failed test
+/// keeps the previous result.
+pub async fn run_bandwidth_test(state: Arc<AppState>, config: BandwidthConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs));
+
+    loop {
+        ticker.tick().await;
+
+        match measure(&config).await {
+            Ok(result) => {
+                info!(
+                    "Bandwidth: {:.1} Mbit/s down, {} up, {}ms latency",
+                    result.download_mbps,
+                    result
+                        .upload_mbps
+                        .map(|mbps| format!("{:.1} Mbit/s", mbps))
+                        .unwrap_or_else(|| "not measured".to_string()),
+                    result.latency_ms
+                );
+                state.bandwidth.set(result);
+            }
+            Err(e) => warn!("Bandwidth test failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::dstack::{fetch_dstack_data, DStackClient};
+use crate::dstack_info::fetch_dstack_info;
+use crate::error::{ApiError, BackendError, ErrorBody};
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+use crate::vms::{app_compose, CREATE_VM_METHOD, REMOVE_VM_METHOD, STOP_VM_METHOD};
+
+/// Event kind of signed benchmark results.
+pub const BENCHMARK_KIND: u16 = 1574;
+
+/// File in `DATA_DIR` holding the latest signed result.
+const BENCHMARK_FILE: &str = "benchmark.json";
+
+/// Port the benchmark container serves its result on inside the CVM.
+const RESULT_VM_PORT: u16 = 8000;
+
+/// How often the result port is polled while the benchmark runs.
+const RESULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A short compute benchmark run in a CVM with the free GPUs attached.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BenchmarkConfig {
+    /// Container image that runs the benchmark and serves the result as
+    /// JSON on port 8000.
+    pub image: Option<String>,
+    /// dstack OS image for the CVM; defaults to the one dstack reports.
+    pub os_image: Option<String>,
+    pub vcpu: u32,
+    pub memory_mb: u32,
+    pub disk_gb: u32,
+    /// Host port the container's result port is mapped to, on 127.0.0.1.
+    pub result_port: u16,
+    /// Time allowed from creating the CVM to reading the result.
+    pub timeout_secs: u64,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            os_image: None,
+            vcpu: 8,
+            memory_mb: 32_768,
+            disk_gb: 40,
+            result_port: 18_400,
+            timeout_secs: 900,
+        }
+    }
+}
+
+/// One GPU's result, as reported by the benchmark container.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GpuBenchmark {
+    /// Device name inside the CVM.
+    pub name: String,
+    /// Dense matrix multiplication throughput.
+    pub matmul_tflops: f64,
+    /// Device memory copy throughput.
+    pub memory_bandwidth_gbps: f64,
+}
+
+/// What the benchmark container serves once it's done.
+#[derive(Debug, Deserialize)]
+struct ContainerResult {
+    gpus: Vec<GpuBenchmark>,
+}
+
+/// Content of a signed benchmark event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BenchmarkResult {
+    pub measured_at: u64,
+    pub image: String,
+    /// dstack slots of the GPUs attached to the benchmark CVM.
+    pub slots: Vec<String>,
+    pub gpus: Vec<GpuBenchmark>,
+    /// Sum over all GPUs.
+    pub matmul_tflops: f64,
+    /// Sum over all GPUs.
+    pub memory_bandwidth_gbps: f64,
+}
+
+/// Runs benchmarks one at a time and keeps the latest signed result.
+pub struct Benchmarker {
+    config: BenchmarkConfig,
+    signer: Arc<dyn NostrSigner>,
+    path: PathBuf,
+    running: AtomicBool,
+    latest: RwLock<Option<Event>>,
+}
+
+impl Benchmarker {
+    /// Loads the previous result from `DATA_DIR/benchmark.json`, if any.
+    pub fn open(config: BenchmarkConfig, signer: Arc<dyn NostrSigner>, data_dir: &Path) -> Self {
+        let path = data_dir.join(BENCHMARK_FILE);
+        Self {
+            latest: RwLock::new(load_benchmark(data_dir)),
+            config,
+            signer,
+            path,
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// The latest signed result.
+    pub fn latest(&self) -> Option<Event> {
+        self.latest.read().unwrap().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Whether a benchmark image is configured.
+    pub fn is_configured(&self) -> bool {
+        self.config.image.is_some()
+    }
+
+    /// Marks a benchmark as running, unless one already is. The caller
+    /// must follow up with [`Benchmarker::run_begun`].
+    pub fn try_begin(&self) -> bool {
+        !self.running.swap(true, Ordering::SeqCst)
+    }
+
+    /// Runs a benchmark, signs it and stores it as the latest result.
+    /// Fails right away if one is already running.
+    pub async fn run(&self, connection: &dyn DStackClient) -> Result<Event, BackendError> {
+        if !self.try_begin() {
+            return Err(BackendError::Benchmark(
+                "a benchmark is already running".to_string(),
+            ));
+        }
+        self.run_begun(connection).await
+    }
+
+    /// Runs the benchmark started with [`Benchmarker::try_begin`].
+    pub async fn run_begun(&self, connection: &dyn DStackClient) -> Result<Event, BackendError> {
+        let result = self.run_exclusive(connection).await;
+        self.running.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn run_exclusive(&self, connection: &dyn DStackClient) -> Result<Event, BackendError> {
+        let result = run_benchmark(connection, &self.config).await?;
+        let event = sign_benchmark(&result, &self.signer).await?;
+
+        let json = serde_json::to_string_pretty(&event)
+            .map_err(|e| BackendError::Benchmark(e.to_string()))?;
+        if let Err(e) = fs::write(&self.path, json) {
+            warn!("Failed to save {}: {}", self.path.display(), e);
+        }
+        *self.latest.write().unwrap() = Some(event.clone());
+        Ok(event)
+    }
+}
+
+/// Reads the signed result saved by a previous run.
+pub fn load_benchmark(data_dir: &Path) -> Option<Event> {
+    let path = data_dir.join(BENCHMARK_FILE);
+    let content = fs::read_to_string(&path).ok()?;
+    match Event::from_json(content.trim()) {
+        Ok(event) => Some(event),
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Signs `result` as a Nostr event with the worker key, so consumers can
+/// check which worker measured it.
+pub async fn sign_benchmark(
+    result: &BenchmarkResult,
+    signer: &Arc<dyn NostrSigner>,
+) -> Result<Event, BackendError> {
+    let content =
+        serde_json::to_string(result).map_err(|e| BackendError::Benchmark(e.to_string()))?;
+    EventBuilder::new(Kind::Custom(BENCHMARK_KIND), content)
+        .sign(signer)
+        .await
+        .map_err(|e| BackendError::Benchmark(format!("failed to sign result: {}", e)))
+}
+
+/// Creates a CVM running the benchmark image with every free GPU attached,
+/// waits for its result and removes the CVM again.
+pub async fn run_benchmark(
+    connection: &dyn DStackClient,
+    config: &BenchmarkConfig,
+) -> Result<BenchmarkResult, BackendError> {
+    let image = config
+        .image
+        .clone()
+        .ok_or_else(|| BackendError::Benchmark("benchmark.image is not set".to_string()))?;
+    let os_image = match &config.os_image {
+        Some(os_image) => os_image.clone(),
+        None => fetch_dstack_info(connection)
+            .await
+            .and_then(|info| info.os_image)
+            .ok_or_else(|| {
+                BackendError::Benchmark(
+                    "dstack doesn't report its OS image, set benchmark.os_image".to_string(),
+                )
+            })?,
+    };
+
+    let slots: Vec<String> = fetch_dstack_data(connection)
+        .await?
+        .gpus
+        .into_iter()
+        .filter(|gpu| gpu.is_free)
+        .map(|gpu| gpu.slot)
+        .collect();
+    if slots.is_empty() {
+        return Err(BackendError::Benchmark("no free GPUs".to_string()));
+    }
+
+    let measured_at = unix_timestamp();
+    let name = format!("dstack-backend-benchmark-{}", measured_at);
+    let compose = app_compose(&name, &compose_file(&image));
+    let vm = serde_json::json!({
+        "name": name,
+        "image": os_image,
+        "compose_file": compose.to_string(),
+        "vcpu": config.vcpu,
+        "memory": config.memory_mb,
+        "disk_size": config.disk_gb,
+        "ports": [{
+            "protocol": "tcp",
+            "host_address": "127.0.0.1",
+            "host_port": config.result_port,
+            "vm_port": RESULT_VM_PORT,
+        }],
+        "gpus": {
+            "attach_mode": "listed",
+            "gpus": slots.iter().map(|slot| serde_json::json!({ "slot": slot })).collect::<Vec<_>>(),
+        },
+    });
+
+    info!(
+        "Starting benchmark CVM {} with {} GPUs ({})",
+        name,
+        slots.len(),
+        image
+    );
+    let created = connection
+        .call(&format!("/prpc/{}?json", CREATE_VM_METHOD), Some(&vm))
+        .await?;
+    let id = created["id"]
+        .as_str()
+        .ok_or_else(|| BackendError::Decode("CreateVm response has no id".to_string()))?
+        .to_string();
+
+    let result = wait_for_result(config).await;
+
+    let id = serde_json::json!({ "id": id });
+    for method in [STOP_VM_METHOD, REMOVE_VM_METHOD] {
+        if let Err(e) = connection
+            .call(&format!("/prpc/{}?json", method), Some(&id))
+            .await
+        {
+            warn!(
+                "Failed to clean up benchmark CVM {}: {} {}",
+                name, method, e
+            );
+        }
+    }
+
+    let gpus = result?.gpus;
+    info!("Benchmark CVM {} finished", name);
+    Ok(BenchmarkResult {
+        measured_at,
+        image,
+        slots,
+        matmul_tflops: gpus.iter().map(|gpu| gpu.matmul_tflops).sum(),
+        memory_bandwidth_gbps: gpus.iter().map(|gpu| gpu.memory_bandwidth_gbps).sum(),
+        gpus,
+    })
+}
+
+/// A compose file running `image` with all GPUs and its result port
+/// published.
+fn compose_file(image: &str) -> String {
+    format!(
+        "services:\n  benchmark:\n    image: {}\n    ports:\n      - \"{port}:{port}\"\n    \
+         deploy:\n      resources:\n        reservations:\n          devices:\n            \
+         - driver: nvidia\n              count: all\n              capabilities: [gpu]\n",
+        image,
+        port = RESULT_VM_PORT
+    )
+}
+
+/// Polls the result port until the container serves a result, giving up
+/// after `timeout_secs`.
+async fn wait_for_result(config: &BenchmarkConfig) -> Result<ContainerResult, BackendError> {
+    let url = format!("http://127.0.0.1:{}/", config.result_port);
+    let client = reqwest::Client::builder()
+        .timeout(RESULT_POLL_INTERVAL)
+        .build()
+        .map_err(|e| BackendError::Benchmark(e.to_string()))?;
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let started = Instant::now();
+
+    loop {
+        // The port refuses connections until the CVM has booted and answers
+        // with an error until the benchmark is done
+        if let Ok(response) = client.get(&url).send().await {
+            if response.status().is_success() {
+                return response
+                    .json()
+                    .await
+                    .map_err(|e| BackendError::Decode(format!("invalid benchmark result: {}", e)));
+            }
+        }
+        if started.elapsed() >= timeout {
+            return Err(BackendError::Benchmark(format!(
+                "no result after {}s",
+                config.timeout_secs
+            )));
+        }
+        tokio::time::sleep(RESULT_POLL_INTERVAL).await;
+    }
+}
+
+/// `GET /benchmark`: the latest signed result.
+#[utoipa::path(
+    get,
+    path = "/benchmark",
+    tag = "gpus",
+    responses(
+        (status = 200, description = "Nostr event signed by the worker key, with a `BenchmarkResult` as content", body = serde_json::Value),
+        (status = 404, description = "No benchmark has been run", body = ErrorBody),
+    )
+)]
+pub async fn benchmark_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Event>, ApiError> {
+    state
+        .benchmark
+        .latest()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("No benchmark has been run"))
+}
+
+/// `POST /benchmark`: starts a benchmark in the background. The result
+/// replaces the one served by `GET /benchmark` once it's done.
+#[utoipa::path(
+    post,
+    path = "/benchmark",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "Benchmark started"),
+        (status = 409, description = "A benchmark is already running", body = ErrorBody),
+        (status = 503, description = "No benchmark image is configured", body = ErrorBody),
+    )
+)]
+pub async fn start_benchmark_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    start_benchmark(state)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Starts a benchmark in the background, unless none is configured or one
+/// is already running.
+pub fn start_benchmark(state: Arc<AppState>) -> Result<(), ApiError> {
+    if !state.benchmark.is_configured() {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "benchmark_disabled",
+            "No benchmark image is configured",
+        ));
+    }
+    if !state.benchmark.try_begin() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "benchmark_running",
+            "A benchmark is already running",
+        ));
+    }
+
+    tokio::spawn(async move {
+        match state
+            .benchmark
+            .run_begun(state.connection.client().as_ref())
+            .await
+        {
+            Ok(event) => info!("Benchmark result signed as event {}", event.id),
+            Err(e) => error!("{}", e),
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,327 @@
+//! Minimal dstack prpc server with configurable GPU fixtures, latency and
+//! failures, for exercising the backend without real hardware.
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{any, get, put},
+    Json, Router,
+};
+use clap::Parser;
+use dstack_backend::dstack::{DStackResponse, GpuInfo};
+use dstack_backend::error::{method_not_allowed_handler, not_found_handler, ApiError};
+use dstack_backend::request_id::request_context;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+#[derive(Debug, Parser)]
+#[command(name = "mock-dstack", version, about = "Mock dstack prpc server")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:19060")]
+    listen: SocketAddr,
+    /// JSON file with a ListGpus response (`{"gpus": [...], "allow_attach_all": true}`)
+    #[arg(long)]
+    fixture: Option<PathBuf>,
+    /// Number of generated GPUs when no fixture is given
+    #[arg(long, default_value_t = 8)]
+    gpus: usize,
+    /// Description of the generated GPUs
+    #[arg(long, default_value = "NVIDIA H100 80GB HBM3")]
+    model: String,
+    /// PCI product ID of the generated GPUs
+    #[arg(long, default_value = "2330")]
+    product_id: String,
+    /// Number of generated GPUs already attached to CVMs
+    #[arg(long, default_value_t = 0)]
+    busy: usize,
+    #[command(flatten)]
+    settings: Settings,
+}
+
+/// Behaviour knobs, settable on the command line and at runtime through
+/// `PUT /mock/settings`.
+#[derive(Debug, Clone, Default, clap::Args, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    /// Delay added to every prpc response, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+    /// Fail every Nth prpc request (0 disables)
+    #[arg(long, default_value_t = 0)]
+    fail_every: u64,
+    /// Fail every prpc request
+    #[arg(long)]
+    down: bool,
+    /// Answer with a body that isn't valid JSON
+    #[arg(long)]
+    malformed: bool,
+    /// HTTP status used for injected failures
+    #[arg(long, default_value_t = 503)]
+    fail_status: u16,
+}
+
+struct MockState {
+    inventory: RwLock<DStackResponse>,
+    settings: RwLock<Settings>,
+    requests: AtomicU64,
+    /// CVMs created with `CreateVm`, by ID.
+    vms: Mutex<HashMap<String, MockVm>>,
+}
+
+struct MockVm {
+    name: String,
+    gpus: Vec<String>,
+    created_at: Instant,
+    server: tokio::task::AbortHandle,
+}
+
+impl MockVm {
+    /// The CVM as dstack's `Status` reports it.
+    fn status(&self, id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "name": self.name,
+            "status": "running",
+            "uptime": format!("{}s", self.created_at.elapsed().as_secs()),
+            "configuration": {
+                "gpus": {
+                    "attach_mode": "listed",
+                    "gpus": self.gpus.iter().map(|slot| serde_json::json!({ "slot": slot })).collect::<Vec<_>>(),
+                },
+            },
+        })
+    }
+}
+
+/// Stands in for a benchmark CVM: serves a canned result for each attached
+/// GPU on the first mapped host port until the CVM is removed.
+async fn create_vm(
+    state: &MockState,
+    vm: &serde_json::Value,
+) -> Result<serde_json::Value, ApiError> {
+    let gpus: Vec<serde_json::Value> = vm["gpus"]["gpus"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|_| {
+            serde_json::json!({
+                "name": "NVIDIA H100 80GB HBM3",
+                "matmul_tflops": 756.2,
+                "memory_bandwidth_gbps": 2890.5
+            })
+        })
+        .collect();
+    let result = serde_json::json!({ "gpus": gpus });
+
+    let id = format!("mock-vm-{}", state.requests.load(Ordering::Relaxed));
+    let port = vm["ports"][0]["host_port"].as_u64().unwrap_or_default() as u16;
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to bind port {}: {}", port, e)))?;
+    let app = Router::new().route("/", get(move || async move { Json(result) }));
+    let server = tokio::spawn(async move { axum::serve(listener, app).await });
+    info!("Created VM {} serving on port {}", id, port);
+
+    let slots = vm["gpus"]["gpus"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|gpu| gpu["slot"].as_str().map(str::to_string))
+        .collect();
+    state.vms.lock().unwrap().insert(
+        id.clone(),
+        MockVm {
+            name: vm["name"].as_str().unwrap_or_default().to_string(),
+            gpus: slots,
+            created_at: Instant::now(),
+            server: server.abort_handle(),
+        },
+    );
+    Ok(serde_json::json!({ "id": id }))
+}
+
+fn generated_inventory(args: &Args) -> DStackResponse {
+    let gpus = (0..args.gpus)
+        .map(|i| GpuInfo {
+            slot: format!("0000:{:02x}:00.0", 0x18 + i * 0x10),
+            product_id: args.product_id.clone(),
+            description: args.model.clone(),
+            is_free: i >= args.busy,
+        })
+        .collect();
+
+    DStackResponse {
+        gpus,
+        allow_attach_all: true,
+    }
+}
+
+fn load_inventory(args: &Args) -> Result<DStackResponse, Box<dyn std::error::Error>> {
+    match &args.fixture {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            Ok(serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid fixture {}: {}", path.display(), e))?)
+        }
+        None => Ok(generated_inventory(args)),
+    }
+}
+
+async fn prpc_handler(
+    State(state): State<Arc<MockState>>,
+    Path(method): Path<String>,
+    body: Option<Json<serde_json::Value>>,
+) -> Response {
+    let settings = state.settings.read().unwrap().clone();
+    let count = state.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    info!("prpc {} (request #{})", method, count);
+
+    if settings.latency_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(settings.latency_ms)).await;
+    }
+
+    if settings.down || (settings.fail_every > 0 && count % settings.fail_every == 0) {
+        let status =
+            StatusCode::from_u16(settings.fail_status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        return ApiError::new(status, "injected_failure", "Injected failure").into_response();
+    }
+    if settings.malformed {
+        return (StatusCode::OK, "{not json").into_response();
+    }
+
+    let response = match method.as_str() {
+        "ListGpus" => serde_json::to_value(&*state.inventory.read().unwrap()).unwrap(),
+        "Info" => {
+            serde_json::json!({
+                "name": "mock-dstack",
+                "version": env!("CARGO_PKG_VERSION"),
+                "os_image": "dstack-mock-0.0.0",
+                "kms": { "url": "https://kms.example.com", "mock": true }
+            })
+        }
+        "Version" => serde_json::json!({ "version": env!("CARGO_PKG_VERSION"), "rev": "mock" }),
+        "Status" => {
+            let vms = state.vms.lock().unwrap();
+            let vms: Vec<_> = vms.iter().map(|(id, vm)| vm.status(id)).collect();
+            serde_json::json!({ "vms": vms })
+        }
+        "CreateVm" => {
+            let vm = body.map(|Json(body)| body).unwrap_or_default();
+            match create_vm(&state, &vm).await {
+                Ok(response) => response,
+                Err(e) => return e.into_response(),
+            }
+        }
+        "StopVm" | "PullImage" => serde_json::json!({}),
+        "RemoveVm" => {
+            let id = body.as_ref().and_then(|Json(body)| body["id"].as_str());
+            match id.and_then(|id| state.vms.lock().unwrap().remove(id)) {
+                Some(vm) => {
+                    vm.server.abort();
+                    serde_json::json!({})
+                }
+                None => return ApiError::not_found(format!("Unknown VM {:?}", id)).into_response(),
+            }
+        }
+        "GetQuote" => serde_json::json!({ "quote": "00", "event_log": "[]" }),
+        "GetKey" => {
+            // Deterministic per path, like the real KMS
+            let path = body.as_ref().and_then(|Json(body)| body["path"].as_str());
+            let key = Sha256::digest(format!("mock-dstack:{}", path.unwrap_or_default()));
+            serde_json::json!({ "key": hex::encode(key), "signature_chain": [] })
+        }
+        "AttachGpu" | "DetachGpu" => {
+            let slot = body.as_ref().and_then(|Json(body)| body["slot"].as_str());
+            let mut inventory = state.inventory.write().unwrap();
+            match inventory
+                .gpus
+                .iter_mut()
+                .find(|gpu| Some(gpu.slot.as_str()) == slot)
+            {
+                Some(gpu) => {
+                    gpu.is_free = method == "DetachGpu";
+                    serde_json::json!({})
+                }
+                None => {
+                    return ApiError::not_found(format!("Unknown GPU slot {:?}", slot))
+                        .into_response()
+                }
+            }
+        }
+        _ => return ApiError::not_found(format!("Unknown prpc method {}", method)).into_response(),
+    };
+
+    Json(response).into_response()
+}
+
+async fn put_settings(
+    State(state): State<Arc<MockState>>,
+    settings: Result<Json<Settings>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Json(settings) = settings?;
+    info!("Updated settings: {:?}", settings);
+    *state.settings.write().unwrap() = settings;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Counters for tests checking how often the backend called.
+async fn get_stats(State(state): State<Arc<MockState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "requests": state.requests.load(Ordering::Relaxed) }))
+}
+
+async fn put_gpus(
+    State(state): State<Arc<MockState>>,
+    inventory: Result<Json<DStackResponse>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Json(inventory) = inventory?;
+    info!("Updated inventory: {} GPUs", inventory.gpus.len());
+    *state.inventory.write().unwrap() = inventory;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "mock_dstack=info,dstack_backend=info".into()),
+        )
+        .init();
+
+    let args = Args::parse();
+    let state = Arc::new(MockState {
+        inventory: RwLock::new(load_inventory(&args)?),
+        settings: RwLock::new(args.settings.clone()),
+        requests: AtomicU64::new(0),
+        vms: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/prpc/:method", any(prpc_handler))
+        // The guest agent serves its methods without the prpc prefix
+        .route("/:method", any(prpc_handler))
+        .route("/mock/settings", put(put_settings))
+        .route("/mock/gpus", put(put_gpus))
+        .route("/mock/stats", get(get_stats))
+        .fallback(not_found_handler)
+        .method_not_allowed_fallback(method_not_allowed_handler)
+        .layer(middleware::from_fn(request_context))
+        .with_state(state);
+
+    info!("mock-dstack listening on {}", args.listen);
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
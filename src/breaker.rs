@@ -0,0 +1,350 @@
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::BackendError;
+
+/// When to stop calling dstack after repeated failures.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the circuit (0 disables the breaker).
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a single probe is let through.
+    pub reset_secs: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            reset_secs: 30,
+        }
+    }
+}
+
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+        last_error: BackendError,
+    },
+    HalfOpen {
+        since: Instant,
+        last_error: BackendError,
+    },
+}
+
+/// Circuit breaker for dstack requests. While open, calls fail fast with the
+/// last error instead of reaching dstack; once the reset timeout passes, one
+/// probe from the background poller is let through to decide whether to
+/// close the circuit again.
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    fn reset_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.reset_secs)
+    }
+
+    /// Runs `call` unless the circuit is open, updating the breaker with the
+    /// outcome. Fails fast while the circuit is open or half-open.
+    pub async fn call<T, F>(&self, call: F) -> Result<T, BackendError>
+    where
+        F: Future<Output = Result<T, BackendError>>,
+    {
+        self.run(call, false).await
+    }
+
+    /// Like [`call`](Self::call), but once the reset timeout has passed, lets
+    /// `call` through as the single probe that closes or reopens the circuit.
+    /// Only the poller probes, so requests keep being served from its
+    /// snapshot instead of racing for the probe.
+    pub async fn probe<T, F>(&self, call: F) -> Result<T, BackendError>
+    where
+        F: Future<Output = Result<T, BackendError>>,
+    {
+        self.run(call, true).await
+    }
+
+    /// Whether calls are being rejected, i.e. the circuit is open or
+    /// half-open.
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), BreakerState::Closed { .. })
+    }
+
+    async fn run<T, F>(&self, call: F, probe: bool) -> Result<T, BackendError>
+    where
+        F: Future<Output = Result<T, BackendError>>,
+    {
+        self.acquire(probe)?;
+
+        let result = call.await;
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(e) => self.record_failure(e),
+        }
+        result
+    }
+
+    fn acquire(&self, probe: bool) -> Result<(), BackendError> {
+        if self.config.failure_threshold == 0 {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match &*state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until, last_error } if probe && now >= *until => {
+                info!("dstack circuit half-open, probing");
+                *state = BreakerState::HalfOpen {
+                    since: now,
+                    last_error: last_error.clone(),
+                };
+                Ok(())
+            }
+            // Kept free of countdowns so repeated polls report the same error
+            BreakerState::Open { last_error, .. } => {
+                Err(BackendError::CircuitOpen(last_error.to_string()))
+            }
+            // A probe that never reported back (e.g. its request was dropped)
+            // shouldn't keep the circuit half-open forever
+            BreakerState::HalfOpen { since, last_error }
+                if probe && now.duration_since(*since) >= self.reset_timeout() =>
+            {
+                *state = BreakerState::HalfOpen {
+                    since: now,
+                    last_error: last_error.clone(),
+                };
+                Ok(())
+            }
+            BreakerState::HalfOpen { last_error, .. } => {
+                Err(BackendError::CircuitOpen(last_error.to_string()))
+            }
+        }
+    }
+
+    /// Closes the circuit, e.g. after switching to another dstack endpoint.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, BreakerState::Closed { .. }) {
+            info!("dstack recovered, circuit closed");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    fn record_failure(&self, error: &BackendError) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let failures = match &*state {
+            BreakerState::Closed { failures } => failures + 1,
+            _ => self.config.failure_threshold,
+        };
+
+        if failures >= self.config.failure_threshold {
+            warn!(
+                "dstack circuit open for {}s after {} failures",
+                self.config.reset_secs, failures
+            );
+            *state = BreakerState::Open {
+                until: Instant::now() + self.reset_timeout(),
+                last_error: error.clone(),
+            };
+        } else {
+            *state = BreakerState::Closed { failures };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            failure_threshold,
+            reset_secs: 60,
+        })
+    }
+
+    fn failure() -> BackendError {
+        BackendError::Timeout(Duration::from_secs(1))
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<(), BackendError> {
+        breaker.call(async { Err::<(), _>(failure()) }).await
+    }
+
+    /// Moves the reset timeout of an open circuit into the past.
+    fn elapse_reset_timeout(breaker: &CircuitBreaker) {
+        let mut state = breaker.state.lock().unwrap();
+        match &*state {
+            BreakerState::Open { last_error, .. } => {
+                *state = BreakerState::Open {
+                    until: Instant::now() - Duration::from_secs(1),
+                    last_error: last_error.clone(),
+                }
+            }
+            BreakerState::HalfOpen { last_error, .. } => {
+                *state = BreakerState::HalfOpen {
+                    since: Instant::now() - breaker.reset_timeout(),
+                    last_error: last_error.clone(),
+                }
+            }
+            BreakerState::Closed { .. } => panic!("circuit is closed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures() {
+        let breaker = breaker(3);
+
+        fail(&breaker).await.unwrap_err();
+        fail(&breaker).await.unwrap_err();
+        // A success resets the count
+        breaker.call(async { Ok(()) }).await.unwrap();
+        fail(&breaker).await.unwrap_err();
+        fail(&breaker).await.unwrap_err();
+        assert!(!breaker.is_open());
+
+        fail(&breaker).await.unwrap_err();
+        assert!(breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn fails_fast_while_open() {
+        let breaker = breaker(1);
+        fail(&breaker).await.unwrap_err();
+
+        let mut called = false;
+        let result = breaker
+            .call(async {
+                called = true;
+                Ok(())
+            })
+            .await;
+
+        assert!(matches!(result, Err(BackendError::CircuitOpen(_))));
+        assert!(!called);
+        // Probes wait for the reset timeout too
+        let result = breaker.probe(async { Ok(()) }).await;
+        assert!(matches!(result, Err(BackendError::CircuitOpen(_))));
+    }
+
+    #[tokio::test]
+    async fn only_a_probe_is_let_through_after_the_reset_timeout() {
+        let breaker = breaker(1);
+        fail(&breaker).await.unwrap_err();
+        elapse_reset_timeout(&breaker);
+
+        let result = breaker.call(async { Ok(()) }).await;
+        assert!(matches!(result, Err(BackendError::CircuitOpen(_))));
+
+        breaker.probe(async { Ok(()) }).await.unwrap();
+        assert!(!breaker.is_open());
+        breaker.call(async { Ok(()) }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_reopens_the_circuit() {
+        let breaker = breaker(3);
+        for _ in 0..3 {
+            fail(&breaker).await.unwrap_err();
+        }
+        elapse_reset_timeout(&breaker);
+
+        let result = breaker.probe(async { Err::<(), _>(failure()) }).await;
+        assert!(matches!(result, Err(BackendError::Timeout(_))));
+        assert!(matches!(
+            *breaker.state.lock().unwrap(),
+            BreakerState::Open { until, .. } if until > Instant::now()
+        ));
+
+        // Reopened for a full reset timeout, so the next probe waits again
+        let result = breaker.probe(async { Ok(()) }).await;
+        assert!(matches!(result, Err(BackendError::CircuitOpen(_))));
+    }
+
+    #[tokio::test]
+    async fn half_open_lets_one_probe_through() {
+        let breaker = breaker(1);
+        fail(&breaker).await.unwrap_err();
+        elapse_reset_timeout(&breaker);
+        let (done, wait) = tokio::sync::oneshot::channel::<()>();
+
+        let (first, others) = tokio::join!(
+            breaker.probe(async {
+                wait.await.unwrap();
+                Ok(())
+            }),
+            async {
+                // The first probe is in flight
+                let probe = breaker.probe(async { Ok(()) }).await;
+                let call = breaker.call(async { Ok(()) }).await;
+                done.send(()).unwrap();
+                (probe, call)
+            },
+        );
+
+        first.unwrap();
+        assert!(matches!(others.0, Err(BackendError::CircuitOpen(_))));
+        assert!(matches!(others.1, Err(BackendError::CircuitOpen(_))));
+        assert!(!breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn a_lost_probe_is_replaced_after_the_reset_timeout() {
+        let breaker = breaker(1);
+        fail(&breaker).await.unwrap_err();
+        elapse_reset_timeout(&breaker);
+
+        // The probe is dropped before it reports back
+        let probe = breaker.probe(std::future::pending::<Result<(), BackendError>>());
+        tokio::time::timeout(Duration::from_millis(1), probe)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            *breaker.state.lock().unwrap(),
+            BreakerState::HalfOpen { .. }
+        ));
+        let result = breaker.probe(async { Ok(()) }).await;
+        assert!(matches!(result, Err(BackendError::CircuitOpen(_))));
+
+        elapse_reset_timeout(&breaker);
+        breaker.probe(async { Ok(()) }).await.unwrap();
+        assert!(!breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn a_zero_threshold_disables_the_breaker() {
+        let breaker = breaker(0);
+
+        for _ in 0..10 {
+            fail(&breaker).await.unwrap_err();
+        }
+
+        assert!(!breaker.is_open());
+        breaker.call(async { Ok(()) }).await.unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+//! NIP-46 remote signing: with a `bunker://` URI configured, every event the
+//! worker publishes is signed by a remote signer over Nostr relays, so the
+//! worker's secret key never touches this host. The backend authenticates
+//! to the signer with a local client key of its own.
+//!
+//! <https://github.com/nostr-protocol/nips/blob/master/46.md>
+
+use async_trait::async_trait;
+use nostr_sdk::prelude::*;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::error::BackendError;
+
+/// A [`NostrSigner`] forwarding every operation to a NIP-46 remote signer.
+pub struct BunkerSigner {
+    client_keys: Keys,
+    remote_signer: PublicKey,
+    public_key: PublicKey,
+    client: Client,
+    timeout: Duration,
+}
+
+impl fmt::Debug for BunkerSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BunkerSigner")
+            .field("remote_signer", &self.remote_signer)
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+/// Parses a `bunker://<remote signer pubkey>?relay=...&secret=...` URI.
+pub fn parse_bunker_uri(uri: &str) -> Result<(PublicKey, Vec<RelayUrl>, Option<String>), String> {
+    match NostrConnectURI::parse(uri).map_err(|e| e.to_string())? {
+        NostrConnectURI::Bunker {
+            remote_signer_public_key,
+            relays,
+            secret,
+        } => {
+            if relays.is_empty() {
+                return Err("bunker URI has no relay".to_string());
+            }
+            Ok((remote_signer_public_key, relays, secret))
+        }
+        NostrConnectURI::Client { .. } => Err("expected a bunker:// URI".to_string()),
+    }
+}
+
+impl BunkerSigner {
+    /// Connects to the signer's relays, sends the `connect` request and
+    /// asks for the public key the signer holds. Each request waits up to
+    /// `timeout` for an answer, including time spent waiting for the signer
+    /// operator to approve it.
+    pub async fn connect(
+        uri: &str,
+        client_keys: Keys,
+        timeout: Duration,
+    ) -> Result<Self, BackendError> {
+        let (remote_signer, relays, secret) =
+            parse_bunker_uri(uri).map_err(BackendError::Config)?;
+
+        let client = Client::new(client_keys.clone());
+        for relay in &relays {
+            client.add_relay(relay.as_str()).await.map_err(|e| {
+                BackendError::Signer(format!("failed to add relay {}: {}", relay, e))
+            })?;
+        }
+        client.connect().await;
+
+        let filter = Filter::new()
+            .kind(Kind::NostrConnect)
+            .author(remote_signer)
+            .pubkey(client_keys.public_key())
+            .since(Timestamp::now());
+        client
+            .subscribe(vec![filter], None)
+            .await
+            .map_err(|e| BackendError::Signer(format!("failed to subscribe: {}", e)))?;
+
+        let mut signer = Self {
+            client_keys,
+            remote_signer,
+            public_key: remote_signer,
+            client,
+            timeout,
+        };
+
+        info!("Connecting to remote signer {}", remote_signer);
+        signer
+            .request(nip46::Request::Connect {
+                public_key: remote_signer,
+                secret,
+            })
+            .await?;
+        signer.public_key = match signer.request(nip46::Request::GetPublicKey).await? {
+            ResponseResult::GetPublicKey(public_key) => public_key,
+            other => {
+                return Err(BackendError::Signer(format!(
+                    "unexpected get_public_key response: {}",
+                    other
+                )))
+            }
+        };
+        info!("Remote signer holds key {}", signer.public_key);
+
+        Ok(signer)
+    }
+
+    /// Sends a request to the remote signer and waits for its response.
+    async fn request(&self, request: nip46::Request) -> Result<ResponseResult, BackendError> {
+        let method = request.method();
+        let message = Message::request(request);
+        let content = nip44::encrypt(
+            self.client_keys.secret_key(),
+            &self.remote_signer,
+            message.as_json(),
+            nip44::Version::default(),
+        )
+        .map_err(|e| BackendError::Signer(e.to_string()))?;
+        let event = EventBuilder::new(Kind::NostrConnect, content)
+            .tag(Tag::public_key(self.remote_signer))
+            .sign_with_keys(&self.client_keys)
+            .map_err(|e| BackendError::Signer(e.to_string()))?;
+
+        // Listen before sending so a fast response can't be missed
+        let mut notifications = self.client.notifications();
+        self.client
+            .send_event(event)
+            .await
+            .map_err(|e| BackendError::Signer(format!("failed to send {}: {}", method, e)))?;
+
+        tokio::time::timeout(
+            self.timeout,
+            self.response(&mut notifications, message.id()),
+        )
+        .await
+        .map_err(|_| {
+            BackendError::Signer(format!(
+                "{} got no response within {}s",
+                method,
+                self.timeout.as_secs()
+            ))
+        })?
+    }
+
+    async fn response(
+        &self,
+        notifications: &mut broadcast::Receiver<RelayPoolNotification>,
+        id: &str,
+    ) -> Result<ResponseResult, BackendError> {
+        loop {
+            let notification = match notifications.recv().await {
+                Ok(notification) => notification,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    return Err(BackendError::Signer("relay pool shut down".to_string()))
+                }
+            };
+
+            let RelayPoolNotification::Event { event, .. } = notification else {
+                continue;
+            };
+            if event.kind != Kind::NostrConnect || event.pubkey != self.remote_signer {
+                continue;
+            }
+            let Some(json) = self.decrypt(&event.content) else {
+                continue;
+            };
+            let Ok(Message::Response {
+                id: response_id,
+                result,
+                error,
+            }) = Message::from_json(json)
+            else {
+                continue;
+            };
+            if response_id != id {
+                continue;
+            }
+
+            match (result, error) {
+                // The signer wants the operator to approve the request on a
+                // web page first; the real response follows
+                (Some(ResponseResult::AuthUrl), url) => {
+                    warn!(
+                        "Remote signer asks for approval at {}",
+                        url.unwrap_or_default()
+                    );
+                }
+                (_, Some(error)) => return Err(BackendError::Signer(error)),
+                (Some(result), None) => return Ok(result),
+                (None, None) => return Err(BackendError::Signer("empty response".to_string())),
+            }
+        }
+    }
+
+    /// Decrypts a response, which signers send NIP-44 or (older ones) NIP-04
+    /// encrypted.
+    fn decrypt(&self, content: &str) -> Option<String> {
+        let secret_key = self.client_keys.secret_key();
+        nip44::decrypt(secret_key, &self.remote_signer, content)
+            .or_else(|_| nip04::decrypt(secret_key, &self.remote_signer, content))
+            .ok()
+    }
+
+    /// Sends an encryption or decryption request, whose result is plain text.
+    async fn crypt(&self, request: nip46::Request) -> Result<String, SignerError> {
+        self.request(request)
+            .await
+            .map(|result| result.to_string())
+            .map_err(SignerError::backend)
+    }
+}
+
+#[async_trait]
+impl NostrSigner for BunkerSigner {
+    fn backend(&self) -> SignerBackend<'_> {
+        SignerBackend::NostrConnect
+    }
+
+    async fn get_public_key(&self) -> Result<PublicKey, SignerError> {
+        Ok(self.public_key)
+    }
+
+    async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<Event, SignerError> {
+        let event = match self
+            .request(nip46::Request::SignEvent(unsigned))
+            .await
+            .map_err(SignerError::backend)?
+        {
+            ResponseResult::SignEvent(event) => *event,
+            other => {
+                return Err(SignerError::backend(BackendError::Signer(format!(
+                    "unexpected sign_event response: {}",
+                    other
+                ))))
+            }
+        };
+
+        // Don't publish whatever the signer returns without checking it
+        if event.pubkey != self.public_key {
+            return Err(SignerError::backend(BackendError::Signer(format!(
+                "event signed by {} instead of {}",
+                event.pubkey, self.public_key
+            ))));
+        }
+        event.verify().map_err(SignerError::backend)?;
+        Ok(event)
+    }
+
+    async fn nip04_encrypt(
+        &self,
+        public_key: &PublicKey,
+        content: &str,
+    ) -> Result<String, SignerError> {
+        self.crypt(nip46::Request::Nip04Encrypt {
+            public_key: *public_key,
+            text: content.to_string(),
+        })
+        .await
+    }
+
+    async fn nip04_decrypt(
+        &self,
+        public_key: &PublicKey,
+        encrypted_content: &str,
+    ) -> Result<String, SignerError> {
+        self.crypt(nip46::Request::Nip04Decrypt {
+            public_key: *public_key,
+            ciphertext: encrypted_content.to_string(),
+        })
+        .await
+    }
+
+    async fn nip44_encrypt(
+        &self,
+        public_key: &PublicKey,
+        content: &str,
+    ) -> Result<String, SignerError> {
+        self.crypt(nip46::Request::Nip44Encrypt {
+            public_key: *public_key,
+            text: content.to_string(),
+        })
+        .await
+    }
+
+    async fn nip44_decrypt(
+        &self,
+        public_key: &PublicKey,
+        payload: &str,
+    ) -> Result<String, SignerError> {
+        self.crypt(nip46::Request::Nip44Decrypt {
+            public_key: *public_key,
+            ciphertext: payload.to_string(),
+        })
+        .await
+    }
+}
@@ -0,0 +1,88 @@
+//! EVM chains for on-chain operations. Several chains can be configured
+//! under `[[chains]]` and one is selected per node with `CHAIN`, so testnet
+//! and mainnet fleets share a binary and a config file. Without a selection,
+//! `chain_rpc_url` and the contract settings form a chain named `default`.
+
+use alloy::primitives::Address;
+use alloy::providers::Provider;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Name of the chain formed by the flat `chain_rpc_url` settings.
+pub const DEFAULT_CHAIN: &str = "default";
+
+/// A `[[chains]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    pub name: String,
+    pub chain_id: u64,
+    pub rpc_url: String,
+    #[serde(default)]
+    pub registry_contract: Option<Address>,
+    #[serde(default)]
+    pub staking_contract: Option<Address>,
+}
+
+/// The chain an on-chain operation runs against.
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub name: String,
+    pub rpc_url: String,
+    /// The chain ID the RPC must report, when configured.
+    pub chain_id: Option<u64>,
+}
+
+/// Identifies the chain in logs, records and API responses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ChainTag {
+    pub name: String,
+    pub chain_id: u64,
+}
+
+impl fmt::Display for ChainTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (chain {})", self.name, self.chain_id)
+    }
+}
+
+impl Chain {
+    pub fn url(&self) -> Result<reqwest::Url, String> {
+        self.rpc_url.parse().map_err(|e| {
+            format!(
+                "Invalid RPC URL {} of chain {}: {}",
+                self.rpc_url, self.name, e
+            )
+        })
+    }
+
+    /// Asks the RPC for its chain ID. Fails when it isn't the configured
+    /// one, so a node never acts on another network's endpoint.
+    pub async fn tag(&self, provider: &impl Provider) -> Result<ChainTag, String> {
+        let chain_id = provider
+            .get_chain_id()
+            .await
+            .map_err(|e| format!("eth_chainId on chain {} failed: {}", self.name, e))?;
+        if let Some(expected) = self.chain_id {
+            if chain_id != expected {
+                return Err(format!(
+                    "RPC of chain {} is on chain ID {}, expected {}",
+                    self.name, chain_id, expected
+                ));
+            }
+        }
+        Ok(ChainTag {
+            name: self.name.clone(),
+            chain_id,
+        })
+    }
+
+    /// Whether `tag` was recorded on this chain.
+    pub fn matches(&self, tag: &ChainTag) -> bool {
+        tag.name == self.name
+            && self
+                .chain_id
+                .is_none_or(|chain_id| chain_id == tag.chain_id)
+    }
+}
@@ -0,0 +1,120 @@
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::api_version::ApiVersion;
+use crate::health::{DephyWorkerRespondedStatus, Metadata};
+use crate::server::AppState;
+
+/// Ephemeral event kind for liveness challenges addressed to a worker via a
+/// `p` tag. The content is an opaque nonce echoed back in the response.
+pub const CHALLENGE_KIND: u16 = 21573;
+
+/// Ephemeral event kind for the worker's signed challenge response.
+pub const RESPONSE_KIND: u16 = 21574;
+
+/// Challenges older than this are ignored to avoid answering stale replays.
+const MAX_CHALLENGE_AGE: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Serialize)]
+pub struct ChallengeResponse {
+    pub challenge: String,
+    pub status: DephyWorkerRespondedStatus,
+    pub node_type: String,
+    pub metadata: Option<Metadata>,
+}
+
+/// Listens for challenge events addressed to this worker and answers each
+/// with a signed response containing the current GPU status.
+pub async fn run_challenge_responder(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+) {
+    let public_key = match signer.get_public_key().await {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            error!("Failed to get the worker public key: {}", e);
+            return;
+        }
+    };
+    let filter = Filter::new()
+        .kind(Kind::Custom(CHALLENGE_KIND))
+        .pubkey(public_key)
+        .since(Timestamp::now());
+
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![filter], None).await {
+        error!("Failed to subscribe to liveness challenges: {}", e);
+        return;
+    }
+    info!("Listening for liveness challenges");
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(
+                    "Challenge responder lagged, skipped {} notifications",
+                    skipped
+                );
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if event.kind != Kind::Custom(CHALLENGE_KIND) {
+            continue;
+        }
+
+        match respond(&state, &public_key, &event) {
+            Some(builder) => match builder.sign(&signer).await {
+                Ok(response) => match client.send_event(response).await {
+                    Ok(output) => {
+                        state.relay_stats.record(&output.success, &output.failed);
+                        info!(
+                            "Answered liveness challenge {} from {}",
+                            event.id, event.pubkey
+                        )
+                    }
+                    Err(e) => error!("Failed to publish challenge response: {}", e),
+                },
+                Err(e) => error!("Failed to sign challenge response: {}", e),
+            },
+            None => debug!("Ignoring challenge {}", event.id),
+        }
+    }
+}
+
+fn respond(state: &AppState, public_key: &PublicKey, challenge: &Event) -> Option<EventBuilder> {
+    if !challenge.tags.public_keys().any(|pk| pk == public_key) {
+        return None;
+    }
+    let age = Timestamp::now()
+        .as_u64()
+        .saturating_sub(challenge.created_at.as_u64());
+    if age > MAX_CHALLENGE_AGE.as_secs() {
+        return None;
+    }
+
+    // Answer from the poller's cached snapshot so challenges can't be used to
+    // hammer dstack; it is at most one poll interval old
+    let info = ApiVersion::Legacy.present(state.poller.latest()?, state.legacy_metadata);
+    let content = ChallengeResponse {
+        challenge: challenge.content.clone(),
+        status: info.status,
+        node_type: state.node_type.clone(),
+        metadata: info.metadata,
+    };
+
+    let content = serde_json::to_string(&content).ok()?;
+    Some(
+        EventBuilder::new(Kind::Custom(RESPONSE_KIND), content)
+            .tags([Tag::event(challenge.id), Tag::public_key(challenge.pubkey)]),
+    )
+}
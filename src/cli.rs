@@ -0,0 +1,935 @@
+use alloy::primitives::U256;
+use alloy::signers::local::PrivateKeySigner;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use nostr_sdk::prelude::*;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+
+use crate::addresses::interface_addresses;
+use crate::alerts::run_status_alerts;
+use crate::bandwidth::{run_bandwidth_test, BandwidthCache};
+use crate::benchmark::{load_benchmark, Benchmarker};
+use crate::breaker::CircuitBreaker;
+use crate::challenge::run_challenge_responder;
+use crate::commands::run_command_channel;
+use crate::config::{Config, ConfigError};
+use crate::dephy::run_dephy_sessions;
+use crate::doctor::run_checks;
+use crate::dstack::detect_node_type;
+use crate::dstack_info::{run_dstack_info_refresh, DstackInfoCache};
+use crate::eip712::{new_registration_nonce, RegistrationDomain, WorkerRegistration};
+use crate::gpu_changes::GpuChangeLog;
+use crate::gpu_telemetry::{run_gpu_telemetry, GpuTelemetryCache};
+use crate::health::{BackendInfo, Metadata};
+use crate::heartbeat::run_heartbeat;
+use crate::history::{unix_timestamp, HealthHistory};
+use crate::images::ImagePrefetcher;
+use crate::jobs::run_job_intake;
+use crate::keys::{rotation_statement, rotation_statement_file};
+use crate::kms::KmsKeyMode;
+use crate::leases::GpuLeases;
+use crate::maintenance::MaintenanceMode;
+use crate::node_type::NodeTypeTable;
+use crate::ownership::{
+    load_ownership_proof, ownership_challenge, save_owner_signature, OwnershipProof,
+    OWNER_SIGNATURE_FILE,
+};
+use crate::poller::{run_poller, StatusPoller};
+use crate::public_ip::{run_public_ip_refresh, PublicIp};
+use crate::registration::{
+    auto_register, deregistration_url, DeregistrationRequest, RegistrationInfo,
+};
+use crate::registry_events::{run_registry_watch, RegistryLog};
+use crate::relay::{connect_relays, run_relay_supervisor, RelayStats};
+use crate::relay_list::run_relay_lists;
+use crate::reload::{run_reload_on_sighup, ReloadableConfig};
+use crate::replay::new_nonce;
+use crate::rewards::RewardRateSource;
+use crate::score::current_capability_score;
+use crate::server::{self, get_local_ip, AppState};
+use crate::stake::{run_stake_check, Stake, StakeStatus};
+use crate::tls;
+use crate::webhook::run_webhooks;
+use crate::whitelist::{
+    run_whitelist_check, run_whitelist_watch, WhitelistSource, WhitelistStatus,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "dstack-backend", version, about = "dstack GPU mining backend")]
+pub struct Cli {
+    /// Path to the TOML configuration file
+    #[arg(long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Log output format
+    #[arg(long, global = true, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, including span fields such as the request ID
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the backend service (default)
+    Serve,
+    /// Generate the Nostr keypair if needed and print it
+    Keygen {
+        /// Also print the secret key
+        #[arg(long)]
+        show_secret: bool,
+    },
+    /// Import, export or rotate the Nostr identity
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Print registration info, or submit it to a registrar
+    Register {
+        /// Registrar URL to POST the registration info to (defaults to
+        /// `registrar_url` from the config)
+        #[arg(long)]
+        submit: Option<String>,
+        /// Print as JSON instead of text
+        #[arg(long)]
+        json: bool,
+        /// Submit the registration to the configured registry contract
+        #[arg(long)]
+        onchain: bool,
+        #[command(flatten)]
+        typed: TypedRegistrationArgs,
+    },
+    /// Print a signed request to be taken off the whitelist, or submit it
+    Deregister {
+        /// Deregistration endpoint (defaults to `deregister` next to
+        /// `registrar_url`, e.g. `.../api/deregister`)
+        #[arg(long)]
+        url: Option<String>,
+        /// Why the worker is leaving, passed on to the administrator
+        #[arg(long)]
+        reason: Option<String>,
+        /// POST the request instead of printing it
+        #[arg(long)]
+        submit: bool,
+    },
+    /// Print the ownership challenge for the owner to sign, or sign it
+    OwnerProof {
+        /// Owner private key to sign the challenge with locally
+        #[arg(long, env = "OWNER_PRIVATE_KEY", hide_env_values = true)]
+        private_key: Option<String>,
+    },
+    /// Benchmark the free GPUs in a CVM and print the signed result
+    Benchmark,
+    /// Check the configuration, key, dstack, relays and listen address
+    /// and exit non-zero if any check fails
+    #[command(alias = "check")]
+    Doctor,
+    /// Query a running backend's /health endpoint
+    Status {
+        /// Base URL of the running backend
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        url: String,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Options for the owner's EIP-712 signature of the registration.
+#[derive(Debug, Args)]
+pub struct TypedRegistrationArgs {
+    /// Print the EIP-712 typed registration for the owner to sign with
+    /// eth_signTypedData_v4, instead of registering
+    #[arg(long)]
+    typed_data: bool,
+    /// Owner private key to sign the typed registration with locally
+    #[arg(long, conflicts_with = "typed_signature")]
+    owner_key: Option<String>,
+    /// Wallet-made signature of the typed registration, verified and
+    /// included in the registration
+    #[arg(long, requires = "nonce")]
+    typed_signature: Option<String>,
+    /// Nonce of the typed registration, random by default. Pass the printed
+    /// one along with --typed-signature
+    #[arg(long)]
+    nonce: Option<U256>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeysCommand {
+    /// Import a secret key (nsec or hex) from NOSTR_SECRET_KEY or stdin
+    Import {
+        /// Replace an existing key, archiving it first
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the public key, and the secret key with --secret
+    Export {
+        /// Also print the secret key (nsec)
+        #[arg(long)]
+        secret: bool,
+        /// Don't ask for confirmation before printing the secret key
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Generate a new keypair, archive the old one and print a signed
+    /// rotation statement
+    Rotate,
+}
+
+pub async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = cli.config.as_deref();
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(load_config(config_path).await?, cli.config.clone()).await,
+        Command::Keygen { show_secret } => keygen(
+            Config::load_partial(config_path).map_err(config_errors)?,
+            show_secret,
+        ),
+        Command::Keys { command } => keys(
+            Config::load_partial(config_path).map_err(config_errors)?,
+            command,
+        ),
+        Command::Register {
+            submit,
+            json,
+            onchain,
+            typed,
+        } => {
+            register(
+                load_config(config_path).await?,
+                submit,
+                json,
+                onchain,
+                typed,
+            )
+            .await
+        }
+        Command::Deregister {
+            url,
+            reason,
+            submit,
+        } => deregister(load_config(config_path).await?, url, reason, submit).await,
+        Command::OwnerProof { private_key } => {
+            owner_proof(load_config(config_path).await?, private_key).await
+        }
+        Command::Benchmark => benchmark(Config::load(config_path).map_err(config_errors)?).await,
+        Command::Doctor => doctor(config_path).await,
+        Command::Status { url, json } => status(&url, json).await,
+    }
+}
+
+/// Loads and validates the config and resolves an ENS owner name.
+async fn load_config(config_path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = Config::load(config_path).map_err(config_errors)?;
+    config.resolve_owner().await?;
+    Ok(config)
+}
+
+fn config_errors(errors: Vec<ConfigError>) -> Box<dyn std::error::Error> {
+    error!("Invalid configuration:");
+    for e in &errors {
+        error!("  {}", e);
+    }
+    format!("{} configuration error(s)", errors.len()).into()
+}
+
+async fn serve(
+    config: Config,
+    config_path: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Validated by Config::load, so the owner address is always present here
+    let owner_address = config.owner_address.ok_or("owner_address is required")?;
+    let owner_address_formatted = owner_address.to_string();
+
+    info!("Starting dstack Backend Monitor");
+    info!("Listen address: {}", config.listen_addr);
+    info!("dstack URL config: {}", config.dstack_url);
+    info!("Data directory: {:?}", config.data_dir);
+    info!("Nostr relays: {:?}", config.relays);
+
+    match &config.owner_ens {
+        Some(name) => info!("Owner address: {} ({})", owner_address_formatted, name),
+        None => info!("Owner address: {}", owner_address_formatted),
+    }
+
+    // Load the TLS certificate up front so a bad path fails fast
+    let tls = if config.tls.enabled() {
+        Some(tls::acceptor(&config.tls)?)
+    } else {
+        None
+    };
+
+    // Parse DSTACK_URL to determine connection type
+    let connection = config.dstack_connection()?;
+    let guest_agent = config.guest_agent_client()?;
+
+    // Get local IP address
+    let local_ip = get_local_ip();
+
+    // Load or create the Nostr keypair, or connect to the remote signer
+    let signer = config.signer().await?;
+
+    let nostr_pubkey = signer.get_public_key().await?.to_hex();
+
+    info!("Nostr public key: {}", nostr_pubkey);
+
+    // Fetch dstack data to determine node type
+    info!("Connecting to dstack to determine node type...");
+    let node_types = NodeTypeTable::new(config.node_types.clone());
+    let node_type = detect_node_type(connection.client().as_ref(), &node_types).await;
+
+    if node_type == "Unknown" {
+        error!("Could not determine node type from dstack. Defaulting to 'Unknown'.");
+        error!("Please ensure dstack is running and accessible.");
+    }
+
+    // Verify the owner's signature over this worker's identity, if provided
+    let owner_proof = load_ownership_proof(
+        config.owner_signature.as_deref(),
+        &config.data_dir,
+        &nostr_pubkey,
+        &node_type,
+        owner_address,
+    );
+
+    // Register with the registrar if configured, otherwise log the
+    // information for manual registration
+    let stake = query_stake(&config).await;
+    let pubkeys = config.pubkey_roles(&nostr_pubkey);
+    let registration = RegistrationInfo {
+        nostr_pubkey: nostr_pubkey.clone(),
+        pubkeys: pubkeys[1..].to_vec(),
+        owner_address: owner_address_formatted,
+        owner_ens: config.owner_ens.clone(),
+        node_type: node_type.clone(),
+        attestation: None,
+        owner_proof: owner_proof.clone(),
+        capability: current_capability_score(
+            connection.client().as_ref(),
+            &node_types,
+            &config.data_dir,
+        )
+        .await,
+        benchmark: load_benchmark(&config.data_dir),
+        chain: config.chain.clone(),
+        stake_ok: stake.map(|stake| stake.ok),
+        staked_amount: stake.map(|stake| stake.staked.to_string()),
+        eip712: None,
+    };
+    match &config.registrar_url {
+        Some(registrar_url) => {
+            auto_register(
+                registration.clone(),
+                registrar_url,
+                &signer,
+                guest_agent.as_ref(),
+            )
+            .await
+        }
+        None => registration.log_manual_registration(),
+    }
+
+    // Submit the registration on-chain if a registry contract is configured
+    if let Some(onchain) = config.onchain_registration() {
+        if let Err(e) = onchain
+            .register(
+                &config.data_dir,
+                &registration.nostr_pubkey,
+                &registration.node_type,
+                owner_address,
+            )
+            .await
+        {
+            error!("On-chain registration failed: {}", e);
+        }
+    }
+
+    // Connect to Nostr relays before serving so /readyz can report them
+    let nostr_client = if config.relays.is_empty() {
+        None
+    } else {
+        Some(connect_relays(&signer, &config.relays).await)
+    };
+
+    let admin_auth = config.admin_auth(&nostr_pubkey);
+
+    // Create shared state
+    let state = Arc::new(AppState {
+        connection,
+        breaker: CircuitBreaker::new(config.dstack_breaker.clone()),
+        guest_agent,
+        nostr_pubkey,
+        topic: config.dephy_topic.clone(),
+        pubkeys,
+        node_type,
+        node_types,
+        local_ip,
+        advertise_address: config.advertise_address,
+        interface_addresses: interface_addresses(),
+        public_ip: PublicIp::default(),
+        bandwidth: BandwidthCache::default(),
+        benchmark: Benchmarker::open(config.benchmark.clone(), signer.clone(), &config.data_dir),
+        dstack_info: DstackInfoCache::default(),
+        owner_proof,
+        whitelist: WhitelistStatus::default(),
+        stake: StakeStatus::default(),
+        registry: config
+            .registry_watch()
+            .map(|_| RegistryLog::open(&config.data_dir)),
+        rewards: RewardRateSource::new(
+            config.reward_rates_url.clone(),
+            config.reward_rates.clone(),
+        ),
+        relay_stats: RelayStats::default(),
+        legacy_metadata: config.legacy_metadata,
+        reloadable: RwLock::new(ReloadableConfig::new(&config)),
+        dstack_proxy_methods: config.dstack_proxy_methods.clone(),
+        dstack_proxy_auth: config.dstack_proxy_auth,
+        admin_auth,
+        history: HealthHistory::open(&config.data_dir, config.health_history_max_entries),
+        maintenance: MaintenanceMode::open(&config.data_dir),
+        leases: GpuLeases::open(&config.data_dir, signer.clone()),
+        deploying: tokio::sync::Mutex::new(()),
+        images: ImagePrefetcher::new(config.image_pull_socket.clone()),
+        data_dir: config.data_dir.clone(),
+        gpu_changes: GpuChangeLog::open(&config.data_dir),
+        gpu_telemetry: GpuTelemetryCache::default(),
+        poller: StatusPoller::new(),
+        nostr_client: nostr_client.clone(),
+        sse_keepalive: config.sse_keepalive(),
+        concurrency_limits: config.concurrency.clone(),
+    });
+
+    // Poll dstack in the background to detect status changes
+    tokio::spawn(run_poller(state.clone()));
+
+    // Apply config file changes on SIGHUP
+    tokio::spawn(run_reload_on_sighup(state.clone(), config_path));
+
+    // Report dstack's version, OS image and KMS in health metadata
+    tokio::spawn(run_dstack_info_refresh(
+        state.clone(),
+        config.dstack_info_interval(),
+    ));
+
+    // Keep the public IP current if discovery is enabled
+    if config.public_ip.enabled {
+        tokio::spawn(run_public_ip_refresh(
+            state.clone(),
+            config.public_ip.clone(),
+        ));
+    }
+
+    // Measure throughput to the test endpoint if enabled
+    if config.bandwidth.enabled {
+        tokio::spawn(run_bandwidth_test(state.clone(), config.bandwidth.clone()));
+    }
+
+    // Read GPU utilization, memory, temperature and power from nvidia-smi
+    if config.gpu_telemetry.enabled {
+        tokio::spawn(run_gpu_telemetry(
+            state.clone(),
+            config.gpu_telemetry.clone(),
+        ));
+    }
+
+    // Follow registry events about this worker, e.g. bans
+    if let Some(watch) = config.registry_watch() {
+        tokio::spawn(run_registry_watch(state.clone(), watch));
+    }
+
+    // Check that the owner keeps enough stake
+    if let Some(check) = config.stake_check() {
+        tokio::spawn(run_stake_check(state.clone(), check));
+    }
+
+    // Check the worker's own key against the whitelist service
+    if let Some(check) = config.whitelist_check() {
+        tokio::spawn(run_whitelist_check(state.clone(), check));
+    }
+
+    // Notify webhooks of status and GPU availability changes. Runs without
+    // webhooks too, in case some are added by a reload.
+    if !config.webhooks.is_empty() {
+        info!(
+            "Sending status changes to {} webhooks",
+            config.webhooks.len()
+        );
+    }
+    tokio::spawn(run_webhooks(state.clone()));
+
+    // Publish heartbeats and answer liveness challenges on Nostr relays
+    if let Some(client) = nostr_client {
+        tokio::spawn(run_heartbeat(
+            state.clone(),
+            signer.clone(),
+            client.clone(),
+            config.heartbeat_interval(),
+        ));
+        tokio::spawn(run_relay_supervisor(state.clone(), client.clone()));
+        tokio::spawn(run_relay_lists(
+            state.clone(),
+            signer.clone(),
+            client.clone(),
+            config.relays.clone(),
+            config.coordinator_pubkey(),
+        ));
+        tokio::spawn(run_challenge_responder(
+            state.clone(),
+            signer.clone(),
+            client.clone(),
+        ));
+        tokio::spawn(run_dephy_sessions(
+            state.clone(),
+            signer.clone(),
+            client.clone(),
+        ));
+        if let Some(publisher) = config.whitelist_publisher() {
+            tokio::spawn(run_whitelist_watch(
+                state.clone(),
+                client.clone(),
+                WhitelistSource {
+                    publisher,
+                    list: config.whitelist_list.clone(),
+                },
+            ));
+        }
+        let admins = config.command_pubkeys();
+        if !admins.is_empty() {
+            tokio::spawn(run_command_channel(
+                state.clone(),
+                signer.clone(),
+                client.clone(),
+                admins,
+            ));
+        }
+        let coordinators = config.job_pubkeys();
+        if !coordinators.is_empty() {
+            tokio::spawn(run_job_intake(
+                state.clone(),
+                signer.clone(),
+                client.clone(),
+                coordinators,
+            ));
+        }
+        if let Some(admin) = config.admin_pubkey() {
+            tokio::spawn(run_status_alerts(state.clone(), signer, client, admin));
+        }
+        info!(
+            "Publishing heartbeats every {}s to {} relays",
+            config.heartbeat_interval_secs,
+            config.relays.len()
+        );
+    } else {
+        info!("No Nostr relays configured, heartbeat publishing disabled");
+    }
+
+    // Serve the gRPC status service on its own port
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = config.grpc_listen_addr {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::grpc::serve(addr, state).await {
+                error!("gRPC server on {} failed: {}", addr, e);
+            }
+        });
+    }
+
+    // Run the server
+    server::serve(config.listen_addr, state, tls).await?;
+    Ok(())
+}
+
+const REMOTE_SIGNER_KEY: &str = "the key is held by the remote signer (bunker_uri)";
+const KMS_KEY: &str = "the key is protected by the dstack KMS (key_kms), run the backend to use it";
+
+fn keygen(config: Config, show_secret: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if config.bunker_uri.is_some() {
+        return Err(REMOTE_SIGNER_KEY.into());
+    }
+    if config.key_kms != KmsKeyMode::Off {
+        return Err(KMS_KEY.into());
+    }
+    let keys = config.key_store().load_or_create()?;
+
+    println!("Key file:    {}", config.data_dir.join("key").display());
+    println!("Public key:  {}", keys.public_key().to_hex());
+    println!("npub:        {}", keys.public_key().to_bech32()?);
+    if show_secret {
+        println!("Secret key:  {}", keys.secret_key().to_secret_hex());
+        println!("nsec:        {}", keys.secret_key().to_bech32()?);
+    }
+
+    Ok(())
+}
+
+fn keys(config: Config, command: KeysCommand) -> Result<(), Box<dyn std::error::Error>> {
+    if config.bunker_uri.is_some() {
+        return Err(REMOTE_SIGNER_KEY.into());
+    }
+    if config.key_kms != KmsKeyMode::Off {
+        return Err(KMS_KEY.into());
+    }
+    let store = config.key_store();
+    if store.mnemonic.is_some() && !matches!(command, KeysCommand::Export { .. }) {
+        return Err(
+            "the key is derived from a mnemonic, change mnemonic_account to switch identities"
+                .into(),
+        );
+    }
+
+    match command {
+        KeysCommand::Import { force } => {
+            if store.key_file().exists() && !force {
+                return Err(format!(
+                    "{} already exists, use --force to replace it",
+                    store.key_file().display()
+                )
+                .into());
+            }
+
+            let secret = match std::env::var("NOSTR_SECRET_KEY") {
+                Ok(secret) => secret,
+                Err(_) => {
+                    if std::io::stdin().is_terminal() {
+                        eprintln!("Paste the secret key (nsec or hex) and press enter:");
+                    }
+                    let mut secret = String::new();
+                    std::io::stdin().read_line(&mut secret)?;
+                    secret
+                }
+            };
+            let keys =
+                Keys::parse(secret.trim()).map_err(|e| format!("Invalid secret key: {}", e))?;
+
+            store.archive()?;
+            store.save(&keys)?;
+            println!("Imported key into {}", store.key_file().display());
+            println!("Public key:  {}", keys.public_key().to_hex());
+            println!("npub:        {}", keys.public_key().to_bech32()?);
+        }
+        KeysCommand::Export { secret, yes } => {
+            let keys = store.load_or_create()?;
+            println!("Public key:  {}", keys.public_key().to_hex());
+            println!("npub:        {}", keys.public_key().to_bech32()?);
+            if secret {
+                if !yes
+                    && !confirm(
+                        "Print the secret key? Anyone who sees it can impersonate this worker",
+                    )?
+                {
+                    return Err("Aborted".into());
+                }
+                println!("nsec:        {}", keys.secret_key().to_bech32()?);
+            }
+        }
+        KeysCommand::Rotate => {
+            // Generating a key here would sign a rotation away from a key
+            // nobody has seen
+            let old = store.load()?.ok_or("no key to rotate")?;
+            let new = Keys::generate();
+            let statement = rotation_statement(&old, &new)?;
+
+            let archived = store.archive()?.ok_or("no key to rotate")?;
+            store.save(&new)?;
+
+            let statement = serde_json::to_string_pretty(&statement)?;
+            let statement_file =
+                rotation_statement_file(&config.data_dir, &new.public_key().to_hex());
+            std::fs::write(&statement_file, &statement)?;
+
+            eprintln!("Old key archived to {}", archived.display());
+            eprintln!("Old public key: {}", old.public_key().to_hex());
+            eprintln!("New public key: {}", new.public_key().to_hex());
+            eprintln!("Rotation statement saved to {}", statement_file.display());
+            eprintln!("Restart the backend and re-register the worker with the new key.");
+            println!("{}", statement);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the owner's stake once for registration, if stake verification is
+/// configured.
+async fn query_stake(config: &Config) -> Option<Stake> {
+    match config.stake_check()?.query().await {
+        Ok(stake) => Some(stake),
+        Err(e) => {
+            warn!("Registering without stake information: {}", e);
+            None
+        }
+    }
+}
+
+/// Asks a yes/no question on the terminal. Declines when stdin isn't a
+/// terminal, so scripts have to pass --yes explicitly.
+fn confirm(question: &str) -> std::io::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+async fn register(
+    config: Config,
+    submit: Option<String>,
+    json: bool,
+    onchain: bool,
+    typed: TypedRegistrationArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let signer = config.signer().await?;
+    let connection = config.dstack_client()?;
+    let node_types = NodeTypeTable::new(config.node_types.clone());
+    let node_type = detect_node_type(connection.as_ref(), &node_types).await;
+
+    let owner_address = config.owner_address.ok_or("owner_address is required")?;
+    let nostr_pubkey = signer.get_public_key().await?.to_hex();
+    let owner_proof = load_ownership_proof(
+        config.owner_signature.as_deref(),
+        &config.data_dir,
+        &nostr_pubkey,
+        &node_type,
+        owner_address,
+    );
+    let stake = query_stake(&config).await;
+    let mut registration = RegistrationInfo {
+        pubkeys: config.pubkey_roles(&nostr_pubkey)[1..].to_vec(),
+        nostr_pubkey,
+        owner_address: owner_address.to_string(),
+        owner_ens: config.owner_ens.clone(),
+        node_type,
+        attestation: None,
+        owner_proof,
+        capability: current_capability_score(connection.as_ref(), &node_types, &config.data_dir)
+            .await,
+        benchmark: load_benchmark(&config.data_dir),
+        chain: config.chain.clone(),
+        stake_ok: stake.map(|stake| stake.ok),
+        staked_amount: stake.map(|stake| stake.staked.to_string()),
+        eip712: None,
+    };
+
+    if typed.typed_data || typed.owner_key.is_some() || typed.typed_signature.is_some() {
+        let domain =
+            RegistrationDomain::resolve(config.chain().as_ref(), config.registry_address()).await?;
+        let typed_registration = WorkerRegistration::new(
+            &registration.nostr_pubkey,
+            &registration.node_type,
+            owner_address,
+            typed.nonce.unwrap_or_else(new_registration_nonce),
+        )?;
+        if typed.typed_data {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&typed_registration.typed_data(&domain))?
+            );
+            return Ok(());
+        }
+        if let Some(key) = &typed.owner_key {
+            registration.eip712 = Some(typed_registration.sign(&domain, &key.parse()?)?);
+        } else if let Some(signature) = &typed.typed_signature {
+            registration.eip712 = Some(typed_registration.verify(&domain, signature)?);
+        }
+    }
+
+    if onchain {
+        let onchain_registration = config
+            .onchain_registration()
+            .ok_or("on-chain registration is not configured")?;
+        let record = onchain_registration
+            .register(
+                &config.data_dir,
+                &registration.nostr_pubkey,
+                &registration.node_type,
+                owner_address,
+            )
+            .await?;
+        println!(
+            "Registered on-chain on {} in tx {}",
+            onchain_registration.chain.name, record.tx_hash
+        );
+        return Ok(());
+    }
+
+    if let Some(registrar_url) = submit.or(config.registrar_url.clone()) {
+        let guest_agent = config.guest_agent_client()?;
+        registration.attach_attestation(guest_agent.as_ref()).await;
+        let response = registration.submit(&registrar_url, &signer).await?;
+        println!("Registration submitted to {}", registrar_url);
+        if !response.is_empty() {
+            println!("{}", response);
+        }
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&registration)?);
+    } else {
+        println!("Nostr Public Key: {}", registration.nostr_pubkey);
+        println!("Owner Address:    {}", registration.owner_address);
+        if let Some(name) = &registration.owner_ens {
+            println!("Owner ENS Name:   {}", name);
+        }
+        println!("Node Type:        {}", registration.node_type);
+    }
+
+    Ok(())
+}
+
+async fn deregister(
+    config: Config,
+    url: Option<String>,
+    reason: Option<String>,
+    submit: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = match (url, &config.registrar_url) {
+        (Some(url), _) => url,
+        (None, Some(registrar_url)) => deregistration_url(registrar_url)?,
+        (None, None) => return Err("no deregistration URL, pass --url or set registrar_url".into()),
+    };
+    let signer = config.signer().await?;
+    let owner_address = config.owner_address.ok_or("owner_address is required")?;
+    let request = DeregistrationRequest {
+        nostr_pubkey: signer.get_public_key().await?.to_hex(),
+        owner_address: owner_address.to_string(),
+        reason,
+        requested_at: unix_timestamp(),
+        nonce: new_nonce(),
+    };
+    let signed = request.sign(&url, &signer).await?;
+
+    if submit {
+        let response = signed.send().await?;
+        println!("Deregistration submitted to {}", url);
+        if !response.is_empty() {
+            println!("{}", response);
+        }
+    } else {
+        println!("POST {}", signed.url);
+        println!("Content-Type: application/json");
+        println!("Authorization: {}", signed.authorization);
+        println!();
+        println!("{}", String::from_utf8_lossy(&signed.body));
+    }
+    Ok(())
+}
+
+async fn benchmark(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let signer = config.signer().await?;
+    let connection = config.dstack_client()?;
+    let benchmarker = Benchmarker::open(config.benchmark.clone(), signer, &config.data_dir);
+
+    let event = benchmarker.run(connection.as_ref()).await?;
+    println!("{}", serde_json::to_string_pretty(&event)?);
+    Ok(())
+}
+
+async fn owner_proof(
+    config: Config,
+    private_key: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let nostr_signer = config.signer().await?;
+    let connection = config.dstack_client()?;
+    let node_types = NodeTypeTable::new(config.node_types.clone());
+    let node_type = detect_node_type(connection.as_ref(), &node_types).await;
+    let owner_address = config.owner_address.ok_or("owner_address is required")?;
+    let nostr_pubkey = nostr_signer.get_public_key().await?.to_hex();
+
+    let Some(private_key) = private_key else {
+        println!("Sign the following message with the owner account (personal_sign / EIP-191),");
+        println!(
+            "then set OWNER_SIGNATURE or write the signature to {}:",
+            config.data_dir.join(OWNER_SIGNATURE_FILE).display()
+        );
+        println!();
+        println!(
+            "{}",
+            ownership_challenge(&nostr_pubkey, &node_type, owner_address)
+        );
+        return Ok(());
+    };
+
+    let signer: PrivateKeySigner = private_key.parse()?;
+    if signer.address() != owner_address {
+        return Err(format!(
+            "private key belongs to {}, not the owner {}",
+            signer.address(),
+            owner_address
+        )
+        .into());
+    }
+
+    let proof = OwnershipProof::sign(&signer, &nostr_pubkey, &node_type)?;
+    save_owner_signature(&config.data_dir, &proof.signature)?;
+    println!("{}", proof.signature);
+
+    Ok(())
+}
+
+async fn doctor(config_path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let checks = run_checks(config_path).await;
+    for check in &checks {
+        println!("{}", check);
+    }
+
+    let failed = checks.iter().filter(|check| !check.passed()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} checks failed", failed, checks.len()).into());
+    }
+    println!("All {} checks passed", checks.len());
+    Ok(())
+}
+
+async fn status(url: &str, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let health_url = format!("{}/health", url.trim_end_matches('/'));
+    let response = reqwest::get(&health_url).await?;
+    let info: BackendInfo = response.json().await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        println!("Status:     {}", info.status);
+        println!("Version:    {}", info.version);
+        println!("Topic:      {}", info.topic);
+        for pubkey in &info.pubkeys {
+            println!("Pubkey:     {}", pubkey);
+        }
+        println!(
+            "IP address: {}",
+            info.ip_address.as_deref().unwrap_or("unknown")
+        );
+        match &info.metadata {
+            Some(Metadata::Structured(metadata)) => {
+                println!("Metadata:\n{}", serde_json::to_string_pretty(metadata)?)
+            }
+            // Legacy metadata is a JSON string on success and an error message otherwise
+            Some(Metadata::Legacy(metadata)) => {
+                match serde_json::from_str::<serde_json::Value>(metadata) {
+                    Ok(value) => println!("Metadata:\n{}", serde_json::to_string_pretty(&value)?),
+                    Err(_) => println!("Metadata:   {}", metadata),
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Same as the HTTP status of /health, so a drained worker fails too
+    if !info.status.is_serving() {
+        return Err(format!("backend reports status {}", info.status).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+//! Remote admin commands over Nostr, for workers without inbound
+//! connectivity. An allowlisted admin sends a command event that `p`-tags the
+//! worker, with the NIP-44 encrypted command as content. The worker executes
+//! it and answers with a signed, encrypted acknowledgment. Each command
+//! carries a nonce, so a captured command can't be replayed.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::api_version::ApiVersion;
+use crate::benchmark::start_benchmark;
+use crate::keys::rotation_statement_file;
+use crate::maintenance::{drain, undrain};
+use crate::poller::check_and_update;
+use crate::replay::ReplayGuard;
+use crate::server::AppState;
+
+/// Ephemeral event kind of admin commands addressed to a worker.
+pub const COMMAND_KIND: u16 = 21575;
+
+/// Ephemeral event kind of the worker's acknowledgment.
+pub const COMMAND_ACK_KIND: u16 = 21576;
+
+/// Decrypted content of a command event.
+#[derive(Debug, Deserialize)]
+pub struct CommandRequest {
+    /// Random value, unique per command of an admin.
+    pub nonce: String,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Command {
+    Drain {
+        #[serde(default)]
+        reason: Option<String>,
+        /// Publish a heartbeat with the new status right away.
+        #[serde(default)]
+        notify: bool,
+    },
+    Undrain {
+        #[serde(default)]
+        notify: bool,
+    },
+    /// Polls dstack now instead of at the next interval.
+    Refresh,
+    /// Starts a benchmark in the background.
+    Benchmark,
+    /// Returns the statement of the rotation that created the current key.
+    RotateReport,
+}
+
+/// Encrypted content of an acknowledgment.
+#[derive(Debug, Serialize)]
+pub struct CommandAck {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CommandAck {
+    fn from_result(result: Result<Option<serde_json::Value>, String>) -> Self {
+        match result {
+            Ok(result) => Self {
+                ok: true,
+                result,
+                error: None,
+            },
+            Err(error) => Self {
+                ok: false,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Listens for command events from the `admins` addressed to this worker,
+/// executes them and acknowledges each one.
+pub async fn run_command_channel(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    admins: Vec<PublicKey>,
+) {
+    let public_key = match signer.get_public_key().await {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            error!("Failed to get the worker public key: {}", e);
+            return;
+        }
+    };
+    let filter = Filter::new()
+        .kind(Kind::Custom(COMMAND_KIND))
+        .authors(admins.clone())
+        .pubkey(public_key)
+        .since(Timestamp::now());
+
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![filter], None).await {
+        error!("Failed to subscribe to admin commands: {}", e);
+        return;
+    }
+    let replay = ReplayGuard::open(&state.data_dir);
+    info!("Accepting admin commands from {} keys", admins.len());
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Command channel lagged, skipped {} notifications", skipped);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if event.kind != Kind::Custom(COMMAND_KIND)
+            || !admins.contains(&event.pubkey)
+            || !event.tags.public_keys().any(|pk| *pk == public_key)
+            || event.verify().is_err()
+        {
+            continue;
+        }
+        let request = match signer.nip44_decrypt(&event.pubkey, &event.content).await {
+            Ok(content) => serde_json::from_str::<CommandRequest>(&content)
+                .map_err(|e| format!("Invalid command: {}", e)),
+            Err(e) => Err(format!("Failed to decrypt command: {}", e)),
+        };
+        let ack = match request {
+            Ok(request) => {
+                // Nonces are per admin, so admins can't burn each other's
+                let nonce = format!("{}:{}", event.pubkey, request.nonce);
+                if let Err(e) = replay.check(&nonce, event.created_at.as_u64()) {
+                    warn!("Ignoring command {} from {}: {}", event.id, event.pubkey, e);
+                    continue;
+                }
+                info!(
+                    "Executing {:?} from admin {}",
+                    request.command, event.pubkey
+                );
+                execute(&state, request.command).await
+            }
+            Err(e) => {
+                warn!(
+                    "Rejecting command {} from {}: {}",
+                    event.id, event.pubkey, e
+                );
+                CommandAck::from_result(Err(e))
+            }
+        };
+        acknowledge(&state, &signer, &client, &event, &ack).await;
+    }
+}
+
+async fn execute(state: &Arc<AppState>, command: Command) -> CommandAck {
+    let result = match command {
+        Command::Drain { reason, notify } => drain(state, reason, notify)
+            .map(|maintenance| serde_json::to_value(maintenance).ok())
+            .map_err(|e| e.message),
+        Command::Undrain { notify } => undrain(state, notify).map(|()| None).map_err(|e| e.message),
+        Command::Refresh => {
+            let info = check_and_update(state).await;
+            let info = ApiVersion::Legacy.present(info, state.legacy_metadata);
+            Ok(serde_json::to_value(info).ok())
+        }
+        Command::Benchmark => start_benchmark(state.clone())
+            .map(|()| None)
+            .map_err(|e| e.message),
+        Command::RotateReport => {
+            let path = rotation_statement_file(&state.data_dir, &state.nostr_pubkey);
+            fs::read_to_string(&path)
+                .map_err(|_| "No key rotation is recorded for the current key".to_string())
+                .and_then(|statement| {
+                    serde_json::from_str(&statement)
+                        .map(Some)
+                        .map_err(|e| format!("Invalid {}: {}", path.display(), e))
+                })
+        }
+    };
+    if let Err(e) = &result {
+        warn!("Admin command failed: {}", e);
+    }
+    CommandAck::from_result(result)
+}
+
+/// Sends `ack` to the command's author, encrypted, tagging the command.
+async fn acknowledge(
+    state: &AppState,
+    signer: &Arc<dyn NostrSigner>,
+    client: &Client,
+    command: &Event,
+    ack: &CommandAck,
+) {
+    let content = match serde_json::to_string(ack) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to serialize command acknowledgment: {}", e);
+            return;
+        }
+    };
+    let content = match signer.nip44_encrypt(&command.pubkey, &content).await {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to encrypt command acknowledgment: {}", e);
+            return;
+        }
+    };
+    let builder = EventBuilder::new(Kind::Custom(COMMAND_ACK_KIND), content)
+        .tags([Tag::event(command.id), Tag::public_key(command.pubkey)]);
+    match builder.sign(signer).await {
+        Ok(event) => match client.send_event(event).await {
+            Ok(output) => {
+                state.relay_stats.record(&output.success, &output.failed);
+                info!("Acknowledged command {}", command.id)
+            }
+            Err(e) => error!("Failed to publish command acknowledgment: {}", e),
+        },
+        Err(e) => error!("Failed to sign command acknowledgment: {}", e),
+    }
+}
@@ -0,0 +1,1706 @@
+use alloy::primitives::{Address, U256};
+use alloy::signers::local::PrivateKeySigner;
+use nostr_sdk::{Keys, NostrSigner, PublicKey, SecretKey};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::auth::{derive_worker_token, AdminAuth};
+use crate::bandwidth::BandwidthConfig;
+use crate::benchmark::BenchmarkConfig;
+use crate::breaker::BreakerConfig;
+use crate::bunker::{parse_bunker_uri, BunkerSigner};
+use crate::chain::{Chain, ChainConfig, DEFAULT_CHAIN};
+use crate::connection::{self, load_dstack_url, DStackConnection};
+use crate::dephy::DEFAULT_TOPIC;
+use crate::dstack::{parse_vsock_addr, DStackClient, Timeouts};
+use crate::ens::{self, is_ens_name};
+use crate::error::BackendError;
+use crate::gpu_telemetry::GpuTelemetryConfig;
+use crate::health::{parse_pubkey_roles, PubkeyRole, WORKER_ROLE};
+use crate::keys::{KeyDerivation, KeyPermissions, KeyStore, Mnemonic};
+use crate::kms::{self, KmsKeyMode, DEFAULT_KMS_KEY_PATH};
+use crate::limits::ConcurrencyLimits;
+use crate::listen::ListenAddr;
+use crate::node_type::NodeTypeRule;
+use crate::onchain::OnchainRegistration;
+use crate::proxy::{is_valid_method, DEFAULT_PROXY_METHODS};
+use crate::public_ip::PublicIpConfig;
+use crate::registry_events::RegistryWatch;
+use crate::retry::RetryPolicy;
+use crate::rewards::RewardRates;
+use crate::rules::HealthRules;
+use crate::stake::StakeCheck;
+use crate::tls::TlsConfig;
+use crate::whitelist::{WhitelistCheck, DEFAULT_WHITELIST_LIST};
+
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+const MIN_ADMIN_TOKEN_LEN: usize = 16;
+
+/// Backend configuration, read from `config.toml` (or `CONFIG_FILE`) with
+/// environment variables taking precedence over file values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_addr: ListenAddr,
+    pub grpc_listen_addr: Option<SocketAddr>,
+    pub tls: TlsConfig,
+    pub dstack_url: String,
+    pub guest_agent_url: String,
+    pub data_dir: PathBuf,
+    pub key_passphrase: Option<String>,
+    pub key_permissions: KeyPermissions,
+    pub mnemonic: Option<String>,
+    pub mnemonic_passphrase: Option<String>,
+    pub mnemonic_account: u32,
+    pub key_kms: KmsKeyMode,
+    pub kms_key_path: String,
+    pub bunker_uri: Option<String>,
+    pub bunker_timeout_secs: u64,
+    /// Resolved from `owner_ens` by [`Config::resolve_owner`] when that is set.
+    pub owner_address: Option<Address>,
+    pub owner_ens: Option<String>,
+    pub ens_rpc_url: Option<String>,
+    pub owner_signature: Option<String>,
+    pub registrar_url: Option<String>,
+    pub chains: Vec<ChainConfig>,
+    /// Name of the `chains` entry this node uses.
+    pub chain: Option<String>,
+    pub chain_rpc_url: Option<String>,
+    pub registry_contract: Option<Address>,
+    pub registration_signer_key: Option<String>,
+    pub registry_poll_interval_secs: u64,
+    pub registry_start_block: Option<u64>,
+    pub staking_contract: Option<Address>,
+    pub min_stake: U256,
+    pub stake_check_interval_secs: u64,
+    pub relays: Vec<String>,
+    pub admin_npub: Option<String>,
+    pub command_npubs: Vec<String>,
+    pub job_npubs: Vec<String>,
+    pub coordinator_npub: Option<String>,
+    pub dephy_topic: String,
+    pub whitelist_npub: Option<String>,
+    pub whitelist_list: String,
+    pub whitelist_url: Option<String>,
+    pub whitelist_check_interval_secs: u64,
+    pub whitelist_cache_ttl_secs: u64,
+    pub reward_rates_url: Option<String>,
+    pub reward_rates: Option<RewardRates>,
+    pub image_pull_socket: Option<PathBuf>,
+    pub extra_pubkeys: Vec<PubkeyRole>,
+    pub webhooks: Vec<String>,
+    pub webhook_secret: Option<String>,
+    pub heartbeat_interval_secs: u64,
+    pub poll_interval_secs: u64,
+    pub dstack_info_interval_secs: u64,
+    pub sse_keepalive_secs: u64,
+    pub node_types: Vec<NodeTypeRule>,
+    pub legacy_metadata: bool,
+    pub health_rules: HealthRules,
+    pub dstack_connect_timeout_ms: u64,
+    pub dstack_request_timeout_ms: u64,
+    pub dstack_retry: RetryPolicy,
+    pub dstack_breaker: BreakerConfig,
+    pub dstack_proxy_methods: Vec<String>,
+    pub admin_token: Option<String>,
+    pub admin_tokens: Vec<String>,
+    pub admin_token_secret: Option<String>,
+    pub dstack_proxy_auth: bool,
+    pub health_history_max_entries: usize,
+    pub concurrency: ConcurrencyLimits,
+    pub public_ip: PublicIpConfig,
+    pub gpu_telemetry: GpuTelemetryConfig,
+    pub bandwidth: BandwidthConfig,
+    pub benchmark: BenchmarkConfig,
+    pub advertise_address: Option<IpAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], 8080)).into(),
+            grpc_listen_addr: None,
+            tls: TlsConfig::default(),
+            dstack_url: "http://localhost:19060".to_string(),
+            guest_agent_url: "unix:///var/run/dstack.sock".to_string(),
+            data_dir: PathBuf::from("./data"),
+            key_passphrase: None,
+            key_permissions: KeyPermissions::default(),
+            mnemonic: None,
+            mnemonic_passphrase: None,
+            mnemonic_account: 0,
+            key_kms: KmsKeyMode::default(),
+            kms_key_path: DEFAULT_KMS_KEY_PATH.to_string(),
+            bunker_uri: None,
+            bunker_timeout_secs: 60,
+            owner_address: None,
+            owner_ens: None,
+            ens_rpc_url: None,
+            owner_signature: None,
+            registrar_url: None,
+            chains: Vec::new(),
+            chain: None,
+            chain_rpc_url: None,
+            registry_contract: None,
+            registration_signer_key: None,
+            registry_poll_interval_secs: 60,
+            registry_start_block: None,
+            staking_contract: None,
+            min_stake: U256::ZERO,
+            stake_check_interval_secs: 600,
+            relays: Vec::new(),
+            admin_npub: None,
+            command_npubs: Vec::new(),
+            job_npubs: Vec::new(),
+            coordinator_npub: None,
+            dephy_topic: DEFAULT_TOPIC.to_string(),
+            whitelist_npub: None,
+            whitelist_list: DEFAULT_WHITELIST_LIST.to_string(),
+            whitelist_url: None,
+            whitelist_check_interval_secs: 600,
+            whitelist_cache_ttl_secs: 3600,
+            reward_rates_url: None,
+            image_pull_socket: None,
+            reward_rates: None,
+            extra_pubkeys: Vec::new(),
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            heartbeat_interval_secs: 60,
+            poll_interval_secs: 10,
+            dstack_info_interval_secs: 3600,
+            sse_keepalive_secs: 30,
+            node_types: Vec::new(),
+            legacy_metadata: false,
+            health_rules: HealthRules::default(),
+            dstack_connect_timeout_ms: Timeouts::default().connect.as_millis() as u64,
+            dstack_request_timeout_ms: Timeouts::default().request.as_millis() as u64,
+            dstack_retry: RetryPolicy::default(),
+            dstack_breaker: BreakerConfig::default(),
+            dstack_proxy_methods: DEFAULT_PROXY_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            admin_token: None,
+            admin_tokens: Vec::new(),
+            admin_token_secret: None,
+            dstack_proxy_auth: false,
+            health_history_max_entries: 10_000,
+            concurrency: ConcurrencyLimits::default(),
+            public_ip: PublicIpConfig::default(),
+            gpu_telemetry: GpuTelemetryConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            benchmark: BenchmarkConfig::default(),
+            advertise_address: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the config file (if present), applies environment overrides and
+    /// validates the result. All problems found are returned together.
+    pub fn load(path: Option<&Path>) -> Result<Self, Vec<ConfigError>> {
+        let (config, mut errors) = Self::load_unvalidated(path)?;
+        for e in config.validate() {
+            // Don't report a missing owner address on top of an unparsable one
+            if e.field == "owner_address" && errors.iter().any(|e| e.field == "OWNER_ADDRESS") {
+                continue;
+            }
+            errors.push(e);
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Like [`Config::load`] but skips validation, for commands that only
+    /// need a subset of the settings (e.g. the data directory).
+    pub fn load_partial(path: Option<&Path>) -> Result<Self, Vec<ConfigError>> {
+        match Self::load_unvalidated(path)? {
+            (config, errors) if errors.is_empty() => Ok(config),
+            (_, errors) => Err(errors),
+        }
+    }
+
+    fn load_unvalidated(path: Option<&Path>) -> Result<(Self, Vec<ConfigError>), Vec<ConfigError>> {
+        let mut config = match path {
+            Some(path) if !path.exists() => {
+                return Err(vec![ConfigError::new(
+                    "CONFIG_FILE",
+                    format!("config file {:?} does not exist", path),
+                )]);
+            }
+            Some(path) => Self::from_file(path).map_err(|e| vec![e])?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE)).map_err(|e| vec![e])?
+            }
+            None => Self::default(),
+        };
+
+        let errors = config.apply_env();
+        if let Some(url) = load_dstack_url(&config.data_dir) {
+            config.dstack_url = url;
+        }
+        Ok((config, errors))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::new("CONFIG_FILE", format!("{:?}: {}", path, e)))?;
+        Self::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(|e| {
+            // Report the key on the offending line rather than the raw span
+            let field = e
+                .span()
+                .map(|span| content[..span.start].matches('\n').count())
+                .and_then(|line| content.lines().nth(line))
+                .and_then(|line| line.split_once('='))
+                .map(|(key, _)| key.trim().to_string())
+                .unwrap_or_else(|| "config".to_string());
+            ConfigError::new(field, e.message().to_string())
+        })
+    }
+
+    /// Overrides file values with environment variables, returning an error
+    /// for each variable that is set but cannot be parsed.
+    fn apply_env(&mut self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        if let Some(value) = env_var("LISTEN_ADDR") {
+            match parse_field("LISTEN_ADDR", &value) {
+                Ok(addr) => self.listen_addr = addr,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("GRPC_LISTEN_ADDR") {
+            match parse_field("GRPC_LISTEN_ADDR", &value) {
+                Ok(addr) => self.grpc_listen_addr = Some(addr),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("TLS_CERT_PATH") {
+            self.tls.cert_path = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("TLS_KEY_PATH") {
+            self.tls.key_path = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("TLS_CLIENT_CA_PATH") {
+            self.tls.client_ca_path = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("TLS_RELOAD_SECS") {
+            match parse_field("TLS_RELOAD_SECS", &value) {
+                Ok(secs) => self.tls.reload_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_URL").or_else(|| env_var("DSTACK_BACKEND_DSTACK_URL"))
+        {
+            self.dstack_url = value;
+        }
+        if let Some(value) = env_var("GUEST_AGENT_URL") {
+            self.guest_agent_url = value;
+        }
+        if let Some(value) = env_var("DATA_DIR") {
+            self.data_dir = PathBuf::from(value);
+        }
+        if let Some(value) = env_var("KEY_PASSPHRASE") {
+            self.key_passphrase = Some(value);
+        } else if let Some(path) = env_var("KEY_PASSPHRASE_FILE") {
+            match std::fs::read_to_string(&path) {
+                Ok(value) => self.key_passphrase = Some(value.trim_end().to_string()),
+                Err(e) => errors.push(ConfigError::new(
+                    "KEY_PASSPHRASE_FILE",
+                    format!("{:?}: {}", path, e),
+                )),
+            }
+        }
+        if let Some(value) = env_var("KEY_PERMISSIONS") {
+            match parse_field("KEY_PERMISSIONS", &value) {
+                Ok(permissions) => self.key_permissions = permissions,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("NOSTR_MNEMONIC") {
+            self.mnemonic = Some(value);
+        }
+        if let Some(value) = env_var("NOSTR_MNEMONIC_PASSPHRASE") {
+            self.mnemonic_passphrase = Some(value);
+        }
+        if let Some(value) = env_var("NOSTR_MNEMONIC_ACCOUNT") {
+            match parse_field("NOSTR_MNEMONIC_ACCOUNT", &value) {
+                Ok(account) => self.mnemonic_account = account,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("KEY_KMS") {
+            match parse_field("KEY_KMS", &value) {
+                Ok(mode) => self.key_kms = mode,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("KMS_KEY_PATH") {
+            self.kms_key_path = value;
+        }
+        if let Some(value) = env_var("NOSTR_BUNKER_URI") {
+            self.bunker_uri = Some(value);
+        }
+        if let Some(value) = env_var("NOSTR_BUNKER_TIMEOUT_SECS") {
+            match parse_field("NOSTR_BUNKER_TIMEOUT_SECS", &value) {
+                Ok(secs) => self.bunker_timeout_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("OWNER_ADDRESS") {
+            if is_ens_name(&value) {
+                self.owner_ens = Some(value);
+                self.owner_address = None;
+            } else {
+                match parse_field("OWNER_ADDRESS", &value) {
+                    Ok(address) => {
+                        self.owner_address = Some(address);
+                        self.owner_ens = None;
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        if let Some(value) = env_var("ENS_RPC_URL") {
+            self.ens_rpc_url = Some(value);
+        }
+        if let Some(value) = env_var("OWNER_SIGNATURE") {
+            self.owner_signature = Some(value);
+        }
+        if let Some(value) = env_var("REGISTRAR_URL") {
+            self.registrar_url = Some(value);
+        }
+        if let Some(value) = env_var("CHAIN") {
+            self.chain = Some(value);
+        }
+        if let Some(value) = env_var("CHAIN_RPC_URL") {
+            self.chain_rpc_url = Some(value);
+        }
+        if let Some(value) = env_var("REGISTRY_CONTRACT") {
+            match parse_field("REGISTRY_CONTRACT", &value) {
+                Ok(address) => self.registry_contract = Some(address),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("REGISTRATION_SIGNER_KEY") {
+            self.registration_signer_key = Some(value);
+        }
+        if let Some(value) = env_var("REGISTRY_POLL_INTERVAL_SECS") {
+            match parse_field("REGISTRY_POLL_INTERVAL_SECS", &value) {
+                Ok(secs) => self.registry_poll_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("REGISTRY_START_BLOCK") {
+            match parse_field("REGISTRY_START_BLOCK", &value) {
+                Ok(block) => self.registry_start_block = Some(block),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("STAKING_CONTRACT") {
+            match parse_field("STAKING_CONTRACT", &value) {
+                Ok(address) => self.staking_contract = Some(address),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("MIN_STAKE") {
+            match parse_field("MIN_STAKE", &value) {
+                Ok(amount) => self.min_stake = amount,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("STAKE_CHECK_INTERVAL_SECS") {
+            match parse_field("STAKE_CHECK_INTERVAL_SECS", &value) {
+                Ok(secs) => self.stake_check_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("NOSTR_RELAYS") {
+            self.relays = crate::relay::parse_relay_list(&value);
+        }
+        if let Some(value) = env_var("LEGACY_METADATA") {
+            match parse_field("LEGACY_METADATA", &value) {
+                Ok(legacy) => self.legacy_metadata = legacy,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("POLL_INTERVAL_SECS") {
+            match parse_field("POLL_INTERVAL_SECS", &value) {
+                Ok(secs) => self.poll_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_INFO_INTERVAL_SECS") {
+            match parse_field("DSTACK_INFO_INTERVAL_SECS", &value) {
+                Ok(secs) => self.dstack_info_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("ADMIN_NPUB") {
+            self.admin_npub = Some(value);
+        }
+        if let Some(value) = env_var("COMMAND_NPUBS") {
+            self.command_npubs = value
+                .split(',')
+                .map(|npub| npub.trim())
+                .filter(|npub| !npub.is_empty())
+                .map(|npub| npub.to_string())
+                .collect();
+        }
+        if let Some(value) = env_var("JOB_NPUBS") {
+            self.job_npubs = value
+                .split(',')
+                .map(|npub| npub.trim())
+                .filter(|npub| !npub.is_empty())
+                .map(|npub| npub.to_string())
+                .collect();
+        }
+        if let Some(value) = env_var("COORDINATOR_NPUB") {
+            self.coordinator_npub = Some(value);
+        }
+        if let Some(value) = env_var("DEPHY_TOPIC") {
+            self.dephy_topic = value;
+        }
+        if let Some(value) = env_var("WHITELIST_NPUB") {
+            self.whitelist_npub = Some(value);
+        }
+        if let Some(value) = env_var("WHITELIST_LIST") {
+            self.whitelist_list = value;
+        }
+        if let Some(value) = env_var("WHITELIST_URL") {
+            self.whitelist_url = Some(value);
+        }
+        if let Some(value) = env_var("REWARD_RATES_URL") {
+            self.reward_rates_url = Some(value);
+        }
+        if let Some(value) = env_var("IMAGE_PULL_SOCKET") {
+            self.image_pull_socket = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("WHITELIST_CHECK_INTERVAL_SECS") {
+            match parse_field("WHITELIST_CHECK_INTERVAL_SECS", &value) {
+                Ok(secs) => self.whitelist_check_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("WHITELIST_CACHE_TTL_SECS") {
+            match parse_field("WHITELIST_CACHE_TTL_SECS", &value) {
+                Ok(secs) => self.whitelist_cache_ttl_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("NOSTR_EXTRA_PUBKEYS") {
+            match parse_pubkey_roles(&value) {
+                Ok(pubkeys) => self.extra_pubkeys = pubkeys,
+                Err(e) => errors.push(ConfigError::new("NOSTR_EXTRA_PUBKEYS", e)),
+            }
+        }
+        if let Some(value) = env_var("WEBHOOKS") {
+            self.webhooks = crate::webhook::parse_webhook_list(&value);
+        }
+        if let Some(value) = env_var("WEBHOOK_SECRET") {
+            self.webhook_secret = Some(value);
+        }
+        if let Some(value) = env_var("DSTACK_PROXY_METHODS") {
+            self.dstack_proxy_methods = value
+                .split(',')
+                .map(|method| method.trim())
+                .filter(|method| !method.is_empty())
+                .map(|method| method.to_string())
+                .collect();
+        }
+        if let Some(value) = env_var("ADMIN_TOKEN") {
+            self.admin_token = Some(value);
+        }
+        if let Some(value) = env_var("ADMIN_TOKENS") {
+            self.admin_tokens = value
+                .split(',')
+                .map(|token| token.trim())
+                .filter(|token| !token.is_empty())
+                .map(|token| token.to_string())
+                .collect();
+        }
+        if let Some(value) = env_var("ADMIN_TOKEN_SECRET") {
+            self.admin_token_secret = Some(value);
+        }
+        if let Some(value) = env_var("DSTACK_PROXY_AUTH") {
+            match parse_field("DSTACK_PROXY_AUTH", &value) {
+                Ok(auth) => self.dstack_proxy_auth = auth,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_CONNECT_TIMEOUT_MS") {
+            match parse_field("DSTACK_CONNECT_TIMEOUT_MS", &value) {
+                Ok(ms) => self.dstack_connect_timeout_ms = ms,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_REQUEST_TIMEOUT_MS") {
+            match parse_field("DSTACK_REQUEST_TIMEOUT_MS", &value) {
+                Ok(ms) => self.dstack_request_timeout_ms = ms,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_RETRY_MAX_ATTEMPTS") {
+            match parse_field("DSTACK_RETRY_MAX_ATTEMPTS", &value) {
+                Ok(attempts) => self.dstack_retry.max_attempts = attempts,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_RETRY_MAX_ELAPSED_MS") {
+            match parse_field("DSTACK_RETRY_MAX_ELAPSED_MS", &value) {
+                Ok(ms) => self.dstack_retry.max_elapsed_ms = ms,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_BREAKER_THRESHOLD") {
+            match parse_field("DSTACK_BREAKER_THRESHOLD", &value) {
+                Ok(threshold) => self.dstack_breaker.failure_threshold = threshold,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DSTACK_BREAKER_RESET_SECS") {
+            match parse_field("DSTACK_BREAKER_RESET_SECS", &value) {
+                Ok(secs) => self.dstack_breaker.reset_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("HEALTH_MIN_FREE_GPUS") {
+            match parse_field("HEALTH_MIN_FREE_GPUS", &value) {
+                Ok(min) => self.health_rules.min_free_gpus = Some(min),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("HEALTH_MAX_LATENCY_MS") {
+            match parse_field("HEALTH_MAX_LATENCY_MS", &value) {
+                Ok(max) => self.health_rules.max_latency_ms = Some(max),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("HEALTH_REQUIRE_ATTACH_ALL") {
+            match parse_field("HEALTH_REQUIRE_ATTACH_ALL", &value) {
+                Ok(require) => self.health_rules.require_attach_all = require,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("MAX_CONCURRENT_REQUESTS") {
+            match parse_field("MAX_CONCURRENT_REQUESTS", &value) {
+                Ok(max) => self.concurrency.max_requests = max,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("MAX_CONCURRENT_DSTACK_REQUESTS") {
+            match parse_field("MAX_CONCURRENT_DSTACK_REQUESTS", &value) {
+                Ok(max) => self.concurrency.max_dstack_requests = max,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("ADVERTISE_ADDRESS") {
+            match parse_field("ADVERTISE_ADDRESS", &value) {
+                Ok(ip) => self.advertise_address = Some(ip),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("PUBLIC_IP_DISCOVERY") {
+            match parse_field("PUBLIC_IP_DISCOVERY", &value) {
+                Ok(enabled) => self.public_ip.enabled = enabled,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("PUBLIC_IP_RESOLVERS") {
+            self.public_ip.resolvers = value
+                .split(',')
+                .map(|resolver| resolver.trim())
+                .filter(|resolver| !resolver.is_empty())
+                .map(|resolver| resolver.to_string())
+                .collect();
+        }
+        if let Some(value) = env_var("PUBLIC_IP_TIMEOUT_MS") {
+            match parse_field("PUBLIC_IP_TIMEOUT_MS", &value) {
+                Ok(ms) => self.public_ip.timeout_ms = ms,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("PUBLIC_IP_REFRESH_SECS") {
+            match parse_field("PUBLIC_IP_REFRESH_SECS", &value) {
+                Ok(secs) => self.public_ip.refresh_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("GPU_TELEMETRY") {
+            match parse_field("GPU_TELEMETRY", &value) {
+                Ok(enabled) => self.gpu_telemetry.enabled = enabled,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("NVIDIA_SMI_PATH") {
+            self.gpu_telemetry.nvidia_smi_path = value;
+        }
+        if let Some(value) = env_var("GPU_TELEMETRY_INTERVAL_SECS") {
+            match parse_field("GPU_TELEMETRY_INTERVAL_SECS", &value) {
+                Ok(secs) => self.gpu_telemetry.interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("GPU_XID_MONITOR") {
+            match parse_field("GPU_XID_MONITOR", &value) {
+                Ok(enabled) => self.gpu_telemetry.xid_monitor = enabled,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("DMESG_PATH") {
+            self.gpu_telemetry.dmesg_path = value;
+        }
+        if let Some(value) = env_var("BANDWIDTH_TEST") {
+            match parse_field("BANDWIDTH_TEST", &value) {
+                Ok(enabled) => self.bandwidth.enabled = enabled,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("BANDWIDTH_DOWNLOAD_URL") {
+            self.bandwidth.download_url = value;
+        }
+        if let Some(value) = env_var("BANDWIDTH_UPLOAD_URL") {
+            self.bandwidth.upload_url = value;
+        }
+        if let Some(value) = env_var("BANDWIDTH_UPLOAD_BYTES") {
+            match parse_field("BANDWIDTH_UPLOAD_BYTES", &value) {
+                Ok(bytes) => self.bandwidth.upload_bytes = bytes,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("BANDWIDTH_INTERVAL_SECS") {
+            match parse_field("BANDWIDTH_INTERVAL_SECS", &value) {
+                Ok(secs) => self.bandwidth.interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("BANDWIDTH_TIMEOUT_SECS") {
+            match parse_field("BANDWIDTH_TIMEOUT_SECS", &value) {
+                Ok(secs) => self.bandwidth.timeout_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("BENCHMARK_IMAGE") {
+            self.benchmark.image = Some(value);
+        }
+        if let Some(value) = env_var("BENCHMARK_OS_IMAGE") {
+            self.benchmark.os_image = Some(value);
+        }
+        if let Some(value) = env_var("BENCHMARK_RESULT_PORT") {
+            match parse_field("BENCHMARK_RESULT_PORT", &value) {
+                Ok(port) => self.benchmark.result_port = port,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("BENCHMARK_TIMEOUT_SECS") {
+            match parse_field("BENCHMARK_TIMEOUT_SECS", &value) {
+                Ok(secs) => self.benchmark.timeout_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(value) = env_var("HEARTBEAT_INTERVAL_SECS") {
+            match parse_field("HEARTBEAT_INTERVAL_SECS", &value) {
+                Ok(secs) => self.heartbeat_interval_secs = secs,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        errors
+    }
+
+    fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        match (&self.owner_address, &self.owner_ens) {
+            (None, None) => errors.push(ConfigError::new(
+                "owner_address",
+                "is required for worker registration (set OWNER_ADDRESS)",
+            )),
+            (Some(_), Some(_)) => errors.push(ConfigError::new(
+                "owner_ens",
+                "set either owner_address or owner_ens, not both",
+            )),
+            (None, Some(name)) => {
+                if !is_ens_name(name) {
+                    errors.push(ConfigError::new(
+                        "owner_ens",
+                        format!("{:?} is not an ENS name", name),
+                    ));
+                }
+                if self.ens_rpc_url().is_none() {
+                    errors.push(ConfigError::new(
+                        "owner_ens",
+                        "resolving an ENS name needs ens_rpc_url or chain_rpc_url",
+                    ));
+                }
+            }
+            (Some(_), None) => {}
+        }
+        if self.grpc_listen_addr.is_some() && !cfg!(feature = "grpc") {
+            errors.push(ConfigError::new(
+                "grpc_listen_addr",
+                "needs a build with the grpc feature",
+            ));
+        }
+        if self.tls.client_ca_path.is_some() && !self.tls.enabled() {
+            errors.push(ConfigError::new(
+                "tls.client_ca_path",
+                "client certificates need TLS, set cert_path and key_path",
+            ));
+        }
+        if self.tls.enabled() {
+            if matches!(self.listen_addr, ListenAddr::Unix(_)) {
+                errors.push(ConfigError::new(
+                    "tls",
+                    "is not supported when listen_addr is a unix:// socket",
+                ));
+            }
+            if self.tls.cert_path.is_none() || self.tls.key_path.is_none() {
+                errors.push(ConfigError::new("tls", "needs both cert_path and key_path"));
+            }
+            if self.tls.reload_secs == 0 {
+                errors.push(ConfigError::new(
+                    "tls.reload_secs",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        if self.key_passphrase.as_ref().is_some_and(|p| p.is_empty()) {
+            errors.push(ConfigError::new("key_passphrase", "must not be empty"));
+        }
+        if let Some(mnemonic) = self.key_store().mnemonic {
+            if let Err(e) = mnemonic.derive() {
+                errors.push(ConfigError::new("mnemonic", e.to_string()));
+            }
+        } else if self.mnemonic_passphrase.is_some() {
+            errors.push(ConfigError::new(
+                "mnemonic_passphrase",
+                "is only used together with mnemonic",
+            ));
+        }
+        if let Some(uri) = &self.bunker_uri {
+            if let Err(e) = parse_bunker_uri(uri) {
+                errors.push(ConfigError::new("bunker_uri", e));
+            }
+            if self.mnemonic.is_some() {
+                errors.push(ConfigError::new(
+                    "bunker_uri",
+                    "can't be combined with mnemonic",
+                ));
+            }
+        }
+        if self.key_kms != KmsKeyMode::Off {
+            for (field, set) in [
+                ("key_passphrase", self.key_passphrase.is_some()),
+                ("mnemonic", self.mnemonic.is_some()),
+                ("bunker_uri", self.bunker_uri.is_some()),
+            ] {
+                if set {
+                    errors.push(ConfigError::new(field, "can't be combined with key_kms"));
+                }
+            }
+            if self.kms_key_path.is_empty() {
+                errors.push(ConfigError::new("kms_key_path", "must not be empty"));
+            }
+        }
+        if self.bunker_timeout_secs == 0 {
+            errors.push(ConfigError::new(
+                "bunker_timeout_secs",
+                "must be greater than zero",
+            ));
+        }
+        for (field, url) in [
+            ("dstack_url", &self.dstack_url),
+            ("guest_agent_url", &self.guest_agent_url),
+        ] {
+            if let Err(e) = validate_dstack_url(url) {
+                errors.push(ConfigError::new(field, e));
+            }
+        }
+        if let Some(url) = &self.registrar_url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                errors.push(ConfigError::new(
+                    "registrar_url",
+                    format!("{:?} is not an http:// or https:// URL", url),
+                ));
+            }
+        }
+        let mut chain_names = HashSet::new();
+        for (i, chain) in self.chains.iter().enumerate() {
+            if chain.name.is_empty() || chain.name == DEFAULT_CHAIN {
+                errors.push(ConfigError::new(
+                    format!("chains[{}].name", i),
+                    format!("must be set and not {:?}", DEFAULT_CHAIN),
+                ));
+            } else if !chain_names.insert(chain.name.as_str()) {
+                errors.push(ConfigError::new(
+                    format!("chains[{}].name", i),
+                    format!("{:?} is used by another chain", chain.name),
+                ));
+            }
+        }
+        if let Some(name) = &self.chain {
+            if self.selected_chain().is_none() {
+                let configured: Vec<&str> = self
+                    .chains
+                    .iter()
+                    .map(|chain| chain.name.as_str())
+                    .collect();
+                errors.push(ConfigError::new(
+                    "chain",
+                    format!(
+                        "{:?} is not a configured chain (configured: {})",
+                        name,
+                        configured.join(", ")
+                    ),
+                ));
+            }
+        }
+        match (
+            self.chain(),
+            self.registry_address(),
+            &self.registration_signer_key,
+        ) {
+            // The RPC URL alone is used by the stake check, and with the
+            // contract alone registry events are followed
+            (_, None, None) | (Some(_), Some(_), None) => {}
+            (Some(_), Some(_), Some(key)) => {
+                if key.parse::<PrivateKeySigner>().is_err() {
+                    errors.push(ConfigError::new(
+                        "registration_signer_key",
+                        "is not a valid hex private key",
+                    ));
+                }
+            }
+            _ => errors.push(ConfigError::new(
+                "chain_rpc_url",
+                "on-chain registration needs chain_rpc_url (or chain), registry_contract and registration_signer_key together",
+            )),
+        }
+        if self.registry_address().is_some() && self.registry_poll_interval_secs == 0 {
+            errors.push(ConfigError::new(
+                "registry_poll_interval_secs",
+                "must be greater than zero",
+            ));
+        }
+        if self.staking_address().is_some() {
+            if self.chain().is_none() {
+                errors.push(ConfigError::new(
+                    "staking_contract",
+                    "stake verification needs chain_rpc_url or chain",
+                ));
+            }
+            if self.stake_check_interval_secs == 0 {
+                errors.push(ConfigError::new(
+                    "stake_check_interval_secs",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        for relay in &self.relays {
+            if !(relay.starts_with("ws://") || relay.starts_with("wss://")) {
+                errors.push(ConfigError::new(
+                    "relays",
+                    format!("{:?} is not a ws:// or wss:// URL", relay),
+                ));
+            }
+        }
+        if let Some(admin) = &self.admin_npub {
+            if PublicKey::parse(admin).is_err() {
+                errors.push(ConfigError::new(
+                    "admin_npub",
+                    format!("{:?} is not a valid npub or hex public key", admin),
+                ));
+            } else if self.relays.is_empty() {
+                errors.push(ConfigError::new(
+                    "admin_npub",
+                    "status alerts need at least one relay in relays",
+                ));
+            }
+        }
+        for (i, admin) in self.command_npubs.iter().enumerate() {
+            if PublicKey::parse(admin).is_err() {
+                errors.push(ConfigError::new(
+                    format!("command_npubs[{}]", i),
+                    format!("{:?} is not a valid npub or hex public key", admin),
+                ));
+            }
+        }
+        if !self.command_npubs.is_empty() && self.relays.is_empty() {
+            errors.push(ConfigError::new(
+                "command_npubs",
+                "remote commands need at least one relay in relays",
+            ));
+        }
+        for (i, coordinator) in self.job_npubs.iter().enumerate() {
+            if PublicKey::parse(coordinator).is_err() {
+                errors.push(ConfigError::new(
+                    format!("job_npubs[{}]", i),
+                    format!("{:?} is not a valid npub or hex public key", coordinator),
+                ));
+            }
+        }
+        if !self.job_npubs.is_empty() && self.relays.is_empty() {
+            errors.push(ConfigError::new(
+                "job_npubs",
+                "job assignments need at least one relay in relays",
+            ));
+        }
+        if let Some(coordinator) = &self.coordinator_npub {
+            if PublicKey::parse(coordinator).is_err() {
+                errors.push(ConfigError::new(
+                    "coordinator_npub",
+                    format!("{:?} is not a valid npub or hex public key", coordinator),
+                ));
+            } else if self.relays.is_empty() {
+                errors.push(ConfigError::new(
+                    "coordinator_npub",
+                    "relay discovery needs at least one relay in relays",
+                ));
+            }
+        }
+        if self.dephy_topic.is_empty() {
+            errors.push(ConfigError::new("dephy_topic", "must not be empty"));
+        }
+        if let Some(publisher) = &self.whitelist_npub {
+            if PublicKey::parse(publisher).is_err() {
+                errors.push(ConfigError::new(
+                    "whitelist_npub",
+                    format!("{:?} is not a valid npub or hex public key", publisher),
+                ));
+            } else if self.relays.is_empty() {
+                errors.push(ConfigError::new(
+                    "whitelist_npub",
+                    "the published whitelist needs at least one relay in relays",
+                ));
+            }
+        }
+        if self.whitelist_list.is_empty() {
+            errors.push(ConfigError::new("whitelist_list", "must not be empty"));
+        }
+        if let Some(url) = &self.whitelist_url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                errors.push(ConfigError::new(
+                    "whitelist_url",
+                    format!("{:?} is not an http:// or https:// URL", url),
+                ));
+            }
+        }
+        if let Some(url) = &self.reward_rates_url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                errors.push(ConfigError::new(
+                    "reward_rates_url",
+                    format!("{:?} is not an http:// or https:// URL", url),
+                ));
+            }
+        }
+        if let Some(rewards) = &self.reward_rates {
+            for (node_type, rate) in &rewards.rates {
+                if !rate.is_finite() || *rate < 0.0 {
+                    errors.push(ConfigError::new(
+                        format!("reward_rates.rates.{}", node_type),
+                        "must be a non-negative number",
+                    ));
+                }
+            }
+        }
+        for (field, secs) in [
+            (
+                "whitelist_check_interval_secs",
+                self.whitelist_check_interval_secs,
+            ),
+            ("whitelist_cache_ttl_secs", self.whitelist_cache_ttl_secs),
+        ] {
+            if secs == 0 {
+                errors.push(ConfigError::new(field, "must be greater than zero"));
+            }
+        }
+        let mut seen = HashSet::new();
+        for (i, entry) in self.extra_pubkeys.iter().enumerate() {
+            let field = format!("extra_pubkeys[{}]", i);
+            match PublicKey::parse(&entry.pubkey) {
+                Ok(pubkey) if !seen.insert(pubkey) => {
+                    errors.push(ConfigError::new(field.as_str(), "duplicate pubkey"));
+                }
+                Ok(_) => {}
+                Err(e) => errors.push(ConfigError::new(
+                    field.as_str(),
+                    format!("invalid pubkey: {}", e),
+                )),
+            }
+            if entry.role.is_empty() {
+                errors.push(ConfigError::new(field.as_str(), "role must not be empty"));
+            } else if entry.role == WORKER_ROLE {
+                errors.push(ConfigError::new(
+                    &field,
+                    format!("role {:?} is reserved for the worker key", WORKER_ROLE),
+                ));
+            }
+        }
+        for (i, url) in self.webhooks.iter().enumerate() {
+            let valid = reqwest::Url::parse(url)
+                .map(|url| matches!(url.scheme(), "http" | "https"))
+                .unwrap_or(false);
+            if !valid {
+                errors.push(ConfigError::new(
+                    format!("webhooks[{}]", i),
+                    format!("{:?} is not a valid http(s) URL", url),
+                ));
+            }
+        }
+        let tokens = self
+            .admin_token
+            .iter()
+            .map(|token| ("admin_token".to_string(), token))
+            .chain(
+                self.admin_tokens
+                    .iter()
+                    .enumerate()
+                    .map(|(i, token)| (format!("admin_tokens[{}]", i), token)),
+            )
+            .chain(
+                self.admin_token_secret
+                    .iter()
+                    .map(|secret| ("admin_token_secret".to_string(), secret)),
+            );
+        for (field, token) in tokens {
+            if token.len() < MIN_ADMIN_TOKEN_LEN {
+                errors.push(ConfigError::new(
+                    field,
+                    format!("must be at least {} characters", MIN_ADMIN_TOKEN_LEN),
+                ));
+            }
+        }
+        for (field, ms) in [
+            ("dstack_connect_timeout_ms", self.dstack_connect_timeout_ms),
+            ("dstack_request_timeout_ms", self.dstack_request_timeout_ms),
+        ] {
+            if ms == 0 {
+                errors.push(ConfigError::new(field, "must be greater than zero"));
+            }
+        }
+        if self.dstack_retry.max_attempts == 0 {
+            errors.push(ConfigError::new(
+                "dstack_retry.max_attempts",
+                "must be at least 1",
+            ));
+        }
+        if self.dstack_retry.multiplier < 1.0 {
+            errors.push(ConfigError::new(
+                "dstack_retry.multiplier",
+                "must be at least 1.0",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.dstack_retry.jitter) {
+            errors.push(ConfigError::new(
+                "dstack_retry.jitter",
+                "must be between 0.0 and 1.0",
+            ));
+        }
+        for (i, method) in self.dstack_proxy_methods.iter().enumerate() {
+            if !is_valid_method(method) {
+                errors.push(ConfigError::new(
+                    format!("dstack_proxy_methods[{}]", i),
+                    format!("{:?} is not a valid prpc method name", method),
+                ));
+            }
+        }
+        for (i, rule) in self.node_types.iter().enumerate() {
+            if rule.model.trim().is_empty() {
+                errors.push(ConfigError::new(
+                    format!("node_types[{}].model", i),
+                    "must not be empty",
+                ));
+            }
+            if rule.product_ids.is_empty() && rule.patterns.is_empty() {
+                errors.push(ConfigError::new(
+                    format!("node_types[{}]", i),
+                    "needs at least one of product_ids or patterns",
+                ));
+            }
+        }
+        for (field, max) in [
+            ("concurrency.max_requests", self.concurrency.max_requests),
+            (
+                "concurrency.max_dstack_requests",
+                self.concurrency.max_dstack_requests,
+            ),
+        ] {
+            if max == 0 {
+                errors.push(ConfigError::new(field, "must be greater than zero"));
+            }
+        }
+        if self.public_ip.enabled {
+            if self.public_ip.resolvers.is_empty() {
+                errors.push(ConfigError::new(
+                    "public_ip.resolvers",
+                    "needs at least one resolver",
+                ));
+            }
+            for (i, resolver) in self.public_ip.resolvers.iter().enumerate() {
+                let valid = match resolver.strip_prefix("stun://") {
+                    Some(server) => server.contains(':'),
+                    None => reqwest::Url::parse(resolver)
+                        .map(|url| matches!(url.scheme(), "http" | "https"))
+                        .unwrap_or(false),
+                };
+                if !valid {
+                    errors.push(ConfigError::new(
+                        format!("public_ip.resolvers[{}]", i),
+                        format!(
+                            "{:?} is neither stun://host:port nor an http(s) URL",
+                            resolver
+                        ),
+                    ));
+                }
+            }
+            for (field, value) in [
+                ("public_ip.timeout_ms", self.public_ip.timeout_ms),
+                ("public_ip.refresh_secs", self.public_ip.refresh_secs),
+            ] {
+                if value == 0 {
+                    errors.push(ConfigError::new(field, "must be greater than zero"));
+                }
+            }
+        }
+        if self.bandwidth.enabled {
+            for (field, url) in [
+                ("bandwidth.download_url", &self.bandwidth.download_url),
+                ("bandwidth.upload_url", &self.bandwidth.upload_url),
+            ] {
+                let valid = reqwest::Url::parse(url)
+                    .map(|url| matches!(url.scheme(), "http" | "https"))
+                    .unwrap_or(false);
+                if !valid {
+                    errors.push(ConfigError::new(
+                        field,
+                        format!("{:?} is not an http(s) URL", url),
+                    ));
+                }
+            }
+            for (field, value) in [
+                ("bandwidth.interval_secs", self.bandwidth.interval_secs),
+                ("bandwidth.timeout_secs", self.bandwidth.timeout_secs),
+            ] {
+                if value == 0 {
+                    errors.push(ConfigError::new(field, "must be greater than zero"));
+                }
+            }
+        }
+        if self.benchmark.image.is_some() {
+            for (field, value) in [
+                ("benchmark.vcpu", self.benchmark.vcpu as u64),
+                ("benchmark.memory_mb", self.benchmark.memory_mb as u64),
+                ("benchmark.disk_gb", self.benchmark.disk_gb as u64),
+                ("benchmark.result_port", self.benchmark.result_port as u64),
+                ("benchmark.timeout_secs", self.benchmark.timeout_secs),
+            ] {
+                if value == 0 {
+                    errors.push(ConfigError::new(field, "must be greater than zero"));
+                }
+            }
+        }
+        if self.gpu_telemetry.enabled {
+            if self.gpu_telemetry.nvidia_smi_path.is_empty() {
+                errors.push(ConfigError::new(
+                    "gpu_telemetry.nvidia_smi_path",
+                    "must not be empty",
+                ));
+            }
+            if self.gpu_telemetry.xid_monitor && self.gpu_telemetry.dmesg_path.is_empty() {
+                errors.push(ConfigError::new(
+                    "gpu_telemetry.dmesg_path",
+                    "must not be empty",
+                ));
+            }
+            if self.gpu_telemetry.interval_secs == 0 {
+                errors.push(ConfigError::new(
+                    "gpu_telemetry.interval_secs",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        if self.health_history_max_entries == 0 {
+            errors.push(ConfigError::new(
+                "health_history_max_entries",
+                "must be greater than zero",
+            ));
+        }
+        if self.poll_interval_secs == 0 {
+            errors.push(ConfigError::new(
+                "poll_interval_secs",
+                "must be greater than zero",
+            ));
+        }
+        if self.dstack_info_interval_secs == 0 {
+            errors.push(ConfigError::new(
+                "dstack_info_interval_secs",
+                "must be greater than zero",
+            ));
+        }
+        if self.sse_keepalive_secs == 0 {
+            errors.push(ConfigError::new(
+                "sse_keepalive_secs",
+                "must be greater than zero",
+            ));
+        }
+        if self.heartbeat_interval_secs == 0 {
+            errors.push(ConfigError::new(
+                "heartbeat_interval_secs",
+                "must be greater than zero",
+            ));
+        }
+
+        errors
+    }
+
+    /// The RPC endpoint ENS names are resolved through.
+    pub fn ens_rpc_url(&self) -> Option<&str> {
+        self.ens_rpc_url
+            .as_deref()
+            .or(self.chain_rpc_url.as_deref())
+    }
+
+    /// Resolves `owner_ens` into `owner_address`. Without an ENS name this
+    /// does nothing.
+    pub async fn resolve_owner(&mut self) -> Result<(), String> {
+        if let Some(name) = &self.owner_ens {
+            let address = ens::resolve_cached(&self.data_dir, self.ens_rpc_url(), name).await?;
+            self.owner_address = Some(address);
+        }
+        Ok(())
+    }
+
+    /// The `chains` entry selected with `chain`.
+    fn selected_chain(&self) -> Option<&ChainConfig> {
+        let name = self.chain.as_deref()?;
+        self.chains.iter().find(|chain| chain.name == name)
+    }
+
+    /// The chain on-chain operations run against: the selected `chains`
+    /// entry, or the `default` chain of `chain_rpc_url`. A `chain_rpc_url`
+    /// also replaces the RPC of the selected chain.
+    pub fn chain(&self) -> Option<Chain> {
+        match self.selected_chain() {
+            Some(selected) => Some(Chain {
+                name: selected.name.clone(),
+                rpc_url: self
+                    .chain_rpc_url
+                    .clone()
+                    .unwrap_or_else(|| selected.rpc_url.clone()),
+                chain_id: Some(selected.chain_id),
+            }),
+            None => Some(Chain {
+                name: DEFAULT_CHAIN.to_string(),
+                rpc_url: self.chain_rpc_url.clone()?,
+                chain_id: None,
+            }),
+        }
+    }
+
+    /// `registry_contract`, or that of the selected chain.
+    pub fn registry_address(&self) -> Option<Address> {
+        self.registry_contract
+            .or_else(|| self.selected_chain()?.registry_contract)
+    }
+
+    /// `staking_contract`, or that of the selected chain.
+    pub fn staking_address(&self) -> Option<Address> {
+        self.staking_contract
+            .or_else(|| self.selected_chain()?.staking_contract)
+    }
+
+    /// On-chain registration settings, if all of them are configured.
+    pub fn onchain_registration(&self) -> Option<OnchainRegistration> {
+        Some(OnchainRegistration {
+            chain: self.chain()?,
+            contract: self.registry_address()?,
+            signer: self.registration_signer_key.as_ref()?.parse().ok()?,
+        })
+    }
+
+    pub fn dstack_timeouts(&self) -> Timeouts {
+        Timeouts {
+            connect: Duration::from_millis(self.dstack_connect_timeout_ms),
+            request: Duration::from_millis(self.dstack_request_timeout_ms),
+        }
+    }
+
+    /// dstack client for `dstack_url`, retrying per `dstack_retry`.
+    pub fn dstack_client(&self) -> Result<Arc<dyn DStackClient>, BackendError> {
+        connection::connect(&self.dstack_url, self.dstack_timeouts(), &self.dstack_retry)
+    }
+
+    /// Like [`Config::dstack_client`], but can be repointed at runtime.
+    pub fn dstack_connection(&self) -> Result<DStackConnection, BackendError> {
+        DStackConnection::new(
+            &self.dstack_url,
+            self.dstack_timeouts(),
+            self.dstack_retry.clone(),
+            &self.data_dir,
+        )
+    }
+
+    /// Guest agent client for `guest_agent_url`, retrying per `dstack_retry`.
+    pub fn guest_agent_client(&self) -> Result<Arc<dyn DStackClient>, BackendError> {
+        connection::connect(
+            &self.guest_agent_url,
+            self.dstack_timeouts(),
+            &self.dstack_retry,
+        )
+    }
+
+    pub fn key_store(&self) -> KeyStore<'_> {
+        KeyStore {
+            data_dir: &self.data_dir,
+            passphrase: self.key_passphrase.as_deref(),
+            permissions: self.key_permissions,
+            mnemonic: self.mnemonic.as_deref().map(|phrase| Mnemonic {
+                phrase,
+                passphrase: self.mnemonic_passphrase.as_deref(),
+                account: self.mnemonic_account,
+            }),
+        }
+    }
+
+    /// Loads the local Nostr keys, from the key file or the dstack KMS per
+    /// `key_kms`.
+    async fn load_keys(&self) -> Result<Keys, BackendError> {
+        if self.key_kms == KmsKeyMode::Off {
+            return self.key_store().load_or_create();
+        }
+
+        let guest_agent = self.guest_agent_client()?;
+        let secret = kms::get_key(guest_agent.as_ref(), &self.kms_key_path).await?;
+        let store = self.key_store();
+        match self.key_kms {
+            KmsKeyMode::Seal => {
+                info!("Sealing the Nostr key with the dstack KMS");
+                let passphrase = hex::encode(secret);
+                KeyStore {
+                    passphrase: Some(&passphrase),
+                    ..store
+                }
+                .load_or_create()
+            }
+            _ => {
+                let secret_key = SecretKey::from_slice(&secret)
+                    .map_err(|e| BackendError::KeyStore(format!("invalid KMS key: {}", e)))?;
+                let keys = Keys::new(secret_key);
+                info!("Derived Nostr keypair from the dstack KMS");
+                if store.key_file().exists() {
+                    warn!(
+                        "Ignoring {:?}, the key is derived from the dstack KMS",
+                        store.key_file()
+                    );
+                }
+                store.record_derivation(&KeyDerivation {
+                    source: "kms".to_string(),
+                    path: self.kms_key_path.clone(),
+                    account: None,
+                    pubkey: keys.public_key().to_hex(),
+                })?;
+                Ok(keys)
+            }
+        }
+    }
+
+    /// The worker's Nostr signer: the NIP-46 remote signer with `bunker_uri`,
+    /// the local key otherwise. The remote signer is reached with a client
+    /// key kept in `DATA_DIR/bunker`.
+    pub async fn signer(&self) -> Result<Arc<dyn NostrSigner>, BackendError> {
+        let Some(uri) = &self.bunker_uri else {
+            return Ok(Arc::new(self.load_keys().await?));
+        };
+
+        let client_dir = self.data_dir.join("bunker");
+        let client_keys = KeyStore {
+            data_dir: &client_dir,
+            mnemonic: None,
+            ..self.key_store()
+        }
+        .load_or_create()?;
+        let signer = BunkerSigner::connect(
+            uri,
+            client_keys,
+            Duration::from_secs(self.bunker_timeout_secs),
+        )
+        .await?;
+        Ok(Arc::new(signer))
+    }
+
+    /// Admin bearer tokens: the static ones plus, with `admin_token_secret`,
+    /// the one derived for this worker.
+    pub fn admin_auth(&self, nostr_pubkey: &str) -> AdminAuth {
+        let derived = self
+            .admin_token_secret
+            .as_ref()
+            .map(|secret| derive_worker_token(secret, nostr_pubkey));
+        AdminAuth::new(
+            self.admin_token
+                .iter()
+                .chain(&self.admin_tokens)
+                .cloned()
+                .chain(derived),
+        )
+    }
+
+    /// The worker key followed by `extra_pubkeys`, all as hex.
+    pub fn pubkey_roles(&self, nostr_pubkey: &str) -> Vec<PubkeyRole> {
+        let worker = PubkeyRole {
+            pubkey: nostr_pubkey.to_string(),
+            role: WORKER_ROLE.to_string(),
+        };
+        let extra = self.extra_pubkeys.iter().filter_map(|entry| {
+            Some(PubkeyRole {
+                pubkey: PublicKey::parse(&entry.pubkey).ok()?.to_hex(),
+                role: entry.role.clone(),
+            })
+        });
+        // Listing the worker key again under another role would make it
+        // ambiguous which one signs
+        std::iter::once(worker)
+            .chain(extra.filter(|entry| entry.pubkey != nostr_pubkey))
+            .collect()
+    }
+
+    pub fn admin_pubkey(&self) -> Option<PublicKey> {
+        PublicKey::parse(self.admin_npub.as_ref()?).ok()
+    }
+
+    /// Keys allowed to send remote commands.
+    pub fn command_pubkeys(&self) -> Vec<PublicKey> {
+        self.command_npubs
+            .iter()
+            .filter_map(|npub| PublicKey::parse(npub).ok())
+            .collect()
+    }
+
+    pub fn job_pubkeys(&self) -> Vec<PublicKey> {
+        self.job_npubs
+            .iter()
+            .filter_map(|npub| PublicKey::parse(npub).ok())
+            .collect()
+    }
+
+    pub fn coordinator_pubkey(&self) -> Option<PublicKey> {
+        PublicKey::parse(self.coordinator_npub.as_ref()?).ok()
+    }
+
+    pub fn whitelist_publisher(&self) -> Option<PublicKey> {
+        PublicKey::parse(self.whitelist_npub.as_ref()?).ok()
+    }
+
+    /// The whitelist service check, when `whitelist_url` is set.
+    pub fn whitelist_check(&self) -> Option<WhitelistCheck> {
+        Some(WhitelistCheck {
+            url: self.whitelist_url.clone()?,
+            interval: Duration::from_secs(self.whitelist_check_interval_secs),
+            ttl: Duration::from_secs(self.whitelist_cache_ttl_secs),
+        })
+    }
+
+    /// Registry event polling, when `registry_contract` is set.
+    pub fn registry_watch(&self) -> Option<RegistryWatch> {
+        Some(RegistryWatch {
+            chain: self.chain()?,
+            contract: self.registry_address()?,
+            start_block: self.registry_start_block,
+            interval: Duration::from_secs(self.registry_poll_interval_secs),
+        })
+    }
+
+    /// The owner's stake check, when `staking_contract` is set.
+    pub fn stake_check(&self) -> Option<StakeCheck> {
+        Some(StakeCheck {
+            chain: self.chain()?,
+            contract: self.staking_address()?,
+            owner: self.owner_address?,
+            minimum: self.min_stake,
+            interval: Duration::from_secs(self.stake_check_interval_secs),
+        })
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    pub fn dstack_info_interval(&self) -> Duration {
+        Duration::from_secs(self.dstack_info_interval_secs)
+    }
+
+    pub fn sse_keepalive(&self) -> Duration {
+        Duration::from_secs(self.sse_keepalive_secs)
+    }
+}
+
+/// Checks that `url` is a dstack URL the backend can connect to.
+pub fn validate_dstack_url(url: &str) -> Result<(), String> {
+    if let Some(addr) = url.strip_prefix("vsock://") {
+        parse_vsock_addr(addr)?;
+        if !cfg!(feature = "vsock") {
+            return Err("vsock:// URLs need a build with the vsock feature".to_string());
+        }
+        Ok(())
+    } else if url.starts_with("http://")
+        || url.starts_with("https://")
+        || url.starts_with("unix://")
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "unsupported scheme in {:?}, expected http://, https://, unix:// or vsock://",
+            url
+        ))
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_field<T>(field: &str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| ConfigError::new(field, format!("invalid value {:?}: {}", value, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Serializes the tests that set environment variables, which are
+    /// shared by the whole test process.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn config_file(dir: &Path, content: &str) -> PathBuf {
+        let path = dir.join("config.toml");
+        let data_dir = dir.join("data");
+        std::fs::write(
+            &path,
+            format!(
+                "data_dir = {:?}\n{}",
+                data_dir.display().to_string(),
+                content
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    fn fields(errors: &[ConfigError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let _env = ENV.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_file(dir.path(), "poll_interval_secs = 30\n");
+
+        std::env::set_var("POLL_INTERVAL_SECS", "5");
+        let config = Config::load_partial(Some(&path));
+        std::env::remove_var("POLL_INTERVAL_SECS");
+
+        assert_eq!(config.unwrap().poll_interval_secs, 5);
+        assert_eq!(
+            Config::load_partial(Some(&path))
+                .unwrap()
+                .poll_interval_secs,
+            30
+        );
+    }
+
+    #[test]
+    fn reports_an_invalid_env_value_by_name() {
+        let _env = ENV.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_file(dir.path(), "");
+
+        std::env::set_var("POLL_INTERVAL_SECS", "soon");
+        let errors = Config::load_partial(Some(&path)).unwrap_err();
+        std::env::remove_var("POLL_INTERVAL_SECS");
+
+        assert_eq!(fields(&errors), ["POLL_INTERVAL_SECS"]);
+        assert!(errors[0].message.contains("\"soon\""), "{}", errors[0]);
+    }
+
+    #[test]
+    fn rejects_a_zero_poll_interval() {
+        let config = Config::from_toml("poll_interval_secs = 0\n").unwrap();
+
+        assert!(fields(&config.validate()).contains(&"poll_interval_secs"));
+    }
+
+    #[test]
+    fn rejects_a_zero_tls_reload_interval() {
+        let config = Config::from_toml(
+            "[tls]\ncert_path = \"cert.pem\"\nkey_path = \"key.pem\"\nreload_secs = 0\n",
+        )
+        .unwrap();
+
+        assert!(fields(&config.validate()).contains(&"tls.reload_secs"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let error = Config::from_toml("pol_interval_secs = 5\n").unwrap_err();
+
+        assert_eq!(error.field, "pol_interval_secs");
+        assert!(error.message.contains("unknown field"), "{}", error);
+
+        let error = Config::from_toml("[tls]\nreload_sec = 5\n").unwrap_err();
+        assert_eq!(error.field, "reload_sec");
+    }
+}
@@ -0,0 +1,143 @@
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::config::validate_dstack_url;
+use crate::dstack::{self, DStackClient, Timeouts};
+use crate::error::{ApiError, BackendError, ErrorBody};
+use crate::retry::{RetryPolicy, RetryingClient};
+use crate::server::AppState;
+
+/// File in `DATA_DIR` holding a dstack URL set through the admin API. It
+/// takes precedence over `dstack_url` from the config and environment.
+const DSTACK_URL_FILE: &str = "dstack_url";
+
+/// Reads the dstack URL saved by `PUT /admin/dstack-url`, if any.
+pub fn load_dstack_url(data_dir: &Path) -> Option<String> {
+    let url = fs::read_to_string(data_dir.join(DSTACK_URL_FILE)).ok()?;
+    let url = url.trim();
+    (!url.is_empty()).then(|| url.to_string())
+}
+
+/// The dstack client, which can be repointed at another URL at runtime.
+/// Requests in flight finish on the client they started with.
+pub struct DStackConnection {
+    current: RwLock<(String, Arc<dyn DStackClient>)>,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    path: PathBuf,
+}
+
+impl DStackConnection {
+    pub fn new(
+        url: &str,
+        timeouts: Timeouts,
+        retry: RetryPolicy,
+        data_dir: &Path,
+    ) -> Result<Self, BackendError> {
+        let client = connect(url, timeouts, &retry)?;
+        Ok(Self {
+            current: RwLock::new((url.to_string(), client)),
+            timeouts,
+            retry,
+            path: data_dir.join(DSTACK_URL_FILE),
+        })
+    }
+
+    /// A connection to an already built client, e.g. a
+    /// [`MockDStack`](crate::mock::MockDStack). `url` is only reported.
+    pub fn with_client(
+        url: &str,
+        client: Arc<dyn DStackClient>,
+        timeouts: Timeouts,
+        retry: RetryPolicy,
+        data_dir: &Path,
+    ) -> Self {
+        Self {
+            current: RwLock::new((url.to_string(), client)),
+            timeouts,
+            retry,
+            path: data_dir.join(DSTACK_URL_FILE),
+        }
+    }
+
+    pub fn client(&self) -> Arc<dyn DStackClient> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    pub fn url(&self) -> String {
+        self.current.read().unwrap().0.clone()
+    }
+
+    /// Switches to `url` and saves it so it survives restarts. Returns the
+    /// previous URL.
+    pub fn set_url(&self, url: &str) -> Result<String, BackendError> {
+        validate_dstack_url(url).map_err(BackendError::Config)?;
+        let client = connect(url, self.timeouts, &self.retry)?;
+        fs::write(&self.path, format!("{}\n", url)).map_err(|e| {
+            BackendError::Config(format!("failed to save {}: {}", self.path.display(), e))
+        })?;
+
+        let mut current = self.current.write().unwrap();
+        let (previous, _) = std::mem::replace(&mut *current, (url.to_string(), client));
+        Ok(previous)
+    }
+}
+
+/// dstack client for `url`, retrying per `retry`.
+pub fn connect(
+    url: &str,
+    timeouts: Timeouts,
+    retry: &RetryPolicy,
+) -> Result<Arc<dyn DStackClient>, BackendError> {
+    let client = dstack::connect(url, timeouts)?;
+    Ok(Arc::new(RetryingClient::new(client, retry.clone())))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DstackUrlRequest {
+    url: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DstackUrlResponse {
+    url: String,
+    previous: String,
+}
+
+/// `PUT /admin/dstack-url`: repoints the backend at another dstack
+/// endpoint, e.g. after dstack moved ports or to a Unix socket.
+#[utoipa::path(
+    put,
+    path = "/admin/dstack-url",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = DstackUrlRequest,
+    responses(
+        (status = 200, description = "Switched to the new URL", body = DstackUrlResponse),
+        (status = 400, description = "Invalid URL", body = ErrorBody),
+    )
+)]
+pub async fn set_dstack_url_handler(
+    State(state): State<Arc<AppState>>,
+    request: Result<Json<DstackUrlRequest>, JsonRejection>,
+) -> Result<Json<DstackUrlResponse>, ApiError> {
+    let Json(request) = request?;
+    let url = request.url.trim().to_string();
+
+    validate_dstack_url(&url).map_err(ApiError::bad_request)?;
+
+    let previous = state.connection.set_url(&url)?;
+    // Failures of the old endpoint say nothing about the new one
+    state.breaker.reset();
+    info!("dstack URL changed from {} to {}", previous, url);
+
+    Ok(Json(DstackUrlResponse { url, previous }))
+}
@@ -0,0 +1,151 @@
+//! Worker side of the DePHY message flow over Nostr. DePHY messages are
+//! events of kind `1573` on a topic (`t` tag) and grouped into sessions
+//! (`s` tag). The worker answers every status request addressed to it
+//! (`p` tag) in the same session with its status and capability.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::api_version::ApiVersion;
+use crate::health::{DephyWorkerRespondedStatus, Metadata};
+use crate::score::CapabilityScore;
+use crate::server::AppState;
+
+/// Event kind of DePHY messages.
+pub const DEPHY_MESSAGE_KIND: u16 = 1573;
+
+/// Topic the worker joins unless configured otherwise.
+pub const DEFAULT_TOPIC: &str = "dstack-gpu-monitor";
+
+/// Requests older than this are ignored rather than answered late.
+const MAX_REQUEST_AGE: Duration = Duration::from_secs(300);
+
+/// Content of a DePHY message in a worker session.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionMessage {
+    /// Asks the worker for its current status.
+    StatusRequest,
+    StatusResponse {
+        status: DephyWorkerRespondedStatus,
+        node_type: String,
+        metadata: Option<Box<Metadata>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capability: Option<CapabilityScore>,
+    },
+}
+
+fn session_tag() -> TagKind<'static> {
+    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::S))
+}
+
+/// Subscribes to DePHY messages on the configured topic that mention this
+/// worker and answers each status request with a signed status response.
+pub async fn run_dephy_sessions(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+) {
+    let public_key = match signer.get_public_key().await {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            error!("Failed to get the worker public key: {}", e);
+            return;
+        }
+    };
+    let filter = Filter::new()
+        .kind(Kind::Custom(DEPHY_MESSAGE_KIND))
+        .hashtag(state.topic.as_str())
+        .pubkey(public_key)
+        .since(Timestamp::now());
+
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![filter], None).await {
+        error!("Failed to subscribe to DePHY sessions: {}", e);
+        return;
+    }
+    info!("Accepting DePHY sessions on topic {:?}", state.topic);
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(
+                    "DePHY session handler lagged, skipped {} notifications",
+                    skipped
+                );
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if event.kind != Kind::Custom(DEPHY_MESSAGE_KIND) || event.pubkey == public_key {
+            continue;
+        }
+
+        match respond(&state, &public_key, &event) {
+            Some(builder) => match builder.sign(&signer).await {
+                Ok(response) => match client.send_event(response).await {
+                    Ok(output) => {
+                        state.relay_stats.record(&output.success, &output.failed);
+                        info!(
+                            "Answered DePHY status request {} from {}",
+                            event.id, event.pubkey
+                        )
+                    }
+                    Err(e) => error!("Failed to publish DePHY status response: {}", e),
+                },
+                Err(e) => error!("Failed to sign DePHY status response: {}", e),
+            },
+            None => debug!("Ignoring DePHY message {}", event.id),
+        }
+    }
+}
+
+fn respond(state: &AppState, public_key: &PublicKey, request: &Event) -> Option<EventBuilder> {
+    if !request.tags.public_keys().any(|pk| pk == public_key)
+        || !request.tags.hashtags().any(|topic| topic == state.topic)
+    {
+        return None;
+    }
+    let session = request.tags.find(session_tag())?.content()?;
+    let age = Timestamp::now()
+        .as_u64()
+        .saturating_sub(request.created_at.as_u64());
+    if age > MAX_REQUEST_AGE.as_secs() {
+        return None;
+    }
+    match serde_json::from_str(&request.content) {
+        Ok(SessionMessage::StatusRequest) => {}
+        Ok(_) => return None,
+        Err(e) => {
+            debug!("Unparseable DePHY message {}: {}", request.id, e);
+            return None;
+        }
+    }
+
+    // Answer from the poller's cached snapshot, like liveness challenges
+    let info = ApiVersion::Legacy.present(state.poller.latest()?, state.legacy_metadata);
+    let content = SessionMessage::StatusResponse {
+        status: info.status,
+        node_type: state.node_type.clone(),
+        metadata: info.metadata.map(Box::new),
+        capability: info.capability,
+    };
+
+    let content = serde_json::to_string(&content).ok()?;
+    Some(
+        EventBuilder::new(Kind::Custom(DEPHY_MESSAGE_KIND), content).tags([
+            Tag::custom(session_tag(), [session]),
+            Tag::hashtag(state.topic.as_str()),
+            Tag::public_key(request.pubkey),
+            Tag::event(request.id),
+        ]),
+    )
+}
@@ -0,0 +1,220 @@
+//! `dstack-backend doctor`: checks a provisioned worker without starting
+//! the backend, for provisioning scripts and container health checks.
+
+use std::fmt;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::dstack::fetch_dstack_data;
+use crate::ens;
+use crate::kms::KmsKeyMode;
+use crate::listen::ListenAddr;
+
+/// Timeout of each network check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of one check.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        Self { name, result }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.result {
+            Ok(detail) => write!(f, "[ ok ] {}: {}", self.name, detail),
+            Err(detail) => write!(f, "[FAIL] {}: {}", self.name, detail),
+        }
+    }
+}
+
+/// Runs every check. Stops after the configuration check if the config
+/// can't be read at all, since the others depend on it.
+pub async fn run_checks(config_path: Option<&Path>) -> Vec<Check> {
+    let config = match Config::load_partial(config_path) {
+        Ok(config) => config,
+        Err(errors) => return vec![Check::new("Configuration", Err(join_errors(&errors)))],
+    };
+
+    let mut checks = vec![
+        Check::new(
+            "Configuration",
+            match Config::load(config_path) {
+                Ok(_) => Ok(match config_path {
+                    Some(path) => format!("{} is valid", path.display()),
+                    None => "valid".to_string(),
+                }),
+                Err(errors) => Err(join_errors(&errors)),
+            },
+        ),
+        Check::new("Owner address", check_owner(&config).await),
+        Check::new("Nostr key", check_key(&config)),
+        Check::new("dstack", check_dstack(&config).await),
+    ];
+    for relay in &config.relays {
+        checks.push(Check::new("Nostr relay", check_relay(relay).await));
+    }
+    checks.push(Check::new(
+        "Listen address",
+        check_listen_addr(&config).await,
+    ));
+    checks
+}
+
+fn join_errors(errors: &[impl fmt::Display]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+async fn check_owner(config: &Config) -> Result<String, String> {
+    match (&config.owner_ens, config.owner_address) {
+        (Some(name), _) => {
+            let address = ens::resolve_cached(&config.data_dir, config.ens_rpc_url(), name).await?;
+            Ok(format!("{} resolves to {}", name, address))
+        }
+        (None, Some(address)) => Ok(address.to_string()),
+        (None, None) => Err("not set (OWNER_ADDRESS)".to_string()),
+    }
+}
+
+fn check_key(config: &Config) -> Result<String, String> {
+    if config.bunker_uri.is_some() {
+        return Ok("held by the remote signer, not checked".to_string());
+    }
+    if config.key_kms == KmsKeyMode::Derive {
+        return Ok("derived from the dstack KMS, not checked".to_string());
+    }
+    if config.key_kms == KmsKeyMode::Seal {
+        return Ok("sealed with the dstack KMS, not checked".to_string());
+    }
+
+    let store = config.key_store();
+    match store.check() {
+        Ok(Some(pubkey)) => Ok(pubkey.to_hex()),
+        Ok(None) => Ok(format!(
+            "{} does not exist yet, it is generated on first start",
+            store.key_file().display()
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn check_dstack(config: &Config) -> Result<String, String> {
+    let client = config.dstack_client().map_err(|e| e.to_string())?;
+    let response = tokio::time::timeout(CHECK_TIMEOUT, fetch_dstack_data(client.as_ref()))
+        .await
+        .map_err(|_| format!("{}: timed out", config.dstack_url))?
+        .map_err(|e| format!("{}: {}", config.dstack_url, e))?;
+    Ok(format!(
+        "{} reports {} GPUs",
+        config.dstack_url,
+        response.gpus.len()
+    ))
+}
+
+/// Fetches the relay's NIP-11 information document, which relays serve
+/// over plain HTTP(S) on the same URL.
+async fn check_relay(relay: &str) -> Result<String, String> {
+    let url = match relay.split_once("://") {
+        Some(("wss", rest)) => format!("https://{}", rest),
+        Some(("ws", rest)) => format!("http://{}", rest),
+        _ => return Err(format!("{}: expected a ws:// or wss:// URL", relay)),
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = client
+        .get(&url)
+        .header("Accept", "application/nostr+json")
+        .send()
+        .await
+        .map_err(|e| format!("{}: {}", relay, e))?;
+    Ok(format!("{} answered with {}", relay, response.status()))
+}
+
+/// The listen address must be free, or taken by a backend that is already
+/// running (e.g. when used as a container health check).
+async fn check_listen_addr(config: &Config) -> Result<String, String> {
+    match &config.listen_addr {
+        ListenAddr::Tcp(addr) => match tokio::net::TcpListener::bind(addr).await {
+            Ok(_) => Ok(format!("{} is available", addr)),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                let host = match addr.ip() {
+                    ip if ip.is_unspecified() && ip.is_ipv4() => "127.0.0.1".to_string(),
+                    ip if ip.is_unspecified() => "[::1]".to_string(),
+                    ip if ip.is_ipv6() => format!("[{}]", ip),
+                    ip => ip.to_string(),
+                };
+                let scheme = if config.tls.enabled() {
+                    "https"
+                } else {
+                    "http"
+                };
+                let url = format!("{}://{}:{}/livez", scheme, host, addr.port());
+                if probe_livez(&url).await {
+                    Ok(format!("{} is in use by a running backend", addr))
+                } else {
+                    Err(format!("{} is in use by another process", addr))
+                }
+            }
+            Err(e) => Err(format!("{}: {}", addr, e)),
+        },
+        ListenAddr::Unix(path) => match std::fs::symlink_metadata(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    Some(dir) if !dir.is_dir() => Err(format!("{} does not exist", dir.display())),
+                    _ => Ok(format!("{} is available", path.display())),
+                }
+            }
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+            Ok(_) => match UnixStream::connect(path) {
+                Ok(_) => Ok(format!("{} is in use by a running backend", path.display())),
+                Err(_) if is_socket(path) => Ok(format!(
+                    "{} is a stale socket, it is replaced on start",
+                    path.display()
+                )),
+                Err(_) => Err(format!("{} exists and is not a socket", path.display())),
+            },
+        },
+    }
+}
+
+fn is_socket(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+async fn probe_livez(url: &str) -> bool {
+    let Ok(client) = reqwest::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        // Only checking that the backend answers, not who it is
+        .danger_accept_invalid_certs(true)
+        .build()
+    else {
+        return false;
+    };
+    client
+        .get(url)
+        .send()
+        .await
+        .is_ok_and(|response| response.status().is_success())
+}
@@ -0,0 +1,378 @@
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request};
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::Client;
+use hyperlocal::{UnixClientExt, UnixConnector, Uri as UnixUri};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info};
+use utoipa::ToSchema;
+
+use crate::error::BackendError;
+use crate::node_type::NodeTypeTable;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GpuInfo {
+    pub slot: String,
+    pub product_id: String,
+    pub description: String,
+    pub is_free: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DStackResponse {
+    pub gpus: Vec<GpuInfo>,
+    pub allow_attach_all: bool,
+}
+
+/// A transport to dstack (or the guest agent). Requests with a body are
+/// JSON POSTs, all others GETs.
+#[async_trait]
+pub trait DStackClient: Send + Sync {
+    /// Sends a request to `path` and returns the JSON response.
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError>;
+
+    /// Like [`call`](Self::call), for writes the caller knows are safe to
+    /// send twice. Retrying clients retry these too, while plain `call`
+    /// only retries reads.
+    async fn call_retrying(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        self.call(path, body).await
+    }
+
+    /// Human-readable endpoint for log messages.
+    fn endpoint(&self) -> String;
+}
+
+/// Extracts the method name from `/prpc/{method}?json` or `/{method}`.
+pub fn method_name(path: &str) -> &str {
+    let path = path.split('?').next().unwrap_or(path);
+    let path = path.strip_prefix("/prpc").unwrap_or(path);
+    path.trim_start_matches('/')
+}
+
+impl dyn DStackClient + '_ {
+    /// Sends a request to `path` and decodes the JSON response.
+    pub async fn request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<T, BackendError> {
+        let value = self.call(path, body).await?;
+        serde_json::from_value(value).map_err(|e| BackendError::Decode(e.to_string()))
+    }
+}
+
+/// Connect and whole-request timeouts applied to every dstack request.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(3),
+            request: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Builds a client from a `DSTACK_URL` value, using a Unix socket for
+/// `unix://` URLs, vsock for `vsock://cid:port` URLs (with the `vsock`
+/// feature) and plain HTTP otherwise.
+pub fn connect(
+    dstack_url: &str,
+    timeouts: Timeouts,
+) -> Result<Arc<dyn DStackClient>, BackendError> {
+    if let Some(socket_path) = dstack_url.strip_prefix("unix://") {
+        info!("Using Unix socket connection: {}", socket_path);
+        Ok(Arc::new(UnixSocketClient {
+            socket_path: socket_path.to_string(),
+            client: Client::unix(),
+            timeout: timeouts.request,
+        }))
+    } else if let Some(addr) = dstack_url.strip_prefix("vsock://") {
+        let (cid, port) = parse_vsock_addr(addr).map_err(BackendError::Config)?;
+        connect_vsock(cid, port, timeouts)
+    } else {
+        info!("Using HTTP connection: {}", dstack_url);
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request)
+            .build()
+            .map_err(|e| BackendError::Config(format!("Failed to create HTTP client: {}", e)))?;
+        Ok(Arc::new(HttpClient {
+            url: dstack_url.trim_end_matches('/').to_string(),
+            client,
+            timeout: timeouts.request,
+        }))
+    }
+}
+
+/// Parses the `cid:port` part of a `vsock://cid:port` URL.
+pub fn parse_vsock_addr(addr: &str) -> Result<(u32, u32), String> {
+    let (cid, port) = addr
+        .trim_end_matches('/')
+        .split_once(':')
+        .ok_or_else(|| format!("expected vsock://cid:port, got vsock://{}", addr))?;
+    let cid = cid
+        .parse()
+        .map_err(|_| format!("invalid vsock CID {:?}", cid))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid vsock port {:?}", port))?;
+    Ok((cid, port))
+}
+
+#[cfg(feature = "vsock")]
+fn connect_vsock(
+    cid: u32,
+    port: u32,
+    timeouts: Timeouts,
+) -> Result<Arc<dyn DStackClient>, BackendError> {
+    use hyper_util::rt::TokioExecutor;
+
+    info!("Using vsock connection: cid {} port {}", cid, port);
+    Ok(Arc::new(VsockClient {
+        cid,
+        port,
+        client: Client::builder(TokioExecutor::new()).build(crate::vsock::VsockConnector {
+            cid,
+            port,
+            connect_timeout: timeouts.connect,
+        }),
+        timeout: timeouts.request,
+    }))
+}
+
+#[cfg(not(feature = "vsock"))]
+fn connect_vsock(
+    _cid: u32,
+    _port: u32,
+    _timeouts: Timeouts,
+) -> Result<Arc<dyn DStackClient>, BackendError> {
+    Err(BackendError::Config(
+        "vsock:// URLs need a build with the vsock feature".to_string(),
+    ))
+}
+
+/// dstack over plain HTTP(S).
+#[derive(Clone)]
+pub struct HttpClient {
+    url: String,
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl DStackClient for HttpClient {
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        let full_url = format!("{}{}", self.url, path);
+        debug!("dstack request via HTTP: {}", full_url);
+
+        let request = match body {
+            Some(body) => self.client.post(&full_url).json(body),
+            None => self.client.get(&full_url),
+        };
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                BackendError::Timeout(self.timeout)
+            } else {
+                BackendError::Transport(format!("HTTP request failed: {}", e))
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(status_error(status, &body));
+        }
+
+        let body = response.bytes().await.map_err(|e| {
+            if e.is_timeout() {
+                BackendError::Timeout(self.timeout)
+            } else {
+                BackendError::Transport(format!("Failed to read response body: {}", e))
+            }
+        })?;
+        serde_json::from_slice(&body).map_err(|e| BackendError::Decode(e.to_string()))
+    }
+
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// dstack over a Unix domain socket.
+#[derive(Clone)]
+pub struct UnixSocketClient {
+    socket_path: String,
+    client: Client<UnixConnector, Full<Bytes>>,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl DStackClient for UnixSocketClient {
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        debug!(
+            "dstack request via Unix socket {}: {}",
+            self.socket_path, path
+        );
+
+        let uri: hyper::Uri = UnixUri::new(&self.socket_path, path).into();
+        send_hyper(&self.client, uri, body, "Unix socket", self.timeout).await
+    }
+
+    fn endpoint(&self) -> String {
+        format!("unix://{}", self.socket_path)
+    }
+}
+
+/// dstack over vsock, for host agents that aren't reachable by TCP or a
+/// Unix socket.
+#[cfg(feature = "vsock")]
+#[derive(Clone)]
+pub struct VsockClient {
+    cid: u32,
+    port: u32,
+    client: Client<crate::vsock::VsockConnector, Full<Bytes>>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "vsock")]
+#[async_trait]
+impl DStackClient for VsockClient {
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        debug!(
+            "dstack request via vsock {}:{}: {}",
+            self.cid, self.port, path
+        );
+
+        // The connector ignores the authority, only the path matters
+        let uri: hyper::Uri = format!("http://127.0.0.1{}", path)
+            .parse()
+            .map_err(|e| BackendError::InvalidRequest(format!("invalid path {}: {}", path, e)))?;
+        send_hyper(&self.client, uri, body, "vsock", self.timeout).await
+    }
+
+    fn endpoint(&self) -> String {
+        format!("vsock://{}:{}", self.cid, self.port)
+    }
+}
+
+/// Sends a request over a hyper client (Unix socket or vsock) and decodes
+/// the JSON response, giving up after `timeout`.
+async fn send_hyper<C>(
+    client: &Client<C, Full<Bytes>>,
+    uri: hyper::Uri,
+    body: Option<&serde_json::Value>,
+    transport: &str,
+    timeout: Duration,
+) -> Result<serde_json::Value, BackendError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    tokio::time::timeout(timeout, send_hyper_inner(client, uri, body, transport))
+        .await
+        .map_err(|_| BackendError::Timeout(timeout))?
+}
+
+async fn send_hyper_inner<C>(
+    client: &Client<C, Full<Bytes>>,
+    uri: hyper::Uri,
+    body: Option<&serde_json::Value>,
+    transport: &str,
+) -> Result<serde_json::Value, BackendError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let builder = Request::builder().uri(uri).header("Host", "127.0.0.1");
+    let req = match body {
+        Some(body) => builder
+            .method(Method::POST)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body.to_string()))),
+        None => builder.body(Full::new(Bytes::new())),
+    }
+    .map_err(|e| BackendError::InvalidRequest(e.to_string()))?;
+
+    let response = client
+        .request(req)
+        .await
+        .map_err(|e| BackendError::Transport(format!("{} request failed: {}", transport, e)))?;
+
+    let status = response.status();
+    let body_bytes = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| BackendError::Transport(format!("Failed to read response body: {}", e)))?
+        .to_bytes();
+
+    if !status.is_success() {
+        return Err(status_error(status, &String::from_utf8_lossy(&body_bytes)));
+    }
+
+    serde_json::from_slice(&body_bytes).map_err(|e| BackendError::Decode(e.to_string()))
+}
+
+/// Error for a non-2xx response, keeping the start of the body for logs.
+fn status_error(status: hyper::StatusCode, body: &str) -> BackendError {
+    const MAX_BODY_CHARS: usize = 200;
+    BackendError::Status(status, body.trim().chars().take(MAX_BODY_CHARS).collect())
+}
+
+pub async fn fetch_dstack_data(
+    connection: &dyn DStackClient,
+) -> Result<DStackResponse, BackendError> {
+    info!("Checking dstack health at: {}", connection.endpoint());
+    connection.request("/prpc/ListGpus?json", None).await
+}
+
+/// Derives the node type using the default detection table.
+pub fn determine_node_type(dstack_response: &DStackResponse) -> String {
+    NodeTypeTable::default().determine(dstack_response)
+}
+
+/// Queries dstack for the GPU inventory and derives the node type. Returns
+/// "Unknown" if dstack doesn't answer; retries are up to the client.
+pub async fn detect_node_type(connection: &dyn DStackClient, table: &NodeTypeTable) -> String {
+    match fetch_dstack_data(connection).await {
+        Ok(data) => {
+            let node_type = table.determine(&data);
+            info!("Successfully determined node type: {}", node_type);
+            node_type
+        }
+        Err(e) => {
+            error!("Failed to fetch dstack data: {}", e);
+            "Unknown".to_string()
+        }
+    }
+}
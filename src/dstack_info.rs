@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::dstack::DStackClient;
+use crate::server::AppState;
+
+/// What dstack reports about itself, so coordinators can tell which workers
+/// run outdated releases.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DstackInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Source revision of the dstack build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Host OS image the CVMs boot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_image: Option<String>,
+    /// Key management service dstack is configured with, as reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kms: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionResponse {
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    rev: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InfoResponse {
+    #[serde(default, alias = "os_image_hash", alias = "image")]
+    os_image: Option<String>,
+    #[serde(default, alias = "key_provider_info", alias = "kms_url")]
+    kms: Option<serde_json::Value>,
+}
+
+/// The most recently fetched dstack info.
+#[derive(Debug, Default)]
+pub struct DstackInfoCache {
+    current: RwLock<Option<DstackInfo>>,
+}
+
+impl DstackInfoCache {
+    pub fn get(&self) -> Option<DstackInfo> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Stores `info`, returning whether it changed.
+    fn set(&self, info: DstackInfo) -> bool {
+        let mut current = self.current.write().unwrap();
+        let changed = current.as_ref() != Some(&info);
+        *current = Some(info);
+        changed
+    }
+}
+
+/// Calls dstack's `Version` and `Info` methods. Either may fail on its own
+/// (older releases lack `Info`); `None` means neither answered.
+pub async fn fetch_dstack_info(connection: &dyn DStackClient) -> Option<DstackInfo> {
+    let version = connection
+        .request::<VersionResponse>("/prpc/Version?json", None)
+        .await;
+    let host = connection
+        .request::<InfoResponse>("/prpc/Info?json", None)
+        .await;
+    let answered = version.is_ok() || host.is_ok();
+
+    let mut info = DstackInfo::default();
+    match version {
+        Ok(version) => {
+            info.version = version.version;
+            info.rev = version.rev;
+        }
+        Err(e) => warn!("Failed to fetch dstack version: {}", e),
+    }
+    match host {
+        Ok(host) => {
+            info.os_image = host.os_image;
+            info.kms = host.kms;
+        }
+        Err(e) => warn!("Failed to fetch dstack info: {}", e),
+    }
+
+    answered.then_some(info)
+}
+
+/// Fetches dstack's info at startup and then every `interval`. A failed
+/// fetch keeps the previous info.
+pub async fn run_dstack_info_refresh(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        if let Some(info) = fetch_dstack_info(state.connection.client().as_ref()).await {
+            if state.dstack_info.set(info.clone()) {
+                info!(
+                    "dstack version {}, OS image {}",
+                    info.version.as_deref().unwrap_or("unknown"),
+                    info.os_image.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! EIP-712 typed registration payloads: the owner signs a
+//! `WorkerRegistration` struct with `eth_signTypedData_v4`, so registrars and
+//! the registry contract can verify registrations in a standard,
+//! wallet-compatible format.
+
+use alloy::primitives::{Address, FixedBytes, Signature, U256};
+use alloy::providers::ProviderBuilder;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use alloy::sol;
+use alloy::sol_types::{Eip712Domain, SolStruct};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::chain::Chain;
+
+pub const DOMAIN_NAME: &str = "dstack Mining Worker Registry";
+pub const DOMAIN_VERSION: &str = "1";
+
+sol! {
+    struct WorkerRegistration {
+        bytes32 nostrPubkey;
+        string nodeType;
+        address owner;
+        uint256 nonce;
+    }
+}
+
+/// The signing domain. The chain and the registry contract are bound when
+/// known, so a signature can't be reused on another chain or contract.
+#[derive(Debug, Clone, Default)]
+pub struct RegistrationDomain {
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<Address>,
+}
+
+impl RegistrationDomain {
+    /// Binds `chain`, if given, and the registry `contract`.
+    pub async fn resolve(chain: Option<&Chain>, contract: Option<Address>) -> Result<Self, String> {
+        let chain_id = match chain {
+            Some(chain) => {
+                let provider = ProviderBuilder::new().connect_http(chain.url()?);
+                Some(chain.tag(&provider).await?.chain_id)
+            }
+            None => None,
+        };
+        Ok(Self {
+            chain_id,
+            verifying_contract: contract,
+        })
+    }
+
+    fn eip712(&self) -> Eip712Domain {
+        Eip712Domain::new(
+            Some(DOMAIN_NAME.into()),
+            Some(DOMAIN_VERSION.into()),
+            self.chain_id.map(U256::from),
+            self.verifying_contract,
+            None,
+        )
+    }
+
+    /// `EIP712Domain` fields, which must list exactly the fields set.
+    fn types(&self) -> Vec<serde_json::Value> {
+        let mut types = vec![
+            json!({"name": "name", "type": "string"}),
+            json!({"name": "version", "type": "string"}),
+        ];
+        if self.chain_id.is_some() {
+            types.push(json!({"name": "chainId", "type": "uint256"}));
+        }
+        if self.verifying_contract.is_some() {
+            types.push(json!({"name": "verifyingContract", "type": "address"}));
+        }
+        types
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut domain = json!({"name": DOMAIN_NAME, "version": DOMAIN_VERSION});
+        if let Some(chain_id) = self.chain_id {
+            domain["chainId"] = json!(chain_id);
+        }
+        if let Some(contract) = self.verifying_contract {
+            domain["verifyingContract"] = json!(contract.to_string());
+        }
+        domain
+    }
+}
+
+/// A random registration nonce.
+pub fn new_registration_nonce() -> U256 {
+    U256::from(rand::thread_rng().gen::<u128>())
+}
+
+impl WorkerRegistration {
+    pub fn new(
+        nostr_pubkey: &str,
+        node_type: &str,
+        owner: Address,
+        nonce: U256,
+    ) -> Result<Self, String> {
+        let nostr_pubkey: FixedBytes<32> = nostr_pubkey
+            .parse()
+            .map_err(|e| format!("Invalid Nostr public key {}: {}", nostr_pubkey, e))?;
+        Ok(Self {
+            nostrPubkey: nostr_pubkey,
+            nodeType: node_type.to_string(),
+            owner,
+            nonce,
+        })
+    }
+
+    /// The typed data as `eth_signTypedData_v4` takes it.
+    pub fn typed_data(&self, domain: &RegistrationDomain) -> serde_json::Value {
+        json!({
+            "types": {
+                "EIP712Domain": domain.types(),
+                "WorkerRegistration": [
+                    {"name": "nostrPubkey", "type": "bytes32"},
+                    {"name": "nodeType", "type": "string"},
+                    {"name": "owner", "type": "address"},
+                    {"name": "nonce", "type": "uint256"},
+                ],
+            },
+            "primaryType": "WorkerRegistration",
+            "domain": domain.to_json(),
+            "message": {
+                "nostrPubkey": self.nostrPubkey.to_string(),
+                "nodeType": self.nodeType,
+                "owner": self.owner.to_string(),
+                "nonce": self.nonce.to_string(),
+            },
+        })
+    }
+
+    /// Signs the registration with the owner key.
+    pub fn sign(
+        &self,
+        domain: &RegistrationDomain,
+        signer: &PrivateKeySigner,
+    ) -> Result<TypedRegistration, String> {
+        if signer.address() != self.owner {
+            return Err(format!(
+                "private key belongs to {}, not the owner {}",
+                signer.address(),
+                self.owner
+            ));
+        }
+        let signature = signer
+            .sign_hash_sync(&self.eip712_signing_hash(&domain.eip712()))
+            .map_err(|e| format!("Failed to sign registration: {}", e))?;
+        Ok(TypedRegistration {
+            typed_data: self.typed_data(domain),
+            signature: signature.to_string(),
+        })
+    }
+
+    /// Checks that `signature` over the registration recovers to its owner.
+    pub fn verify(
+        &self,
+        domain: &RegistrationDomain,
+        signature: &str,
+    ) -> Result<TypedRegistration, String> {
+        let parsed: Signature = signature
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+        let recovered = parsed
+            .recover_address_from_prehash(&self.eip712_signing_hash(&domain.eip712()))
+            .map_err(|e| format!("Failed to recover signer: {}", e))?;
+        if recovered != self.owner {
+            return Err(format!(
+                "signature was made by {}, not the owner {}",
+                recovered, self.owner
+            ));
+        }
+        Ok(TypedRegistration {
+            typed_data: self.typed_data(domain),
+            signature: parsed.to_string(),
+        })
+    }
+}
+
+/// An owner-signed `WorkerRegistration`, with the typed data it signs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypedRegistration {
+    pub typed_data: serde_json::Value,
+    pub signature: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER_KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const NOSTR_PUBKEY: &str = "0x79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn domain() -> RegistrationDomain {
+        RegistrationDomain {
+            chain_id: Some(8453),
+            verifying_contract: Some(Address::repeat_byte(0x11)),
+        }
+    }
+
+    fn signed() -> (WorkerRegistration, String) {
+        let signer: PrivateKeySigner = OWNER_KEY.parse().unwrap();
+        let registration =
+            WorkerRegistration::new(NOSTR_PUBKEY, "H100", signer.address(), U256::from(7)).unwrap();
+        let signature = registration.sign(&domain(), &signer).unwrap().signature;
+        (registration, signature)
+    }
+
+    #[test]
+    fn verifies_its_own_signature() {
+        let (registration, signature) = signed();
+
+        let verified = registration.verify(&domain(), &signature).unwrap();
+
+        assert_eq!(verified.signature, signature);
+        assert_eq!(verified.typed_data["domain"]["chainId"], 8453);
+        assert_eq!(verified.typed_data["message"]["nonce"], "7");
+    }
+
+    #[test]
+    fn rejects_a_signature_for_another_chain() {
+        let (registration, signature) = signed();
+        let other = RegistrationDomain {
+            chain_id: Some(1),
+            ..domain()
+        };
+
+        assert!(registration.verify(&other, &signature).is_err());
+    }
+
+    #[test]
+    fn rejects_a_signature_for_another_contract() {
+        let (registration, signature) = signed();
+        for verifying_contract in [Some(Address::repeat_byte(0x22)), None] {
+            let other = RegistrationDomain {
+                verifying_contract,
+                ..domain()
+            };
+
+            assert!(registration.verify(&other, &signature).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_a_signature_over_another_message() {
+        let (registration, signature) = signed();
+        let other = WorkerRegistration {
+            nodeType: "A100".to_string(),
+            ..registration
+        };
+
+        assert!(other.verify(&domain(), &signature).is_err());
+    }
+
+    #[test]
+    fn refuses_to_sign_for_another_owner() {
+        let signer: PrivateKeySigner = OWNER_KEY.parse().unwrap();
+        let registration =
+            WorkerRegistration::new(NOSTR_PUBKEY, "H100", Address::ZERO, U256::from(7)).unwrap();
+
+        assert!(registration.sign(&domain(), &signer).is_err());
+    }
+
+    #[test]
+    fn lists_only_the_domain_fields_set() {
+        let (registration, _) = signed();
+
+        let typed_data = registration.typed_data(&RegistrationDomain::default());
+
+        assert_eq!(
+            typed_data["types"]["EIP712Domain"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(typed_data["domain"].get("chainId").is_none());
+    }
+}
@@ -0,0 +1,158 @@
+//! ENS names for the owner address. A name given as `OWNER_ADDRESS` is
+//! resolved through the ENS registry at startup. The result is cached under
+//! `DATA_DIR`, so commands don't each query the chain and a resolved name
+//! survives an RPC outage.
+
+use alloy::primitives::{address, keccak256, Address, B256};
+use alloy::providers::ProviderBuilder;
+use alloy::sol;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::history::unix_timestamp;
+
+sol! {
+    #[sol(rpc)]
+    interface IEnsRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+
+    #[sol(rpc)]
+    interface IEnsResolver {
+        function addr(bytes32 node) external view returns (address);
+    }
+}
+
+/// The ENS registry, at the same address on mainnet and the testnets.
+pub const ENS_REGISTRY: Address = address!("00000000000C2E074eC69A0dFb2997BA6C7d2e1e");
+
+/// The last resolution of the owner name.
+pub const ENS_CACHE_FILE: &str = "ens_cache.json";
+
+/// How long a cached resolution is used without asking the chain again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Whether `value` is an ENS name rather than a hex address.
+pub fn is_ens_name(value: &str) -> bool {
+    !value.starts_with("0x") && value.contains('.')
+}
+
+/// The ENS namehash of `name`. Names are only lowercased, not fully
+/// normalized.
+pub fn namehash(name: &str) -> B256 {
+    let mut node = B256::ZERO;
+    for label in name.to_lowercase().rsplit('.') {
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(node.as_slice());
+        data[32..].copy_from_slice(keccak256(label.as_bytes()).as_slice());
+        node = keccak256(data);
+    }
+    node
+}
+
+/// Resolves `name` with the resolver the ENS registry has for it.
+pub async fn resolve(rpc_url: &str, name: &str) -> Result<Address, String> {
+    let url = rpc_url
+        .parse()
+        .map_err(|e| format!("Invalid ENS RPC URL {}: {}", rpc_url, e))?;
+    let provider = ProviderBuilder::new().connect_http(url);
+    let node = namehash(name);
+
+    let resolver = IEnsRegistry::new(ENS_REGISTRY, &provider)
+        .resolver(node)
+        .call()
+        .await
+        .map_err(|e| format!("resolver call failed: {}", e))?;
+    if resolver == Address::ZERO {
+        return Err(format!("{} has no resolver", name));
+    }
+    let address = IEnsResolver::new(resolver, &provider)
+        .addr(node)
+        .call()
+        .await
+        .map_err(|e| format!("addr call failed: {}", e))?;
+    if address == Address::ZERO {
+        return Err(format!("{} does not resolve to an address", name));
+    }
+    Ok(address)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedName {
+    name: String,
+    address: Address,
+    resolved_at: u64,
+}
+
+/// Resolves `name`, answering from the cache under `data_dir` while it is
+/// fresh and falling back to an outdated entry when the chain can't be
+/// reached.
+pub async fn resolve_cached(
+    data_dir: &Path,
+    rpc_url: Option<&str>,
+    name: &str,
+) -> Result<Address, String> {
+    let path = data_dir.join(ENS_CACHE_FILE);
+    let cached = fs::read_to_string(&path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<CachedName>(&json).ok())
+        .filter(|cached| cached.name == name);
+    let age = cached
+        .as_ref()
+        .map(|cached| unix_timestamp().saturating_sub(cached.resolved_at));
+    if let (Some(cached), Some(age)) = (&cached, age) {
+        if age < CACHE_TTL.as_secs() {
+            return Ok(cached.address);
+        }
+    }
+
+    let Some(rpc_url) = rpc_url else {
+        return Err(format!(
+            "cannot resolve ENS name {}: no RPC configured (set ENS_RPC_URL or CHAIN_RPC_URL)",
+            name
+        ));
+    };
+    match resolve(rpc_url, name).await {
+        Ok(address) => {
+            info!("Resolved ENS name {} to {}", name, address);
+            let entry = CachedName {
+                name: name.to_string(),
+                address,
+                resolved_at: unix_timestamp(),
+            };
+            if let Err(e) = save_cache(data_dir, &entry) {
+                warn!(
+                    "Failed to cache the ENS resolution in {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+            Ok(address)
+        }
+        Err(e) => match cached {
+            Some(cached) => {
+                warn!(
+                    "Failed to resolve ENS name {}, using the cached address {} from {}s ago: {}",
+                    name,
+                    cached.address,
+                    age.unwrap_or_default(),
+                    e
+                );
+                Ok(cached.address)
+            }
+            None => Err(format!("Failed to resolve ENS name {}: {}", name, e)),
+        },
+    }
+}
+
+fn save_cache(data_dir: &Path, entry: &CachedName) -> io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    fs::write(
+        data_dir.join(ENS_CACHE_FILE),
+        serde_json::to_string_pretty(entry)?,
+    )
+}
@@ -0,0 +1,188 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::time::Duration;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::request_id::current_request_id;
+
+/// Errors from talking to dstack and managing backend state.
+#[derive(Debug, Clone, Error)]
+pub enum BackendError {
+    /// The request couldn't be sent or its response couldn't be read.
+    #[error("{0}")]
+    Transport(String),
+    /// dstack answered with an error status, and the response body.
+    #[error("HTTP error: {0}: {1}")]
+    Status(StatusCode, String),
+    /// The request couldn't be built, e.g. from an invalid path.
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    /// dstack answered, but not with the expected JSON.
+    #[error("Failed to parse JSON: {0}")]
+    Decode(String),
+    #[error("dstack request timed out after {}ms", .0.as_millis())]
+    Timeout(Duration),
+    /// The circuit breaker is rejecting calls after repeated failures.
+    #[error("dstack circuit open, {0}")]
+    CircuitOpen(String),
+    #[error("Key store error: {0}")]
+    KeyStore(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("TLS error: {0}")]
+    Tls(String),
+    /// The NIP-46 remote signer failed or didn't answer.
+    #[error("Remote signer error: {0}")]
+    Signer(String),
+    #[error("Benchmark failed: {0}")]
+    Benchmark(String),
+}
+
+impl BackendError {
+    /// Machine-readable error class, reported in health metadata.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BackendError::Transport(_) => "transport",
+            BackendError::Status(..) => "http_status",
+            BackendError::InvalidRequest(_) => "invalid_request",
+            BackendError::Decode(_) => "decode",
+            BackendError::Timeout(_) => "timeout",
+            BackendError::CircuitOpen(_) => "circuit_open",
+            BackendError::KeyStore(_) => "key_store",
+            BackendError::Config(_) => "config",
+            BackendError::Tls(_) => "tls",
+            BackendError::Signer(_) => "signer",
+            BackendError::Benchmark(_) => "benchmark",
+        }
+    }
+
+    /// Status for HTTP handlers whose dstack call failed.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            BackendError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            BackendError::CircuitOpen(_) => StatusCode::SERVICE_UNAVAILABLE,
+            // dstack rejecting the request is passed on, its own failures
+            // are a bad gateway
+            BackendError::Status(status, _) if status.is_client_error() => *status,
+            BackendError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            BackendError::KeyStore(_)
+            | BackendError::Config(_)
+            | BackendError::Tls(_)
+            | BackendError::Benchmark(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            BackendError::Transport(_)
+            | BackendError::Status(..)
+            | BackendError::Decode(_)
+            | BackendError::Signer(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// Whether retrying the same request might succeed: connection and I/O
+    /// failures, timeouts, and dstack answering 5xx or 429.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BackendError::Transport(_) | BackendError::Timeout(_) => true,
+            BackendError::Status(status, _) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Error returned by HTTP handlers, rendered as
+/// `{"error": {"code", "message", "request_id"}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+}
+
+impl From<BackendError> for ApiError {
+    fn from(e: BackendError) -> Self {
+        Self::new(e.status_code(), e.code(), e.to_string())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_body", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), "invalid_query", rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+                request_id: current_request_id(),
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Fallback for unknown routes.
+pub async fn not_found_handler() -> ApiError {
+    ApiError::not_found("No such endpoint")
+}
+
+/// Fallback for known routes called with the wrong method.
+pub async fn method_not_allowed_handler() -> ApiError {
+    ApiError::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "method_not_allowed",
+        "Method not allowed for this endpoint",
+    )
+}
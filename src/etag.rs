@@ -0,0 +1,55 @@
+use axum::http::header::{ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+
+/// Weak entity tag for a response body. Weak because the same tag is sent
+/// for the compressed and uncompressed encodings.
+pub fn etag(body: &[u8]) -> HeaderValue {
+    let digest = Sha256::digest(body);
+    HeaderValue::from_str(&format!("W/\"{}\"", hex::encode(&digest[..16])))
+        .expect("hex is a valid header value")
+}
+
+/// Whether `If-None-Match` lists `etag` (or `*`), using the weak
+/// comparison required for `If-None-Match`.
+pub fn matches_if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Renders a JSON body with an `ETag`, or an empty `304 Not Modified` if
+/// the client already has it. Only successful responses are revalidated.
+pub fn json_with_etag(
+    headers: &HeaderMap,
+    status: StatusCode,
+    body: Vec<u8>,
+    etag: HeaderValue,
+) -> Response {
+    if status == StatusCode::OK && matches_if_none_match(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+
+    (
+        status,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::dstack::GpuInfo;
+use crate::history::{append_line, load_lines, rewrite, unix_timestamp};
+
+pub const GPU_CHANGES_FILE: &str = "gpu_changes.jsonl";
+
+/// Number of GPU changes kept for `/gpus/changes`.
+const MAX_ENTRIES: usize = 1000;
+const CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuChangeKind {
+    /// A slot appeared in `ListGpus`.
+    Added,
+    /// A slot disappeared from `ListGpus`, e.g. the GPU fell off the bus.
+    Removed,
+    /// A GPU became free.
+    Freed,
+    /// A GPU was taken by a CVM.
+    Allocated,
+    /// A GPU started reporting ECC or XID errors.
+    Faulted,
+    /// A GPU's fault cleared.
+    Recovered,
+}
+
+impl fmt::Display for GpuChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Freed => "freed",
+            Self::Allocated => "allocated",
+            Self::Faulted => "faulted",
+            Self::Recovered => "recovered",
+        })
+    }
+}
+
+/// A difference between two consecutive GPU inventories.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GpuChange {
+    pub timestamp: u64,
+    pub kind: GpuChangeKind,
+    pub slot: String,
+    pub product_id: String,
+    pub description: String,
+    /// What the fault is, for `faulted` changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl GpuChange {
+    fn new(timestamp: u64, kind: GpuChangeKind, gpu: &GpuInfo) -> Self {
+        Self {
+            timestamp,
+            kind,
+            slot: gpu.slot.clone(),
+            product_id: gpu.product_id.clone(),
+            description: gpu.description.clone(),
+            detail: None,
+        }
+    }
+}
+
+/// Lists what changed between `previous` and `current`, matching GPUs by
+/// slot: removals first, then additions and `is_free` changes in the order
+/// of `current`.
+pub fn diff(previous: &[GpuInfo], current: &[GpuInfo], timestamp: u64) -> Vec<GpuChange> {
+    let mut changes: Vec<GpuChange> = previous
+        .iter()
+        .filter(|gpu| !current.iter().any(|other| other.slot == gpu.slot))
+        .map(|gpu| GpuChange::new(timestamp, GpuChangeKind::Removed, gpu))
+        .collect();
+
+    for gpu in current {
+        let kind = match previous.iter().find(|other| other.slot == gpu.slot) {
+            None => GpuChangeKind::Added,
+            Some(other) if other.is_free == gpu.is_free => continue,
+            Some(_) if gpu.is_free => GpuChangeKind::Freed,
+            Some(_) => GpuChangeKind::Allocated,
+        };
+        changes.push(GpuChange::new(timestamp, kind, gpu));
+    }
+
+    changes
+}
+
+struct Inner {
+    /// Inventory from the last successful `ListGpus`, `None` until then.
+    inventory: Option<Vec<GpuInfo>>,
+    changes: VecDeque<GpuChange>,
+    lines_on_disk: usize,
+}
+
+/// GPU inventory changes seen by the background poller, stored as JSON
+/// lines under `DATA_DIR` and broadcast as they happen.
+pub struct GpuChangeLog {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+    events: broadcast::Sender<Vec<GpuChange>>,
+}
+
+impl GpuChangeLog {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join(GPU_CHANGES_FILE);
+        let (changes, lines_on_disk) = load_lines(&path, MAX_ENTRIES, "GPU change");
+        let (events, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            path,
+            inner: Mutex::new(Inner {
+                inventory: None,
+                changes,
+                lines_on_disk,
+            }),
+            events,
+        }
+    }
+
+    /// Receives every non-empty batch of changes.
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<GpuChange>> {
+        self.events.subscribe()
+    }
+
+    /// Compares a successful `ListGpus` response with the previous one,
+    /// then records, logs and broadcasts the differences. The first
+    /// inventory after startup is the baseline and produces no changes.
+    pub fn observe(&self, gpus: &[GpuInfo]) -> Vec<GpuChange> {
+        let mut inner = self.inner.lock().unwrap();
+        let changes = match inner.inventory.replace(gpus.to_vec()) {
+            Some(previous) => diff(&previous, gpus, unix_timestamp()),
+            None => return Vec::new(),
+        };
+        self.record(&mut inner, changes)
+    }
+
+    /// Records GPUs whose fault appeared (`Some`) or cleared (`None`).
+    pub fn record_faults(&self, faults: Vec<(String, Option<String>)>) -> Vec<GpuChange> {
+        let mut inner = self.inner.lock().unwrap();
+        let timestamp = unix_timestamp();
+        let changes = faults
+            .into_iter()
+            .map(|(slot, fault)| {
+                // Telemetry has no model, take it from the dstack inventory
+                let gpu = inner
+                    .inventory
+                    .iter()
+                    .flatten()
+                    .find(|gpu| gpu.slot == slot);
+                GpuChange {
+                    timestamp,
+                    kind: match fault {
+                        Some(_) => GpuChangeKind::Faulted,
+                        None => GpuChangeKind::Recovered,
+                    },
+                    product_id: gpu.map(|gpu| gpu.product_id.clone()).unwrap_or_default(),
+                    description: gpu.map(|gpu| gpu.description.clone()).unwrap_or_default(),
+                    slot,
+                    detail: fault,
+                }
+            })
+            .collect();
+        self.record(&mut inner, changes)
+    }
+
+    fn record(&self, inner: &mut Inner, changes: Vec<GpuChange>) -> Vec<GpuChange> {
+        if changes.is_empty() {
+            return changes;
+        }
+
+        for change in &changes {
+            match change.kind {
+                GpuChangeKind::Removed => warn!(
+                    "GPU {} ({}) disappeared from the inventory",
+                    change.slot, change.description
+                ),
+                GpuChangeKind::Faulted => warn!(
+                    "GPU {} ({}) faulted: {}",
+                    change.slot,
+                    change.description,
+                    change.detail.as_deref().unwrap_or_default()
+                ),
+                kind => info!("GPU {} ({}) {}", change.slot, change.description, kind),
+            }
+
+            if let Err(e) = append_line(&self.path, change) {
+                error!("Failed to write GPU change to {:?}: {}", self.path, e);
+            } else {
+                inner.lines_on_disk += 1;
+            }
+            inner.changes.push_back(change.clone());
+            if inner.changes.len() > MAX_ENTRIES {
+                inner.changes.pop_front();
+            }
+        }
+
+        if inner.lines_on_disk >= MAX_ENTRIES * 2 {
+            match rewrite(&self.path, &inner.changes) {
+                Ok(()) => inner.lines_on_disk = inner.changes.len(),
+                Err(e) => error!("Failed to compact GPU changes {:?}: {}", self.path, e),
+            }
+        }
+
+        // No subscribers is not an error, the changes are still recorded
+        let _ = self.events.send(changes.clone());
+        changes
+    }
+
+    /// Returns up to `limit` changes with `timestamp >= since`, oldest first.
+    pub fn query(&self, since: u64, limit: usize) -> Vec<GpuChange> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .changes
+            .iter()
+            .filter(|change| change.timestamp >= since)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
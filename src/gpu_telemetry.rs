@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::server::AppState;
+
+/// Fields requested from `nvidia-smi --query-gpu`, in column order.
+const QUERY_FIELDS: &str = "pci.bus_id,utilization.gpu,memory.used,memory.total,\
+    temperature.gpu,power.draw,ecc.errors.corrected.volatile.total,\
+    ecc.errors.uncorrected.volatile.total";
+
+/// XIDs that indicate failing hardware rather than a misbehaving
+/// application: double-bit ECC, row remapping, NVLink, falling off the bus,
+/// contained/uncontained memory errors and GSP failures.
+const CRITICAL_XIDS: &[u32] = &[48, 61, 62, 63, 64, 74, 79, 92, 94, 95, 119, 120];
+
+/// Collection of per-GPU utilization, memory, temperature and power draw
+/// from `nvidia-smi`, alongside the dstack inventory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpuTelemetryConfig {
+    pub enabled: bool,
+    /// `nvidia-smi` binary, looked up in `PATH` unless it's a path.
+    pub nvidia_smi_path: String,
+    /// How often readings are taken.
+    pub interval_secs: u64,
+    /// Also scan the kernel log for NVIDIA XID errors.
+    pub xid_monitor: bool,
+    /// `dmesg` binary used to read the kernel log.
+    pub dmesg_path: String,
+}
+
+impl Default for GpuTelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nvidia_smi_path: "nvidia-smi".to_string(),
+            interval_secs: 15,
+            xid_monitor: false,
+            dmesg_path: "dmesg".to_string(),
+        }
+    }
+}
+
+/// One GPU's readings. A value the driver reports as unsupported is `None`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GpuTelemetry {
+    /// PCI address, in the same form as dstack's `slot`.
+    pub slot: String,
+    pub utilization_percent: Option<f64>,
+    pub memory_used_mib: Option<f64>,
+    pub memory_total_mib: Option<f64>,
+    pub temperature_celsius: Option<f64>,
+    pub power_draw_watts: Option<f64>,
+    /// Corrected ECC errors since the driver loaded.
+    pub ecc_corrected: Option<u64>,
+    /// Uncorrected ECC errors since the driver loaded.
+    pub ecc_uncorrected: Option<u64>,
+    /// XID errors for this GPU in the kernel log, with the XID monitor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xid_errors: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_xid: Option<u32>,
+    /// Why the GPU is considered unhealthy: uncorrected ECC errors or a
+    /// hardware XID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<String>,
+}
+
+impl GpuTelemetry {
+    fn update_fault(&mut self, critical_xid: Option<u32>) {
+        let mut faults = Vec::new();
+        if let Some(errors) = self.ecc_uncorrected.filter(|errors| *errors > 0) {
+            faults.push(format!("{} uncorrected ECC errors", errors));
+        }
+        if let Some(xid) = critical_xid {
+            faults.push(format!("Xid {}", xid));
+        }
+        self.fault = (!faults.is_empty()).then(|| faults.join(", "));
+    }
+}
+
+/// The most recent readings.
+#[derive(Debug, Default)]
+pub struct GpuTelemetryCache {
+    current: RwLock<Vec<GpuTelemetry>>,
+}
+
+impl GpuTelemetryCache {
+    pub fn get(&self) -> Vec<GpuTelemetry> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn set(&self, telemetry: Vec<GpuTelemetry>) {
+        *self.current.write().unwrap() = telemetry;
+    }
+}
+
+/// Runs `nvidia-smi` once and parses its readings.
+pub async fn collect(nvidia_smi_path: &str) -> Result<Vec<GpuTelemetry>, String> {
+    let output = Command::new(nvidia_smi_path)
+        .arg(format!("--query-gpu={}", QUERY_FIELDS))
+        .arg("--format=csv,noheader,nounits")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", nvidia_smi_path, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} exited with {}: {}",
+            nvidia_smi_path,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<GpuTelemetry, String> {
+    let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+    let [bus_id, utilization, memory_used, memory_total, temperature, power_draw, ecc_corrected, ecc_uncorrected] =
+        fields[..]
+    else {
+        return Err(format!("unexpected nvidia-smi line: {:?}", line));
+    };
+
+    Ok(GpuTelemetry {
+        slot: normalize_bus_id(bus_id),
+        utilization_percent: parse_reading(utilization),
+        memory_used_mib: parse_reading(memory_used),
+        memory_total_mib: parse_reading(memory_total),
+        temperature_celsius: parse_reading(temperature),
+        power_draw_watts: parse_reading(power_draw),
+        ecc_corrected: ecc_corrected.parse().ok(),
+        ecc_uncorrected: ecc_uncorrected.parse().ok(),
+        xid_errors: None,
+        last_xid: None,
+        fault: None,
+    })
+}
+
+/// XIDs logged for one GPU.
+#[derive(Debug, Default)]
+struct XidSummary {
+    count: u64,
+    last: Option<u32>,
+    last_critical: Option<u32>,
+}
+
+/// Runs `dmesg` and counts the NVIDIA XID errors in the kernel log, keyed
+/// by the `domain:bus:device` part of the PCI address.
+async fn scan_xids(dmesg_path: &str) -> Result<Vec<(String, XidSummary)>, String> {
+    let output = Command::new(dmesg_path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", dmesg_path, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} exited with {}: {}",
+            dmesg_path,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut summaries: Vec<(String, XidSummary)> = Vec::new();
+    for (device, xid) in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_xid_line)
+    {
+        let index = match summaries.iter().position(|(known, _)| *known == device) {
+            Some(index) => index,
+            None => {
+                summaries.push((device, XidSummary::default()));
+                summaries.len() - 1
+            }
+        };
+        let summary = &mut summaries[index].1;
+        summary.count += 1;
+        summary.last = Some(xid);
+        if CRITICAL_XIDS.contains(&xid) {
+            summary.last_critical = Some(xid);
+        }
+    }
+    Ok(summaries)
+}
+
+/// Parses `NVRM: Xid (PCI:0000:18:00): 79, pid=..., GPU has fallen off the
+/// bus.` into the normalized device address and the XID.
+fn parse_xid_line(line: &str) -> Option<(String, u32)> {
+    let rest = &line[line.find("NVRM: Xid (PCI:")? + "NVRM: Xid (PCI:".len()..];
+    let (device, rest) = rest.split_once("):")?;
+    let xid = rest.trim_start().split(',').next()?.trim().parse().ok()?;
+    Some((normalize_bus_id(device), xid))
+}
+
+/// `[N/A]`, `[Not Supported]` and the like become `None`.
+fn parse_reading(value: &str) -> Option<f64> {
+    value.parse().ok()
+}
+
+/// nvidia-smi reports `00000000:18:00.0`; dstack's slots use a 4-digit PCI
+/// domain and lowercase hex (`0000:18:00.0`).
+pub fn normalize_bus_id(bus_id: &str) -> String {
+    let bus_id = bus_id.to_ascii_lowercase();
+    match bus_id.split_once(':') {
+        Some((domain, rest)) => match u32::from_str_radix(domain, 16) {
+            Ok(domain) => format!("{:04x}:{}", domain, rest),
+            Err(_) => bus_id,
+        },
+        None => bus_id,
+    }
+}
+
+/// Adds XID counts to the readings and works out each GPU's fault.
+fn apply_xids(telemetry: &mut [GpuTelemetry], xids: Option<&[(String, XidSummary)]>) {
+    for gpu in telemetry {
+        let summary = xids.map(|xids| {
+            xids.iter()
+                .find(|(device, _)| gpu.slot.starts_with(device.as_str()))
+                .map(|(_, summary)| summary)
+        });
+        if let Some(summary) = summary {
+            gpu.xid_errors = Some(summary.map_or(0, |summary| summary.count));
+            gpu.last_xid = summary.and_then(|summary| summary.last);
+        }
+        gpu.update_fault(summary.flatten().and_then(|summary| summary.last_critical));
+    }
+}
+
+/// Pairs each GPU whose fault changed with its new fault (`None` once it
+/// cleared).
+fn fault_changes(
+    previous: &[GpuTelemetry],
+    current: &[GpuTelemetry],
+) -> Vec<(String, Option<String>)> {
+    current
+        .iter()
+        .filter_map(|gpu| {
+            let before = previous
+                .iter()
+                .find(|other| other.slot == gpu.slot)
+                .and_then(|other| other.fault.as_ref());
+            (before != gpu.fault.as_ref()).then(|| (gpu.slot.clone(), gpu.fault.clone()))
+        })
+        .collect()
+}
+
+/// Takes readings every `interval_secs`. A failed run clears the previous
+/// readings rather than reporting stale ones. GPUs that start or stop
+/// reporting a fault are recorded as GPU changes.
+pub async fn run_gpu_telemetry(state: Arc<AppState>, config: GpuTelemetryConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    // Log when collection starts or stops working, not on every tick
+    let mut working = None;
+    let mut xids_working = None;
+
+    loop {
+        ticker.tick().await;
+
+        match collect(&config.nvidia_smi_path).await {
+            Ok(mut telemetry) => {
+                if working != Some(true) {
+                    info!("Collecting telemetry for {} GPUs", telemetry.len());
+                }
+                working = Some(true);
+
+                let xids = if config.xid_monitor {
+                    match scan_xids(&config.dmesg_path).await {
+                        Ok(xids) => {
+                            xids_working = Some(true);
+                            Some(xids)
+                        }
+                        Err(e) => {
+                            if xids_working != Some(false) {
+                                warn!("XID monitoring unavailable: {}", e);
+                            }
+                            xids_working = Some(false);
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                apply_xids(&mut telemetry, xids.as_deref());
+
+                let faults = fault_changes(&state.gpu_telemetry.get(), &telemetry);
+                state.gpu_telemetry.set(telemetry);
+                state.gpu_changes.record_faults(faults);
+            }
+            Err(e) => {
+                if working != Some(false) {
+                    warn!("GPU telemetry unavailable: {}", e);
+                }
+                working = Some(false);
+                state.gpu_telemetry.set(Vec::new());
+            }
+        }
+    }
+}
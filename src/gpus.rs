@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::error::{ApiError, ErrorBody};
+use crate::poller::refresh_status;
+use crate::server::AppState;
+
+/// dstack prpc method that assigns a GPU to CVMs.
+pub const ATTACH_GPU_METHOD: &str = "AttachGpu";
+/// dstack prpc method that releases a GPU from CVMs.
+pub const DETACH_GPU_METHOD: &str = "DetachGpu";
+
+async fn call_gpu_method(
+    state: &Arc<AppState>,
+    method: &str,
+    slot: &str,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !state
+        .poller
+        .latest()
+        .and_then(|info| info.metadata)
+        .map(|metadata| metadata.to_structured(&state.node_type))
+        .is_some_and(|metadata| metadata.gpus.iter().any(|gpu| gpu.slot == slot))
+    {
+        return Err(ApiError::not_found(format!("Unknown GPU slot {}", slot)));
+    }
+
+    let body = serde_json::json!({ "slot": slot });
+    let response = state
+        .connection
+        .client()
+        .request(&format!("/prpc/{}?json", method), Some(&body))
+        .await
+        .map_err(|e| {
+            error!("dstack {} for GPU {} failed: {}", method, slot, e);
+            ApiError::from(e)
+        })?;
+
+    info!("dstack {} succeeded for GPU {}", method, slot);
+    refresh_status(state);
+    Ok(Json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/gpus/{slot}/attach",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("slot" = String, Path, description = "PCI slot from `ListGpus`")),
+    responses(
+        (status = 200, description = "dstack's response", body = serde_json::Value),
+        (status = 404, description = "Unknown GPU slot", body = ErrorBody),
+    )
+)]
+pub async fn attach_gpu_handler(
+    State(state): State<Arc<AppState>>,
+    Path(slot): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    call_gpu_method(&state, ATTACH_GPU_METHOD, &slot).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/gpus/{slot}/detach",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("slot" = String, Path, description = "PCI slot from `ListGpus`")),
+    responses(
+        (status = 200, description = "dstack's response", body = serde_json::Value),
+        (status = 404, description = "Unknown GPU slot", body = ErrorBody),
+    )
+)]
+pub async fn detach_gpu_handler(
+    State(state): State<Arc<AppState>>,
+    Path(slot): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    call_gpu_method(&state, DETACH_GPU_METHOD, &slot).await
+}
@@ -0,0 +1,92 @@
+//! gRPC status service on `GRPC_LISTEN_ADDR`, mirroring `GET /health`,
+//! the dstack GPU inventory and the status streams of the HTTP API. Built
+//! with the `grpc` feature.
+
+// tonic's handlers return `Result<_, tonic::Status>`, which is large
+#![allow(clippy::result_large_err)]
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::dstack::fetch_dstack_data;
+use crate::poller::latest_health;
+use crate::proto;
+use crate::proto::worker_status_server::{WorkerStatus, WorkerStatusServer};
+use crate::server::AppState;
+
+pub struct WorkerStatusService {
+    state: Arc<AppState>,
+}
+
+impl WorkerStatusService {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+type HealthStream = Pin<Box<dyn Stream<Item = Result<proto::Health, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl WorkerStatus for WorkerStatusService {
+    async fn get_health(
+        &self,
+        _request: Request<proto::GetHealthRequest>,
+    ) -> Result<Response<proto::Health>, Status> {
+        let info = latest_health(&self.state).await;
+        Ok(Response::new(proto::Health::from_info(
+            info,
+            &self.state.node_type,
+        )))
+    }
+
+    async fn list_gpus(
+        &self,
+        _request: Request<proto::ListGpusRequest>,
+    ) -> Result<Response<proto::ListGpusResponse>, Status> {
+        let response = self
+            .state
+            .breaker
+            .call(fetch_dstack_data(self.state.connection.client().as_ref()))
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(response.into()))
+    }
+
+    type WatchStatusStream = HealthStream;
+
+    /// Sends the latest snapshot, then every change the poller broadcasts.
+    async fn watch_status(
+        &self,
+        _request: Request<proto::WatchStatusRequest>,
+    ) -> Result<Response<Self::WatchStatusStream>, Status> {
+        // Subscribe first so a change between the two isn't missed
+        let changes = BroadcastStream::new(self.state.poller.subscribe());
+        let initial = tokio_stream::iter(self.state.poller.latest());
+
+        let state = self.state.clone();
+        let stream = initial
+            .chain(changes.filter_map(|change| match change {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    warn!("gRPC status watcher lagged: {}", e);
+                    None
+                }
+            }))
+            .map(move |info| Ok(proto::Health::from_info(info, &state.node_type)));
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves the status service on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, state: Arc<AppState>) -> Result<(), tonic::transport::Error> {
+    info!("gRPC status service listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(WorkerStatusServer::new(WorkerStatusService::new(state)))
+        .serve(addr)
+        .await
+}
@@ -0,0 +1,414 @@
+use enum_tools::EnumTools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Instant;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::addresses::Address;
+use crate::bandwidth::BandwidthResult;
+use crate::dstack::{fetch_dstack_data, GpuInfo};
+use crate::dstack_info::DstackInfo;
+use crate::gpu_telemetry::GpuTelemetry;
+use crate::history::{unix_timestamp, HealthRecord, HealthStats};
+use crate::host::{self, HostMetrics};
+use crate::maintenance::Maintenance;
+use crate::ownership::OwnershipProof;
+use crate::score::{capability_score, CapabilityScore};
+use crate::server::AppState;
+
+/// Role of the worker's own signing key in [`PubkeyRole`] lists.
+pub const WORKER_ROLE: &str = "worker";
+
+/// A public key (hex) associated with the worker and what it's used for,
+/// e.g. a cold identity key or a team member's key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PubkeyRole {
+    pub pubkey: String,
+    pub role: String,
+}
+
+/// Parses a comma-separated `role:pubkey` list (e.g. from
+/// `NOSTR_EXTRA_PUBKEYS`), dropping empty entries.
+pub fn parse_pubkey_roles(value: &str) -> Result<Vec<PubkeyRole>, String> {
+    value
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (role, pubkey) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("expected role:pubkey, got {:?}", entry))?;
+            Ok(PubkeyRole {
+                pubkey: pubkey.trim().to_string(),
+                role: role.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BackendInfo {
+    pub version: String,
+    pub topic: String,
+    pub pubkeys: HashSet<String>,
+    /// Every key in `pubkeys` with its role, the worker key first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pubkey_roles: Vec<PubkeyRole>,
+    pub status: DephyWorkerRespondedStatus,
+    pub metadata: Option<Metadata>,
+    /// The advertised address if one is configured, `local_ip` otherwise.
+    pub ip_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_ip: Option<String>,
+    /// Address seen from outside the worker's NAT, with public IP discovery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<Address>,
+    /// Readings from the GPU telemetry collector, when enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gpu_telemetry: Vec<GpuTelemetry>,
+    /// Composite score for ranking workers, while dstack is reachable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capability: Option<CapabilityScore>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<HealthStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<HostMetrics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_proof: Option<OwnershipProof>,
+    /// Whether the worker is on the published whitelist, once one was seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelisted: Option<bool>,
+    /// Whether the owner's stake meets the minimum, once it was checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stake_ok: Option<bool>,
+    /// The owner's staked balance in the token's smallest unit, as a
+    /// decimal string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staked_amount: Option<String>,
+    /// Whether the worker is banned in the registry contract, once followed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banned: Option<bool>,
+}
+
+impl BackendInfo {
+    /// Replaces structured metadata with the legacy string, for
+    /// `LEGACY_METADATA` consumers.
+    pub fn with_legacy_metadata(self) -> Self {
+        let metadata = self.metadata.map(|metadata| match metadata {
+            Metadata::Structured(metadata) => Metadata::Legacy(metadata.to_legacy_string()),
+            legacy => legacy,
+        });
+        Self { metadata, ..self }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, EnumTools, ToSchema)]
+#[enum_tools(Debug, Display, FromStr, TryFrom, Into)]
+#[repr(i32)]
+pub enum DephyWorkerRespondedStatus {
+    Available = 1,
+    Unavailable = 2,
+    /// dstack is reachable but one of the configured health rules failed.
+    Degraded = 3,
+    /// The operator drained the worker to take it out of scheduling.
+    Maintenance = 4,
+}
+
+impl DephyWorkerRespondedStatus {
+    /// Whether the worker can take work: `/health` answers `200` for these
+    /// and `503` otherwise.
+    pub fn is_serving(self) -> bool {
+        matches!(
+            self,
+            DephyWorkerRespondedStatus::Available | DephyWorkerRespondedStatus::Degraded
+        )
+    }
+}
+
+/// Structured description of the worker's GPU inventory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct WorkerMetadata {
+    pub node_type: String,
+    pub gpu_count: usize,
+    pub free_gpu_count: usize,
+    pub gpus: Vec<GpuInfo>,
+    pub allow_attach_all: Option<bool>,
+    /// GPUs leased to a scheduler, reported as not free until the lease
+    /// ends.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reserved_gpus: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Machine-readable class of the first error, see [`crate::error::BackendError::code`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degraded_reasons: Vec<String>,
+    /// Last known dstack version, OS image and KMS, kept while dstack is
+    /// unreachable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dstack: Option<Box<DstackInfo>>,
+    /// Latest bandwidth self-test result, when enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<Box<BandwidthResult>>,
+    /// Set while the worker is drained.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
+}
+
+impl WorkerMetadata {
+    /// Renders the metadata the way it was reported before it was typed: a
+    /// JSON string on success and `Error: ...` on failure.
+    pub fn to_legacy_string(&self) -> String {
+        if let Some(error) = self.errors.first() {
+            return format!("Error: {}", error);
+        }
+
+        serde_json::json!({
+            "gpu_count": self.gpu_count,
+            "gpus": self.gpus,
+            "allow_attach_all": self.allow_attach_all.unwrap_or_default()
+        })
+        .to_string()
+    }
+}
+
+/// `BackendInfo.metadata` as a nested object, or as the legacy stringified
+/// JSON for consumers that still double-parse it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum Metadata {
+    Structured(WorkerMetadata),
+    Legacy(String),
+}
+
+impl Metadata {
+    /// Recovers the structured metadata, parsing it back out of the legacy
+    /// string if needed.
+    pub fn to_structured(&self, node_type: &str) -> WorkerMetadata {
+        let metadata = match self {
+            Metadata::Structured(metadata) => return metadata.clone(),
+            Metadata::Legacy(metadata) => metadata,
+        };
+
+        if let Some(error) = metadata.strip_prefix("Error: ") {
+            return WorkerMetadata {
+                node_type: node_type.to_string(),
+                errors: vec![error.to_string()],
+                ..Default::default()
+            };
+        }
+
+        #[derive(Deserialize)]
+        struct LegacyMetadata {
+            gpus: Vec<GpuInfo>,
+            allow_attach_all: bool,
+        }
+
+        match serde_json::from_str::<LegacyMetadata>(metadata) {
+            Ok(legacy) => WorkerMetadata {
+                node_type: node_type.to_string(),
+                gpu_count: legacy.gpus.len(),
+                free_gpu_count: legacy.gpus.iter().filter(|gpu| gpu.is_free).count(),
+                gpus: legacy.gpus,
+                allow_attach_all: Some(legacy.allow_attach_all),
+                ..Default::default()
+            },
+            Err(e) => WorkerMetadata {
+                node_type: node_type.to_string(),
+                errors: vec![format!("Unreadable metadata: {}", e)],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Queries dstack and returns the worker status with its structured metadata.
+/// Fails fast while the circuit breaker is open.
+pub async fn check_worker_health(state: &AppState) -> (DephyWorkerRespondedStatus, WorkerMetadata) {
+    let (status, metadata, _) = query_dstack(state, false).await;
+    apply_overrides(state, status, metadata)
+}
+
+/// Asks dstack for the GPUs, as the breaker's probe with `probe`, and
+/// returns what it reported with the request's latency.
+async fn query_dstack(
+    state: &AppState,
+    probe: bool,
+) -> (DephyWorkerRespondedStatus, WorkerMetadata, u64) {
+    let started = Instant::now();
+    let client = state.connection.client();
+    let fetch = fetch_dstack_data(client.as_ref());
+    let result = if probe {
+        state.breaker.probe(fetch).await
+    } else {
+        state.breaker.call(fetch).await
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (status, metadata) = match result {
+        Ok(mut dstack_data) => {
+            info!("dstack is available with {} GPUs", dstack_data.gpus.len());
+
+            let reserved_gpus = state.leases.mark_reserved(&mut dstack_data.gpus);
+            let mut metadata = WorkerMetadata {
+                node_type: state.node_type.clone(),
+                gpu_count: dstack_data.gpus.len(),
+                free_gpu_count: dstack_data.gpus.iter().filter(|gpu| gpu.is_free).count(),
+                gpus: dstack_data.gpus,
+                allow_attach_all: Some(dstack_data.allow_attach_all),
+                reserved_gpus,
+                dstack: state.dstack_info.get().map(Box::new),
+                bandwidth: state.bandwidth.get().map(Box::new),
+                ..Default::default()
+            };
+
+            metadata.degraded_reasons = state
+                .reloadable
+                .read()
+                .unwrap()
+                .health_rules
+                .evaluate(&metadata, latency_ms);
+            if metadata.degraded_reasons.is_empty() {
+                (DephyWorkerRespondedStatus::Available, metadata)
+            } else {
+                warn!(
+                    "dstack is degraded: {}",
+                    metadata.degraded_reasons.join(", ")
+                );
+                (DephyWorkerRespondedStatus::Degraded, metadata)
+            }
+        }
+        Err(e) => {
+            error!("Failed to connect to dstack: {}", e);
+
+            let metadata = WorkerMetadata {
+                node_type: state.node_type.clone(),
+                errors: vec![e.to_string()],
+                error_code: Some(e.code().to_string()),
+                dstack: state.dstack_info.get().map(Box::new),
+                bandwidth: state.bandwidth.get().map(Box::new),
+                ..Default::default()
+            };
+
+            (DephyWorkerRespondedStatus::Unavailable, metadata)
+        }
+    };
+
+    (status, metadata, latency_ms)
+}
+
+/// Reports a banned or drained worker as such, whatever dstack says.
+fn apply_overrides(
+    state: &AppState,
+    status: DephyWorkerRespondedStatus,
+    metadata: WorkerMetadata,
+) -> (DephyWorkerRespondedStatus, WorkerMetadata) {
+    // A worker banned on-chain gets no work, so it stops advertising itself
+    // as available
+    let ban_reason = state
+        .registry
+        .as_ref()
+        .and_then(|registry| registry.ban_reason());
+    let (status, metadata) = match ban_reason {
+        Some(reason) if status != DephyWorkerRespondedStatus::Unavailable => {
+            let mut metadata = metadata;
+            metadata
+                .errors
+                .push(format!("Worker is banned on-chain: {}", reason));
+            metadata.error_code = Some("banned".to_string());
+            (DephyWorkerRespondedStatus::Unavailable, metadata)
+        }
+        _ => (status, metadata),
+    };
+
+    // A drained worker reports Maintenance whatever dstack says. The history
+    // keeps what dstack said, so availability stays meaningful.
+    match state.maintenance.get() {
+        Some(maintenance) => (
+            DephyWorkerRespondedStatus::Maintenance,
+            WorkerMetadata {
+                maintenance: Some(maintenance),
+                ..metadata
+            },
+        ),
+        None => (status, metadata),
+    }
+}
+
+pub async fn check_dstack_health(state: &AppState) -> BackendInfo {
+    let (status, metadata) = check_worker_health(state).await;
+    backend_info(state, status, metadata)
+}
+
+/// The poller's check, which is also the one that probes dstack once the
+/// circuit breaker's reset timeout has passed. Only these checks are
+/// recorded in the history, so it has one entry per poll interval.
+pub async fn poll_dstack_health(state: &AppState) -> BackendInfo {
+    let (status, metadata, latency_ms) = query_dstack(state, true).await;
+    state
+        .history
+        .record(HealthRecord {
+            timestamp: unix_timestamp(),
+            status,
+            gpu_count: metadata.gpu_count,
+            free_gpu_count: metadata.free_gpu_count,
+            latency_ms,
+            error: metadata.errors.first().cloned(),
+        })
+        .await;
+    let (status, metadata) = apply_overrides(state, status, metadata);
+    backend_info(state, status, metadata)
+}
+
+fn backend_info(
+    state: &AppState,
+    status: DephyWorkerRespondedStatus,
+    metadata: WorkerMetadata,
+) -> BackendInfo {
+    let pubkeys = state
+        .pubkeys
+        .iter()
+        .map(|pubkey| pubkey.pubkey.clone())
+        .collect();
+
+    let host = host::collect(&state.data_dir);
+    // Without an inventory there's nothing meaningful to score
+    let capability = metadata.error_code.is_none().then(|| {
+        capability_score(
+            &state.node_types,
+            &metadata.gpus,
+            host.memory_total_bytes,
+            state.bandwidth.get().as_ref(),
+        )
+    });
+
+    let stake = state.stake.get();
+    BackendInfo {
+        version: "1.0.0".to_string(),
+        topic: state.topic.clone(),
+        pubkeys,
+        pubkey_roles: state.pubkeys.clone(),
+        status,
+        metadata: Some(Metadata::Structured(metadata)),
+        ip_address: state.ip_address(),
+        local_ip: state.local_ip.clone(),
+        public_ip: state.public_ip.get().map(|ip| ip.to_string()),
+        addresses: state.addresses(),
+        gpu_telemetry: state.gpu_telemetry.get(),
+        capability,
+        stats: Some(state.history.stats(state.history_max_gap())),
+        host: Some(host),
+        owner_proof: state.owner_proof.clone(),
+        whitelisted: state.whitelist.get(),
+        stake_ok: stake.map(|stake| stake.ok),
+        staked_amount: stake.map(|stake| stake.staked.to_string()),
+        banned: state
+            .registry
+            .as_ref()
+            .and_then(|registry| registry.banned()),
+    }
+}
@@ -0,0 +1,98 @@
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::addresses::Address;
+use crate::health::{check_worker_health, DephyWorkerRespondedStatus, PubkeyRole, WorkerMetadata};
+use crate::server::AppState;
+
+/// Addressable event kind used for worker heartbeats. Relays only keep the
+/// latest event per worker, so consumers can read current liveness directly.
+pub const HEARTBEAT_KIND: u16 = 31573;
+
+#[derive(Debug, Serialize)]
+pub struct HeartbeatContent {
+    pub status: DephyWorkerRespondedStatus,
+    pub pubkeys: Vec<PubkeyRole>,
+    pub node_type: String,
+    pub metadata: WorkerMetadata,
+    pub ip_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<String>,
+    pub addresses: Vec<Address>,
+}
+
+pub async fn build_heartbeat(
+    state: &AppState,
+    signer: &Arc<dyn NostrSigner>,
+    interval: Duration,
+) -> Result<Event, Box<dyn std::error::Error>> {
+    let (status, metadata) = check_worker_health(state).await;
+
+    let content = HeartbeatContent {
+        status,
+        pubkeys: state.pubkeys.clone(),
+        node_type: state.node_type.clone(),
+        metadata,
+        ip_address: state.ip_address(),
+        public_ip: state.public_ip.get().map(|ip| ip.to_string()),
+        addresses: state.addresses(),
+    };
+
+    // Expire heartbeats after a few missed intervals so stale workers drop out
+    let expiration = Timestamp::now() + interval * 3;
+
+    let event = EventBuilder::new(
+        Kind::Custom(HEARTBEAT_KIND),
+        serde_json::to_string(&content)?,
+    )
+    .tags([
+        Tag::identifier("heartbeat"),
+        Tag::hashtag(state.topic.as_str()),
+        Tag::expiration(expiration),
+    ])
+    .sign(signer)
+    .await?;
+
+    Ok(event)
+}
+
+/// Periodically publishes a signed heartbeat event to all connected relays.
+pub async fn run_heartbeat(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = state.maintenance.notice_requested() => info!("Publishing drain status change"),
+        }
+
+        let event = match build_heartbeat(&state, &signer, interval).await {
+            Ok(event) => event,
+            Err(e) => {
+                error!("Failed to build heartbeat event: {}", e);
+                continue;
+            }
+        };
+
+        match client.send_event(event).await {
+            Ok(output) => {
+                state.relay_stats.record(&output.success, &output.failed);
+                info!(
+                    "Published heartbeat {} to {} relays ({} failed)",
+                    output.val,
+                    output.success.len(),
+                    output.failed.len()
+                )
+            }
+            Err(e) => error!("Failed to publish heartbeat: {}", e),
+        }
+    }
+}
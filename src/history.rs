@@ -0,0 +1,243 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::health::DephyWorkerRespondedStatus;
+
+pub const HISTORY_FILE: &str = "health_history.jsonl";
+
+/// Outcome of a single dstack health check.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthRecord {
+    pub timestamp: u64,
+    pub status: DephyWorkerRespondedStatus,
+    pub gpu_count: usize,
+    pub free_gpu_count: usize,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Uptime and availability derived from the health history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HealthStats {
+    /// Unix time the backend process started.
+    pub started_at: u64,
+    /// Health checks performed since the process started.
+    pub checks: u64,
+    /// Checks in a row, up to the latest one, that found dstack unavailable.
+    pub consecutive_failures: u64,
+    /// Percentage of the last 24 hours dstack was available (`Available` or
+    /// `Degraded`), weighted by the time each check stands for, if the
+    /// history covers any of it.
+    pub availability_24h: Option<f64>,
+    /// The same over the last 7 days, limited to the retained history.
+    pub availability_7d: Option<f64>,
+}
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Time covered by the health history over a period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Uptime {
+    pub checks: u64,
+    /// Seconds the history covers.
+    pub observed_secs: u64,
+    /// Seconds of those dstack was available (`Available` or `Degraded`).
+    pub available_secs: u64,
+}
+
+struct Inner {
+    records: VecDeque<HealthRecord>,
+    lines_on_disk: usize,
+    checks: u64,
+}
+
+/// Append-only health check log stored as JSON lines under `DATA_DIR`.
+/// The most recent `max_entries` records are kept in memory for queries and
+/// the file is compacted once it grows to twice that size.
+pub struct HealthHistory {
+    path: PathBuf,
+    max_entries: usize,
+    started_at: u64,
+    inner: Mutex<Inner>,
+}
+
+impl HealthHistory {
+    pub fn open(data_dir: &Path, max_entries: usize) -> Self {
+        let path = data_dir.join(HISTORY_FILE);
+        let (records, lines_on_disk) = load_lines(&path, max_entries, "health history");
+
+        Self {
+            path,
+            max_entries,
+            started_at: unix_timestamp(),
+            inner: Mutex::new(Inner {
+                records,
+                lines_on_disk,
+                checks: 0,
+            }),
+        }
+    }
+
+    /// Adds a check to the history. The file is written on the blocking
+    /// pool, compacting it once it holds twice `max_entries` lines.
+    pub async fn record(&self, record: HealthRecord) {
+        let compact = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.checks += 1;
+            inner.records.push_back(record.clone());
+            if inner.records.len() > self.max_entries {
+                inner.records.pop_front();
+            }
+            (inner.lines_on_disk + 1 >= self.max_entries * 2).then(|| inner.records.clone())
+        };
+
+        let path = self.path.clone();
+        let written = tokio::task::spawn_blocking(move || match compact {
+            Some(records) => rewrite(&path, &records).map(|()| Some(records.len())),
+            None => append_line(&path, &record).map(|()| None),
+        })
+        .await;
+
+        let mut inner = self.inner.lock().unwrap();
+        match written {
+            Ok(Ok(Some(lines))) => inner.lines_on_disk = lines,
+            Ok(Ok(None)) => inner.lines_on_disk += 1,
+            Ok(Err(e)) => error!("Failed to write health history to {:?}: {}", self.path, e),
+            Err(e) => error!("Health history writer failed: {}", e),
+        }
+    }
+
+    /// Returns up to `limit` records with `timestamp >= since`, oldest first.
+    pub fn query(&self, since: u64, limit: usize) -> Vec<HealthRecord> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .records
+            .iter()
+            .filter(|record| record.timestamp >= since)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl HealthHistory {
+    /// Stats over the history, where each check stands for at most
+    /// `max_gap` seconds as in [`uptime`](Self::uptime).
+    pub fn stats(&self, max_gap: u64) -> HealthStats {
+        let inner = self.inner.lock().unwrap();
+        let now = unix_timestamp();
+
+        let consecutive_failures = inner
+            .records
+            .iter()
+            .rev()
+            .take_while(|record| record.status == DephyWorkerRespondedStatus::Unavailable)
+            .count() as u64;
+        let availability = |window: u64| {
+            let uptime = uptime(&inner.records, now.saturating_sub(window), now, max_gap);
+            (uptime.observed_secs > 0)
+                .then(|| uptime.available_secs as f64 * 100.0 / uptime.observed_secs as f64)
+        };
+
+        HealthStats {
+            started_at: self.started_at,
+            checks: inner.checks,
+            consecutive_failures,
+            availability_24h: availability(DAY_SECS),
+            availability_7d: availability(7 * DAY_SECS),
+        }
+    }
+
+    /// Uptime since `since`. Each check covers the time until the next one
+    /// (or now), but at most `max_gap` seconds.
+    pub fn uptime(&self, since: u64, max_gap: u64) -> Uptime {
+        let inner = self.inner.lock().unwrap();
+        uptime(&inner.records, since, unix_timestamp(), max_gap)
+    }
+}
+
+fn uptime(records: &VecDeque<HealthRecord>, since: u64, now: u64, max_gap: u64) -> Uptime {
+    let records: Vec<&HealthRecord> = records
+        .iter()
+        .filter(|record| record.timestamp >= since)
+        .collect();
+
+    let mut uptime = Uptime::default();
+    for (i, record) in records.iter().enumerate() {
+        let next = records.get(i + 1).map_or(now, |next| next.timestamp);
+        let covered = next.saturating_sub(record.timestamp).min(max_gap);
+        uptime.checks += 1;
+        uptime.observed_secs += covered;
+        if record.status != DephyWorkerRespondedStatus::Unavailable {
+            uptime.available_secs += covered;
+        }
+    }
+    uptime
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reads the last `max_entries` records of a JSON lines file, skipping
+/// corrupt lines. Also returns the number of lines in the file.
+pub(crate) fn load_lines<T: DeserializeOwned>(
+    path: &Path,
+    max_entries: usize,
+    what: &str,
+) -> (VecDeque<T>, usize) {
+    let mut records = VecDeque::new();
+    let mut lines_on_disk = 0;
+
+    if let Ok(file) = File::open(path) {
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            lines_on_disk += 1;
+            match serde_json::from_str(&line) {
+                Ok(record) => {
+                    records.push_back(record);
+                    if records.len() > max_entries {
+                        records.pop_front();
+                    }
+                }
+                Err(e) => warn!("Skipping corrupt {} line: {}", what, e),
+            }
+        }
+        info!("Loaded {} {} records from {:?}", records.len(), what, path);
+    }
+
+    (records, lines_on_disk)
+}
+
+/// Appends `record` to a JSON lines file, creating it if needed.
+pub(crate) fn append_line<T: Serialize>(path: &Path, record: &T) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+}
+
+/// Replaces a JSON lines file with `records`.
+pub(crate) fn rewrite<T: Serialize>(path: &Path, records: &VecDeque<T>) -> std::io::Result<()> {
+    // Write to a temporary file first so a crash never truncates the history
+    let tmp_path = path.with_extension("jsonl.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        for record in records {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(tmp_path, path)
+}
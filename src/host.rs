@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use utoipa::ToSchema;
+
+/// Host CPU, memory and disk capacity, so CPU-only nodes report more than
+/// an empty GPU list. Values that can't be read are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HostMetrics {
+    /// CPU cores available to the backend.
+    pub cpu_cores: Option<usize>,
+    /// 1, 5 and 15 minute load averages.
+    pub load_average: Option<[f64; 3]>,
+    pub memory_total_bytes: Option<u64>,
+    pub memory_available_bytes: Option<u64>,
+    /// Size of the filesystem holding `DATA_DIR`.
+    pub disk_total_bytes: Option<u64>,
+    /// Space left on that filesystem for unprivileged users.
+    pub disk_free_bytes: Option<u64>,
+}
+
+/// Reads the current host metrics. Cheap enough to call per request.
+pub fn collect(data_dir: &Path) -> HostMetrics {
+    let (memory_total_bytes, memory_available_bytes) = memory();
+    let (disk_total_bytes, disk_free_bytes) = disk(data_dir).unzip();
+
+    HostMetrics {
+        cpu_cores: std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .ok(),
+        load_average: load_average(),
+        memory_total_bytes,
+        memory_available_bytes,
+        disk_total_bytes,
+        disk_free_bytes,
+    }
+}
+
+fn load_average() -> Option<[f64; 3]> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    let mut fields = loadavg.split_whitespace().map(|field| field.parse().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+/// `MemTotal` and `MemAvailable` from `/proc/meminfo`, in bytes.
+fn memory() -> (Option<u64>, Option<u64>) {
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .map(|kib: u64| kib * 1024)
+    };
+    (field("MemTotal"), field("MemAvailable"))
+}
+
+/// Total and available bytes on the filesystem holding `path`.
+fn disk(path: &Path) -> Option<(u64, u64)> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let block_size = stat.f_frsize as u64;
+    Some((
+        stat.f_blocks as u64 * block_size,
+        stat.f_bavail as u64 * block_size,
+    ))
+}
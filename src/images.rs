@@ -0,0 +1,387 @@
+//! Container image prefetch. `POST /images/prefetch` pulls images ahead of
+//! job scheduling, so large CUDA images don't add minutes to every job's
+//! cold start. Images are pulled through a Docker-compatible API socket
+//! (`IMAGE_PULL_SOCKET`), or through dstack's `PullImage` method without
+//! one. Progress is published as `image_prefetch` events on
+//! `/health/stream`.
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use hyper_util::client::legacy::Client;
+use hyperlocal::{UnixClientExt, UnixConnector, Uri as UnixUri};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+
+/// dstack prpc method that pulls an image on the host.
+pub const PULL_IMAGE_METHOD: &str = "PullImage";
+
+const MAX_IMAGES: usize = 16;
+
+/// How often a pull's progress is published at most.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pulls still running after this are failed.
+const PULL_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefetchState {
+    Pulling,
+    Done,
+    Failed,
+}
+
+/// The latest progress of an image's pull.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PrefetchProgress {
+    pub image: String,
+    pub state: PrefetchState,
+    /// Layers downloaded or already present, of `layers_total`. Only known
+    /// for pulls through the socket, like the byte counts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers_done: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers_total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloaded_bytes: Option<u64>,
+    /// Size of the layers whose size is known so far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PrefetchProgress {
+    fn started(image: &str) -> Self {
+        Self {
+            image: image.to_string(),
+            state: PrefetchState::Pulling,
+            layers_done: None,
+            layers_total: None,
+            downloaded_bytes: None,
+            total_bytes: None,
+            started_at: unix_timestamp(),
+            finished_at: None,
+            error: None,
+        }
+    }
+}
+
+/// Pulls images in the background and keeps the latest progress of each,
+/// broadcasting every update.
+pub struct ImagePrefetcher {
+    socket: Option<PathBuf>,
+    pulls: RwLock<BTreeMap<String, PrefetchProgress>>,
+    events: broadcast::Sender<PrefetchProgress>,
+}
+
+impl ImagePrefetcher {
+    pub fn new(socket: Option<PathBuf>) -> Self {
+        let (events, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            socket,
+            pulls: RwLock::new(BTreeMap::new()),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PrefetchProgress> {
+        self.events.subscribe()
+    }
+
+    /// The latest progress of every image pulled since startup.
+    pub fn list(&self) -> Vec<PrefetchProgress> {
+        self.pulls.read().unwrap().values().cloned().collect()
+    }
+
+    /// Records a new pull of `image`, unless one is already running.
+    /// Returns the pull's progress and whether it is new.
+    fn begin(&self, image: &str) -> (PrefetchProgress, bool) {
+        let mut pulls = self.pulls.write().unwrap();
+        if let Some(pull) = pulls.get(image) {
+            if pull.state == PrefetchState::Pulling {
+                return (pull.clone(), false);
+            }
+        }
+        let progress = PrefetchProgress::started(image);
+        pulls.insert(image.to_string(), progress.clone());
+        // No subscribers is not an error
+        let _ = self.events.send(progress.clone());
+        (progress, true)
+    }
+
+    fn update(&self, progress: PrefetchProgress) {
+        self.pulls
+            .write()
+            .unwrap()
+            .insert(progress.image.clone(), progress.clone());
+        let _ = self.events.send(progress);
+    }
+}
+
+/// Pulls `image` and records how it went.
+async fn prefetch(state: Arc<AppState>, progress: PrefetchProgress) {
+    let image = progress.image.clone();
+    let prefetcher = &state.images;
+    let result = match &prefetcher.socket {
+        Some(socket) => {
+            let mut published = Instant::now();
+            let pulled = pull_from_socket(socket, &image, |layers| {
+                if published.elapsed() >= PROGRESS_INTERVAL {
+                    published = Instant::now();
+                    prefetcher.update(layers.apply(progress.clone()));
+                }
+            });
+            match tokio::time::timeout(PULL_TIMEOUT, pulled).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("pull timed out after {:?}", PULL_TIMEOUT)),
+            }
+        }
+        None => state
+            .connection
+            .client()
+            // Pulling an image that is already there is a no-op
+            .call_retrying(
+                &format!("/prpc/{}?json", PULL_IMAGE_METHOD),
+                Some(&serde_json::json!({ "image": image })),
+            )
+            .await
+            .map(|_| Layers::default())
+            .map_err(|e| format!("dstack {} failed: {}", PULL_IMAGE_METHOD, e)),
+    };
+
+    let finished = match result {
+        Ok(layers) => {
+            info!("Prefetched image {}", image);
+            PrefetchProgress {
+                state: PrefetchState::Done,
+                finished_at: Some(unix_timestamp()),
+                ..layers.apply(progress)
+            }
+        }
+        Err(e) => {
+            warn!("Failed to prefetch image {}: {}", image, e);
+            PrefetchProgress {
+                state: PrefetchState::Failed,
+                finished_at: Some(unix_timestamp()),
+                error: Some(e),
+                ..progress
+            }
+        }
+    };
+    prefetcher.update(finished);
+}
+
+/// A line of the Docker Engine API's pull progress stream.
+#[derive(Debug, Deserialize)]
+struct PullMessage {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default, rename = "progressDetail")]
+    progress_detail: Option<ProgressDetail>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProgressDetail {
+    #[serde(default)]
+    current: u64,
+    #[serde(default)]
+    total: u64,
+}
+
+#[derive(Debug, Default)]
+struct Layer {
+    downloaded: u64,
+    size: u64,
+    done: bool,
+}
+
+/// Download progress by layer ID.
+#[derive(Debug, Default)]
+struct Layers(BTreeMap<String, Layer>);
+
+impl Layers {
+    fn observe(&mut self, message: &PullMessage) {
+        // The first line's ID is the tag being pulled, not a layer
+        let Some(id) = &message.id else { return };
+        if message.status.starts_with("Pulling from") {
+            return;
+        }
+        let layer = self.0.entry(id.clone()).or_default();
+        match message.status.as_str() {
+            "Downloading" => {
+                if let Some(detail) = &message.progress_detail {
+                    layer.downloaded = detail.current;
+                    layer.size = detail.total;
+                }
+            }
+            "Download complete" => layer.downloaded = layer.size,
+            "Pull complete" | "Already exists" => {
+                layer.downloaded = layer.size;
+                layer.done = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// `progress` with the layer counts, for pulls that report them.
+    fn apply(&self, progress: PrefetchProgress) -> PrefetchProgress {
+        if self.0.is_empty() {
+            return progress;
+        }
+        PrefetchProgress {
+            layers_done: Some(self.0.values().filter(|layer| layer.done).count()),
+            layers_total: Some(self.0.len()),
+            downloaded_bytes: Some(self.0.values().map(|layer| layer.downloaded).sum()),
+            total_bytes: Some(self.0.values().map(|layer| layer.size).sum()),
+            ..progress
+        }
+    }
+}
+
+/// Pulls `image` with the Docker Engine API on `socket`, calling
+/// `on_progress` for each progress line.
+async fn pull_from_socket(
+    socket: &Path,
+    image: &str,
+    mut on_progress: impl FnMut(&Layers),
+) -> Result<Layers, String> {
+    let url =
+        reqwest::Url::parse_with_params("http://localhost/images/create", [("fromImage", image)])
+            .map_err(|e| format!("Invalid image {}: {}", image, e))?;
+    let path = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(hyper::Uri::from(UnixUri::new(socket, &path)))
+        .header("Host", "localhost")
+        .body(Full::new(Bytes::new()))
+        .map_err(|e| format!("Failed to build request: {}", e))?;
+    let client: Client<UnixConnector, Full<Bytes>> = Client::unix();
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| format!("request to {} failed: {}", socket.display(), e))?;
+
+    let status = response.status();
+    let mut body = response.into_body();
+    if !status.is_success() {
+        let body = body
+            .collect()
+            .await
+            .map(|body| String::from_utf8_lossy(&body.to_bytes()).trim().to_string())
+            .unwrap_or_default();
+        return Err(format!("HTTP error: {} {}", status, body));
+    }
+
+    // Progress comes as one JSON object per line, errors included
+    let mut layers = Layers::default();
+    let mut buffer = Vec::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| format!("Failed to read pull progress: {}", e))?;
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        buffer.extend_from_slice(&data);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let Ok(message) = serde_json::from_slice::<PullMessage>(&line) else {
+                continue;
+            };
+            if let Some(error) = message.error {
+                return Err(error);
+            }
+            layers.observe(&message);
+            on_progress(&layers);
+        }
+    }
+    Ok(layers)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PrefetchRequest {
+    /// Image references, e.g. `nvcr.io/nvidia/pytorch:24.08-py3`.
+    pub images: Vec<String>,
+}
+
+/// `POST /images/prefetch`: starts pulling the images in the background.
+#[utoipa::path(
+    post,
+    path = "/images/prefetch",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = PrefetchRequest,
+    responses(
+        (status = 202, description = "Pulls started, or already running", body = Vec<PrefetchProgress>),
+        (status = 400, description = "Invalid image list", body = ErrorBody),
+    )
+)]
+pub async fn prefetch_handler(
+    State(state): State<Arc<AppState>>,
+    request: Result<Json<PrefetchRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<Vec<PrefetchProgress>>), ApiError> {
+    let Json(request) = request?;
+    if request.images.is_empty() || request.images.len() > MAX_IMAGES {
+        return Err(ApiError::bad_request(format!(
+            "images must list between 1 and {} images",
+            MAX_IMAGES
+        )));
+    }
+    if let Some(image) = request
+        .images
+        .iter()
+        .find(|image| image.is_empty() || image.contains(char::is_whitespace))
+    {
+        return Err(ApiError::bad_request(format!("Invalid image {:?}", image)));
+    }
+
+    let mut pulls = Vec::new();
+    for image in &request.images {
+        let (progress, started) = state.images.begin(image);
+        if started {
+            info!("Prefetching image {}", image);
+            tokio::spawn(prefetch(state.clone(), progress.clone()));
+        }
+        pulls.push(progress);
+    }
+    Ok((StatusCode::ACCEPTED, Json(pulls)))
+}
+
+/// `GET /images/prefetch`: the progress of every prefetch since startup.
+#[utoipa::path(
+    get,
+    path = "/images/prefetch",
+    tag = "worker",
+    responses(
+        (status = 200, description = "Latest progress by image", body = Vec<PrefetchProgress>),
+    )
+)]
+pub async fn prefetch_status_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<PrefetchProgress>> {
+    Json(state.images.list())
+}
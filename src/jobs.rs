@@ -0,0 +1,678 @@
+//! Job assignments over Nostr. A coordinator in `JOB_NPUBS` sends a job
+//! event that `p`-tags the worker, with the NIP-44 encrypted assignment as
+//! content. The worker checks it against its free GPUs, runs the image in a
+//! CVM through dstack for the assigned duration and removes the CVM again.
+//! Each step is reported to the coordinator as a signed, encrypted status
+//! event.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::history::unix_timestamp;
+use crate::leases::Lease;
+use crate::poller::refresh_status;
+use crate::replay::ReplayGuard;
+use crate::server::AppState;
+use crate::vms::{deploy, list_vms, remove_vm, DeploySpec};
+
+/// Event kind of job assignments addressed to a worker.
+pub const JOB_KIND: u16 = 1576;
+
+/// Event kind of the worker's job status reports.
+pub const JOB_STATUS_KIND: u16 = 1577;
+
+/// Jobs whose CVM is running, in `DATA_DIR`.
+const JOBS_FILE: &str = "jobs.json";
+
+/// Job IDs seen within the replay window, in `DATA_DIR`.
+const SEEN_JOBS_FILE: &str = "seen_jobs.json";
+
+/// Job IDs name the CVM and, with the coordinator key, make the nonce.
+const MAX_JOB_ID_LEN: usize = 48;
+
+/// Longest image reference taken, Docker's limit.
+const MAX_IMAGE_LEN: usize = 255;
+
+const MAX_JOB_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// How often a running job is checked on and reported.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the GPUs picked for a job stay leased while its CVM is created.
+const DEPLOY_LEASE_SECS: u64 = 5 * 60;
+
+/// Decrypted content of a job event.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobAssignment {
+    /// Unique per coordinator, it is also the replay nonce.
+    pub job_id: String,
+    /// Container image to run.
+    pub image: String,
+    /// Number of GPUs to attach, picked among the free ones.
+    #[serde(default)]
+    pub gpus: usize,
+    pub vcpu: u32,
+    pub memory_mb: u32,
+    pub disk_gb: u32,
+    /// How long the job runs before its CVM is removed.
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// The assignment is invalid or the worker can't take it.
+    Rejected,
+    /// The CVM runs. Reported when it starts and then every minute.
+    Running,
+    /// The duration elapsed or the workload exited on its own.
+    Completed,
+    /// The CVM couldn't be created or disappeared.
+    Failed,
+}
+
+/// Encrypted content of a status event.
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+    /// Missing when the assignment couldn't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    pub state: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub gpus: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_secs: Option<u64>,
+    /// The CVM status dstack reports, e.g. `exited`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobStatus {
+    fn new(job_id: Option<String>, state: JobState) -> Self {
+        Self {
+            job_id,
+            state,
+            vm_id: None,
+            gpus: Vec::new(),
+            elapsed_secs: None,
+            vm_status: None,
+            error: None,
+        }
+    }
+
+    fn rejected(job_id: Option<String>, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(job_id, JobState::Rejected)
+        }
+    }
+}
+
+/// A job whose CVM was created, persisted so it is still removed on time
+/// after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RunningJob {
+    job_id: String,
+    /// The assignment, tagged in status events.
+    event_id: EventId,
+    coordinator: PublicKey,
+    vm_id: String,
+    gpus: Vec<String>,
+    started_at: u64,
+    ends_at: u64,
+}
+
+impl RunningJob {
+    fn status(&self, state: JobState) -> JobStatus {
+        JobStatus {
+            vm_id: Some(self.vm_id.clone()),
+            gpus: self.gpus.clone(),
+            elapsed_secs: Some(unix_timestamp().saturating_sub(self.started_at)),
+            ..JobStatus::new(Some(self.job_id.clone()), state)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct JobLog {
+    path: PathBuf,
+    jobs: Mutex<Vec<RunningJob>>,
+}
+
+impl JobLog {
+    fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join(JOBS_FILE);
+        let jobs = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path,
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    fn all(&self) -> Vec<RunningJob> {
+        self.jobs.lock().unwrap().clone()
+    }
+
+    fn insert(&self, job: RunningJob) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.push(job);
+        self.save(&jobs);
+    }
+
+    fn remove(&self, job_id: &str) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|job| job.job_id != job_id);
+        self.save(&jobs);
+    }
+
+    fn save(&self, jobs: &[RunningJob]) {
+        let result = serde_json::to_string_pretty(jobs)
+            .map_err(std::io::Error::from)
+            .and_then(|json| fs::write(&self.path, json));
+        if let Err(e) = result {
+            warn!("Failed to persist jobs to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// What a job task needs to run a job and report on it.
+#[derive(Clone)]
+struct JobRunner {
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    jobs: Arc<JobLog>,
+}
+
+/// Decrypts the NIP-44 content of a job event sent to the worker.
+async fn read_assignment(
+    signer: &Arc<dyn NostrSigner>,
+    event: &Event,
+) -> Result<JobAssignment, String> {
+    let content = signer
+        .nip44_decrypt(&event.pubkey, &event.content)
+        .await
+        .map_err(|e| format!("Failed to decrypt job assignment: {}", e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid job assignment: {}", e))
+}
+
+/// Listens for job events from the `coordinators` addressed to this worker
+/// and runs each job in its own task. Jobs still running from before a
+/// restart are picked up again.
+pub async fn run_job_intake(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    coordinators: Vec<PublicKey>,
+) {
+    let public_key = match signer.get_public_key().await {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            error!("Failed to get the worker public key: {}", e);
+            return;
+        }
+    };
+    let filter = Filter::new()
+        .kind(Kind::Custom(JOB_KIND))
+        .authors(coordinators.clone())
+        .pubkey(public_key)
+        .since(Timestamp::now());
+
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![filter], None).await {
+        error!("Failed to subscribe to job assignments: {}", e);
+        return;
+    }
+    let replay = ReplayGuard::open_at(state.data_dir.join(SEEN_JOBS_FILE));
+    let runner = JobRunner {
+        jobs: Arc::new(JobLog::open(&state.data_dir)),
+        state,
+        signer,
+        client,
+    };
+    for job in runner.jobs.all() {
+        info!("Resuming job {} in CVM {}", job.job_id, job.vm_id);
+        tokio::spawn(runner.clone().supervise(job));
+    }
+    info!(
+        "Accepting job assignments from {} coordinators",
+        coordinators.len()
+    );
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Job intake lagged, skipped {} notifications", skipped);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if event.kind != Kind::Custom(JOB_KIND)
+            || !coordinators.contains(&event.pubkey)
+            || !event.tags.public_keys().any(|pk| *pk == public_key)
+            || event.verify().is_err()
+        {
+            continue;
+        }
+        let assignment = match read_assignment(&runner.signer, &event).await {
+            Ok(assignment) => assignment,
+            Err(e) => {
+                warn!("Rejecting job {} from {}: {}", event.id, event.pubkey, e);
+                runner
+                    .report(&event.pubkey, event.id, &JobStatus::rejected(None, e))
+                    .await;
+                continue;
+            }
+        };
+
+        if assignment.job_id.len() > MAX_JOB_ID_LEN
+            || !assignment
+                .job_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            let e = format!(
+                "job_id must be up to {} letters, digits, '-' or '_'",
+                MAX_JOB_ID_LEN
+            );
+            warn!("Rejecting job {} from {}: {}", event.id, event.pubkey, e);
+            runner
+                .report(&event.pubkey, event.id, &JobStatus::rejected(None, e))
+                .await;
+            continue;
+        }
+
+        // Job IDs are per coordinator, so coordinators can't burn each other's
+        let nonce = format!("{}:{}", event.pubkey, assignment.job_id);
+        if let Err(e) = replay.check(&nonce, event.created_at.as_u64()) {
+            warn!(
+                "Ignoring job {} from {}: {}",
+                assignment.job_id, event.pubkey, e
+            );
+            continue;
+        }
+        info!(
+            "Received job {} from coordinator {}: {} with {} GPUs for {}s",
+            assignment.job_id,
+            event.pubkey,
+            assignment.image,
+            assignment.gpus,
+            assignment.duration_secs
+        );
+        tokio::spawn(runner.clone().run(event.pubkey, event.id, assignment));
+    }
+}
+
+impl JobRunner {
+    /// Deploys the job and supervises it until it ends.
+    async fn run(self, coordinator: PublicKey, event_id: EventId, assignment: JobAssignment) {
+        let job_id = assignment.job_id.clone();
+        let job = match self.start(coordinator, event_id, assignment).await {
+            Ok(job) => job,
+            Err(status) => {
+                warn!(
+                    "Job {} {:?}: {}",
+                    job_id,
+                    status.state,
+                    status.error.as_deref().unwrap_or_default()
+                );
+                self.report(&coordinator, event_id, &status).await;
+                return;
+            }
+        };
+        self.report(&coordinator, event_id, &job.status(JobState::Running))
+            .await;
+        self.supervise(job).await;
+    }
+
+    /// Checks the assignment, leases free GPUs for it and creates its CVM.
+    async fn start(
+        &self,
+        coordinator: PublicKey,
+        event_id: EventId,
+        assignment: JobAssignment,
+    ) -> Result<RunningJob, JobStatus> {
+        let job_id = Some(assignment.job_id.clone());
+        let reject = |error: String| JobStatus::rejected(job_id.clone(), error);
+        if !is_valid_image(&assignment.image) {
+            return Err(reject(format!("Invalid image {:?}", assignment.image)));
+        }
+        if assignment.duration_secs == 0 || assignment.duration_secs > MAX_JOB_SECS {
+            return Err(reject(format!(
+                "duration_secs must be between 1 and {}",
+                MAX_JOB_SECS
+            )));
+        }
+        if self.state.maintenance.get().is_some() {
+            return Err(reject("The worker is drained".to_string()));
+        }
+
+        let leases = self.lease_gpus(&assignment).await.map_err(reject)?;
+        let spec = DeploySpec {
+            name: format!("job-{}", assignment.job_id),
+            compose_file: compose_file(&assignment.image, assignment.gpus > 0),
+            image: None,
+            vcpu: assignment.vcpu,
+            memory_mb: assignment.memory_mb,
+            disk_gb: assignment.disk_gb,
+            gpus: leases.iter().map(|lease| lease.slot.clone()).collect(),
+            ports: Vec::new(),
+            leases: leases.iter().map(|lease| lease.id.clone()).collect(),
+        };
+        let deployed = match deploy(&self.state, spec).await {
+            Ok(deployed) => deployed,
+            Err(e) => {
+                self.release(&leases);
+                let state = if e.status.is_client_error() {
+                    JobState::Rejected
+                } else {
+                    JobState::Failed
+                };
+                return Err(JobStatus {
+                    error: Some(e.message),
+                    ..JobStatus::new(job_id, state)
+                });
+            }
+        };
+
+        let started_at = unix_timestamp();
+        let job = RunningJob {
+            job_id: assignment.job_id,
+            event_id,
+            coordinator,
+            vm_id: deployed.id,
+            gpus: deployed.gpus,
+            started_at,
+            ends_at: started_at + assignment.duration_secs,
+        };
+        self.jobs.insert(job.clone());
+        info!(
+            "Job {} running in CVM {} until {}",
+            job.job_id, job.vm_id, job.ends_at
+        );
+        Ok(job)
+    }
+
+    /// Leases as many free GPUs as the job asks for, so neither another job
+    /// nor a scheduler takes them before the CVM exists.
+    async fn lease_gpus(&self, assignment: &JobAssignment) -> Result<Vec<Lease>, String> {
+        if assignment.gpus == 0 {
+            return Ok(Vec::new());
+        }
+        let free: Vec<String> = self
+            .state
+            .poller
+            .latest()
+            .and_then(|info| info.metadata)
+            .map(|metadata| metadata.to_structured(&self.state.node_type).gpus)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|gpu| gpu.is_free)
+            .map(|gpu| gpu.slot)
+            .collect();
+
+        let holder = format!("job:{}", assignment.job_id);
+        let mut leases = Vec::new();
+        for slot in &free {
+            if leases.len() == assignment.gpus {
+                break;
+            }
+            // Another job may have taken the GPU since the last health check
+            if let Ok((lease, _)) = self
+                .state
+                .leases
+                .grant(slot, Some(holder.clone()), DEPLOY_LEASE_SECS)
+                .await
+            {
+                leases.push(lease);
+            }
+        }
+        if leases.len() < assignment.gpus {
+            self.release(&leases);
+            return Err(format!(
+                "{} GPUs requested, {} free",
+                assignment.gpus,
+                leases.len()
+            ));
+        }
+        Ok(leases)
+    }
+
+    fn release(&self, leases: &[Lease]) {
+        for lease in leases {
+            if let Err(e) = self.state.leases.release(&lease.slot, &lease.id) {
+                warn!("Failed to end lease {}: {}", lease.id, e.message);
+            }
+        }
+    }
+
+    /// Reports on the job every [`PROGRESS_INTERVAL`] until its CVM exits
+    /// or its duration elapses, then removes the CVM.
+    async fn supervise(self, job: RunningJob) {
+        let client = self.state.connection.client();
+        let (state, vm_status, error) = loop {
+            let remaining = job.ends_at.saturating_sub(unix_timestamp());
+            if remaining == 0 {
+                break (JobState::Completed, None, None);
+            }
+            tokio::time::sleep(PROGRESS_INTERVAL.min(Duration::from_secs(remaining))).await;
+            if unix_timestamp() >= job.ends_at {
+                continue;
+            }
+
+            match list_vms(client.as_ref()).await {
+                Ok(vms) => match vms.into_iter().find(|vm| vm.id == job.vm_id) {
+                    Some(vm) if vm.running() => {
+                        let status = JobStatus {
+                            vm_status: Some(vm.status),
+                            ..job.status(JobState::Running)
+                        };
+                        self.report(&job.coordinator, job.event_id, &status).await;
+                    }
+                    Some(vm) => break (JobState::Completed, Some(vm.status), None),
+                    None => {
+                        break (
+                            JobState::Failed,
+                            None,
+                            Some("The CVM disappeared".to_string()),
+                        )
+                    }
+                },
+                Err(e) => warn!("Failed to check on job {}: {}", job.job_id, e),
+            }
+        };
+
+        let error = match state {
+            JobState::Failed => error,
+            _ => remove_vm(client.as_ref(), &job.vm_id)
+                .await
+                .err()
+                .map(|e| format!("Failed to remove the CVM: {}", e)),
+        };
+        self.jobs.remove(&job.job_id);
+        refresh_status(&self.state);
+        info!("Job {} {:?}", job.job_id, state);
+        let status = JobStatus {
+            vm_status,
+            error,
+            ..job.status(state)
+        };
+        self.report(&job.coordinator, job.event_id, &status).await;
+    }
+
+    /// Sends `status` to the coordinator, encrypted, tagging the assignment.
+    async fn report(&self, coordinator: &PublicKey, event_id: EventId, status: &JobStatus) {
+        let content = match serde_json::to_string(status) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Failed to serialize job status: {}", e);
+                return;
+            }
+        };
+        let content = match self.signer.nip44_encrypt(coordinator, &content).await {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Failed to encrypt job status: {}", e);
+                return;
+            }
+        };
+        let builder = EventBuilder::new(Kind::Custom(JOB_STATUS_KIND), content)
+            .tags([Tag::event(event_id), Tag::public_key(*coordinator)]);
+        match builder.sign(&self.signer).await {
+            Ok(event) => match self.client.send_event(event).await {
+                Ok(output) => self
+                    .state
+                    .relay_stats
+                    .record(&output.success, &output.failed),
+                Err(e) => error!("Failed to publish job status: {}", e),
+            },
+            Err(e) => error!("Failed to sign job status: {}", e),
+        }
+    }
+}
+
+/// Whether `image` is an image reference such as `registry/name:tag` or
+/// `name@sha256:<digest>`. It ends up in the job's compose file, so nothing
+/// that YAML would read as syntax gets through.
+fn is_valid_image(image: &str) -> bool {
+    image.len() <= MAX_IMAGE_LEN
+        && image.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && image
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "./:_@-".contains(c))
+}
+
+/// A compose file running `image`, with every GPU of the CVM when it has
+/// any. `image` must have passed [`is_valid_image`].
+fn compose_file(image: &str, gpus: bool) -> String {
+    let mut compose = format!("services:\n  job:\n    image: \"{}\"\n", image);
+    if gpus {
+        compose.push_str(
+            "    deploy:\n      resources:\n        reservations:\n          devices:\n            \
+             - driver: nvidia\n              count: all\n              capabilities: [gpu]\n",
+        );
+    }
+    compose
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_image_references() {
+        for image in [
+            "nginx",
+            "nvcr.io/nvidia/pytorch:24.08-py3",
+            "localhost:5000/team/app_v2:Latest",
+            "ubuntu@sha256:45b23dee08af5e43a7fea6c4cf9c25ccf269ee113168c19722f87876677c5cb2",
+        ] {
+            assert!(is_valid_image(image), "{}", image);
+        }
+    }
+
+    #[test]
+    fn rejects_yaml_in_images() {
+        for image in [
+            "",
+            "nginx\n    privileged: true",
+            "nginx # comment",
+            "nginx\"",
+            "{nginx}",
+            "-nginx",
+            "Nginx",
+            "nginx\t",
+            "nginx\u{0}",
+        ] {
+            assert!(!is_valid_image(image), "{:?}", image);
+        }
+        assert!(!is_valid_image(&"a".repeat(MAX_IMAGE_LEN + 1)));
+    }
+
+    fn job_event(coordinator: &Keys, worker: &PublicKey, content: &str) -> Event {
+        let content = nip44::encrypt(
+            coordinator.secret_key(),
+            worker,
+            content,
+            nip44::Version::V2,
+        )
+        .unwrap();
+        EventBuilder::new(Kind::Custom(JOB_KIND), content)
+            .tag(Tag::public_key(*worker))
+            .sign_with_keys(coordinator)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn decrypts_assignments_sent_to_the_worker() {
+        let coordinator = Keys::generate();
+        let worker = Keys::generate();
+        let signer: Arc<dyn NostrSigner> = Arc::new(worker.clone());
+        let assignment = serde_json::json!({
+            "job_id": "job-1",
+            "image": "nginx:1.27",
+            "gpus": 1,
+            "vcpu": 4,
+            "memory_mb": 8192,
+            "disk_gb": 40,
+            "duration_secs": 600,
+        });
+
+        let event = job_event(&coordinator, &worker.public_key(), &assignment.to_string());
+        let read = read_assignment(&signer, &event).await.unwrap();
+        assert_eq!(read.job_id, "job-1");
+        assert_eq!(read.gpus, 1);
+
+        // Status reports go back the same way
+        let status = signer
+            .nip44_encrypt(&coordinator.public_key(), "{}")
+            .await
+            .unwrap();
+        let decrypted = nip44::decrypt(coordinator.secret_key(), &worker.public_key(), status);
+        assert_eq!(decrypted.unwrap(), "{}");
+    }
+
+    #[tokio::test]
+    async fn rejects_assignments_it_cannot_read() {
+        let coordinator = Keys::generate();
+        let worker = Keys::generate();
+        let signer: Arc<dyn NostrSigner> = Arc::new(worker.clone());
+
+        let other = Keys::generate().public_key();
+        let event = job_event(&coordinator, &other, "{}");
+        let error = read_assignment(&signer, &event).await.unwrap_err();
+        assert!(error.starts_with("Failed to decrypt"), "{}", error);
+
+        let event = job_event(&coordinator, &worker.public_key(), "{\"job_id\": 1}");
+        let error = read_assignment(&signer, &event).await.unwrap_err();
+        assert!(error.starts_with("Invalid job assignment"), "{}", error);
+    }
+
+    #[test]
+    fn quotes_the_image_in_the_compose_file() {
+        let compose = compose_file("nginx:1.27", false);
+        assert_eq!(compose, "services:\n  job:\n    image: \"nginx:1.27\"\n");
+        assert!(compose_file("nginx", true).contains("capabilities: [gpu]"));
+    }
+}
@@ -0,0 +1,720 @@
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+use crate::error::BackendError;
+use crate::history::unix_timestamp;
+
+/// Prefix of NIP-49 encrypted secret keys.
+const ENCRYPTED_KEY_PREFIX: &str = "ncryptsec1";
+
+/// Event kind of key rotation statements.
+pub const KEY_ROTATION_KIND: u16 = 1578;
+
+/// Content of a key rotation statement.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub old_pubkey: String,
+    pub new_pubkey: String,
+    pub rotated_at: u64,
+}
+
+/// What to do when the key file is readable by other users.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyPermissions {
+    /// Restrict the file to mode 0600 and carry on.
+    #[default]
+    Repair,
+    /// Refuse to start.
+    Strict,
+    /// Don't check.
+    Ignore,
+}
+
+impl FromStr for KeyPermissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "repair" => Ok(Self::Repair),
+            "strict" => Ok(Self::Strict),
+            "ignore" => Ok(Self::Ignore),
+            _ => Err("expected repair, strict or ignore".to_string()),
+        }
+    }
+}
+
+/// File in `DATA_DIR` recording where a derived key came from.
+pub const KEY_DERIVATION_FILE: &str = "key_derivation.json";
+
+/// A BIP-39 mnemonic to derive the worker key from, per NIP-06.
+#[derive(Debug, Clone)]
+pub struct Mnemonic<'a> {
+    pub phrase: &'a str,
+    /// Optional BIP-39 passphrase ("25th word").
+    pub passphrase: Option<&'a str>,
+    pub account: u32,
+}
+
+impl Mnemonic<'_> {
+    pub fn derive(&self) -> Result<Keys, BackendError> {
+        Keys::from_mnemonic_with_account(self.phrase, self.passphrase, Some(self.account))
+            .map_err(|e| BackendError::KeyStore(format!("invalid mnemonic: {}", e)))
+    }
+
+    pub fn derivation_path(&self) -> String {
+        format!("m/44'/1237'/{}'/0/0", self.account)
+    }
+}
+
+/// Contents of [`KEY_DERIVATION_FILE`]. Holds no secrets.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyDerivation {
+    /// `mnemonic` or `kms`.
+    pub source: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<u32>,
+    pub pubkey: String,
+}
+
+/// Location and protection settings of the Nostr key file.
+#[derive(Debug, Clone)]
+pub struct KeyStore<'a> {
+    pub data_dir: &'a Path,
+    pub passphrase: Option<&'a str>,
+    pub permissions: KeyPermissions,
+    pub mnemonic: Option<Mnemonic<'a>>,
+}
+
+impl KeyStore<'_> {
+    pub fn key_file(&self) -> PathBuf {
+        self.data_dir.join("key")
+    }
+
+    fn error(&self, e: &dyn fmt::Display) -> BackendError {
+        BackendError::KeyStore(format!("{}: {}", self.key_file().display(), e))
+    }
+
+    /// Loads the Nostr keypair from `DATA_DIR/key`, generating one on first
+    /// start, or derives it from the configured mnemonic. With a passphrase,
+    /// the key is stored NIP-49 encrypted (scrypt and XChaCha20-Poly1305) and
+    /// an existing plaintext key is encrypted in place.
+    pub fn load_or_create(&self) -> Result<Keys, BackendError> {
+        if let Some(mnemonic) = &self.mnemonic {
+            return self.load_from_mnemonic(mnemonic);
+        }
+
+        if let Some(keys) = self.load_existing()? {
+            return Ok(keys);
+        }
+
+        info!("Generating new Nostr keypair");
+        let keys = Keys::generate();
+        self.save(&keys)?;
+
+        info!("Saved new Nostr keypair to {:?}", self.key_file());
+        info!("Public key: {}", keys.public_key().to_hex());
+
+        Ok(keys)
+    }
+
+    /// Loads the keypair like [`load_or_create`](Self::load_or_create), but
+    /// returns `None` instead of generating one when there is no key yet.
+    pub fn load(&self) -> Result<Option<Keys>, BackendError> {
+        match &self.mnemonic {
+            Some(mnemonic) => self.load_from_mnemonic(mnemonic).map(Some),
+            None => self.load_existing(),
+        }
+    }
+
+    /// Reads the key file, if there is one.
+    fn load_existing(&self) -> Result<Option<Keys>, BackendError> {
+        let keys_file = self.key_file();
+        if !keys_file.exists() {
+            return Ok(None);
+        }
+
+        info!("Loading existing Nostr keypair from {:?}", keys_file);
+        check_permissions(&keys_file, self.permissions).map_err(|e| self.error(&e))?;
+        let content = fs::read_to_string(&keys_file).map_err(|e| self.error(&e))?;
+        let content = content.trim();
+
+        if content.starts_with(ENCRYPTED_KEY_PREFIX) {
+            let passphrase = match self.passphrase {
+                Some(passphrase) => passphrase.to_string(),
+                None => prompt_passphrase().map_err(|e| self.error(&e))?,
+            };
+            let secret_key = EncryptedSecretKey::from_bech32(content)
+                .map_err(|e| self.error(&e))?
+                .to_secret_key(passphrase)
+                .map_err(|e| self.error(&format!("failed to decrypt key: {}", e)))?;
+            return Ok(Some(Keys::new(secret_key)));
+        }
+
+        let keys = Keys::parse(content).map_err(|e| self.error(&e))?;
+        if let Some(passphrase) = self.passphrase {
+            info!("Encrypting existing plaintext Nostr key");
+            write_key(&keys_file, &keys, Some(passphrase)).map_err(|e| self.error(&e))?;
+        }
+        Ok(Some(keys))
+    }
+
+    /// Checks that the key can be loaded, without generating, re-encrypting
+    /// or prompting for anything. Returns `None` if there is no key file
+    /// yet; one is generated on first start.
+    pub fn check(&self) -> Result<Option<PublicKey>, BackendError> {
+        if let Some(mnemonic) = &self.mnemonic {
+            return Ok(Some(mnemonic.derive()?.public_key()));
+        }
+
+        let keys_file = self.key_file();
+        if !keys_file.exists() {
+            return Ok(None);
+        }
+        // A loose mode is only fatal in strict mode, repair mode fixes it
+        if self.permissions == KeyPermissions::Strict {
+            check_permissions(&keys_file, self.permissions).map_err(|e| self.error(&e))?;
+        }
+        let content = fs::read_to_string(&keys_file).map_err(|e| self.error(&e))?;
+        let content = content.trim();
+
+        if content.starts_with(ENCRYPTED_KEY_PREFIX) {
+            let passphrase = self
+                .passphrase
+                .ok_or_else(|| self.error(&"key is encrypted and KEY_PASSPHRASE is not set"))?;
+            let secret_key = EncryptedSecretKey::from_bech32(content)
+                .map_err(|e| self.error(&e))?
+                .to_secret_key(passphrase)
+                .map_err(|e| self.error(&format!("failed to decrypt key: {}", e)))?;
+            return Ok(Some(Keys::new(secret_key).public_key()));
+        }
+
+        let keys = Keys::parse(content).map_err(|e| self.error(&e))?;
+        Ok(Some(keys.public_key()))
+    }
+
+    /// Derives the key from the mnemonic and writes it to the key file for
+    /// other readers of `DATA_DIR/key`. An existing key file must hold the
+    /// same key, so a misconfigured account index can't silently switch the
+    /// worker identity.
+    fn load_from_mnemonic(&self, mnemonic: &Mnemonic<'_>) -> Result<Keys, BackendError> {
+        let keys = mnemonic.derive()?;
+        info!(
+            "Derived Nostr keypair from mnemonic at {}",
+            mnemonic.derivation_path()
+        );
+
+        match self.load_existing()? {
+            Some(existing) if existing.public_key() != keys.public_key() => {
+                return Err(self.error(&format!(
+                    "holds a different key ({}) than mnemonic account {} ({}); move it aside to switch identities",
+                    existing.public_key().to_hex(),
+                    mnemonic.account,
+                    keys.public_key().to_hex()
+                )));
+            }
+            Some(_) => {}
+            None => self.save(&keys)?,
+        }
+
+        self.record_derivation(&KeyDerivation {
+            source: "mnemonic".to_string(),
+            path: mnemonic.derivation_path(),
+            account: Some(mnemonic.account),
+            pubkey: keys.public_key().to_hex(),
+        })?;
+
+        Ok(keys)
+    }
+
+    /// Writes `record` to [`KEY_DERIVATION_FILE`].
+    pub fn record_derivation(&self, record: &KeyDerivation) -> Result<(), BackendError> {
+        let record_file = self.data_dir.join(KEY_DERIVATION_FILE);
+        create_private_dir(self.data_dir)
+            .map_err(|e| e.to_string())
+            .and_then(|()| serde_json::to_string_pretty(record).map_err(|e| e.to_string()))
+            .and_then(|record| fs::write(&record_file, record).map_err(|e| e.to_string()))
+            .map_err(|e| BackendError::KeyStore(format!("{}: {}", record_file.display(), e)))
+    }
+
+    /// Stores `keys` as the worker identity, replacing any existing key file.
+    pub fn save(&self, keys: &Keys) -> Result<(), BackendError> {
+        create_private_dir(self.data_dir).map_err(|e| self.error(&e))?;
+        write_key(&self.key_file(), keys, self.passphrase).map_err(|e| self.error(&e))
+    }
+
+    /// Moves the current key file aside to `key.<unix time>.old` and returns
+    /// the new path, or `None` if there is no key file.
+    pub fn archive(&self) -> Result<Option<PathBuf>, BackendError> {
+        let keys_file = self.key_file();
+        if !keys_file.exists() {
+            return Ok(None);
+        }
+
+        let archived = self.data_dir.join(format!("key.{}.old", unix_timestamp()));
+        fs::rename(&keys_file, &archived).map_err(|e| self.error(&e))?;
+        info!("Archived previous Nostr key to {:?}", archived);
+        Ok(Some(archived))
+    }
+}
+
+/// Builds the statement linking an old worker identity to its replacement:
+/// a [`KEY_ROTATION_KIND`] event signed by the old key that `p`-tags the new
+/// one, and the same statement signed by the new key referencing it, so
+/// holders of either key can verify the link.
+pub fn rotation_statement(old: &Keys, new: &Keys) -> Result<[Event; 2], BackendError> {
+    let content = serde_json::to_string(&KeyRotation {
+        old_pubkey: old.public_key().to_hex(),
+        new_pubkey: new.public_key().to_hex(),
+        rotated_at: unix_timestamp(),
+    })
+    .map_err(|e| BackendError::KeyStore(e.to_string()))?;
+    let sign_error = |e: nostr_sdk::event::builder::Error| {
+        BackendError::KeyStore(format!("failed to sign rotation statement: {}", e))
+    };
+
+    let by_old = EventBuilder::new(Kind::Custom(KEY_ROTATION_KIND), &content)
+        .tag(Tag::public_key(new.public_key()))
+        .sign_with_keys(old)
+        .map_err(sign_error)?;
+    let by_new = EventBuilder::new(Kind::Custom(KEY_ROTATION_KIND), &content)
+        .tags([Tag::event(by_old.id), Tag::public_key(old.public_key())])
+        .sign_with_keys(new)
+        .map_err(sign_error)?;
+    Ok([by_old, by_new])
+}
+
+/// Where `keys rotate` saves the statement for the key `new_pubkey` (hex).
+pub fn rotation_statement_file(data_dir: &Path, new_pubkey: &str) -> PathBuf {
+    data_dir.join(format!("key_rotation.{}.json", new_pubkey))
+}
+
+/// Creates the data directory (and parents) accessible only to the owner.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Checks that the key file belongs to the running user and isn't
+/// accessible to anyone else, restricting it to 0600 in repair mode.
+#[cfg(unix)]
+fn check_permissions(keys_file: &Path, permissions: KeyPermissions) -> Result<(), String> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if permissions == KeyPermissions::Ignore {
+        return Ok(());
+    }
+
+    let metadata = fs::metadata(keys_file).map_err(|e| e.to_string())?;
+    // SAFETY: geteuid has no preconditions and can't fail
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        return Err(format!(
+            "key file is owned by uid {}, but the backend runs as uid {}",
+            metadata.uid(),
+            uid
+        ));
+    }
+
+    let mode = metadata.mode() & 0o777;
+    if mode & 0o077 == 0 {
+        return Ok(());
+    }
+    match permissions {
+        KeyPermissions::Strict => Err(format!(
+            "key file has mode {:o} and is accessible to other users, run chmod 600 on it",
+            mode
+        )),
+        _ => {
+            warn!(
+                "Key file {:?} had mode {:o}, restricting it to 600",
+                keys_file, mode
+            );
+            fs::set_permissions(keys_file, fs::Permissions::from_mode(0o600))
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_keys_file: &Path, _permissions: KeyPermissions) -> Result<(), String> {
+    Ok(())
+}
+
+/// Writes the secret key as hex, or NIP-49 encrypted with a passphrase. The
+/// file is replaced atomically so an interrupted write can't lose the key.
+fn write_key(keys_file: &Path, keys: &Keys, passphrase: Option<&str>) -> Result<(), String> {
+    let content = match passphrase {
+        Some(passphrase) => keys
+            .secret_key()
+            .encrypt(passphrase)
+            .map_err(|e| format!("failed to encrypt key: {}", e))?
+            .to_bech32()
+            .map_err(|e| e.to_string())?,
+        None => keys.secret_key().to_secret_hex(),
+    };
+
+    let tmp_file = keys_file.with_extension("tmp");
+    // A file left over from an interrupted write would keep its mode
+    match fs::remove_file(&tmp_file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.to_string()),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&tmp_file)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| e.to_string())?;
+    fs::rename(&tmp_file, keys_file).map_err(|e| e.to_string())
+}
+
+/// Asks for the key passphrase on the terminal, for encrypted keys when no
+/// passphrase is configured.
+fn prompt_passphrase() -> Result<String, String> {
+    if !io::stdin().is_terminal() {
+        return Err("key is encrypted, set KEY_PASSPHRASE or KEY_PASSPHRASE_FILE".to_string());
+    }
+
+    eprint!("Nostr key passphrase: ");
+    io::stderr().flush().map_err(|e| e.to_string())?;
+    let _echo = EchoGuard::disable();
+    let mut passphrase = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut passphrase)
+        .map_err(|e| e.to_string())?;
+    eprintln!();
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns off terminal echo while the passphrase is typed.
+struct EchoGuard {
+    #[cfg(unix)]
+    original: Option<libc::termios>,
+}
+
+impl EchoGuard {
+    #[cfg(unix)]
+    fn disable() -> Self {
+        // SAFETY: tcgetattr/tcsetattr only read and write the termios struct
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Self { original: None };
+            }
+            let original = termios;
+            termios.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            Self {
+                original: Some(original),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn disable() -> Self {
+        Self {}
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(original) = &self.original {
+            // SAFETY: restores the settings read in disable()
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store<'a>(dir: &'a tempfile::TempDir, passphrase: Option<&'a str>) -> KeyStore<'a> {
+        KeyStore {
+            data_dir: dir.path(),
+            passphrase,
+            permissions: KeyPermissions::Repair,
+            mnemonic: None,
+        }
+    }
+
+    fn read_key_file(store: &KeyStore<'_>) -> String {
+        fs::read_to_string(store.key_file()).unwrap()
+    }
+
+    #[test]
+    fn encrypts_a_plaintext_key_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = store(&dir, None).load_or_create().unwrap();
+        assert_eq!(
+            read_key_file(&store(&dir, None)),
+            keys.secret_key().to_secret_hex()
+        );
+
+        let encrypted = store(&dir, Some("correct horse"));
+        let loaded = encrypted.load_or_create().unwrap();
+
+        assert_eq!(loaded.public_key(), keys.public_key());
+        assert!(read_key_file(&encrypted).starts_with(ENCRYPTED_KEY_PREFIX));
+        assert_eq!(encrypted.check().unwrap(), Some(keys.public_key()));
+        assert_eq!(
+            encrypted.load().unwrap().map(|keys| keys.public_key()),
+            Some(keys.public_key())
+        );
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        store(&dir, Some("correct horse")).load_or_create().unwrap();
+
+        let wrong = store(&dir, Some("battery staple"));
+        let error = wrong.load_or_create().unwrap_err().to_string();
+        assert!(error.contains("failed to decrypt key"), "{}", error);
+        assert!(wrong.check().is_err());
+        // The key file is left as it was
+        assert!(read_key_file(&wrong).starts_with(ENCRYPTED_KEY_PREFIX));
+    }
+
+    #[test]
+    fn check_needs_the_passphrase_of_an_encrypted_key() {
+        let dir = tempfile::tempdir().unwrap();
+        store(&dir, Some("correct horse")).load_or_create().unwrap();
+
+        let error = store(&dir, None).check().unwrap_err().to_string();
+        assert!(error.contains("KEY_PASSPHRASE is not set"), "{}", error);
+    }
+
+    #[test]
+    fn write_key_replaces_the_file_through_a_temporary_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = dir.path().join("key");
+        let tmp_file = dir.path().join("key.tmp");
+        // Left over from an interrupted write
+        fs::write(&tmp_file, "partial").unwrap();
+        fs::write(&keys_file, "old key").unwrap();
+
+        let keys = Keys::generate();
+        write_key(&keys_file, &keys, None).unwrap();
+
+        assert!(!tmp_file.exists());
+        assert_eq!(
+            fs::read_to_string(&keys_file).unwrap(),
+            keys.secret_key().to_secret_hex()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&keys_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    fn key_file_with_mode(dir: &tempfile::TempDir, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let keys_file = dir.path().join("key");
+        fs::write(&keys_file, Keys::generate().secret_key().to_secret_hex()).unwrap();
+        fs::set_permissions(&keys_file, fs::Permissions::from_mode(mode)).unwrap();
+        keys_file
+    }
+
+    #[cfg(unix)]
+    fn mode(keys_file: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::metadata(keys_file).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn repair_restricts_a_readable_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = key_file_with_mode(&dir, 0o644);
+
+        check_permissions(&keys_file, KeyPermissions::Repair).unwrap();
+
+        assert_eq!(mode(&keys_file), 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn strict_rejects_a_readable_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = key_file_with_mode(&dir, 0o644);
+
+        let error = check_permissions(&keys_file, KeyPermissions::Strict).unwrap_err();
+
+        assert!(error.contains("mode 644"), "{}", error);
+        assert_eq!(mode(&keys_file), 0o644);
+        // Loading fails the same way
+        let store = KeyStore {
+            permissions: KeyPermissions::Strict,
+            ..store(&dir, None)
+        };
+        assert!(store.load().is_err());
+        assert!(store.check().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignore_leaves_the_mode_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = key_file_with_mode(&dir, 0o644);
+
+        check_permissions(&keys_file, KeyPermissions::Ignore).unwrap();
+
+        assert_eq!(mode(&keys_file), 0o644);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn accepts_a_private_key_file_in_every_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = key_file_with_mode(&dir, 0o600);
+
+        for permissions in [
+            KeyPermissions::Repair,
+            KeyPermissions::Strict,
+            KeyPermissions::Ignore,
+        ] {
+            check_permissions(&keys_file, permissions).unwrap();
+            assert_eq!(mode(&keys_file), 0o600);
+        }
+    }
+
+    /// Test vector from NIP-06.
+    const MNEMONIC: &str =
+        "leader monkey parrot ring guide accident before fence cannon height naive bean";
+
+    fn mnemonic(account: u32) -> Mnemonic<'static> {
+        Mnemonic {
+            phrase: MNEMONIC,
+            passphrase: None,
+            account,
+        }
+    }
+
+    #[test]
+    fn derives_the_nip06_test_vector() {
+        let keys = mnemonic(0).derive().unwrap();
+
+        assert_eq!(
+            keys.secret_key().to_secret_hex(),
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a"
+        );
+        assert_eq!(
+            keys.public_key().to_hex(),
+            "17162c921dc4d2518f9a101db33695df1afb56ab82f5ff3e5da6eec3ca5cd917"
+        );
+        assert_eq!(mnemonic(0).derivation_path(), "m/44'/1237'/0'/0/0");
+        assert_ne!(
+            mnemonic(1).derive().unwrap().public_key(),
+            keys.public_key()
+        );
+    }
+
+    #[test]
+    fn saves_the_derived_key_and_its_derivation() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyStore {
+            mnemonic: Some(mnemonic(0)),
+            ..store(&dir, None)
+        };
+
+        let keys = store.load_or_create().unwrap();
+
+        assert_eq!(read_key_file(&store), keys.secret_key().to_secret_hex());
+        let record: KeyDerivation = serde_json::from_str(
+            &fs::read_to_string(dir.path().join(KEY_DERIVATION_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(record.account, Some(0));
+        assert_eq!(record.pubkey, keys.public_key().to_hex());
+        // Loading again accepts the key file it wrote
+        assert_eq!(store.load().unwrap(), Some(keys));
+    }
+
+    #[test]
+    fn refuses_a_key_file_from_another_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let account_0 = KeyStore {
+            mnemonic: Some(mnemonic(0)),
+            ..store(&dir, None)
+        };
+        let keys = account_0.load_or_create().unwrap();
+
+        let account_1 = KeyStore {
+            mnemonic: Some(mnemonic(1)),
+            ..store(&dir, None)
+        };
+        let error = account_1.load_or_create().unwrap_err().to_string();
+
+        assert!(error.contains("holds a different key"), "{}", error);
+        assert!(error.contains("move it aside"), "{}", error);
+        // The existing identity is kept
+        assert_eq!(read_key_file(&account_1), keys.secret_key().to_secret_hex());
+    }
+
+    #[test]
+    fn rotation_statements_link_both_keys() {
+        let old = Keys::generate();
+        let new = Keys::generate();
+
+        let [by_old, by_new] = rotation_statement(&old, &new).unwrap();
+
+        by_old.verify().unwrap();
+        by_new.verify().unwrap();
+        assert_eq!(by_old.kind, Kind::Custom(KEY_ROTATION_KIND));
+        assert_eq!(by_old.pubkey, old.public_key());
+        assert_eq!(by_new.pubkey, new.public_key());
+        assert!(by_old.tags.public_keys().any(|pk| *pk == new.public_key()));
+        assert!(by_new.tags.event_ids().any(|id| *id == by_old.id));
+    }
+
+    #[test]
+    fn event_kinds_are_distinct() {
+        let kinds = [
+            crate::dephy::DEPHY_MESSAGE_KIND,
+            crate::heartbeat::HEARTBEAT_KIND,
+            crate::benchmark::BENCHMARK_KIND,
+            crate::leases::LEASE_KIND,
+            crate::jobs::JOB_KIND,
+            crate::jobs::JOB_STATUS_KIND,
+            crate::challenge::CHALLENGE_KIND,
+            crate::challenge::RESPONSE_KIND,
+            crate::commands::COMMAND_KIND,
+            crate::commands::COMMAND_ACK_KIND,
+            KEY_ROTATION_KIND,
+        ];
+
+        let distinct: std::collections::HashSet<_> = kinds.iter().collect();
+        assert_eq!(distinct.len(), kinds.len(), "{:?}", kinds);
+    }
+
+    #[test]
+    fn load_doesnt_create_a_key() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(store(&dir, None).load().unwrap().is_none());
+        assert!(!store(&dir, None).key_file().exists());
+    }
+}
@@ -0,0 +1,61 @@
+//! Keys from the dstack KMS. Inside a CVM, the guest agent's `GetKey`
+//! derives a secret bound to the application identity, so a copy of the
+//! disk taken outside the TEE can't recover it.
+
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::dstack::DStackClient;
+use crate::error::BackendError;
+
+/// Default `GetKey` path for the worker key.
+pub const DEFAULT_KMS_KEY_PATH: &str = "dstack-backend/nostr";
+
+/// How the dstack KMS protects the Nostr key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KmsKeyMode {
+    /// Don't use the KMS.
+    #[default]
+    Off,
+    /// Keep the key file, NIP-49 encrypted with a KMS-derived passphrase.
+    Seal,
+    /// Use the KMS-derived secret as the key itself; nothing is stored.
+    Derive,
+}
+
+impl FromStr for KmsKeyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "seal" => Ok(Self::Seal),
+            "derive" => Ok(Self::Derive),
+            _ => Err("expected off, seal or derive".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GetKeyResponse {
+    key: String,
+}
+
+/// Requests the 32-byte secret for `path` from the guest agent. The same
+/// application always gets the same secret for the same path.
+pub async fn get_key(guest_agent: &dyn DStackClient, path: &str) -> Result<[u8; 32], BackendError> {
+    let response: GetKeyResponse = guest_agent
+        .request(
+            "/GetKey",
+            Some(&serde_json::json!({ "path": path, "purpose": "nostr" })),
+        )
+        .await
+        .map_err(|e| BackendError::KeyStore(format!("KMS key request failed: {}", e)))?;
+
+    let key = hex::decode(response.key.trim_start_matches("0x"))
+        .map_err(|e| BackendError::KeyStore(format!("invalid KMS key: {}", e)))?;
+    key.get(..32)
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| BackendError::KeyStore(format!("KMS key is only {} bytes", key.len())))
+}
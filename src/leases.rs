@@ -0,0 +1,459 @@
+//! GPU leases. A scheduler reserves a GPU with `POST /gpus/{slot}/reserve`
+//! before placing work on it, so two schedulers don't race for the same
+//! card. Each lease is signed by the worker key, kept under `DATA_DIR` and
+//! expires on its own; until then the GPU is reported as not free.
+
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::dstack::GpuInfo;
+use crate::error::{ApiError, ErrorBody};
+use crate::history::unix_timestamp;
+use crate::poller::refresh_status;
+use crate::server::AppState;
+
+/// Event kind of the signed lease token.
+pub const LEASE_KIND: u16 = 1575;
+
+/// Active leases, in `DATA_DIR`.
+const LEASES_FILE: &str = "gpu_leases.json";
+
+const DEFAULT_LEASE_SECS: u64 = 10 * 60;
+const MAX_LEASE_SECS: u64 = 24 * 60 * 60;
+
+/// A GPU reserved for a scheduler until `expires_at`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Lease {
+    /// ID of the lease token, which identifies the lease.
+    pub id: String,
+    pub slot: String,
+    /// Who the GPU is reserved for, as given by the scheduler.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+impl Lease {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at > now
+    }
+}
+
+/// The active leases, persisted so reservations survive a restart.
+pub struct GpuLeases {
+    path: PathBuf,
+    signer: Arc<dyn NostrSigner>,
+    leases: RwLock<Vec<Lease>>,
+    /// Held while a lease is signed, so two requests can't both get the
+    /// same GPU.
+    granting: tokio::sync::Mutex<()>,
+}
+
+impl GpuLeases {
+    pub fn open(data_dir: &std::path::Path, signer: Arc<dyn NostrSigner>) -> Self {
+        let path = data_dir.join(LEASES_FILE);
+        let leases: Vec<Lease> = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let now = unix_timestamp();
+        let leases: Vec<Lease> = leases
+            .into_iter()
+            .filter(|lease| lease.is_active(now))
+            .collect();
+        if !leases.is_empty() {
+            info!("Restored {} GPU leases", leases.len());
+        }
+
+        Self {
+            path,
+            signer,
+            leases: RwLock::new(leases),
+            granting: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The leases that haven't expired. Expired ones are dropped.
+    pub fn active(&self) -> Vec<Lease> {
+        let now = unix_timestamp();
+        if self
+            .leases
+            .read()
+            .unwrap()
+            .iter()
+            .all(|lease| lease.is_active(now))
+        {
+            return self.leases.read().unwrap().clone();
+        }
+
+        let mut leases = self.leases.write().unwrap();
+        leases.retain(|lease| {
+            let active = lease.is_active(now);
+            if !active {
+                info!("Lease {} on GPU {} expired", lease.id, lease.slot);
+            }
+            active
+        });
+        if let Err(e) = self.save(&leases) {
+            warn!("Failed to persist GPU leases: {}", e);
+        }
+        leases.clone()
+    }
+
+    /// The active lease on `slot`, if any.
+    pub fn lease_on(&self, slot: &str) -> Option<Lease> {
+        self.active().into_iter().find(|lease| lease.slot == slot)
+    }
+
+    /// Reports the leased GPUs as not free and returns their slots.
+    pub fn mark_reserved(&self, gpus: &mut [GpuInfo]) -> Vec<String> {
+        let leases = self.active();
+        gpus.iter_mut()
+            .filter(|gpu| leases.iter().any(|lease| lease.slot == gpu.slot))
+            .map(|gpu| {
+                gpu.is_free = false;
+                gpu.slot.clone()
+            })
+            .collect()
+    }
+
+    /// Leases `slot` for `duration_secs`, unless it already is. Returns the
+    /// lease and its token: an event signed by the worker key with the lease
+    /// as content and a NIP-40 expiration.
+    pub async fn grant(
+        &self,
+        slot: &str,
+        holder: Option<String>,
+        duration_secs: u64,
+    ) -> Result<(Lease, Event), ApiError> {
+        let _granting = self.granting.lock().await;
+        if let Some(lease) = self.lease_on(slot) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "gpu_reserved",
+                format!("GPU {} is reserved until {}", slot, lease.expires_at),
+            ));
+        }
+
+        let issued_at = unix_timestamp();
+        let expires_at = issued_at + duration_secs;
+        let content = serde_json::json!({
+            "slot": slot,
+            "holder": holder,
+            "issued_at": issued_at,
+            "expires_at": expires_at,
+        });
+        let token = EventBuilder::new(Kind::Custom(LEASE_KIND), content.to_string())
+            .tag(Tag::expiration(Timestamp::from(expires_at)))
+            .sign(&self.signer)
+            .await
+            .map_err(|e| {
+                ApiError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error",
+                    format!("Failed to sign the lease: {}", e),
+                )
+            })?;
+        let lease = Lease {
+            id: token.id.to_hex(),
+            slot: slot.to_string(),
+            holder,
+            issued_at,
+            expires_at,
+        };
+
+        let mut leases = self.leases.write().unwrap();
+        leases.push(lease.clone());
+        self.save(&leases).map_err(|e| {
+            leases.pop();
+            persist_error(e)
+        })?;
+        Ok((lease, token))
+    }
+
+    /// Ends the lease `id` on `slot` before it expires.
+    pub fn release(&self, slot: &str, id: &str) -> Result<Lease, ApiError> {
+        let now = unix_timestamp();
+        let mut leases = self.leases.write().unwrap();
+        let index = leases
+            .iter()
+            .position(|lease| lease.slot == slot && lease.id == id && lease.is_active(now))
+            .ok_or_else(|| {
+                ApiError::not_found(format!("No active lease {} on GPU {}", id, slot))
+            })?;
+        let lease = leases.remove(index);
+        if let Err(e) = self.save(&leases) {
+            leases.insert(index, lease);
+            return Err(persist_error(e));
+        }
+        Ok(lease)
+    }
+
+    fn save(&self, leases: &[Lease]) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(leases)?)
+    }
+}
+
+fn persist_error(e: std::io::Error) -> ApiError {
+    ApiError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal_error",
+        format!("Failed to persist GPU leases: {}", e),
+    )
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+struct ReserveRequest {
+    /// Lease duration, 600 by default and a day at most.
+    duration_secs: Option<u64>,
+    holder: Option<String>,
+}
+
+impl ReserveRequest {
+    /// The body is optional, `curl -X POST` alone is enough.
+    fn parse(body: &[u8]) -> Result<Self, ApiError> {
+        if body.is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid request body: {}", e)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LeaseGrant {
+    #[serde(flatten)]
+    pub lease: Lease,
+    /// Nostr event signed by the worker key with the lease as content.
+    #[schema(value_type = Object)]
+    pub token: Event,
+}
+
+/// `POST /gpus/{slot}/reserve`: leases a free GPU. Takes an optional
+/// `{"duration_secs", "holder"}` body.
+#[utoipa::path(
+    post,
+    path = "/gpus/{slot}/reserve",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("slot" = String, Path, description = "PCI slot from `ListGpus`")),
+    request_body(content = Option<ReserveRequest>, description = "Optional"),
+    responses(
+        (status = 201, description = "GPU leased", body = LeaseGrant),
+        (status = 400, description = "Invalid body", body = ErrorBody),
+        (status = 404, description = "Unknown GPU slot", body = ErrorBody),
+        (status = 409, description = "The GPU is in use or already reserved", body = ErrorBody),
+    )
+)]
+pub async fn reserve_gpu_handler(
+    State(state): State<Arc<AppState>>,
+    Path(slot): Path<String>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<LeaseGrant>), ApiError> {
+    let request = ReserveRequest::parse(&body)?;
+    let duration_secs = request.duration_secs.unwrap_or(DEFAULT_LEASE_SECS);
+    if duration_secs == 0 || duration_secs > MAX_LEASE_SECS {
+        return Err(ApiError::bad_request(format!(
+            "duration_secs must be between 1 and {}",
+            MAX_LEASE_SECS
+        )));
+    }
+
+    let metadata = state
+        .poller
+        .latest()
+        .and_then(|info| info.metadata)
+        .map(|metadata| metadata.to_structured(&state.node_type));
+    let gpu = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.gpus.iter().find(|gpu| gpu.slot == slot))
+        .ok_or_else(|| ApiError::not_found(format!("Unknown GPU slot {}", slot)))?;
+    // Leased GPUs are reported as not free too, the lease check below tells
+    // those apart
+    let reserved = metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.reserved_gpus.contains(&slot));
+    if !gpu.is_free && !reserved {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "gpu_busy",
+            format!("GPU {} is not free", slot),
+        ));
+    }
+
+    let (lease, token) = state
+        .leases
+        .grant(&slot, request.holder, duration_secs)
+        .await?;
+    info!(
+        "Leased GPU {} until {} ({})",
+        slot, lease.expires_at, lease.id
+    );
+    refresh_status(&state);
+    Ok((StatusCode::CREATED, Json(LeaseGrant { lease, token })))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReleaseQuery {
+    /// ID of the lease to end.
+    lease: String,
+}
+
+/// `DELETE /gpus/{slot}/reserve?lease=<id>`: ends a lease early.
+#[utoipa::path(
+    delete,
+    path = "/gpus/{slot}/reserve",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("slot" = String, Path, description = "PCI slot from `ListGpus`"), ReleaseQuery),
+    responses(
+        (status = 204, description = "Lease ended"),
+        (status = 400, description = "Missing lease ID", body = ErrorBody),
+        (status = 404, description = "No such active lease", body = ErrorBody),
+    )
+)]
+pub async fn release_gpu_handler(
+    State(state): State<Arc<AppState>>,
+    Path(slot): Path<String>,
+    query: Result<Query<ReleaseQuery>, QueryRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Query(query) = query?;
+    state.leases.release(&slot, &query.lease)?;
+    info!("Released lease {} on GPU {}", query.lease, slot);
+    refresh_status(&state);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /gpus/leases`: the active GPU leases.
+#[utoipa::path(
+    get,
+    path = "/gpus/leases",
+    tag = "gpus",
+    responses(
+        (status = 200, description = "Active leases", body = Vec<Lease>),
+    )
+)]
+pub async fn leases_handler(State(state): State<Arc<AppState>>) -> Json<Vec<Lease>> {
+    Json(state.leases.active())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT: &str = "0000:18:00.0";
+
+    fn leases(dir: &tempfile::TempDir) -> GpuLeases {
+        GpuLeases::open(dir.path(), Arc::new(Keys::generate()))
+    }
+
+    fn expire(leases: &GpuLeases) {
+        let now = unix_timestamp();
+        for lease in leases.leases.write().unwrap().iter_mut() {
+            lease.expires_at = now - 1;
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_a_second_lease_on_the_same_gpu() {
+        let dir = tempfile::tempdir().unwrap();
+        let leases = leases(&dir);
+
+        let (lease, token) = leases
+            .grant(SLOT, Some("scheduler-a".to_string()), 60)
+            .await
+            .unwrap();
+        assert_eq!(lease.id, token.id.to_hex());
+        token.verify().unwrap();
+
+        let error = leases.grant(SLOT, None, 60).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::CONFLICT);
+        assert_eq!(error.code, "gpu_reserved");
+
+        // Other GPUs can still be leased
+        leases.grant("0000:28:00.0", None, 60).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn grants_only_one_of_two_concurrent_leases() {
+        let dir = tempfile::tempdir().unwrap();
+        let leases = leases(&dir);
+
+        let (a, b) = tokio::join!(leases.grant(SLOT, None, 60), leases.grant(SLOT, None, 60));
+
+        assert!(a.is_ok() != b.is_ok());
+        assert_eq!(leases.active().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_leases_free_the_gpu() {
+        let dir = tempfile::tempdir().unwrap();
+        let leases = leases(&dir);
+        let mut gpus = vec![GpuInfo {
+            slot: SLOT.to_string(),
+            product_id: "2330".to_string(),
+            description: "NVIDIA H100".to_string(),
+            is_free: true,
+        }];
+
+        let (lease, _) = leases.grant(SLOT, None, 60).await.unwrap();
+        assert_eq!(leases.mark_reserved(&mut gpus), vec![SLOT.to_string()]);
+        assert!(!gpus[0].is_free);
+
+        expire(&leases);
+        assert!(leases.active().is_empty());
+        assert!(leases.lease_on(SLOT).is_none());
+        assert!(
+            matches!(leases.release(SLOT, &lease.id), Err(e) if e.status == StatusCode::NOT_FOUND)
+        );
+        leases.grant(SLOT, None, 60).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_lease_holder_can_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let leases = leases(&dir);
+        let (lease, _) = leases.grant(SLOT, None, 60).await.unwrap();
+
+        for (slot, id) in [(SLOT, "not-the-lease"), ("0000:28:00.0", lease.id.as_str())] {
+            let error = leases.release(slot, id).unwrap_err();
+            assert_eq!(error.status, StatusCode::NOT_FOUND);
+        }
+        assert_eq!(leases.lease_on(SLOT), Some(lease.clone()));
+
+        assert_eq!(leases.release(SLOT, &lease.id).unwrap(), lease);
+        assert!(leases.lease_on(SLOT).is_none());
+    }
+
+    #[tokio::test]
+    async fn leases_survive_a_restart_until_they_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let (lease, _) = leases(&dir).grant(SLOT, None, 60).await.unwrap();
+
+        assert_eq!(leases(&dir).active(), vec![lease]);
+
+        let expired = leases(&dir);
+        expire(&expired);
+        expired.active();
+        assert!(leases(&dir).active().is_empty());
+    }
+}
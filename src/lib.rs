@@ -0,0 +1,74 @@
+pub mod addresses;
+pub mod alerts;
+pub mod api_version;
+pub mod attestation;
+pub mod auth;
+pub mod bandwidth;
+pub mod benchmark;
+pub mod breaker;
+pub mod bunker;
+pub mod chain;
+pub mod challenge;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod dephy;
+pub mod doctor;
+pub mod dstack;
+pub mod dstack_info;
+pub mod eip712;
+pub mod ens;
+pub mod error;
+pub mod etag;
+pub mod gpu_changes;
+pub mod gpu_telemetry;
+pub mod gpus;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod heartbeat;
+pub mod history;
+pub mod host;
+pub mod images;
+pub mod jobs;
+pub mod keys;
+pub mod kms;
+pub mod leases;
+pub mod limits;
+pub mod listen;
+pub mod maintenance;
+pub mod metrics;
+pub mod mock;
+pub mod nip98;
+pub mod node_type;
+pub mod onchain;
+pub mod openapi;
+pub mod ownership;
+pub mod poller;
+pub mod probes;
+pub mod proto;
+pub mod proxy;
+pub mod prpc;
+pub mod public_ip;
+pub mod registration;
+pub mod registry_events;
+pub mod relay;
+pub mod relay_list;
+pub mod reload;
+pub mod replay;
+pub mod request_id;
+pub mod retry;
+pub mod rewards;
+pub mod rules;
+pub mod score;
+pub mod server;
+pub mod stake;
+pub mod stream;
+pub mod systemd;
+pub mod tls;
+pub mod vms;
+#[cfg(feature = "vsock")]
+pub mod vsock;
+pub mod webhook;
+pub mod whitelist;
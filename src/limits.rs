@@ -0,0 +1,45 @@
+use axum::http::StatusCode;
+use axum::BoxError;
+use serde::Deserialize;
+use tower::load_shed::error::Overloaded;
+use tracing::warn;
+
+use crate::error::ApiError;
+
+/// Caps on in-flight requests. Requests over a cap are shed with a `503`
+/// straight away rather than queueing behind a slow dstack.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyLimits {
+    /// Requests in flight across all routes.
+    pub max_requests: usize,
+    /// Requests in flight on routes that call dstack or the guest agent.
+    pub max_dstack_requests: usize,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self {
+            max_requests: 1024,
+            max_dstack_requests: 32,
+        }
+    }
+}
+
+/// Error handler for the load shedding layers.
+pub async fn handle_overload(err: BoxError) -> ApiError {
+    if err.is::<Overloaded>() {
+        warn!("Shedding request, concurrency limit reached");
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded",
+            "Too many concurrent requests, try again later",
+        )
+    } else {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            err.to_string(),
+        )
+    }
+}
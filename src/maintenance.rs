@@ -0,0 +1,203 @@
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::history::unix_timestamp;
+use crate::poller::refresh_status;
+use crate::server::AppState;
+
+/// File in `DATA_DIR` that exists while the worker is drained.
+const MAINTENANCE_FILE: &str = "maintenance.json";
+
+/// Why and since when the worker is drained.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Maintenance {
+    pub since: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// The drain flag, persisted so a drained worker stays out of scheduling
+/// across restarts.
+#[derive(Debug)]
+pub struct MaintenanceMode {
+    path: PathBuf,
+    current: RwLock<Option<Maintenance>>,
+    /// Asks the heartbeat task to publish the new status right away.
+    notice: Notify,
+}
+
+impl MaintenanceMode {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join(MAINTENANCE_FILE);
+        let current = match fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(maintenance) => {
+                    info!("Worker is drained, reporting Maintenance");
+                    Some(maintenance)
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid {}: {}", path.display(), e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+
+        Self {
+            path,
+            current: RwLock::new(current),
+            notice: Notify::new(),
+        }
+    }
+
+    pub fn get(&self) -> Option<Maintenance> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Waits until a drain or undrain asks for a notice.
+    pub async fn notice_requested(&self) {
+        self.notice.notified().await
+    }
+
+    /// Drains the worker, or lifts the drain with `None`.
+    pub fn set(&self, maintenance: Option<Maintenance>) -> std::io::Result<()> {
+        match &maintenance {
+            Some(maintenance) => fs::write(&self.path, serde_json::to_string(maintenance)?)?,
+            None => match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            },
+        }
+        *self.current.write().unwrap() = maintenance;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+struct DrainRequest {
+    reason: Option<String>,
+    /// Publish a heartbeat with the new status right away instead of at the
+    /// next interval.
+    notify: bool,
+}
+
+impl DrainRequest {
+    /// The body is optional, `curl -X POST` alone is enough.
+    fn parse(body: &[u8]) -> Result<Self, ApiError> {
+        if body.is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid request body: {}", e)))
+    }
+}
+
+/// `POST /admin/drain`: reports `Maintenance` until undrained. Takes an
+/// optional `{"reason", "notify"}` body.
+#[utoipa::path(
+    post,
+    path = "/admin/drain",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body(content = Option<DrainRequest>, description = "Optional"),
+    responses(
+        (status = 200, description = "Drained", body = Maintenance),
+        (status = 400, description = "Invalid body", body = ErrorBody),
+    )
+)]
+pub async fn drain_handler(
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> Result<Json<Maintenance>, ApiError> {
+    let request = DrainRequest::parse(&body)?;
+    drain(&state, request.reason, request.notify).map(Json)
+}
+
+/// `POST /admin/undrain`: returns to reporting the dstack status.
+#[utoipa::path(
+    post,
+    path = "/admin/undrain",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body(content = Option<DrainRequest>, description = "Optional, only `notify` applies"),
+    responses(
+        (status = 200, description = "Undrained", body = serde_json::Value),
+        (status = 400, description = "Invalid body", body = ErrorBody),
+    )
+)]
+pub async fn undrain_handler(
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let request = DrainRequest::parse(&body)?;
+    undrain(&state, request.notify)?;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// Drains the worker, publishing a notice right away with `notify`.
+pub fn drain(
+    state: &Arc<AppState>,
+    reason: Option<String>,
+    notify: bool,
+) -> Result<Maintenance, ApiError> {
+    // Keep the original drain time when only the reason changes
+    let maintenance = Maintenance {
+        since: state
+            .maintenance
+            .get()
+            .map_or_else(unix_timestamp, |current| current.since),
+        reason,
+    };
+    set_maintenance(state, Some(maintenance.clone()), notify)?;
+    info!(
+        "Worker drained{}",
+        maintenance
+            .reason
+            .as_deref()
+            .map(|reason| format!(": {}", reason))
+            .unwrap_or_default()
+    );
+    Ok(maintenance)
+}
+
+/// Lifts the drain, publishing a notice right away with `notify`.
+pub fn undrain(state: &Arc<AppState>, notify: bool) -> Result<(), ApiError> {
+    set_maintenance(state, None, notify)?;
+    info!("Worker undrained");
+    Ok(())
+}
+
+fn set_maintenance(
+    state: &Arc<AppState>,
+    maintenance: Option<Maintenance>,
+    notify: bool,
+) -> Result<(), ApiError> {
+    state.maintenance.set(maintenance).map_err(|e| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("Failed to persist the drain flag: {}", e),
+        )
+    })?;
+    refresh_status(state);
+    if !notify {
+        return Ok(());
+    }
+    match &state.nostr_client {
+        // Stores a permit if the heartbeat task is busy, so it isn't lost
+        Some(_) => state.maintenance.notice.notify_one(),
+        None => warn!("No Nostr relays configured, not publishing a notice"),
+    }
+    Ok(())
+}
@@ -0,0 +1,286 @@
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::gpu_telemetry::GpuTelemetry;
+use crate::health::DephyWorkerRespondedStatus;
+use crate::host;
+use crate::server::AppState;
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Renders metrics in the Prometheus text exposition format.
+#[derive(Default)]
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    /// Writes a metric family. Samples are `(labels, value)`; families
+    /// without samples are left out.
+    fn metric(&mut self, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+        if samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                let _ = writeln!(self.out, "{} {}", name, value);
+            } else {
+                let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, samples: &[(String, f64)]) {
+        self.metric(name, "gauge", help, samples);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, samples: &[(String, f64)]) {
+        self.metric(name, "counter", help, samples);
+    }
+}
+
+/// Escapes a label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `GET /metrics`: the background poller's latest snapshot, health check
+/// stats and GPU telemetry. Never calls dstack.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Response {
+    let mut metrics = MetricsWriter::default();
+
+    if let Some(info) = state.poller.latest() {
+        let samples: Vec<_> = [
+            DephyWorkerRespondedStatus::Available,
+            DephyWorkerRespondedStatus::Degraded,
+            DephyWorkerRespondedStatus::Unavailable,
+            DephyWorkerRespondedStatus::Maintenance,
+        ]
+        .into_iter()
+        .map(|status| {
+            (
+                format!("status=\"{}\"", status),
+                (info.status == status) as u8 as f64,
+            )
+        })
+        .collect();
+        metrics.gauge(
+            "dstack_worker_status",
+            "Worker status from the latest poll (1 for the current status)",
+            &samples,
+        );
+
+        if let Some(metadata) = &info.metadata {
+            let metadata = metadata.to_structured(&state.node_type);
+            metrics.gauge(
+                "dstack_gpus",
+                "GPUs reported by dstack",
+                &[(String::new(), metadata.gpu_count as f64)],
+            );
+            metrics.gauge(
+                "dstack_gpus_free",
+                "GPUs not attached to a CVM",
+                &[(String::new(), metadata.free_gpu_count as f64)],
+            );
+        }
+
+        if let Some(capability) = &info.capability {
+            let samples: Vec<_> = [
+                ("gpu", capability.gpu),
+                ("memory", capability.memory),
+                ("bandwidth", capability.bandwidth),
+            ]
+            .into_iter()
+            .map(|(component, value)| (format!("component=\"{}\"", component), value))
+            .collect();
+            metrics.gauge(
+                "dstack_capability_score",
+                "Capability score from the latest poll",
+                &[(String::new(), capability.score)],
+            );
+            metrics.gauge(
+                "dstack_capability_score_component",
+                "Components of the capability score",
+                &samples,
+            );
+        }
+    }
+
+    let stats = state.history.stats(state.history_max_gap());
+    metrics.counter(
+        "dstack_health_checks_total",
+        "dstack health checks since the backend started",
+        &[(String::new(), stats.checks as f64)],
+    );
+    metrics.gauge(
+        "dstack_health_consecutive_failures",
+        "Health checks in a row that found dstack unavailable",
+        &[(String::new(), stats.consecutive_failures as f64)],
+    );
+    let availability: Vec<_> = [
+        ("24h", stats.availability_24h),
+        ("7d", stats.availability_7d),
+    ]
+    .into_iter()
+    .filter_map(|(window, value)| Some((format!("window=\"{}\"", window), value? / 100.0)))
+    .collect();
+    metrics.gauge(
+        "dstack_availability_ratio",
+        "Share of recorded health checks that found dstack available",
+        &availability,
+    );
+    metrics.gauge(
+        "dstack_backend_start_time_seconds",
+        "Unix time the backend started",
+        &[(String::new(), stats.started_at as f64)],
+    );
+
+    let host = host::collect(&state.data_dir);
+    let value = |value: Option<f64>| -> Vec<(String, f64)> {
+        value
+            .map(|value| (String::new(), value))
+            .into_iter()
+            .collect()
+    };
+    metrics.gauge(
+        "dstack_host_cpu_cores",
+        "CPU cores available to the backend",
+        &value(host.cpu_cores.map(|cores| cores as f64)),
+    );
+    let load_average: Vec<_> = host
+        .load_average
+        .map(|load| {
+            ["1m", "5m", "15m"]
+                .into_iter()
+                .zip(load)
+                .map(|(period, load)| (format!("period=\"{}\"", period), load))
+                .collect()
+        })
+        .unwrap_or_default();
+    metrics.gauge(
+        "dstack_host_load_average",
+        "Host load average",
+        &load_average,
+    );
+    metrics.gauge(
+        "dstack_host_memory_total_bytes",
+        "Host memory",
+        &value(host.memory_total_bytes.map(|bytes| bytes as f64)),
+    );
+    metrics.gauge(
+        "dstack_host_memory_available_bytes",
+        "Host memory available for new workloads",
+        &value(host.memory_available_bytes.map(|bytes| bytes as f64)),
+    );
+    metrics.gauge(
+        "dstack_host_disk_total_bytes",
+        "Size of the filesystem holding DATA_DIR",
+        &value(host.disk_total_bytes.map(|bytes| bytes as f64)),
+    );
+    metrics.gauge(
+        "dstack_host_disk_free_bytes",
+        "Free space on the filesystem holding DATA_DIR",
+        &value(host.disk_free_bytes.map(|bytes| bytes as f64)),
+    );
+
+    let bandwidth = state.bandwidth.get();
+    metrics.gauge(
+        "dstack_bandwidth_download_mbps",
+        "Download throughput from the latest bandwidth test, in Mbit/s",
+        &value(bandwidth.as_ref().map(|result| result.download_mbps)),
+    );
+    metrics.gauge(
+        "dstack_bandwidth_upload_mbps",
+        "Upload throughput from the latest bandwidth test, in Mbit/s",
+        &value(bandwidth.as_ref().and_then(|result| result.upload_mbps)),
+    );
+    metrics.gauge(
+        "dstack_bandwidth_latency_seconds",
+        "Latency to the bandwidth test endpoint",
+        &value(
+            bandwidth
+                .as_ref()
+                .map(|result| result.latency_ms as f64 / 1000.0),
+        ),
+    );
+
+    let telemetry = state.gpu_telemetry.get();
+    let readings = |value: fn(&GpuTelemetry) -> Option<f64>| -> Vec<(String, f64)> {
+        telemetry
+            .iter()
+            .filter_map(|gpu| Some((format!("slot=\"{}\"", label(&gpu.slot)), value(gpu)?)))
+            .collect()
+    };
+    metrics.gauge(
+        "dstack_gpu_utilization_percent",
+        "GPU utilization",
+        &readings(|gpu| gpu.utilization_percent),
+    );
+    metrics.gauge(
+        "dstack_gpu_memory_used_bytes",
+        "GPU memory in use",
+        &readings(|gpu| Some(gpu.memory_used_mib? * 1024.0 * 1024.0)),
+    );
+    metrics.gauge(
+        "dstack_gpu_memory_total_bytes",
+        "Total GPU memory",
+        &readings(|gpu| Some(gpu.memory_total_mib? * 1024.0 * 1024.0)),
+    );
+    metrics.gauge(
+        "dstack_gpu_temperature_celsius",
+        "GPU core temperature",
+        &readings(|gpu| gpu.temperature_celsius),
+    );
+    metrics.gauge(
+        "dstack_gpu_power_draw_watts",
+        "GPU power draw",
+        &readings(|gpu| gpu.power_draw_watts),
+    );
+    let ecc_errors: Vec<_> = telemetry
+        .iter()
+        .flat_map(|gpu| {
+            [
+                ("corrected", gpu.ecc_corrected),
+                ("uncorrected", gpu.ecc_uncorrected),
+            ]
+            .into_iter()
+            .filter_map(|(kind, errors)| {
+                Some((
+                    format!("slot=\"{}\",type=\"{}\"", label(&gpu.slot), kind),
+                    errors? as f64,
+                ))
+            })
+        })
+        .collect();
+    metrics.counter(
+        "dstack_gpu_ecc_errors_total",
+        "Volatile ECC errors since the driver loaded",
+        &ecc_errors,
+    );
+    metrics.gauge(
+        "dstack_gpu_xid_errors",
+        "XID errors for the GPU in the kernel log",
+        &readings(|gpu| Some(gpu.xid_errors? as f64)),
+    );
+    metrics.gauge(
+        "dstack_gpu_fault",
+        "1 if the GPU reports uncorrected ECC errors or a hardware XID",
+        &readings(|gpu| Some(gpu.fault.is_some() as u8 as f64)),
+    );
+
+    ([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], metrics.out).into_response()
+}
@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::bandwidth::BandwidthCache;
+use crate::benchmark::Benchmarker;
+use crate::breaker::CircuitBreaker;
+use crate::config::Config;
+use crate::connection::DStackConnection;
+use crate::dstack::{method_name, DStackClient, DStackResponse, GpuInfo};
+use crate::dstack_info::DstackInfoCache;
+use crate::error::BackendError;
+use crate::gpu_changes::GpuChangeLog;
+use crate::gpu_telemetry::GpuTelemetryCache;
+use crate::history::HealthHistory;
+use crate::images::ImagePrefetcher;
+use crate::leases::GpuLeases;
+use crate::maintenance::MaintenanceMode;
+use crate::node_type::NodeTypeTable;
+use crate::poller::StatusPoller;
+use crate::public_ip::PublicIp;
+use crate::registry_events::RegistryLog;
+use crate::relay::RelayStats;
+use crate::reload::ReloadableConfig;
+use crate::rewards::RewardRateSource;
+use crate::server::AppState;
+use crate::stake::StakeStatus;
+use crate::whitelist::WhitelistStatus;
+
+/// In-memory dstack stand-in with canned responses per prpc method, for
+/// exercising the backend without a live dstack instance.
+#[derive(Default)]
+pub struct MockDStack {
+    responses: Mutex<HashMap<String, Result<serde_json::Value, BackendError>>>,
+    calls: Mutex<Vec<(String, Option<serde_json::Value>)>>,
+}
+
+impl MockDStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A mock whose `ListGpus` returns the given inventory.
+    pub fn with_gpus(gpus: Vec<GpuInfo>, allow_attach_all: bool) -> Self {
+        let mock = Self::new();
+        mock.set_gpus(gpus, allow_attach_all);
+        mock
+    }
+
+    pub fn set_gpus(&self, gpus: Vec<GpuInfo>, allow_attach_all: bool) {
+        let response = DStackResponse {
+            gpus,
+            allow_attach_all,
+        };
+        self.set_response(
+            "ListGpus",
+            serde_json::to_value(response).expect("DStackResponse serializes"),
+        );
+    }
+
+    /// Answers calls to `method` (e.g. `ListGpus` or `GetQuote`) with `response`.
+    pub fn set_response(&self, method: &str, response: serde_json::Value) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(method.to_string(), Ok(response));
+    }
+
+    /// Fails calls to `method` with `error`.
+    pub fn set_error(&self, method: &str, error: BackendError) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(method.to_string(), Err(error));
+    }
+
+    /// Methods called so far with their request bodies, oldest first.
+    pub fn calls(&self) -> Vec<(String, Option<serde_json::Value>)> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DStackClient for MockDStack {
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        let method = method_name(path);
+        self.calls
+            .lock()
+            .unwrap()
+            .push((method.to_string(), body.cloned()));
+
+        self.responses
+            .lock()
+            .unwrap()
+            .get(method)
+            .cloned()
+            .unwrap_or_else(|| {
+                Err(BackendError::Status(
+                    StatusCode::NOT_FOUND,
+                    format!("no response for {}", method),
+                ))
+            })
+    }
+
+    fn endpoint(&self) -> String {
+        "mock://dstack".to_string()
+    }
+}
+
+/// Backend state for `config` on `connection`, with a throwaway key, no
+/// relays and no background tasks, so `server::router` can be exercised
+/// in-process. `config.data_dir` should be a scratch directory.
+pub fn app_state(config: &Config, connection: DStackConnection) -> AppState {
+    let keys = Keys::generate();
+    let nostr_pubkey = keys.public_key().to_hex();
+    let signer: Arc<dyn NostrSigner> = Arc::new(keys);
+    let guest_agent = Arc::new(MockDStack::new());
+
+    AppState {
+        connection,
+        breaker: CircuitBreaker::new(config.dstack_breaker.clone()),
+        guest_agent,
+        pubkeys: config.pubkey_roles(&nostr_pubkey),
+        admin_auth: config.admin_auth(&nostr_pubkey),
+        nostr_pubkey,
+        topic: config.dephy_topic.clone(),
+        node_type: "Unknown".to_string(),
+        node_types: NodeTypeTable::new(config.node_types.clone()),
+        local_ip: None,
+        advertise_address: config.advertise_address,
+        interface_addresses: Vec::new(),
+        public_ip: PublicIp::default(),
+        bandwidth: BandwidthCache::default(),
+        benchmark: Benchmarker::open(config.benchmark.clone(), signer.clone(), &config.data_dir),
+        dstack_info: DstackInfoCache::default(),
+        owner_proof: None,
+        whitelist: WhitelistStatus::default(),
+        stake: StakeStatus::default(),
+        registry: config
+            .registry_watch()
+            .map(|_| RegistryLog::open(&config.data_dir)),
+        rewards: RewardRateSource::new(
+            config.reward_rates_url.clone(),
+            config.reward_rates.clone(),
+        ),
+        relay_stats: RelayStats::default(),
+        legacy_metadata: config.legacy_metadata,
+        reloadable: RwLock::new(ReloadableConfig::new(config)),
+        dstack_proxy_methods: config.dstack_proxy_methods.clone(),
+        dstack_proxy_auth: config.dstack_proxy_auth,
+        history: HealthHistory::open(&config.data_dir, config.health_history_max_entries),
+        maintenance: MaintenanceMode::open(&config.data_dir),
+        leases: GpuLeases::open(&config.data_dir, signer),
+        deploying: tokio::sync::Mutex::new(()),
+        images: ImagePrefetcher::new(config.image_pull_socket.clone()),
+        data_dir: config.data_dir.clone(),
+        gpu_changes: GpuChangeLog::open(&config.data_dir),
+        gpu_telemetry: GpuTelemetryCache::default(),
+        poller: StatusPoller::new(),
+        nostr_client: None,
+        sse_keepalive: config.sse_keepalive(),
+        concurrency_limits: config.concurrency.clone(),
+    }
+}
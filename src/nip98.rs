@@ -0,0 +1,36 @@
+//! NIP-98 HTTP auth: requests to the registrar carry an `Authorization:
+//! Nostr <base64 event>` header signed by the worker's Nostr identity, so the
+//! worker authenticates with its existing identity instead of an API token.
+//!
+//! <https://github.com/nostr-protocol/nips/blob/master/98.md>
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use nostr_sdk::hashes::{sha256, Hash};
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+use crate::replay::new_nonce;
+
+/// Builds the `Authorization` header value for a request to `url`. The event
+/// binds the URL, the method and, if there is one, the SHA-256 of the body,
+/// and carries a random `nonce` tag so receivers can reject replays.
+pub async fn authorization(
+    signer: &Arc<dyn NostrSigner>,
+    url: &str,
+    method: HttpMethod,
+    body: Option<&[u8]>,
+) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    let mut data = HttpData::new(url, method);
+    if let Some(body) = body {
+        data = data.payload(sha256::Hash::hash(body));
+    }
+
+    let event = EventBuilder::http_auth(data)
+        .tag(Tag::custom(TagKind::custom("nonce"), [new_nonce()]))
+        .sign(signer)
+        .await
+        .map_err(|e| format!("Failed to sign NIP-98 event: {}", e))?;
+    Ok(format!("Nostr {}", BASE64.encode(event.as_json())))
+}
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::dstack::{DStackResponse, GpuInfo};
+
+const DEFAULT_TABLE: &str = include_str!("node_types.toml");
+
+/// Maps GPUs to a model name by PCI product ID or description substring.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeTypeRule {
+    pub model: String,
+    #[serde(default)]
+    pub product_ids: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Compute relative to an H100, for the capability score.
+    #[serde(default)]
+    pub weight: Option<f64>,
+}
+
+impl NodeTypeRule {
+    fn matches(&self, gpu: &GpuInfo) -> bool {
+        // dstack may report the ID bare ("2335") or with the vendor ("10de:2335")
+        let product_id = gpu
+            .product_id
+            .rsplit(':')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let description = gpu.description.to_ascii_lowercase();
+
+        self.product_ids
+            .iter()
+            .any(|id| id.to_ascii_lowercase() == product_id)
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| description.contains(&pattern.to_ascii_lowercase()))
+    }
+}
+
+#[derive(Deserialize)]
+struct RuleFile {
+    node_types: Vec<NodeTypeRule>,
+}
+
+/// Ordered list of detection rules: configured rules first, then the
+/// embedded defaults.
+#[derive(Debug, Clone)]
+pub struct NodeTypeTable {
+    rules: Vec<NodeTypeRule>,
+}
+
+impl Default for NodeTypeTable {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl NodeTypeTable {
+    pub fn new(custom_rules: Vec<NodeTypeRule>) -> Self {
+        let mut rules = custom_rules;
+        rules.extend(default_rules());
+        Self { rules }
+    }
+
+    pub fn rules(&self) -> &[NodeTypeRule] {
+        &self.rules
+    }
+
+    pub fn model_for(&self, gpu: &GpuInfo) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(gpu))
+            .map(|rule| rule.model.as_str())
+    }
+
+    /// Capability weight of `gpu`, from the first matching rule that sets
+    /// one.
+    pub fn weight_for(&self, gpu: &GpuInfo) -> Option<f64> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(gpu))
+            .find_map(|rule| rule.weight)
+    }
+
+    /// Derives the node type from the dstack GPU inventory. GPUs without a
+    /// matching rule are reported by their raw description.
+    pub fn determine(&self, dstack_response: &DStackResponse) -> String {
+        let gpu_count = dstack_response.gpus.len();
+        if gpu_count == 0 {
+            return "CPU".to_string();
+        }
+
+        let first_gpu = &dstack_response.gpus[0];
+        let model = match self.model_for(first_gpu) {
+            Some(model) => model.to_string(),
+            None => {
+                let description = first_gpu.description.trim();
+                if description.is_empty() {
+                    return "Unknown".to_string();
+                }
+                warn!(
+                    "No node type rule matches GPU {:?} (product_id {}), reporting raw description",
+                    description, first_gpu.product_id
+                );
+                description.to_string()
+            }
+        };
+
+        format!("node-{}x{}", model, gpu_count)
+    }
+}
+
+pub fn default_rules() -> Vec<NodeTypeRule> {
+    toml::from_str::<RuleFile>(DEFAULT_TABLE)
+        .expect("embedded node type table is valid")
+        .node_types
+}
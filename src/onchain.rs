@@ -0,0 +1,154 @@
+use alloy::primitives::{Address, FixedBytes, TxHash};
+use alloy::providers::ProviderBuilder;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::chain::{Chain, ChainTag, DEFAULT_CHAIN};
+use crate::history::unix_timestamp;
+
+pub const REGISTRATION_TX_FILE: &str = "registration_tx.json";
+
+sol! {
+    #[sol(rpc)]
+    interface IWorkerRegistry {
+        function registerWorker(bytes32 nostrPubkey, string nodeType, address owner) external;
+
+        event WorkerRegistered(bytes32 indexed nostrPubkey, address indexed owner, string nodeType);
+        event WorkerBanned(bytes32 indexed nostrPubkey, string reason);
+        event WorkerUnbanned(bytes32 indexed nostrPubkey);
+    }
+}
+
+/// Settings for submitting the worker registration on-chain.
+#[derive(Debug, Clone)]
+pub struct OnchainRegistration {
+    pub chain: Chain,
+    pub contract: Address,
+    pub signer: PrivateKeySigner,
+}
+
+/// Record of a submitted registration transaction, stored in `DATA_DIR`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationTx {
+    pub tx_hash: TxHash,
+    pub block_number: Option<u64>,
+    /// Missing in records made before chains were tagged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<ChainTag>,
+    pub contract: Address,
+    pub nostr_pubkey: String,
+    pub node_type: String,
+    pub owner_address: Address,
+    pub submitted_at: u64,
+}
+
+impl RegistrationTx {
+    pub fn load(data_dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(data_dir.join(REGISTRATION_TX_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, data_dir: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(data_dir)?;
+        fs::write(
+            data_dir.join(REGISTRATION_TX_FILE),
+            serde_json::to_string_pretty(self)?,
+        )
+    }
+
+    /// The chain the registration was made on, `default` for old records.
+    pub fn on_chain(&self, chain: &Chain) -> bool {
+        match &self.chain {
+            Some(tag) => chain.matches(tag),
+            None => chain.name == DEFAULT_CHAIN,
+        }
+    }
+
+    fn matches(
+        &self,
+        chain: &Chain,
+        contract: Address,
+        nostr_pubkey: &str,
+        node_type: &str,
+        owner: Address,
+    ) -> bool {
+        self.on_chain(chain)
+            && self.contract == contract
+            && self.nostr_pubkey == nostr_pubkey
+            && self.node_type == node_type
+            && self.owner_address == owner
+    }
+}
+
+impl OnchainRegistration {
+    /// Submits `registerWorker` to the registry contract and waits for the
+    /// receipt. A registration already recorded in `data_dir` for the same
+    /// worker details is returned as-is instead of being resubmitted.
+    pub async fn register(
+        &self,
+        data_dir: &Path,
+        nostr_pubkey: &str,
+        node_type: &str,
+        owner: Address,
+    ) -> Result<RegistrationTx, Box<dyn std::error::Error>> {
+        if let Some(existing) = RegistrationTx::load(data_dir) {
+            if existing.matches(&self.chain, self.contract, nostr_pubkey, node_type, owner) {
+                info!(
+                    "Worker already registered on-chain on {} in tx {}",
+                    self.chain.name, existing.tx_hash
+                );
+                return Ok(existing);
+            }
+        }
+
+        let pubkey: FixedBytes<32> = nostr_pubkey.parse()?;
+        let provider = ProviderBuilder::new()
+            .wallet(self.signer.clone())
+            .connect_http(self.chain.url()?);
+        let chain = self.chain.tag(&provider).await?;
+        let registry = IWorkerRegistry::new(self.contract, &provider);
+
+        info!(
+            "Submitting on-chain registration on {} to {} from {}",
+            chain,
+            self.contract,
+            self.signer.address()
+        );
+        let pending = registry
+            .registerWorker(pubkey, node_type.to_string(), owner)
+            .send()
+            .await?;
+        info!("Registration transaction sent: {}", pending.tx_hash());
+
+        let receipt = pending.get_receipt().await?;
+        if !receipt.status() {
+            return Err(format!(
+                "registration transaction {} reverted",
+                receipt.transaction_hash
+            )
+            .into());
+        }
+
+        let record = RegistrationTx {
+            tx_hash: receipt.transaction_hash,
+            block_number: receipt.block_number,
+            chain: Some(chain),
+            contract: self.contract,
+            nostr_pubkey: nostr_pubkey.to_string(),
+            node_type: node_type.to_string(),
+            owner_address: owner,
+            submitted_at: unix_timestamp(),
+        };
+        record.save(data_dir)?;
+        info!(
+            "Registered on-chain on {} in tx {} (block {:?})",
+            self.chain.name, record.tx_hash, record.block_number
+        );
+
+        Ok(record)
+    }
+}
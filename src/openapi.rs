@@ -0,0 +1,87 @@
+//! OpenAPI description of the backend's HTTP API, served at
+//! `/openapi.json` with a Swagger UI at `/docs`.
+
+use std::sync::Arc;
+
+use axum::Router;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::server::AppState;
+use crate::{
+    benchmark, connection, gpus, images, leases, maintenance, metrics, probes, proxy, prpc,
+    registry_events, relay, rewards, server, stream, vms,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "dstack Backend"),
+    servers(
+        (url = "/api/v1"),
+        (url = "/", description = "Unversioned aliases, see the `API-Version` header"),
+    ),
+    paths(
+        server::health_handler,
+        server::health_history_handler,
+        server::gpu_changes_handler,
+        server::attestation_handler,
+        stream::sse_handler,
+        stream::ws_handler,
+        metrics::metrics_handler,
+        probes::livez_handler,
+        probes::readyz_handler,
+        relay::relays_handler,
+        registry_events::registry_events_handler,
+        rewards::rewards_estimate_handler,
+        vms::vms_handler,
+        vms::deploy_vm_handler,
+        vms::remove_vm_handler,
+        images::prefetch_handler,
+        images::prefetch_status_handler,
+        benchmark::benchmark_handler,
+        benchmark::start_benchmark_handler,
+        gpus::attach_gpu_handler,
+        gpus::detach_gpu_handler,
+        leases::reserve_gpu_handler,
+        leases::release_gpu_handler,
+        leases::leases_handler,
+        maintenance::drain_handler,
+        maintenance::undrain_handler,
+        connection::set_dstack_url_handler,
+        proxy::proxy_get_handler,
+        proxy::proxy_post_handler,
+        prpc::prpc_handler,
+    ),
+    components(schemas(benchmark::BenchmarkResult)),
+    modifiers(&AdminToken),
+    tags(
+        (name = "health", description = "Worker status"),
+        (name = "gpus", description = "GPU inventory and benchmarks"),
+        (name = "worker", description = "Worker identity"),
+        (name = "probes", description = "Liveness and readiness"),
+        (name = "admin", description = "Needs an admin bearer token"),
+        (name = "dstack", description = "Read-only dstack prpc proxy"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the `admin_token` bearer scheme used by the admin routes.
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// `/openapi.json` and the Swagger UI at `/docs`.
+pub fn routes() -> Router<Arc<AppState>> {
+    SwaggerUi::new("/docs")
+        .url("/openapi.json", ApiDoc::openapi())
+        .into()
+}
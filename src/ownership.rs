@@ -0,0 +1,110 @@
+use alloy::primitives::{Address, Signature};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+pub const OWNER_SIGNATURE_FILE: &str = "owner_signature";
+
+/// Builds the message the owner signs to prove control of `OWNER_ADDRESS`.
+pub fn ownership_challenge(nostr_pubkey: &str, node_type: &str, owner: Address) -> String {
+    format!(
+        "dstack mining worker ownership\nNostr pubkey: {}\nNode type: {}\nOwner: {}",
+        nostr_pubkey, node_type, owner
+    )
+}
+
+/// EIP-191 (`personal_sign`) signature of the ownership challenge.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OwnershipProof {
+    pub scheme: String,
+    pub message: String,
+    pub signature: String,
+}
+
+impl OwnershipProof {
+    pub fn sign(
+        signer: &PrivateKeySigner,
+        nostr_pubkey: &str,
+        node_type: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let message = ownership_challenge(nostr_pubkey, node_type, signer.address());
+        let signature = signer.sign_message_sync(message.as_bytes())?;
+
+        Ok(Self {
+            scheme: "eip191".to_string(),
+            message,
+            signature: signature.to_string(),
+        })
+    }
+
+    /// Checks that the signature over the message recovers to `owner`.
+    pub fn verify(&self, owner: Address) -> Result<(), String> {
+        let signature: Signature = self
+            .signature
+            .parse()
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+        let recovered = signature
+            .recover_address_from_msg(self.message.as_bytes())
+            .map_err(|e| format!("Failed to recover signer: {}", e))?;
+
+        if recovered != owner {
+            return Err(format!(
+                "signature was made by {}, not the owner {}",
+                recovered, owner
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the owner's signature from config or `DATA_DIR/owner_signature`
+/// and verifies it against the current challenge. Returns `None` (with a
+/// log message) if no valid proof is available.
+pub fn load_ownership_proof(
+    configured_signature: Option<&str>,
+    data_dir: &Path,
+    nostr_pubkey: &str,
+    node_type: &str,
+    owner: Address,
+) -> Option<OwnershipProof> {
+    let signature = match configured_signature {
+        Some(signature) => signature.trim().to_string(),
+        None => match fs::read_to_string(data_dir.join(OWNER_SIGNATURE_FILE)) {
+            Ok(signature) => signature.trim().to_string(),
+            Err(_) => {
+                info!("No owner signature configured, ownership proof disabled");
+                return None;
+            }
+        },
+    };
+
+    let proof = OwnershipProof {
+        scheme: "eip191".to_string(),
+        message: ownership_challenge(nostr_pubkey, node_type, owner),
+        signature,
+    };
+
+    match proof.verify(owner) {
+        Ok(()) => {
+            info!("Verified ownership proof for owner {}", owner);
+            Some(proof)
+        }
+        Err(e) => {
+            error!(
+                "Owner signature does not match the current challenge: {}",
+                e
+            );
+            warn!("Re-sign the challenge printed by `dstack-backend owner-proof`");
+            None
+        }
+    }
+}
+
+pub fn save_owner_signature(data_dir: &Path, signature: &str) -> std::io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    fs::write(data_dir.join(OWNER_SIGNATURE_FILE), signature)
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::health::{check_dstack_health, poll_dstack_health, BackendInfo};
+use crate::server::AppState;
+use crate::systemd;
+
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Latest health snapshot from the background poller, plus a broadcast
+/// channel that fires whenever the status or GPU availability changes.
+pub struct StatusPoller {
+    /// The snapshot and the number of the check that produced it.
+    latest: RwLock<Option<(u64, BackendInfo)>>,
+    /// Number of the last check started.
+    checks: AtomicU64,
+    changes: broadcast::Sender<BackendInfo>,
+}
+
+impl Default for StatusPoller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusPoller {
+    pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            latest: RwLock::new(None),
+            checks: AtomicU64::new(0),
+            changes,
+        }
+    }
+
+    pub fn latest(&self) -> Option<BackendInfo> {
+        self.latest
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(_, info)| info.clone())
+    }
+
+    /// Numbers a check about to query dstack, to pass to
+    /// [`update`](Self::update) with its result.
+    pub fn start_check(&self) -> u64 {
+        self.checks.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BackendInfo> {
+        self.changes.subscribe()
+    }
+
+    /// Stores the result of `check` and broadcasts it if it differs from the
+    /// previous snapshot. A check that started before the one behind the
+    /// current snapshot is ignored, so a slow poll can't overwrite a newer
+    /// result, e.g. one taken right after a GPU was leased. Returns whether
+    /// anything changed.
+    pub fn update(&self, check: u64, info: BackendInfo) -> bool {
+        let mut latest = self.latest.write().unwrap();
+        let changed = match latest.as_ref() {
+            Some((latest_check, _)) if *latest_check > check => return false,
+            Some((_, previous)) => {
+                previous.status != info.status || previous.metadata != info.metadata
+            }
+            None => true,
+        };
+
+        if changed {
+            // No subscribers is not an error, the snapshot is still cached
+            let _ = self.changes.send(info.clone());
+        }
+        *latest = Some((check, info));
+        changed
+    }
+}
+
+/// The latest snapshot, which `/health` and the other status endpoints
+/// serve. Before the first poll, dstack is checked right away instead.
+pub async fn latest_health(state: &AppState) -> BackendInfo {
+    if let Some(info) = state.poller.latest() {
+        return info;
+    }
+    check_and_update(state).await
+}
+
+/// Checks dstack right away and stores the result as the snapshot, unless
+/// a check started later has already finished.
+pub async fn check_and_update(state: &AppState) -> BackendInfo {
+    let check = state.poller.start_check();
+    let info = check_dstack_health(state).await;
+    state.poller.update(check, info.clone());
+    info
+}
+
+/// Checks dstack in the background so the snapshot reflects a change made
+/// through the API, e.g. a lease or a drain, without waiting for the next
+/// poll.
+pub fn refresh_status(state: &Arc<AppState>) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        check_and_update(&state).await;
+    });
+}
+
+/// Polls dstack every `poll_interval_secs` and feeds the results to the
+/// poller. A reloaded interval applies from the next poll.
+pub async fn run_poller(state: Arc<AppState>) {
+    let mut interval = state.settings().poll_interval();
+    let mut ticker = tokio::time::interval(interval);
+    systemd::check_poll_interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let reloaded = state.settings().poll_interval();
+        if reloaded != interval {
+            interval = reloaded;
+            ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+            systemd::check_poll_interval(interval);
+        }
+
+        let check = state.poller.start_check();
+        let info = poll_dstack_health(&state).await;
+        let status = info.status;
+
+        // Diff inventories only between successful responses, an
+        // unreachable dstack doesn't mean the GPUs are gone
+        if let Some(metadata) = &info.metadata {
+            let metadata = metadata.to_structured(&state.node_type);
+            if metadata.errors.is_empty() {
+                state.gpu_changes.observe(&metadata.gpus);
+            }
+        }
+
+        if state.poller.update(check, info) {
+            info!("Worker status changed: {}", status);
+        }
+        systemd::ping_watchdog();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::DephyWorkerRespondedStatus;
+    use serde_json::json;
+
+    fn info(status: DephyWorkerRespondedStatus) -> BackendInfo {
+        serde_json::from_value(json!({
+            "version": "0.1.0",
+            "topic": "/dephy/dstack",
+            "pubkeys": [],
+            "status": status,
+            "metadata": null,
+            "ip_address": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn a_check_finishing_after_a_newer_one_is_ignored() {
+        let poller = StatusPoller::new();
+        let mut changes = poller.subscribe();
+
+        let slow = poller.start_check();
+        let fast = poller.start_check();
+        assert!(poller.update(fast, info(DephyWorkerRespondedStatus::Unavailable)));
+        assert!(!poller.update(slow, info(DephyWorkerRespondedStatus::Available)));
+
+        let latest = poller.latest().unwrap();
+        assert_eq!(latest.status, DephyWorkerRespondedStatus::Unavailable);
+        assert_eq!(
+            changes.try_recv().unwrap().status,
+            DephyWorkerRespondedStatus::Unavailable
+        );
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn a_newer_check_replaces_the_snapshot() {
+        let poller = StatusPoller::new();
+
+        let first = poller.start_check();
+        poller.update(first, info(DephyWorkerRespondedStatus::Available));
+        let second = poller.start_check();
+        assert!(poller.update(second, info(DephyWorkerRespondedStatus::Maintenance)));
+
+        let latest = poller.latest().unwrap();
+        assert_eq!(latest.status, DephyWorkerRespondedStatus::Maintenance);
+    }
+}
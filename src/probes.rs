@@ -0,0 +1,105 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::dstack::fetch_dstack_data;
+use crate::server::AppState;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProbeCheck {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ProbeCheck {
+    fn pass(detail: Option<String>) -> Self {
+        Self { ok: true, detail }
+    }
+
+    fn fail(detail: String) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub dstack: ProbeCheck,
+    pub nostr_key: ProbeCheck,
+    pub relays: ProbeCheck,
+}
+
+/// Liveness: the process is up and serving requests. Never checks dstack.
+#[utoipa::path(
+    get,
+    path = "/livez",
+    tag = "probes",
+    responses((status = 200, description = "The process is up", body = String, content_type = "text/plain"))
+)]
+pub async fn livez_handler() -> &'static str {
+    "ok"
+}
+
+/// Readiness: dstack answers, the Nostr key is loaded and, if relays are
+/// configured, at least one of them is connected.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Ready", body = Readiness),
+        (status = 503, description = "Not ready", body = Readiness),
+    )
+)]
+pub async fn readyz_handler(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let dstack = match state
+        .breaker
+        .call(fetch_dstack_data(state.connection.client().as_ref()))
+        .await
+    {
+        Ok(_) => ProbeCheck::pass(None),
+        Err(e) => ProbeCheck::fail(e.to_string()),
+    };
+
+    let nostr_key = if state.nostr_pubkey.is_empty() {
+        ProbeCheck::fail("no Nostr key loaded".to_string())
+    } else {
+        ProbeCheck::pass(None)
+    };
+
+    let relays = match &state.nostr_client {
+        None => ProbeCheck::pass(Some("no relays configured".to_string())),
+        Some(client) => {
+            let relays = client.relays().await;
+            let connected = relays.values().filter(|relay| relay.is_connected()).count();
+            let detail = format!("{}/{} relays connected", connected, relays.len());
+            if connected > 0 {
+                ProbeCheck::pass(Some(detail))
+            } else {
+                ProbeCheck::fail(detail)
+            }
+        }
+    };
+
+    let ready = dstack.ok && nostr_key.ok && relays.ok;
+    let status_code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status_code,
+        Json(Readiness {
+            ready,
+            dstack,
+            nostr_key,
+            relays,
+        }),
+    )
+}
@@ -0,0 +1,74 @@
+//! Protobuf messages from `proto/worker.proto`, shared by the gRPC service
+//! and the `/prpc` endpoints.
+
+use crate::dstack::{DStackResponse, GpuInfo};
+use crate::health::{BackendInfo, DephyWorkerRespondedStatus};
+
+include!(concat!(env!("OUT_DIR"), "/dstack_backend.v1.rs"));
+
+impl Health {
+    /// Converts a health snapshot, using `node_type` if its metadata
+    /// doesn't carry one.
+    pub fn from_info(info: BackendInfo, node_type: &str) -> Self {
+        let metadata = info
+            .metadata
+            .map(|metadata| metadata.to_structured(node_type))
+            .unwrap_or_default();
+        let maintenance = metadata.maintenance;
+
+        Self {
+            version: info.version,
+            topic: info.topic,
+            pubkeys: info.pubkeys.into_iter().collect(),
+            status: Status::from(info.status).into(),
+            ip_address: info.ip_address,
+            public_ip: info.public_ip,
+            node_type: metadata.node_type,
+            gpu_count: metadata.gpu_count as u32,
+            free_gpu_count: metadata.free_gpu_count as u32,
+            gpus: metadata.gpus.into_iter().map(Gpu::from).collect(),
+            allow_attach_all: metadata.allow_attach_all,
+            errors: metadata.errors,
+            error_code: metadata.error_code,
+            degraded_reasons: metadata.degraded_reasons,
+            maintenance_reason: maintenance.as_ref().and_then(|m| m.reason.clone()),
+            maintenance_since: maintenance.map(|m| m.since),
+            capability_score: info.capability.map(|capability| capability.score),
+            whitelisted: info.whitelisted,
+            stake_ok: info.stake_ok,
+            staked_amount: info.staked_amount,
+            banned: info.banned,
+        }
+    }
+}
+
+impl From<DephyWorkerRespondedStatus> for Status {
+    fn from(status: DephyWorkerRespondedStatus) -> Self {
+        match status {
+            DephyWorkerRespondedStatus::Available => Self::Available,
+            DephyWorkerRespondedStatus::Unavailable => Self::Unavailable,
+            DephyWorkerRespondedStatus::Degraded => Self::Degraded,
+            DephyWorkerRespondedStatus::Maintenance => Self::Maintenance,
+        }
+    }
+}
+
+impl From<GpuInfo> for Gpu {
+    fn from(gpu: GpuInfo) -> Self {
+        Self {
+            slot: gpu.slot,
+            product_id: gpu.product_id,
+            description: gpu.description,
+            is_free: gpu.is_free,
+        }
+    }
+}
+
+impl From<DStackResponse> for ListGpusResponse {
+    fn from(response: DStackResponse) -> Self {
+        Self {
+            gpus: response.gpus.into_iter().map(Gpu::from).collect(),
+            allow_attach_all: response.allow_attach_all,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    Json,
+};
+use std::sync::Arc;
+use tracing::error;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::server::AppState;
+
+/// prpc methods reachable through `/dstack/{method}` unless configured
+/// otherwise. All of them are read-only.
+pub const DEFAULT_PROXY_METHODS: &[&str] = &["Info", "Version", "Status", "ListGpus"];
+
+/// Whether a prpc method name is safe to splice into a request path.
+pub fn is_valid_method(method: &str) -> bool {
+    !method.is_empty() && method.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+async fn forward(
+    state: &AppState,
+    method: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !state.dstack_proxy_methods.iter().any(|m| m == method) {
+        return Err(ApiError::not_found(format!(
+            "dstack method {} is not exposed",
+            method
+        )));
+    }
+
+    state
+        .connection
+        .client()
+        .request(&format!("/prpc/{}?json", method), body)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("dstack proxy call {} failed: {}", method, e);
+            ApiError::from(e)
+        })
+}
+
+/// Forwards a parameterless prpc call to dstack.
+#[utoipa::path(
+    get,
+    path = "/dstack/{method}",
+    tag = "dstack",
+    params(("method" = String, Path, description = "prpc method, e.g. `ListGpus`")),
+    responses(
+        (status = 200, description = "dstack's response", body = serde_json::Value),
+        (status = 404, description = "The method is not exposed", body = ErrorBody),
+    )
+)]
+pub async fn proxy_get_handler(
+    State(state): State<Arc<AppState>>,
+    Path(method): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    forward(&state, &method, None).await
+}
+
+/// Forwards a prpc call with a JSON request body to dstack.
+#[utoipa::path(
+    post,
+    path = "/dstack/{method}",
+    tag = "dstack",
+    params(("method" = String, Path, description = "prpc method, e.g. `ListGpus`")),
+    request_body = serde_json::Value,
+    responses(
+        (status = 200, description = "dstack's response", body = serde_json::Value),
+        (status = 404, description = "The method is not exposed", body = ErrorBody),
+    )
+)]
+pub async fn proxy_post_handler(
+    State(state): State<Arc<AppState>>,
+    Path(method): Path<String>,
+    body: Result<Json<serde_json::Value>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Json(body) = body?;
+    forward(&state, &method, Some(&body)).await
+}
@@ -0,0 +1,130 @@
+//! The backend's own API in dstack's prpc convention, so tooling written
+//! against dstack agents can call it without an adapter: `/prpc/{method}`
+//! takes and returns protobuf, or JSON with `?json`.
+
+use axum::body::Bytes;
+use axum::extract::{Path, RawQuery, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use prost::Message;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::api_version::ApiVersion;
+use crate::dstack::fetch_dstack_data;
+use crate::error::{ApiError, ErrorBody};
+use crate::health::BackendInfo;
+use crate::poller::latest_health;
+use crate::proto;
+use crate::server::AppState;
+
+/// Methods served under `/prpc`.
+pub const PRPC_METHODS: &[&str] = &["GetWorkerInfo", "ListGpus"];
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Json,
+    Protobuf,
+}
+
+impl Encoding {
+    /// JSON with a `json` query flag, like dstack's `?json`, protobuf
+    /// otherwise.
+    fn from_query(query: Option<&str>) -> Self {
+        let json = query.is_some_and(|query| {
+            query
+                .split('&')
+                .any(|pair| pair.split('=').next() == Some("json"))
+        });
+        if json {
+            Self::Json
+        } else {
+            Self::Protobuf
+        }
+    }
+
+    /// Checks the request body. Every method takes an empty message, so a
+    /// valid body is only decoded to reject malformed requests.
+    fn check_request<M: Message + Default>(self, body: &[u8]) -> Result<(), ApiError> {
+        if body.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Self::Json => serde_json::from_slice::<serde_json::Value>(body)
+                .map(|_| ())
+                .map_err(|e| ApiError::bad_request(format!("Invalid JSON request: {}", e))),
+            Self::Protobuf => M::decode(body)
+                .map(|_| ())
+                .map_err(|e| ApiError::bad_request(format!("Invalid protobuf request: {}", e))),
+        }
+    }
+
+    fn respond(self, json: impl serde::Serialize, message: impl Message) -> Response {
+        match self {
+            Self::Json => Json(json).into_response(),
+            Self::Protobuf => (
+                [(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)],
+                message.encode_to_vec(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Calls a backend method prpc-style. `GetWorkerInfo` returns the health
+/// snapshot (`BackendInfo` as JSON, `Health` as protobuf) and `ListGpus` the
+/// dstack GPU inventory. Unlike `GET /health`, the status is only reported
+/// in the body, so prpc clients don't treat an unavailable worker as a
+/// failed call.
+#[utoipa::path(
+    post,
+    path = "/prpc/{method}",
+    tag = "worker",
+    params(
+        ("method" = String, Path, description = "`GetWorkerInfo` or `ListGpus`"),
+        ("json" = Option<String>, Query, description = "Present for JSON, protobuf otherwise"),
+    ),
+    request_body(content = Vec<u8>, description = "Empty, or an empty message in the response encoding", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "`BackendInfo` or dstack's GPU list, as JSON with `?json`, protobuf otherwise", body = BackendInfo),
+        (status = 400, description = "Malformed request body", body = ErrorBody),
+        (status = 404, description = "Unknown method", body = ErrorBody),
+    )
+)]
+pub async fn prpc_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(version): Extension<ApiVersion>,
+    Path(method): Path<String>,
+    RawQuery(query): RawQuery,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let encoding = Encoding::from_query(query.as_deref());
+    match method.as_str() {
+        "GetWorkerInfo" => {
+            encoding.check_request::<proto::GetHealthRequest>(&body)?;
+            let info = latest_health(&state).await;
+            let message = proto::Health::from_info(info.clone(), &state.node_type);
+            Ok(encoding.respond(version.present(info, state.legacy_metadata), message))
+        }
+        "ListGpus" => {
+            encoding.check_request::<proto::ListGpusRequest>(&body)?;
+            let response = state
+                .breaker
+                .call(fetch_dstack_data(state.connection.client().as_ref()))
+                .await
+                .map_err(|e| {
+                    error!("prpc ListGpus failed: {}", e);
+                    ApiError::from(e)
+                })?;
+            Ok(encoding.respond(&response, proto::ListGpusResponse::from(response.clone())))
+        }
+        _ => Err(ApiError::not_found(format!(
+            "Unknown prpc method {}, expected one of: {}",
+            method,
+            PRPC_METHODS.join(", ")
+        ))),
+    }
+}
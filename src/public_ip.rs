@@ -0,0 +1,213 @@
+use rand::RngCore;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::{debug, info, warn};
+
+use crate::server::AppState;
+
+/// STUN magic cookie (RFC 5389).
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+const STUN_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Discovery of the address the worker is reachable at from outside its
+/// NAT, by asking external resolvers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublicIpConfig {
+    pub enabled: bool,
+    /// Resolvers tried in order until one answers: `stun://host:port`, or an
+    /// http(s) URL that answers with the caller's IP as plain text.
+    pub resolvers: Vec<String>,
+    /// Time allowed for each resolver.
+    pub timeout_ms: u64,
+    /// How often the address is looked up again.
+    pub refresh_secs: u64,
+}
+
+impl Default for PublicIpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            resolvers: vec![
+                "stun://stun.l.google.com:19302".to_string(),
+                "https://api.ipify.org".to_string(),
+                "https://ifconfig.me/ip".to_string(),
+            ],
+            timeout_ms: 3000,
+            refresh_secs: 3600,
+        }
+    }
+}
+
+/// The most recently discovered public IP.
+#[derive(Debug, Default)]
+pub struct PublicIp {
+    current: RwLock<Option<IpAddr>>,
+}
+
+impl PublicIp {
+    pub fn get(&self) -> Option<IpAddr> {
+        *self.current.read().unwrap()
+    }
+
+    /// Stores `ip`, returning whether it changed.
+    fn set(&self, ip: IpAddr) -> bool {
+        self.current.write().unwrap().replace(ip) != Some(ip)
+    }
+}
+
+/// Asks each resolver in turn and returns the first answer.
+pub async fn discover(config: &PublicIpConfig) -> Option<IpAddr> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    for resolver in &config.resolvers {
+        match tokio::time::timeout(timeout, resolve(resolver, timeout)).await {
+            Ok(Ok(ip)) => {
+                debug!("Public IP {} from {}", ip, resolver);
+                return Some(ip);
+            }
+            Ok(Err(e)) => warn!("Public IP resolver {} failed: {}", resolver, e),
+            Err(_) => warn!("Public IP resolver {} timed out", resolver),
+        }
+    }
+    None
+}
+
+async fn resolve(resolver: &str, timeout: Duration) -> Result<IpAddr, String> {
+    if let Some(server) = resolver.strip_prefix("stun://") {
+        return stun_binding(server).await;
+    }
+
+    let body = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| e.to_string())?
+        .get(resolver)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    body.trim()
+        .parse()
+        .map_err(|_| format!("not an IP address: {:?}", body.trim()))
+}
+
+/// Sends a STUN binding request and returns the mapped address the server
+/// saw the request come from.
+async fn stun_binding(server: &str) -> Result<IpAddr, String> {
+    let server = lookup_host(server)
+        .await
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} didn't resolve", server))?;
+    let bind = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+
+    let mut transaction_id = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut transaction_id);
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+    socket
+        .send_to(&request, server)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut buf = [0u8; 576];
+    loop {
+        let (len, from) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| e.to_string())?;
+        if from != server {
+            continue;
+        }
+        if let Some(ip) = parse_binding_response(&buf[..len], &transaction_id) {
+            return Ok(ip);
+        }
+    }
+}
+
+fn parse_binding_response(message: &[u8], transaction_id: &[u8; 12]) -> Option<IpAddr> {
+    if message.len() < 20
+        || u16::from_be_bytes([message[0], message[1]]) != STUN_BINDING_RESPONSE
+        || message[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || message[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+
+    let mut mapped = None;
+    let mut attributes = &message[20..];
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + len)?;
+        match kind {
+            STUN_XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+            STUN_MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes
+        let padded = (4 + len).div_ceil(4) * 4;
+        attributes = attributes.get(padded..).unwrap_or_default();
+    }
+    mapped
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value; the address is XORed with the
+/// magic cookie and transaction ID when `xor_with` is set.
+fn parse_address(value: &[u8], xor_with: Option<&[u8; 12]>) -> Option<IpAddr> {
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = xor_with {
+        mask[..4].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+
+    match value.get(1)? {
+        0x01 => {
+            let octets: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            let octets: [u8; 4] = std::array::from_fn(|i| octets[i] ^ mask[i]);
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        0x02 => {
+            let octets: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            let octets: [u8; 16] = std::array::from_fn(|i| octets[i] ^ mask[i]);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Looks up the public IP at startup and then every `refresh_secs`. A
+/// failed lookup keeps the previous address.
+pub async fn run_public_ip_refresh(state: Arc<AppState>, config: PublicIpConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.refresh_secs));
+
+    loop {
+        ticker.tick().await;
+
+        match discover(&config).await {
+            Some(ip) => {
+                if state.public_ip.set(ip) {
+                    info!("Public IP: {}", ip);
+                }
+            }
+            None => warn!("Failed to discover the public IP from any resolver"),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use nostr_sdk::prelude::{Event, HttpMethod, NostrSigner};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::attestation::{get_quote, Attestation};
+use crate::dstack::DStackClient;
+use crate::eip712::TypedRegistration;
+use crate::health::PubkeyRole;
+use crate::nip98;
+use crate::ownership::OwnershipProof;
+use crate::score::CapabilityScore;
+
+/// Information an administrator needs to whitelist this worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationInfo {
+    pub nostr_pubkey: String,
+    /// Additional keys of the worker with their roles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pubkeys: Vec<PubkeyRole>,
+    pub owner_address: String,
+    /// The ENS name `owner_address` was resolved from, if one was configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_ens: Option<String>,
+    pub node_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_proof: Option<OwnershipProof>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capability: Option<CapabilityScore>,
+    /// The latest signed benchmark result, if one has been run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub benchmark: Option<Event>,
+    /// The chain selected with `CHAIN`, which on-chain checks ran against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    /// Whether the owner's stake meets the minimum, with stake verification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stake_ok: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staked_amount: Option<String>,
+    /// The owner's EIP-712 signature of the registration, from `register`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eip712: Option<TypedRegistration>,
+}
+
+impl RegistrationInfo {
+    /// Logs the registration banner operators copy into manual registration.
+    pub fn log_manual_registration(&self) {
+        info!("==================================================================");
+        info!("MANUAL REGISTRATION REQUIRED");
+        info!("Please provide the following information to the administrator:");
+        info!("Nostr Public Key: {}", self.nostr_pubkey);
+        info!("Owner Address:    {}", self.owner_address);
+        if let Some(name) = &self.owner_ens {
+            info!("Owner ENS Name:   {}", name);
+        }
+        info!("Node Type:        {}", self.node_type);
+        info!("==================================================================");
+    }
+
+    /// Attaches a TDX quote from the guest agent as proof of the worker
+    /// identity. Registration proceeds without one if the quote fails.
+    pub async fn attach_attestation(&mut self, guest_agent: &dyn DStackClient) {
+        match get_quote(guest_agent, &self.nostr_pubkey).await {
+            Ok(attestation) => self.attestation = Some(attestation),
+            Err(e) => warn!("Registering without attestation: {}", e),
+        }
+    }
+
+    /// POSTs the registration info as JSON to a registrar endpoint,
+    /// authenticated with a NIP-98 event signed by the worker's key.
+    pub async fn submit(
+        &self,
+        registrar_url: &str,
+        signer: &Arc<dyn NostrSigner>,
+    ) -> Result<String, String> {
+        let body = serde_json::to_vec(self)
+            .map_err(|e| format!("Failed to serialize registration: {}", e))?;
+        SignedRequest::new(registrar_url, body, signer)
+            .await?
+            .send()
+            .await
+    }
+}
+
+/// A worker-initiated request to be taken off the whitelist, for workers
+/// being decommissioned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeregistrationRequest {
+    pub nostr_pubkey: String,
+    pub owner_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Unix time the request was made.
+    pub requested_at: u64,
+    /// Random value so the registrar can reject a replayed request.
+    pub nonce: String,
+}
+
+impl DeregistrationRequest {
+    /// Signs the request for `url`. The NIP-98 event binds the body, so the
+    /// registrar can tell the worker key itself asked to leave.
+    pub async fn sign(
+        &self,
+        url: &str,
+        signer: &Arc<dyn NostrSigner>,
+    ) -> Result<SignedRequest, String> {
+        let body = serde_json::to_vec(self)
+            .map_err(|e| format!("Failed to serialize deregistration: {}", e))?;
+        SignedRequest::new(url, body, signer).await
+    }
+}
+
+/// The deregistration endpoint next to the registration one, e.g.
+/// `https://registry.example.com/api/deregister` for `.../api/register`.
+pub fn deregistration_url(registrar_url: &str) -> Result<String, String> {
+    Url::parse(registrar_url)
+        .and_then(|url| url.join("deregister"))
+        .map(String::from)
+        .map_err(|e| format!("Invalid registrar URL {}: {}", registrar_url, e))
+}
+
+/// A JSON POST with its NIP-98 `Authorization` header, ready to send or to
+/// hand to someone else to send.
+#[derive(Debug, Clone)]
+pub struct SignedRequest {
+    pub url: String,
+    pub authorization: String,
+    pub body: Vec<u8>,
+}
+
+impl SignedRequest {
+    async fn new(url: &str, body: Vec<u8>, signer: &Arc<dyn NostrSigner>) -> Result<Self, String> {
+        let authorization =
+            nip98::authorization(signer, url, HttpMethod::POST, Some(&body)).await?;
+        Ok(Self {
+            url: url.to_string(),
+            authorization,
+            body,
+        })
+    }
+
+    /// POSTs the request and returns the response body.
+    pub async fn send(self) -> Result<String, String> {
+        let response = reqwest::Client::new()
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, self.authorization)
+            .body(self.body)
+            .send()
+            .await
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read response body: {}", e))?;
+
+        if !status.is_success() {
+            return Err(format!("HTTP error: {}: {}", status, body));
+        }
+
+        Ok(body)
+    }
+}
+
+/// Submits the registration to the configured registrar, falling back to
+/// logging the manual registration banner if submission fails.
+pub async fn auto_register(
+    mut registration: RegistrationInfo,
+    registrar_url: &str,
+    signer: &Arc<dyn NostrSigner>,
+    guest_agent: &dyn DStackClient,
+) {
+    registration.attach_attestation(guest_agent).await;
+
+    info!("Submitting registration to {}", registrar_url);
+    match registration.submit(registrar_url, signer).await {
+        Ok(_) => {
+            info!("Registration submitted, pending administrator approval");
+            info!("Nostr Public Key: {}", registration.nostr_pubkey);
+        }
+        Err(e) => {
+            error!("Failed to submit registration to {}: {}", registrar_url, e);
+            registration.log_manual_registration();
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Follows the worker registry contract for events about this worker:
+//! registration, bans and unbans. A banned worker stops advertising itself
+//! as available. Events are polled with `eth_getLogs`, and the latest
+//! events and the scanned block are kept in `DATA_DIR/registry_events.json`.
+
+use alloy::primitives::{Address, B256};
+use alloy::providers::{Provider, ProviderBuilder};
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::chain::{Chain, ChainTag};
+use crate::error::{ApiError, ErrorBody};
+use crate::onchain::IWorkerRegistry::{WorkerBanned, WorkerRegistered, WorkerUnbanned};
+use crate::onchain::RegistrationTx;
+use crate::server::AppState;
+
+pub const REGISTRY_EVENTS_FILE: &str = "registry_events.json";
+
+/// Events kept for `/registry/events`.
+const MAX_EVENTS: usize = 100;
+
+/// Blocks per `eth_getLogs` call, within the limits of common RPC providers.
+const MAX_BLOCK_RANGE: u64 = 10_000;
+
+/// Settings for following the registry contract.
+#[derive(Debug, Clone)]
+pub struct RegistryWatch {
+    pub chain: Chain,
+    pub contract: Address,
+    /// First block to scan when nothing was scanned yet.
+    pub start_block: Option<u64>,
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistryEventKind {
+    Registered,
+    Banned,
+    Unbanned,
+}
+
+/// A registry event about this worker.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RegistryEvent {
+    pub kind: RegistryEventKind,
+    pub block_number: Option<u64>,
+    pub tx_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_type: Option<String>,
+    /// Why the worker was banned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl RegistryEvent {
+    /// The block number for logs, `pending` for a pending block.
+    fn block(&self) -> String {
+        self.block_number
+            .map_or_else(|| "pending".to_string(), |block| block.to_string())
+    }
+
+    fn decode(log: &Log) -> Option<Self> {
+        let (kind, owner, node_type, reason) = match *log.topic0()? {
+            WorkerRegistered::SIGNATURE_HASH => {
+                let event = log.log_decode::<WorkerRegistered>().ok()?.inner.data;
+                (
+                    RegistryEventKind::Registered,
+                    Some(event.owner.to_string()),
+                    Some(event.nodeType),
+                    None,
+                )
+            }
+            WorkerBanned::SIGNATURE_HASH => {
+                let event = log.log_decode::<WorkerBanned>().ok()?.inner.data;
+                (RegistryEventKind::Banned, None, None, Some(event.reason))
+            }
+            WorkerUnbanned::SIGNATURE_HASH => (RegistryEventKind::Unbanned, None, None, None),
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            block_number: log.block_number,
+            tx_hash: log.transaction_hash.map(|hash| hash.to_string()),
+            owner,
+            node_type,
+            reason,
+        })
+    }
+}
+
+/// What the registry says about this worker.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RegistryState {
+    /// The chain the state was recorded on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<ChainTag>,
+    /// Last block scanned, `None` until the first scan.
+    pub last_block: Option<u64>,
+    pub banned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban_reason: Option<String>,
+    /// The latest events, oldest first.
+    pub events: Vec<RegistryEvent>,
+}
+
+/// The registry state, persisted across restarts.
+#[derive(Debug)]
+pub struct RegistryLog {
+    path: PathBuf,
+    state: RwLock<RegistryState>,
+}
+
+impl RegistryLog {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join(REGISTRY_EVENTS_FILE);
+        let state = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {}", path.display(), e);
+                RegistryState::default()
+            }),
+            Err(_) => RegistryState::default(),
+        };
+
+        Self {
+            path,
+            state: RwLock::new(state),
+        }
+    }
+
+    pub fn get(&self) -> RegistryState {
+        self.state.read().unwrap().clone()
+    }
+
+    /// Whether the worker is banned, once the registry was scanned.
+    pub fn banned(&self) -> Option<bool> {
+        let state = self.state.read().unwrap();
+        state.last_block.map(|_| state.banned)
+    }
+
+    /// The ban reason while the worker is banned.
+    pub fn ban_reason(&self) -> Option<String> {
+        let state = self.state.read().unwrap();
+        state
+            .banned
+            .then(|| state.ban_reason.clone().unwrap_or_default())
+    }
+
+    /// Ties the state to `chain`, starting over if it was recorded on
+    /// another chain. State from before chains were tagged is kept.
+    fn bind(&self, chain: &ChainTag) {
+        let mut state = self.state.write().unwrap();
+        if state.chain.as_ref() == Some(chain) {
+            return;
+        }
+        if let Some(previous) = &state.chain {
+            warn!(
+                "Registry events in {} were recorded on {}, starting over on {}",
+                self.path.display(),
+                previous,
+                chain
+            );
+            *state = RegistryState::default();
+        }
+        state.chain = Some(chain.clone());
+        self.save(&state);
+    }
+
+    fn last_block(&self) -> Option<u64> {
+        self.state.read().unwrap().last_block
+    }
+
+    /// Applies the events found up to `last_block` and saves the state.
+    fn record(&self, events: Vec<RegistryEvent>, last_block: u64) {
+        let mut state = self.state.write().unwrap();
+        for event in events {
+            match event.kind {
+                RegistryEventKind::Registered => {
+                    info!("Worker registered on-chain in block {}", event.block())
+                }
+                RegistryEventKind::Banned => {
+                    warn!(
+                        "Worker was BANNED on-chain in block {}: {}",
+                        event.block(),
+                        event.reason.as_deref().unwrap_or_default()
+                    );
+                    state.banned = true;
+                    state.ban_reason = event.reason.clone();
+                }
+                RegistryEventKind::Unbanned => {
+                    info!("Worker was unbanned on-chain in block {}", event.block());
+                    state.banned = false;
+                    state.ban_reason = None;
+                }
+            }
+            state.events.push(event);
+        }
+        let excess = state.events.len().saturating_sub(MAX_EVENTS);
+        state.events.drain(..excess);
+        state.last_block = Some(last_block);
+        self.save(&state);
+    }
+
+    fn save(&self, state: &RegistryState) {
+        match serde_json::to_string(state) {
+            Ok(content) => {
+                if let Err(e) = fs::write(&self.path, content) {
+                    error!("Failed to save {}: {}", self.path.display(), e);
+                }
+            }
+            Err(e) => error!("Failed to serialize registry events: {}", e),
+        }
+    }
+}
+
+/// Polls the registry contract for events about this worker every
+/// `watch.interval`. Without a scanned block, scanning starts at
+/// `watch.start_block`, the block of the recorded on-chain registration, or
+/// the latest block.
+pub async fn run_registry_watch(state: Arc<AppState>, watch: RegistryWatch) {
+    let Some(log) = &state.registry else {
+        return;
+    };
+    let pubkey: B256 = match state.nostr_pubkey.parse() {
+        Ok(pubkey) => pubkey,
+        Err(e) => {
+            error!("Invalid worker public key, registry watch disabled: {}", e);
+            return;
+        }
+    };
+    let url = match watch.chain.url() {
+        Ok(url) => url,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let provider = ProviderBuilder::new().connect_http(url);
+    let start_block = watch.start_block.or_else(|| {
+        RegistrationTx::load(&state.data_dir)
+            .filter(|tx| tx.on_chain(&watch.chain) && tx.contract == watch.contract)
+            .and_then(|tx| tx.block_number)
+    });
+    info!(
+        "Following registry {} on {} every {}s",
+        watch.contract,
+        watch.chain.name,
+        watch.interval.as_secs()
+    );
+
+    let mut ticker = tokio::time::interval(watch.interval);
+    let mut bound = false;
+    loop {
+        ticker.tick().await;
+        // Check the chain before the first poll, so events from another
+        // chain's endpoint never mix into the recorded state
+        if !bound {
+            match watch.chain.tag(&provider).await {
+                Ok(chain) => {
+                    log.bind(&chain);
+                    bound = true;
+                }
+                Err(e) => {
+                    warn!("Failed to poll registry events: {}", e);
+                    continue;
+                }
+            }
+        }
+        if let Err(e) = poll(&provider, &watch, pubkey, start_block, log).await {
+            warn!("Failed to poll registry events: {}", e);
+        }
+    }
+}
+
+async fn poll(
+    provider: &impl Provider,
+    watch: &RegistryWatch,
+    pubkey: B256,
+    start_block: Option<u64>,
+    log: &RegistryLog,
+) -> Result<(), String> {
+    let latest = provider
+        .get_block_number()
+        .await
+        .map_err(|e| format!("eth_blockNumber failed: {}", e))?;
+    let mut from = match log.last_block() {
+        Some(last_block) => last_block + 1,
+        None => start_block.unwrap_or(latest),
+    };
+
+    while from <= latest {
+        let to = latest.min(from + MAX_BLOCK_RANGE - 1);
+        let filter = Filter::new()
+            .address(watch.contract)
+            .event_signature(vec![
+                WorkerRegistered::SIGNATURE_HASH,
+                WorkerBanned::SIGNATURE_HASH,
+                WorkerUnbanned::SIGNATURE_HASH,
+            ])
+            .topic1(pubkey)
+            .from_block(from)
+            .to_block(to);
+        let logs = provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| format!("eth_getLogs for blocks {}-{} failed: {}", from, to, e))?;
+        log.record(logs.iter().filter_map(RegistryEvent::decode).collect(), to);
+        from = to + 1;
+    }
+    Ok(())
+}
+
+/// `GET /registry/events`: this worker's registry events and ban status.
+#[utoipa::path(
+    get,
+    path = "/registry/events",
+    tag = "worker",
+    responses(
+        (status = 200, description = "Registry state and the latest events, oldest first", body = RegistryState),
+        (status = 404, description = "The registry contract isn't followed", body = ErrorBody),
+    )
+)]
+pub async fn registry_events_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<RegistryState>, ApiError> {
+    state
+        .registry
+        .as_ref()
+        .map(|log| Json(log.get()))
+        .ok_or_else(|| {
+            ApiError::not_found("Registry events are not followed, set REGISTRY_CONTRACT")
+        })
+}
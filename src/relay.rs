@@ -0,0 +1,178 @@
+use axum::extract::State;
+use axum::Json;
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+
+/// Parses a comma-separated relay list (e.g. from `NOSTR_RELAYS`), dropping
+/// empty entries.
+pub fn parse_relay_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|relay| relay.trim())
+        .filter(|relay| !relay.is_empty())
+        .map(|relay| relay.to_string())
+        .collect()
+}
+
+/// Creates a Nostr client signing with the worker signer and connects it to
+/// the given relays. Relays that fail to parse are logged and skipped.
+pub async fn connect_relays(signer: &Arc<dyn NostrSigner>, relays: &[String]) -> Client {
+    let client = Client::new(signer.clone());
+
+    for relay in relays {
+        match client.add_relay(relay.as_str()).await {
+            Ok(_) => info!("Added Nostr relay: {}", relay),
+            Err(e) => error!("Failed to add Nostr relay {}: {}", relay, e),
+        }
+    }
+
+    client.connect().await;
+    client
+}
+
+/// How often relays are checked and disconnected ones reconnected.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Publish results of one relay.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RelayCounters {
+    /// Events the relay accepted.
+    pub published: u64,
+    /// Events the relay rejected or that couldn't be sent to it.
+    pub failed: u64,
+    /// Unix time of the last accepted event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_published_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Times the relay was found disconnected and reconnected.
+    pub reconnects: u64,
+}
+
+/// Per-relay publish results, so operators can tell which relays drop
+/// heartbeats.
+#[derive(Debug, Default)]
+pub struct RelayStats {
+    relays: RwLock<HashMap<String, RelayCounters>>,
+}
+
+impl RelayStats {
+    pub fn get(&self, url: &str) -> RelayCounters {
+        self.relays
+            .read()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Records the outcome of publishing one event.
+    pub fn record(&self, success: &HashSet<RelayUrl>, failed: &HashMap<RelayUrl, Option<String>>) {
+        let mut relays = self.relays.write().unwrap();
+        for url in success {
+            let counters = relays.entry(url.to_string()).or_default();
+            counters.published += 1;
+            counters.last_published_at = Some(unix_timestamp());
+        }
+        for (url, error) in failed {
+            let counters = relays.entry(url.to_string()).or_default();
+            counters.failed += 1;
+            counters.last_error = error.clone();
+        }
+    }
+
+    fn record_reconnect(&self, url: &str) {
+        self.relays
+            .write()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .reconnects += 1;
+    }
+}
+
+/// Reconnects relays that dropped their connection, in addition to the
+/// client's own retries, which give up on terminated relays.
+pub async fn run_relay_supervisor(state: Arc<AppState>, client: Client) {
+    let mut ticker = tokio::time::interval(RECONNECT_INTERVAL);
+    // The relays were just connected
+    ticker.reset();
+
+    loop {
+        ticker.tick().await;
+        let disconnected: Vec<_> = client
+            .relays()
+            .await
+            .into_iter()
+            .filter(|(_, relay)| {
+                matches!(
+                    relay.status(),
+                    RelayStatus::Disconnected | RelayStatus::Terminated
+                )
+            })
+            .map(|(url, _)| url.to_string())
+            .collect();
+        if disconnected.is_empty() {
+            continue;
+        }
+
+        for url in &disconnected {
+            warn!("Nostr relay {} is disconnected, reconnecting", url);
+            state.relay_stats.record_reconnect(url);
+        }
+        client.connect().await;
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RelayHealth {
+    pub url: String,
+    /// Connection state as reported by the relay pool, e.g. `Connected`.
+    pub status: String,
+    pub connected: bool,
+    /// Share of events the relay accepted, once one was published.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_rate: Option<f64>,
+    #[serde(flatten)]
+    pub counters: RelayCounters,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RelaysResponse {
+    pub relays: Vec<RelayHealth>,
+}
+
+/// `GET /relays`: connection state and publish results of each relay.
+#[utoipa::path(
+    get,
+    path = "/relays",
+    tag = "health",
+    responses((status = 200, description = "Every configured relay, empty without relays", body = RelaysResponse))
+)]
+pub async fn relays_handler(State(state): State<Arc<AppState>>) -> Json<RelaysResponse> {
+    let mut relays = Vec::new();
+    if let Some(client) = &state.nostr_client {
+        for (url, relay) in client.relays().await {
+            let url = url.to_string();
+            let counters = state.relay_stats.get(&url);
+            let attempts = counters.published + counters.failed;
+            relays.push(RelayHealth {
+                status: relay.status().to_string(),
+                connected: relay.is_connected(),
+                success_rate: (attempts > 0).then(|| counters.published as f64 / attempts as f64),
+                counters,
+                url,
+            });
+        }
+    }
+    relays.sort_by(|a, b| a.url.cmp(&b.url));
+    Json(RelaysResponse { relays })
+}
@@ -0,0 +1,186 @@
+//! NIP-65 relay lists (kind `10002`). The worker publishes the relays it
+//! uses, so others can find its heartbeats, and can follow the relay list of
+//! the coordinator (`COORDINATOR_NPUB`): relays added to that list are joined
+//! and relays removed from it are left, so relays can be rotated network-wide
+//! without redeploying workers. Relays from `NOSTR_RELAYS` are always kept.
+
+use nostr_sdk::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::server::AppState;
+
+/// The coordinator's latest relay list, kept so the worker can reach its
+/// relays after a restart even if the configured ones were retired.
+pub const RELAY_LIST_FILE: &str = "relay_list_event.json";
+
+/// Relays the worker is connected to: the configured ones plus those taken
+/// from the coordinator's relay list.
+struct Relays {
+    configured: BTreeSet<RelayUrl>,
+    discovered: BTreeSet<RelayUrl>,
+}
+
+impl Relays {
+    fn all(&self) -> impl Iterator<Item = &RelayUrl> {
+        self.configured.union(&self.discovered)
+    }
+
+    /// Joins relays the coordinator added and leaves those it dropped.
+    /// Returns whether the relay set changed.
+    async fn follow(&mut self, client: &Client, list: &Event) -> bool {
+        let listed: BTreeSet<RelayUrl> = nip65::extract_relay_list(list)
+            .map(|(url, _)| url.clone())
+            .filter(|url| !self.configured.contains(url))
+            .collect();
+        if listed == self.discovered {
+            return false;
+        }
+
+        for url in self.discovered.difference(&listed) {
+            match client.remove_relay(url.as_str()).await {
+                Ok(()) => info!("Left Nostr relay {}, dropped by the coordinator", url),
+                Err(e) => error!("Failed to remove Nostr relay {}: {}", url, e),
+            }
+        }
+        for url in listed.difference(&self.discovered) {
+            match client.add_relay(url.as_str()).await {
+                Ok(_) => info!(
+                    "Added Nostr relay {} from the coordinator's relay list",
+                    url
+                ),
+                Err(e) => error!("Failed to add Nostr relay {}: {}", url, e),
+            }
+        }
+        client.connect().await;
+        self.discovered = listed;
+        true
+    }
+}
+
+/// Publishes the worker's relay list and, with a `coordinator`, follows the
+/// coordinator's relay list, publishing the worker's again whenever its
+/// relays change.
+pub async fn run_relay_lists(
+    state: Arc<AppState>,
+    signer: Arc<dyn NostrSigner>,
+    client: Client,
+    configured: Vec<String>,
+    coordinator: Option<PublicKey>,
+) {
+    let mut relays = Relays {
+        configured: configured
+            .iter()
+            .filter_map(|relay| RelayUrl::parse(relay).ok())
+            .collect(),
+        discovered: BTreeSet::new(),
+    };
+    let Some(coordinator) = coordinator else {
+        publish(&state, &signer, &client, &relays).await;
+        return;
+    };
+    let path = state.data_dir.join(RELAY_LIST_FILE);
+
+    // Join the relays from the saved list before relays send a newer one
+    let mut latest = load_list(&path, coordinator);
+    if let Some(event) = &latest {
+        relays.follow(&client, event).await;
+    }
+    publish(&state, &signer, &client, &relays).await;
+
+    let filter = Filter::new().kind(Kind::RelayList).author(coordinator);
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![filter], None).await {
+        error!("Failed to subscribe to the coordinator's relay list: {}", e);
+        return;
+    }
+    info!(
+        "Following the relay list of {}",
+        coordinator.to_bech32().unwrap_or_default()
+    );
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Relay list watch lagged, skipped {} notifications", skipped);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if !accepts(coordinator, &event)
+            || latest
+                .as_ref()
+                .is_some_and(|current| current.created_at >= event.created_at)
+        {
+            continue;
+        }
+
+        if relays.follow(&client, &event).await {
+            publish(&state, &signer, &client, &relays).await;
+        }
+        save_list(&path, &event);
+        latest = Some(*event);
+    }
+}
+
+/// Whether `event` is a validly signed relay list of `coordinator`.
+fn accepts(coordinator: PublicKey, event: &Event) -> bool {
+    event.kind == Kind::RelayList && event.pubkey == coordinator && event.verify().is_ok()
+}
+
+/// Publishes the worker's relays as its NIP-65 relay list. The worker reads
+/// and writes on every relay, so no relay is marked.
+async fn publish(
+    state: &AppState,
+    signer: &Arc<dyn NostrSigner>,
+    client: &Client,
+    relays: &Relays,
+) {
+    let builder = EventBuilder::relay_list(relays.all().map(|url| (url.clone(), None)));
+    let event = match builder.sign(signer).await {
+        Ok(event) => event,
+        Err(e) => {
+            error!("Failed to sign relay list: {}", e);
+            return;
+        }
+    };
+    match client.send_event(event).await {
+        Ok(output) => {
+            state.relay_stats.record(&output.success, &output.failed);
+            info!("Published relay list {}", output.val)
+        }
+        Err(e) => error!("Failed to publish relay list: {}", e),
+    }
+}
+
+fn load_list(path: &Path, coordinator: PublicKey) -> Option<Event> {
+    let json = fs::read_to_string(path).ok()?;
+    match Event::from_json(json) {
+        Ok(event) if accepts(coordinator, &event) => Some(event),
+        Ok(_) => {
+            warn!(
+                "Ignoring {}, it is not a relay list of the configured coordinator",
+                path.display()
+            );
+            None
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn save_list(path: &Path, event: &Event) {
+    if let Err(e) = fs::write(path, event.as_json()) {
+        error!("Failed to save the relay list to {}: {}", path.display(), e);
+    }
+}
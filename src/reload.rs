@@ -0,0 +1,142 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::rules::HealthRules;
+use crate::server::AppState;
+
+/// Settings that take effect without a restart when the config file is
+/// reloaded on SIGHUP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableConfig {
+    pub poll_interval_secs: u64,
+    pub relays: Vec<String>,
+    pub webhooks: Vec<String>,
+    pub webhook_secret: Option<String>,
+    pub health_rules: HealthRules,
+}
+
+impl ReloadableConfig {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            poll_interval_secs: config.poll_interval_secs,
+            relays: config.relays.clone(),
+            webhooks: config.webhooks.clone(),
+            webhook_secret: config.webhook_secret.clone(),
+            health_rules: config.health_rules.clone(),
+        }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    /// Describes every setting that differs in `new`. Webhook URLs and the
+    /// secret may carry credentials, so only their count and whether they
+    /// changed are logged.
+    pub fn diff(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.poll_interval_secs != new.poll_interval_secs {
+            changes.push(format!(
+                "poll_interval_secs: {} -> {}",
+                self.poll_interval_secs, new.poll_interval_secs
+            ));
+        }
+        for relay in new
+            .relays
+            .iter()
+            .filter(|relay| !self.relays.contains(relay))
+        {
+            changes.push(format!("relays: +{}", relay));
+        }
+        for relay in self
+            .relays
+            .iter()
+            .filter(|relay| !new.relays.contains(relay))
+        {
+            changes.push(format!("relays: -{}", relay));
+        }
+        if self.webhooks != new.webhooks {
+            changes.push(format!(
+                "webhooks: {} -> {} URLs",
+                self.webhooks.len(),
+                new.webhooks.len()
+            ));
+        }
+        if self.webhook_secret != new.webhook_secret {
+            changes.push("webhook_secret changed".to_string());
+        }
+        if self.health_rules != new.health_rules {
+            changes.push(format!(
+                "health_rules: {:?} -> {:?}",
+                self.health_rules, new.health_rules
+            ));
+        }
+        changes
+    }
+}
+
+/// Re-reads the config file on every SIGHUP and applies the reloadable
+/// settings. An invalid config is logged and the current settings are kept.
+pub async fn run_reload_on_sighup(state: Arc<AppState>, config_path: Option<PathBuf>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, config reload disabled: {}", e);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration");
+        let config = match Config::load(config_path.as_deref()) {
+            Ok(config) => config,
+            Err(errors) => {
+                error!("Invalid configuration, keeping the current settings:");
+                for e in &errors {
+                    error!("  {}", e);
+                }
+                continue;
+            }
+        };
+
+        let new = ReloadableConfig::new(&config);
+        let old = state.settings();
+        let changes = old.diff(&new);
+        if changes.is_empty() {
+            info!("No reloadable settings changed");
+            continue;
+        }
+        for change in &changes {
+            info!("Reloaded {}", change);
+        }
+
+        if old.relays != new.relays {
+            update_relays(&state, &old.relays, &new.relays).await;
+        }
+        *state.reloadable.write().unwrap() = new;
+    }
+}
+
+async fn update_relays(state: &AppState, old: &[String], new: &[String]) {
+    let Some(client) = &state.nostr_client else {
+        warn!("Nostr relays were not configured at startup, restart to use them");
+        return;
+    };
+
+    for relay in old.iter().filter(|relay| !new.contains(relay)) {
+        if let Err(e) = client.remove_relay(relay.as_str()).await {
+            error!("Failed to remove Nostr relay {}: {}", relay, e);
+        }
+    }
+    for relay in new.iter().filter(|relay| !old.contains(relay)) {
+        match client.add_relay(relay.as_str()).await {
+            Ok(_) => info!("Added Nostr relay: {}", relay),
+            Err(e) => error!("Failed to add Nostr relay {}: {}", relay, e),
+        }
+    }
+    client.connect().await;
+}
@@ -0,0 +1,204 @@
+//! Anti-replay protection for signed messages. Messages that trigger actions
+//! carry a random nonce next to their signed timestamp: inbound ones outside
+//! [`REPLAY_WINDOW`] are rejected, and the nonces seen within it are kept in
+//! `DATA_DIR/seen_nonces.json`, so a captured message can't be replayed,
+//! not even across a restart.
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::history::unix_timestamp;
+
+/// How old a signed message may be, and how long its nonce is remembered.
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(300);
+
+/// How far in the future a timestamp may be, for clocks running ahead.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+const MAX_NONCE_LEN: usize = 128;
+
+pub const SEEN_NONCES_FILE: &str = "seen_nonces.json";
+
+/// A random nonce for outbound signed messages.
+pub fn new_nonce() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("message is {0}s old")]
+    Expired(u64),
+    #[error("message is {0}s in the future")]
+    Future(u64),
+    #[error("nonce is empty or longer than {MAX_NONCE_LEN} characters")]
+    InvalidNonce,
+    #[error("nonce was already used")]
+    Reused,
+}
+
+/// Nonces of accepted messages with their timestamps, persisted until they
+/// leave the replay window.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    path: PathBuf,
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ReplayGuard {
+    pub fn open(data_dir: &Path) -> Self {
+        Self::open_at(data_dir.join(SEEN_NONCES_FILE))
+    }
+
+    /// A guard keeping its nonces in `path`, for messages whose nonces
+    /// shouldn't mix with the others.
+    pub fn open_at(path: PathBuf) -> Self {
+        let seen = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            path,
+            seen: Mutex::new(seen),
+        }
+    }
+
+    /// Accepts a message signed at `timestamp` (Unix seconds) carrying
+    /// `nonce` once, if it is within the replay window.
+    pub fn check(&self, nonce: &str, timestamp: u64) -> Result<(), ReplayError> {
+        self.check_at(nonce, timestamp, unix_timestamp())
+    }
+
+    fn check_at(&self, nonce: &str, timestamp: u64, now: u64) -> Result<(), ReplayError> {
+        if now.saturating_sub(timestamp) > REPLAY_WINDOW.as_secs() {
+            return Err(ReplayError::Expired(now - timestamp));
+        }
+        if timestamp.saturating_sub(now) > MAX_CLOCK_SKEW.as_secs() {
+            return Err(ReplayError::Future(timestamp - now));
+        }
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+            return Err(ReplayError::InvalidNonce);
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        // Expired messages are rejected above, their nonces can go
+        seen.retain(|_, seen_at| now.saturating_sub(*seen_at) <= REPLAY_WINDOW.as_secs());
+        if seen.contains_key(nonce) {
+            return Err(ReplayError::Reused);
+        }
+        seen.insert(nonce.to_string(), timestamp);
+
+        match serde_json::to_string(&*seen) {
+            Ok(content) => {
+                if let Err(e) = fs::write(&self.path, content) {
+                    error!(
+                        "Failed to save seen nonces to {}: {}",
+                        self.path.display(),
+                        e
+                    );
+                }
+            }
+            Err(e) => error!("Failed to serialize seen nonces: {}", e),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn guard(dir: &tempfile::TempDir) -> ReplayGuard {
+        ReplayGuard::open(dir.path())
+    }
+
+    #[test]
+    fn rejects_a_reused_nonce_inside_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let guard = guard(&dir);
+
+        guard.check_at("a", NOW, NOW).unwrap();
+        assert!(matches!(
+            guard.check_at("a", NOW + 10, NOW + 10),
+            Err(ReplayError::Reused)
+        ));
+        guard.check_at("b", NOW + 10, NOW + 10).unwrap();
+
+        // Seen nonces survive a restart
+        let reopened = ReplayGuard::open(dir.path());
+        assert!(matches!(
+            reopened.check_at("a", NOW + 20, NOW + 20),
+            Err(ReplayError::Reused)
+        ));
+    }
+
+    #[test]
+    fn forgets_nonces_once_they_leave_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let guard = guard(&dir);
+        let window = REPLAY_WINDOW.as_secs();
+
+        guard.check_at("a", NOW, NOW).unwrap();
+        assert!(matches!(
+            guard.check_at("a", NOW + window, NOW + window),
+            Err(ReplayError::Reused)
+        ));
+        guard
+            .check_at("a", NOW + window + 1, NOW + window + 1)
+            .unwrap();
+    }
+
+    #[test]
+    fn accepts_messages_up_to_the_window_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let guard = guard(&dir);
+        let window = REPLAY_WINDOW.as_secs();
+
+        guard.check_at("a", NOW - window, NOW).unwrap();
+        assert!(matches!(
+            guard.check_at("b", NOW - window - 1, NOW),
+            Err(ReplayError::Expired(age)) if age == window + 1
+        ));
+    }
+
+    #[test]
+    fn accepts_timestamps_up_to_the_clock_skew_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let guard = guard(&dir);
+        let skew = MAX_CLOCK_SKEW.as_secs();
+
+        guard.check_at("a", NOW + skew, NOW).unwrap();
+        assert!(matches!(
+            guard.check_at("b", NOW + skew + 1, NOW),
+            Err(ReplayError::Future(ahead)) if ahead == skew + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_nonces() {
+        let dir = tempfile::tempdir().unwrap();
+        let guard = guard(&dir);
+
+        for nonce in [String::new(), "a".repeat(MAX_NONCE_LEN + 1)] {
+            assert!(matches!(
+                guard.check_at(&nonce, NOW, NOW),
+                Err(ReplayError::InvalidNonce)
+            ));
+        }
+        guard
+            .check_at(&"a".repeat(MAX_NONCE_LEN), NOW, NOW)
+            .unwrap();
+    }
+}
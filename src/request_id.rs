@@ -0,0 +1,76 @@
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::Rng;
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request being handled, if called from within a handler.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Generates a random (version 4) UUID.
+pub fn new_request_id() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Reuses a caller-supplied `x-request-id` so IDs can be followed across
+/// services, as long as it is short printable ASCII.
+fn incoming_request_id(request: &Request) -> Option<String> {
+    let id = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.chars().all(|c| c.is_ascii_graphic());
+    valid.then(|| id.to_string())
+}
+
+/// Middleware that assigns each request an ID, runs the handler inside a
+/// tracing span carrying it, echoes it in the `x-request-id` response
+/// header, and logs one access line per request.
+pub async fn request_context(request: Request, next: Next) -> Response {
+    let request_id = incoming_request_id(&request).unwrap_or_else(new_request_id);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let span = info_span!("request", request_id = %request_id, %method, %path);
+
+    let started = Instant::now();
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    span.in_scope(|| {
+        info!(
+            status = response.status().as_u16(),
+            latency_ms, "request completed"
+        )
+    });
+    response
+}
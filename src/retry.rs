@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::dstack::{method_name, DStackClient};
+use crate::error::BackendError;
+
+/// prpc methods that only read, so they are retried even when sent with a
+/// request body.
+pub const READ_METHODS: &[&str] = &["ListGpus", "Status", "Info", "Version", "GetQuote"];
+
+/// Whether sending the request to `path` again is harmless.
+fn is_read(path: &str, body: Option<&serde_json::Value>) -> bool {
+    body.is_none() || READ_METHODS.contains(&method_name(path))
+}
+
+/// Exponential backoff with jitter for dstack requests.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total attempts per request, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of each delay randomised in either direction (0 to 1).
+    pub jitter: f64,
+    /// Stop retrying once this much time has passed since the first attempt.
+    pub max_elapsed_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 4_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_elapsed_ms: 15_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting at 1), with jitter applied.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let base = self.initial_backoff_ms as f64 * self.multiplier.powi(retry as i32 - 1);
+        let base = base.min(self.max_backoff_ms as f64);
+        let jitter = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_millis((base * (1.0 + jitter)).max(0.0) as u64)
+    }
+
+    pub fn max_elapsed(&self) -> Duration {
+        Duration::from_millis(self.max_elapsed_ms)
+    }
+}
+
+/// Wraps a dstack client and retries failed reads according to a
+/// [`RetryPolicy`]. Writes are sent once unless the caller asks for retries
+/// with [`DStackClient::call_retrying`], since dstack may have applied a
+/// request whose response was lost.
+pub struct RetryingClient {
+    inner: Arc<dyn DStackClient>,
+    policy: RetryPolicy,
+}
+
+impl RetryingClient {
+    pub fn new(inner: Arc<dyn DStackClient>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    async fn send_with_retries(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        let started = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            let error = match self.inner.call(path, body).await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            let delay = self.policy.backoff(attempt);
+            if !error.is_transient()
+                || attempt >= self.policy.max_attempts
+                || started.elapsed() + delay > self.policy.max_elapsed()
+            {
+                return Err(error);
+            }
+
+            warn!(
+                "dstack request {} failed (attempt {}/{}), retrying in {:?}: {}",
+                path, attempt, self.policy.max_attempts, delay, error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl DStackClient for RetryingClient {
+    async fn call(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        if is_read(path, body) {
+            self.send_with_retries(path, body).await
+        } else {
+            self.inner.call(path, body).await
+        }
+    }
+
+    async fn call_retrying(
+        &self,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, BackendError> {
+        self.send_with_retries(path, body).await
+    }
+
+    fn endpoint(&self) -> String {
+        self.inner.endpoint()
+    }
+}
@@ -0,0 +1,225 @@
+//! Estimated reward accrual. The node type's reward rate is multiplied by
+//! the uptime found in the health history, so operators can follow what the
+//! worker earns before the coordinator pays out. Rates come from the
+//! coordinator (`REWARD_RATES_URL`) or from `[reward_rates]` in the config
+//! file.
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::{ApiError, ErrorBody};
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+
+/// Unit of the rates unless they name one.
+pub const DEFAULT_REWARD_UNIT: &str = "PHA";
+
+/// Rate key applying to node types without a rate of their own.
+pub const DEFAULT_RATE_KEY: &str = "*";
+
+/// How long rates fetched from the coordinator are used.
+const RATES_TTL: Duration = Duration::from_secs(600);
+
+/// Timeout of each coordinator request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_PERIOD_HOURS: u64 = 24;
+const MAX_PERIOD_HOURS: u64 = 30 * 24;
+
+/// Reward per hour of uptime, by node type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RewardRates {
+    #[serde(default = "default_unit")]
+    pub unit: String,
+    /// Node type to rate; `*` applies to node types not listed.
+    pub rates: BTreeMap<String, f64>,
+}
+
+fn default_unit() -> String {
+    DEFAULT_REWARD_UNIT.to_string()
+}
+
+impl RewardRates {
+    fn rate(&self, node_type: &str) -> Option<f64> {
+        self.rates
+            .get(node_type)
+            .or_else(|| self.rates.get(DEFAULT_RATE_KEY))
+            .copied()
+    }
+}
+
+/// Where the rate of an estimate came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RateSource {
+    Coordinator,
+    /// Rates from the coordinator that could not be refreshed.
+    CoordinatorCached,
+    Config,
+}
+
+/// The reward rates, fetched from the coordinator and cached, with the
+/// configured rates as fallback.
+#[derive(Debug)]
+pub struct RewardRateSource {
+    url: Option<String>,
+    configured: Option<RewardRates>,
+    fetched: RwLock<Option<(Instant, RewardRates)>>,
+}
+
+impl RewardRateSource {
+    pub fn new(url: Option<String>, configured: Option<RewardRates>) -> Self {
+        Self {
+            url,
+            configured,
+            fetched: RwLock::new(None),
+        }
+    }
+
+    /// The current rates. Coordinator rates are refetched once they are
+    /// older than the TTL; if that fails, the previous ones are used, then
+    /// the configured ones.
+    async fn rates(&self) -> Result<(RewardRates, RateSource), ApiError> {
+        let Some(url) = &self.url else {
+            return self
+                .configured
+                .clone()
+                .map(|rates| (rates, RateSource::Config))
+                .ok_or_else(|| {
+                    ApiError::not_found(
+                        "Reward rates are not configured, set REWARD_RATES_URL or [reward_rates]",
+                    )
+                });
+        };
+
+        let fetched = self.fetched.read().unwrap().clone();
+        if let Some((fetched_at, rates)) = &fetched {
+            if fetched_at.elapsed() < RATES_TTL {
+                return Ok((rates.clone(), RateSource::Coordinator));
+            }
+        }
+        match fetch_rates(url).await {
+            Ok(rates) => {
+                *self.fetched.write().unwrap() = Some((Instant::now(), rates.clone()));
+                Ok((rates, RateSource::Coordinator))
+            }
+            Err(e) => {
+                warn!("Failed to fetch reward rates from {}: {}", url, e);
+                match (fetched, &self.configured) {
+                    (Some((_, rates)), _) => Ok((rates, RateSource::CoordinatorCached)),
+                    (None, Some(rates)) => Ok((rates.clone(), RateSource::Config)),
+                    (None, None) => Err(ApiError::new(
+                        StatusCode::BAD_GATEWAY,
+                        "rates_unavailable",
+                        format!("Failed to fetch reward rates: {}", e),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_rates(url: &str) -> Result<RewardRates, String> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {}", e))?
+        .error_for_status()
+        .map_err(|e| format!("HTTP error: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response: {}", e))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EstimateQuery {
+    /// Period to estimate over, in hours up to now. 24 by default and 720
+    /// at most.
+    hours: Option<u64>,
+}
+
+/// An estimated reward, with the inputs it was calculated from.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RewardEstimate {
+    /// `rate_per_hour` times `uptime_hours`, in `unit`.
+    pub estimated_reward: f64,
+    pub unit: String,
+    pub node_type: String,
+    /// Reward per hour of uptime for the node type.
+    pub rate_per_hour: f64,
+    pub rate_source: RateSource,
+    pub period_hours: u64,
+    /// Unix time the period starts at.
+    pub since: u64,
+    /// Health checks in the period.
+    pub checks: u64,
+    /// Hours of the period covered by the health history.
+    pub observed_hours: f64,
+    /// Hours of those the worker was `Available` or `Degraded`.
+    pub uptime_hours: f64,
+}
+
+/// `GET /rewards/estimate`: the reward estimated to have accrued over the
+/// last `hours`.
+#[utoipa::path(
+    get,
+    path = "/rewards/estimate",
+    tag = "worker",
+    params(EstimateQuery),
+    responses(
+        (status = 200, description = "Estimated reward and its inputs", body = RewardEstimate),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "No reward rate for this node type", body = ErrorBody),
+        (status = 502, description = "The coordinator's rates could not be fetched", body = ErrorBody),
+    )
+)]
+pub async fn rewards_estimate_handler(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<EstimateQuery>, QueryRejection>,
+) -> Result<Json<RewardEstimate>, ApiError> {
+    let Query(query) = query?;
+    let period_hours = query.hours.unwrap_or(DEFAULT_PERIOD_HOURS);
+    if period_hours == 0 || period_hours > MAX_PERIOD_HOURS {
+        return Err(ApiError::bad_request(format!(
+            "hours must be between 1 and {}",
+            MAX_PERIOD_HOURS
+        )));
+    }
+
+    let (rates, rate_source) = state.rewards.rates().await?;
+    let rate_per_hour = rates.rate(&state.node_type).ok_or_else(|| {
+        ApiError::not_found(format!("No reward rate for node type {}", state.node_type))
+    })?;
+
+    let since = unix_timestamp().saturating_sub(period_hours * 60 * 60);
+    let uptime = state.history.uptime(since, state.history_max_gap());
+    let uptime_hours = uptime.available_secs as f64 / 3600.0;
+
+    Ok(Json(RewardEstimate {
+        estimated_reward: rate_per_hour * uptime_hours,
+        unit: rates.unit,
+        node_type: state.node_type.clone(),
+        rate_per_hour,
+        rate_source,
+        period_hours,
+        since,
+        checks: uptime.checks,
+        observed_hours: uptime.observed_secs as f64 / 3600.0,
+        uptime_hours,
+    }))
+}
@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+use crate::health::WorkerMetadata;
+
+/// Thresholds that downgrade a reachable worker from `Available` to
+/// `Degraded`. Every rule is off by default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthRules {
+    /// Degraded when fewer than this many GPUs are free.
+    pub min_free_gpus: Option<usize>,
+    /// Degraded when the dstack request takes longer than this.
+    pub max_latency_ms: Option<u64>,
+    /// Degraded when dstack reports `allow_attach_all = false`.
+    pub require_attach_all: bool,
+}
+
+impl HealthRules {
+    /// Returns a reason for every rule the snapshot violates.
+    pub fn evaluate(&self, metadata: &WorkerMetadata, latency_ms: u64) -> Vec<String> {
+        let mut reasons = Vec::new();
+
+        if let Some(min) = self.min_free_gpus {
+            if metadata.free_gpu_count < min {
+                reasons.push(format!(
+                    "{} free GPUs, fewer than {}",
+                    metadata.free_gpu_count, min
+                ));
+            }
+        }
+        if let Some(max) = self.max_latency_ms {
+            if latency_ms > max {
+                reasons.push(format!("dstack latency {}ms exceeds {}ms", latency_ms, max));
+            }
+        }
+        if self.require_attach_all && metadata.allow_attach_all == Some(false) {
+            reasons.push("allow_attach_all is false".to_string());
+        }
+
+        reasons
+    }
+}
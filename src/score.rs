@@ -0,0 +1,85 @@
+//! A composite capability score, so the coordinator can rank workers
+//! without re-implementing the scoring for each consumer. Each component is
+//! reported next to the total so consumers can re-weigh them.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::bandwidth::BandwidthResult;
+use crate::dstack::{fetch_dstack_data, DStackClient, GpuInfo};
+use crate::host;
+use crate::node_type::NodeTypeTable;
+
+/// Points per GPU with weight 1 (an H100).
+const GPU_POINTS: f64 = 100.0;
+/// Weight of GPUs without one in the node type table.
+const UNKNOWN_GPU_WEIGHT: f64 = 0.1;
+/// Points per GiB of host memory.
+const MEMORY_POINTS_PER_GIB: f64 = 0.05;
+/// Points per Gbit/s of measured download throughput.
+const BANDWIDTH_POINTS_PER_GBPS: f64 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CapabilityScore {
+    /// Sum of the components.
+    pub score: f64,
+    /// Sum of the GPUs' weights, times 100.
+    pub gpu: f64,
+    /// 0.05 per GiB of host memory.
+    pub memory: f64,
+    /// 5 per Gbit/s of download throughput, `0` until measured.
+    pub bandwidth: f64,
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Scores a worker from its GPU inventory, host memory and the latest
+/// bandwidth test.
+pub fn capability_score(
+    node_types: &NodeTypeTable,
+    gpus: &[GpuInfo],
+    memory_bytes: Option<u64>,
+    bandwidth: Option<&BandwidthResult>,
+) -> CapabilityScore {
+    let gpu = gpus
+        .iter()
+        .map(|gpu| node_types.weight_for(gpu).unwrap_or(UNKNOWN_GPU_WEIGHT))
+        .sum::<f64>()
+        * GPU_POINTS;
+    let memory =
+        memory_bytes.unwrap_or_default() as f64 / (1u64 << 30) as f64 * MEMORY_POINTS_PER_GIB;
+    let bandwidth =
+        bandwidth.map_or(0.0, |result| result.download_mbps / 1000.0) * BANDWIDTH_POINTS_PER_GBPS;
+
+    CapabilityScore {
+        score: round(gpu + memory + bandwidth),
+        gpu: round(gpu),
+        memory: round(memory),
+        bandwidth: round(bandwidth),
+    }
+}
+
+/// Scores the worker from a fresh inventory, for registration payloads.
+/// Bandwidth isn't measured yet at that point and counts as zero.
+pub async fn current_capability_score(
+    connection: &dyn DStackClient,
+    node_types: &NodeTypeTable,
+    data_dir: &Path,
+) -> Option<CapabilityScore> {
+    match fetch_dstack_data(connection).await {
+        Ok(dstack_data) => Some(capability_score(
+            node_types,
+            &dstack_data.gpus,
+            host::collect(data_dir).memory_total_bytes,
+            None,
+        )),
+        Err(e) => {
+            warn!("Registering without a capability score: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::{rejection::QueryRejection, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
+    Extension, Router,
+};
+use local_ip_address::local_ip;
+use nostr_sdk::Client;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::CorsLayer;
+use tracing::{error, info};
+use utoipa::IntoParams;
+
+use crate::addresses::{self, Address};
+use crate::api_version::{api_versions_handler, negotiate_version, ApiVersion, API_V1_PREFIX};
+use crate::attestation::{get_quote, Attestation};
+use crate::auth::{require_admin, AdminAuth};
+use crate::bandwidth::BandwidthCache;
+use crate::benchmark::{benchmark_handler, start_benchmark_handler, Benchmarker};
+use crate::breaker::CircuitBreaker;
+use crate::connection::{set_dstack_url_handler, DStackConnection};
+use crate::dstack::DStackClient;
+use crate::dstack_info::DstackInfoCache;
+use crate::error::{method_not_allowed_handler, not_found_handler, ApiError, ErrorBody};
+use crate::etag::{etag, json_with_etag};
+use crate::gpu_changes::{GpuChange, GpuChangeLog};
+use crate::gpu_telemetry::GpuTelemetryCache;
+use crate::gpus::{attach_gpu_handler, detach_gpu_handler};
+use crate::health::{BackendInfo, PubkeyRole};
+use crate::history::{HealthHistory, HealthRecord};
+use crate::images::{prefetch_handler, prefetch_status_handler, ImagePrefetcher};
+use crate::leases::{leases_handler, release_gpu_handler, reserve_gpu_handler, GpuLeases};
+use crate::limits::{handle_overload, ConcurrencyLimits};
+use crate::listen::{self, ListenAddr};
+use crate::maintenance::{drain_handler, undrain_handler, MaintenanceMode};
+use crate::metrics::metrics_handler;
+use crate::node_type::NodeTypeTable;
+use crate::openapi;
+use crate::ownership::OwnershipProof;
+use crate::poller::{latest_health, StatusPoller};
+use crate::probes::{livez_handler, readyz_handler};
+use crate::proxy::{proxy_get_handler, proxy_post_handler};
+use crate::prpc::prpc_handler;
+use crate::public_ip::PublicIp;
+use crate::registry_events::{registry_events_handler, RegistryLog};
+use crate::relay::{relays_handler, RelayStats};
+use crate::reload::ReloadableConfig;
+use crate::request_id::request_context;
+use crate::rewards::{rewards_estimate_handler, RewardRateSource};
+use crate::stake::StakeStatus;
+use crate::stream::{sse_handler, ws_handler};
+use crate::systemd;
+use crate::tls;
+use crate::vms::{deploy_vm_handler, remove_vm_handler, vms_handler};
+use crate::whitelist::WhitelistStatus;
+
+pub struct AppState {
+    pub connection: DStackConnection,
+    pub breaker: CircuitBreaker,
+    pub guest_agent: Arc<dyn DStackClient>,
+    pub nostr_pubkey: String,
+    /// DePHY topic the worker announces itself and takes sessions on.
+    pub topic: String,
+    /// The worker key and any additional keys, with their roles.
+    pub pubkeys: Vec<PubkeyRole>,
+    pub node_type: String,
+    pub node_types: NodeTypeTable,
+    pub local_ip: Option<String>,
+    /// Address pinned by the operator, reported instead of `local_ip`.
+    pub advertise_address: Option<IpAddr>,
+    pub interface_addresses: Vec<Address>,
+    pub public_ip: PublicIp,
+    pub bandwidth: BandwidthCache,
+    pub benchmark: Benchmarker,
+    pub dstack_info: DstackInfoCache,
+    pub owner_proof: Option<OwnershipProof>,
+    pub whitelist: WhitelistStatus,
+    pub stake: StakeStatus,
+    /// Registry events about this worker, when the registry is followed.
+    pub registry: Option<RegistryLog>,
+    pub rewards: RewardRateSource,
+    /// Publish results per Nostr relay.
+    pub relay_stats: RelayStats,
+    pub legacy_metadata: bool,
+    /// Settings reloaded from the config file on SIGHUP.
+    pub reloadable: RwLock<ReloadableConfig>,
+    pub dstack_proxy_methods: Vec<String>,
+    /// Whether `/dstack/{method}` needs an admin token.
+    pub dstack_proxy_auth: bool,
+    pub admin_auth: AdminAuth,
+    pub history: HealthHistory,
+    pub maintenance: MaintenanceMode,
+    pub leases: GpuLeases,
+    /// Held while a CVM is deployed, so two deployments can't both pass the
+    /// GPU check for the same slot.
+    pub deploying: tokio::sync::Mutex<()>,
+    pub images: ImagePrefetcher,
+    /// Where host disk space is measured.
+    pub data_dir: PathBuf,
+    pub gpu_changes: GpuChangeLog,
+    pub gpu_telemetry: GpuTelemetryCache,
+    pub poller: StatusPoller,
+    /// Relay client, when Nostr relays are configured.
+    pub nostr_client: Option<Client>,
+    pub sse_keepalive: Duration,
+    pub concurrency_limits: ConcurrencyLimits,
+}
+
+impl AppState {
+    /// The current reloadable settings.
+    pub fn settings(&self) -> ReloadableConfig {
+        self.reloadable.read().unwrap().clone()
+    }
+
+    /// How long a health check stands for in the history at most: until the
+    /// next one, but not for longer than two poll intervals, so time the
+    /// backend was down isn't counted.
+    pub fn history_max_gap(&self) -> u64 {
+        self.settings().poll_interval().as_secs() * 2
+    }
+
+    /// The advertised address, or the detected local IP.
+    pub fn ip_address(&self) -> Option<String> {
+        self.advertise_address
+            .map(|ip| ip.to_string())
+            .or_else(|| self.local_ip.clone())
+    }
+
+    /// Every known address of the worker, the advertised one first.
+    pub fn addresses(&self) -> Vec<Address> {
+        addresses::collect(
+            self.advertise_address,
+            &self.interface_addresses,
+            self.public_ip.get(),
+        )
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "Available or Degraded", body = BackendInfo),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 503, description = "Unavailable or Maintenance", body = BackendInfo),
+    )
+)]
+async fn health_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(version): Extension<ApiVersion>,
+    headers: HeaderMap,
+) -> Response {
+    let backend_info = version.present(latest_health(&state).await, state.legacy_metadata);
+
+    let status_code = if backend_info.status.is_serving() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    // The counters and host load change on every poll, so leave them out
+    // of the ETag or it would only match until the next one
+    let snapshot = BackendInfo {
+        stats: None,
+        host: None,
+        ..backend_info.clone()
+    };
+    match serde_json::to_vec(&snapshot)
+        .and_then(|snapshot| serde_json::to_vec(&backend_info).map(|body| (body, etag(&snapshot))))
+    {
+        Ok((body, etag)) => json_with_etag(&headers, status_code, body, etag),
+        Err(e) => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("Failed to serialize health: {}", e),
+        )
+        .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    /// Only entries after this Unix time.
+    #[serde(default)]
+    since: u64,
+    /// At most this many entries, 100 by default and 1000 at most.
+    limit: Option<usize>,
+}
+
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+const MAX_HISTORY_LIMIT: usize = 1000;
+
+#[utoipa::path(
+    get,
+    path = "/health/history",
+    tag = "health",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Health checks, oldest first", body = Vec<HealthRecord>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+async fn health_history_handler(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<Json<Vec<HealthRecord>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    Ok(Json(state.history.query(query.since, limit)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/gpus/changes",
+    tag = "gpus",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "GPU inventory changes, oldest first", body = Vec<GpuChange>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+async fn gpu_changes_handler(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<Json<Vec<GpuChange>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    Ok(Json(state.gpu_changes.query(query.since, limit)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/attestation",
+    tag = "worker",
+    responses(
+        (status = 200, description = "TDX quote binding the Nostr key", body = Attestation),
+        (status = 503, description = "The guest agent can't produce a quote", body = ErrorBody),
+    )
+)]
+async fn attestation_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Attestation>, ApiError> {
+    get_quote(state.guest_agent.as_ref(), &state.nostr_pubkey)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Failed to get attestation: {}", e);
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "attestation_unavailable",
+                e,
+            )
+        })
+}
+
+async fn root_handler() -> &'static str {
+    "dstack Backend Health Monitor"
+}
+
+pub fn get_local_ip() -> Option<String> {
+    match local_ip() {
+        Ok(ip) => {
+            info!("Detected local IP: {}", ip);
+            Some(ip.to_string())
+        }
+        Err(e) => {
+            error!("Failed to get local IP: {}", e);
+            None
+        }
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    let limits = &state.concurrency_limits;
+    let shed_dstack = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(handle_overload))
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(limits.max_dstack_requests));
+    let shed_all = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(handle_overload))
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(limits.max_requests));
+
+    let proxy = Router::new().route(
+        "/dstack/:method",
+        get(proxy_get_handler).post(proxy_post_handler),
+    );
+    let proxy = if state.dstack_proxy_auth {
+        proxy.route_layer(middleware::from_fn_with_state(state.clone(), require_admin))
+    } else {
+        proxy
+    };
+
+    let admin = Router::new()
+        .route("/gpus/:slot/attach", post(attach_gpu_handler))
+        .route("/gpus/:slot/detach", post(detach_gpu_handler))
+        .route(
+            "/gpus/:slot/reserve",
+            post(reserve_gpu_handler).delete(release_gpu_handler),
+        )
+        .route("/vms", post(deploy_vm_handler))
+        .route("/vms/:id", delete(remove_vm_handler))
+        .route("/images/prefetch", post(prefetch_handler))
+        .route("/benchmark", post(start_benchmark_handler))
+        .route("/admin/drain", post(drain_handler))
+        .route("/admin/undrain", post(undrain_handler))
+        .route("/admin/dstack-url", put(set_dstack_url_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    // Routes that call dstack or the guest agent share one concurrency cap
+    let dstack = Router::new()
+        .route("/readyz", get(readyz_handler))
+        .route("/health", get(health_handler))
+        .route("/attestation", get(attestation_handler))
+        .route("/vms", get(vms_handler))
+        .route("/prpc/:method", get(prpc_handler).post(prpc_handler))
+        .merge(proxy)
+        .merge(admin)
+        .route_layer(shed_dstack);
+
+    let api = Router::new()
+        .route("/livez", get(livez_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/health/history", get(health_history_handler))
+        .route("/gpus/changes", get(gpu_changes_handler))
+        .route("/gpus/leases", get(leases_handler))
+        .route("/relays", get(relays_handler))
+        .route("/registry/events", get(registry_events_handler))
+        .route("/rewards/estimate", get(rewards_estimate_handler))
+        .route("/images/prefetch", get(prefetch_status_handler))
+        .route("/benchmark", get(benchmark_handler))
+        .route("/health/stream", get(sse_handler))
+        .route("/ws", get(ws_handler))
+        .merge(dstack);
+
+    // The unversioned routes stay as aliases of /api/v1 for existing
+    // consumers
+    Router::new()
+        .route("/", get(root_handler))
+        .route("/api", get(api_versions_handler))
+        .merge(openapi::routes())
+        .nest(API_V1_PREFIX, api.clone())
+        .merge(api)
+        .fallback(not_found_handler)
+        .method_not_allowed_fallback(method_not_allowed_handler)
+        .layer(middleware::from_fn(negotiate_version))
+        .layer(shed_all)
+        .layer(CompressionLayer::new())
+        .layer(CorsLayer::permissive())
+        .layer(middleware::from_fn(request_context))
+        .with_state(state)
+}
+
+pub async fn serve(
+    addr: ListenAddr,
+    state: Arc<AppState>,
+    tls: Option<TlsAcceptor>,
+) -> std::io::Result<()> {
+    let app = router(state);
+
+    let addr = match addr {
+        ListenAddr::Tcp(addr) => addr,
+        // TLS is rejected for Unix sockets by config validation
+        ListenAddr::Unix(path) => {
+            let listener = listen::bind_unix(&path)?;
+            systemd::notify_ready();
+            return listen::serve_unix(listener, app).await;
+        }
+    };
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    systemd::notify_ready();
+    match tls {
+        Some(acceptor) => {
+            info!("Backend listening on https://{}", addr);
+            tls::serve(listener, app, acceptor).await
+        }
+        None => {
+            info!("Backend listening on {}", addr);
+            axum::serve(listener, app).await
+        }
+    }
+}
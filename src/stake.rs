@@ -0,0 +1,115 @@
+//! Verifies that the owner address keeps enough stake in the staking
+//! contract, so workers whose owner withdrew can be told apart.
+
+use alloy::primitives::{Address, U256};
+use alloy::providers::ProviderBuilder;
+use alloy::sol;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::chain::Chain;
+use crate::server::AppState;
+
+sol! {
+    #[sol(rpc)]
+    interface IStaking {
+        function stakedBalance(address owner) external view returns (uint256);
+    }
+}
+
+/// Periodic stake check of the owner address.
+#[derive(Debug, Clone)]
+pub struct StakeCheck {
+    pub chain: Chain,
+    pub contract: Address,
+    pub owner: Address,
+    /// Stake the owner must keep, in the token's smallest unit.
+    pub minimum: U256,
+    pub interval: Duration,
+}
+
+/// The owner's staked balance when last checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stake {
+    pub staked: U256,
+    /// Whether `staked` is at least the configured minimum.
+    pub ok: bool,
+}
+
+impl StakeCheck {
+    /// Reads the owner's staked balance from the staking contract.
+    pub async fn query(&self) -> Result<Stake, String> {
+        let provider = ProviderBuilder::new().connect_http(self.chain.url()?);
+        self.chain.tag(&provider).await?;
+        let staked = IStaking::new(self.contract, &provider)
+            .stakedBalance(self.owner)
+            .call()
+            .await
+            .map_err(|e| format!("stakedBalance call failed: {}", e))?;
+        Ok(Stake {
+            staked,
+            ok: staked >= self.minimum,
+        })
+    }
+}
+
+/// The latest stake check result, `None` until the first check succeeded.
+#[derive(Debug, Default)]
+pub struct StakeStatus {
+    current: RwLock<Option<Stake>>,
+}
+
+impl StakeStatus {
+    pub fn get(&self) -> Option<Stake> {
+        *self.current.read().unwrap()
+    }
+
+    /// Stores the result, returning the previous one.
+    pub fn set(&self, stake: Stake) -> Option<Stake> {
+        self.current.write().unwrap().replace(stake)
+    }
+}
+
+/// Checks the owner's stake every `check.interval`. Failed checks keep the
+/// previous result.
+pub async fn run_stake_check(state: Arc<AppState>, check: StakeCheck) {
+    info!(
+        "Checking the stake of {} in {} on {} every {}s",
+        check.owner,
+        check.contract,
+        check.chain.name,
+        check.interval.as_secs()
+    );
+
+    let mut ticker = tokio::time::interval(check.interval);
+    loop {
+        ticker.tick().await;
+        let stake = match check.query().await {
+            Ok(stake) => stake,
+            Err(e) => {
+                error!("Failed to check the owner's stake: {}", e);
+                continue;
+            }
+        };
+
+        let previous = state.stake.set(stake);
+        match (previous.map(|previous| previous.ok), stake.ok) {
+            (Some(true), false) => warn!(
+                "Stake of {} DROPPED below the minimum: {} < {}",
+                check.owner, stake.staked, check.minimum
+            ),
+            (None, false) => warn!(
+                "Stake of {} is below the minimum: {} < {}",
+                check.owner, stake.staked, check.minimum
+            ),
+            (Some(false), true) | (None, true) => {
+                info!(
+                    "Stake of {} is {}, meeting the minimum",
+                    check.owner, stake.staked
+                )
+            }
+            _ => {}
+        }
+    }
+}
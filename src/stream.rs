@@ -0,0 +1,145 @@
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::Extension;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, warn};
+
+use crate::api_version::ApiVersion;
+use crate::error::{ApiError, ErrorBody};
+use crate::health::BackendInfo;
+use crate::server::AppState;
+
+/// `GET /ws`: pushes the current status on connect and again whenever the
+/// background poller detects a status or GPU availability change.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "health",
+    responses(
+        (status = 101, description = "WebSocket streaming `BackendInfo` messages"),
+        (status = 400, description = "Not a WebSocket upgrade", body = ErrorBody),
+    )
+)]
+pub async fn ws_handler(
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+    State(state): State<Arc<AppState>>,
+    Extension(version): Extension<ApiVersion>,
+) -> Result<Response, ApiError> {
+    let ws = ws.map_err(|rejection| {
+        ApiError::new(
+            rejection.status(),
+            "websocket_required",
+            rejection.body_text(),
+        )
+    })?;
+    Ok(ws.on_upgrade(move |socket| stream_status(socket, state, version)))
+}
+
+async fn stream_status(mut socket: WebSocket, state: Arc<AppState>, version: ApiVersion) {
+    let mut changes = state.poller.subscribe();
+    let present = |info| version.present(info, state.legacy_metadata);
+
+    if let Some(info) = state.poller.latest() {
+        if send_info(&mut socket, &present(info)).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(info) => {
+                    if send_info(&mut socket, &present(info)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client lagged, skipped {} updates", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    debug!("WebSocket client disconnected");
+}
+
+async fn send_info(socket: &mut WebSocket, info: &BackendInfo) -> Result<(), axum::Error> {
+    let payload = serde_json::to_string(info).map_err(axum::Error::new)?;
+    socket.send(Message::Text(payload)).await
+}
+
+/// `GET /health/stream`: Server-Sent Events version of `/ws`. Sends a
+/// `status` event on connect and on every change, and re-sends the cached
+/// snapshot as a `keepalive` event at the configured interval. Image
+/// prefetch progress is sent as `image_prefetch` events.
+#[utoipa::path(
+    get,
+    path = "/health/stream",
+    tag = "health",
+    responses((status = 200, description = "`status` and `keepalive` events carrying `BackendInfo`, and `image_prefetch` events carrying `PrefetchProgress`", content_type = "text/event-stream"))
+)]
+pub async fn sse_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(version): Extension<ApiVersion>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let legacy_metadata = state.legacy_metadata;
+    let present = move |info| version.present(info, legacy_metadata);
+
+    let initial = tokio_stream::iter(state.poller.latest())
+        .map(move |info| status_event("status", &present(info)));
+
+    let changes =
+        BroadcastStream::new(state.poller.subscribe()).filter_map(move |change| match change {
+            Ok(info) => Some(status_event("status", &present(info))),
+            Err(e) => {
+                warn!("SSE client lagged: {}", e);
+                None
+            }
+        });
+
+    let keepalive_state = state.clone();
+    let mut ticker = tokio::time::interval(state.sse_keepalive);
+    // The first tick completes immediately and the initial event covers it
+    ticker.reset();
+    let keepalives = IntervalStream::new(ticker).filter_map(move |_| {
+        keepalive_state
+            .poller
+            .latest()
+            .map(|info| status_event("keepalive", &present(info)))
+    });
+
+    let prefetches =
+        BroadcastStream::new(state.images.subscribe()).filter_map(|progress| match progress {
+            Ok(progress) => Some(Ok(Event::default()
+                .event("image_prefetch")
+                .json_data(progress)
+                .unwrap_or_else(|e| Event::default().event("error").data(e.to_string())))),
+            Err(e) => {
+                warn!("SSE client lagged: {}", e);
+                None
+            }
+        });
+
+    let stream = initial.chain(changes.merge(keepalives).merge(prefetches));
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn status_event(name: &str, info: &BackendInfo) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event(name)
+        .json_data(info)
+        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string())))
+}
@@ -0,0 +1,50 @@
+//! systemd `Type=notify` support. Everything here is a no-op when the
+//! backend isn't started by systemd.
+
+use sd_notify::NotifyState;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Tells systemd startup is complete. Called once the listener is bound,
+/// which is after the keys are loaded.
+pub fn notify_ready() {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    match sd_notify::notify(false, &[NotifyState::Ready]) {
+        Ok(()) => info!("Notified systemd that the backend is ready"),
+        Err(e) => warn!("Failed to notify systemd: {}", e),
+    }
+}
+
+/// The watchdog timeout, when systemd enabled `WatchdogSec=` for this
+/// process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+}
+
+/// Tells systemd the backend is alive. The poller calls this after every
+/// poll, so a wedged poller stops the pings and systemd restarts the
+/// backend.
+pub fn ping_watchdog() {
+    if watchdog_timeout().is_none() {
+        return;
+    }
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+        debug!("Failed to ping the systemd watchdog: {}", e);
+    }
+}
+
+/// Warns when polls are too far apart to keep the watchdog happy.
+pub fn check_poll_interval(interval: Duration) {
+    if let Some(timeout) = watchdog_timeout() {
+        if interval > timeout / 2 {
+            warn!(
+                "Poll interval {:?} is more than half the systemd watchdog timeout {:?}, \
+                 systemd may restart the backend",
+                interval, timeout
+            );
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use crate::error::BackendError;
+
+/// Time allowed for a client to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTPS settings for the backend listener.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: Option<PathBuf>,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: Option<PathBuf>,
+    /// How often the certificate and key files are checked for changes.
+    pub reload_secs: u64,
+    /// PEM CA bundle; when set, clients must present a certificate signed
+    /// by one of these CAs.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: None,
+            key_path: None,
+            reload_secs: 60,
+            client_ca_path: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.cert_path.is_some() || self.key_path.is_some()
+    }
+}
+
+/// Serves the current certificate, swapped in place when the files change
+/// so renewals don't need a restart.
+#[derive(Debug)]
+struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCert {
+    fn load(cert_path: &Path, key_path: &Path) -> Result<Self, BackendError> {
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Arc::new(load_certified_key(cert_path, key_path)?)),
+        })
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let cert = fs::metadata(&self.cert_path).and_then(|m| m.modified());
+        let key = fs::metadata(&self.key_path).and_then(|m| m.modified());
+        Some((cert.ok()?, key.ok()?))
+    }
+
+    fn reload(&self) {
+        match load_certified_key(&self.cert_path, &self.key_path) {
+            Ok(key) => {
+                *self.current.write().unwrap() = Arc::new(key);
+                info!("Reloaded TLS certificate from {:?}", self.cert_path);
+            }
+            // Keep serving the previous certificate, the files may be
+            // halfway through being replaced
+            Err(e) => error!("Failed to reload TLS certificate: {}", e),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, BackendError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| BackendError::Tls(format!("{:?}: {}", cert_path, e)))?;
+    if certs.is_empty() {
+        return Err(BackendError::Tls(format!(
+            "{:?}: no certificates found",
+            cert_path
+        )));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| BackendError::Tls(format!("{:?}: {}", key_path, e)))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|e| BackendError::Tls(format!("{:?}: {}", key_path, e)))?;
+
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified
+        .keys_match()
+        .map_err(|e| BackendError::Tls(format!("{:?} and {:?}: {}", cert_path, key_path, e)))?;
+    Ok(certified)
+}
+
+fn load_client_roots(ca_path: &Path) -> Result<RootCertStore, BackendError> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path)
+        .map_err(|e| BackendError::Tls(format!("{:?}: {}", ca_path, e)))?
+    {
+        let cert = cert.map_err(|e| BackendError::Tls(format!("{:?}: {}", ca_path, e)))?;
+        roots
+            .add(cert)
+            .map_err(|e| BackendError::Tls(format!("{:?}: {}", ca_path, e)))?;
+    }
+    if roots.is_empty() {
+        return Err(BackendError::Tls(format!(
+            "{:?}: no CA certificates found",
+            ca_path
+        )));
+    }
+    Ok(roots)
+}
+
+/// Loads the configured certificate and starts watching it for changes.
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, BackendError> {
+    let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+        return Err(BackendError::Config(
+            "tls needs both cert_path and key_path".to_string(),
+        ));
+    };
+
+    let resolver = Arc::new(ReloadingCert::load(cert_path, key_path)?);
+    tokio::spawn(watch_cert(
+        resolver.clone(),
+        Duration::from_secs(config.reload_secs),
+    ));
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| BackendError::Tls(e.to_string()))?;
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => {
+            let roots = load_client_roots(ca_path)?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| BackendError::Tls(format!("{:?}: {}", ca_path, e)))?;
+            info!("Requiring client certificates signed by {:?}", ca_path);
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    info!("TLS enabled with certificate {:?}", cert_path);
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Reloads the certificate whenever the modification time of either file
+/// changes.
+async fn watch_cert(cert: Arc<ReloadingCert>, interval: Duration) {
+    let mut last_modified = cert.modified();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let modified = cert.modified();
+        if modified.is_some() && modified != last_modified {
+            cert.reload();
+            last_modified = modified;
+        }
+    }
+}
+
+/// Accepts TLS connections and serves `app` over HTTP/1.1 on each of them.
+pub async fn serve(listener: TcpListener, app: Router, acceptor: TlsAcceptor) -> io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Usually out of file descriptors; back off instead of spinning
+                warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", peer);
+                        return;
+                    }
+                };
+
+            let service = TowerToHyperService::new(app);
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades()
+                .await
+            {
+                debug!("Connection from {} closed with error: {}", peer, e);
+            }
+        });
+    }
+}
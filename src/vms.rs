@@ -0,0 +1,541 @@
+//! CVMs running on the host, from dstack's `Status` method, with the GPUs
+//! each one holds. This lets coordinators tell GPUs busy with a workload
+//! from GPUs that are stuck. Admins can also deploy docker-compose
+//! workloads as CVMs and remove them, which is how the coordinator
+//! schedules jobs on the worker.
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::dstack::DStackClient;
+use crate::dstack_info::fetch_dstack_info;
+use crate::error::{ApiError, BackendError, ErrorBody};
+use crate::leases::Lease;
+use crate::poller::refresh_status;
+use crate::server::AppState;
+
+/// dstack prpc method that lists the CVMs.
+pub const STATUS_METHOD: &str = "Status";
+/// dstack prpc method that creates and boots a CVM.
+pub const CREATE_VM_METHOD: &str = "CreateVm";
+/// dstack prpc method that shuts a CVM down.
+pub const STOP_VM_METHOD: &str = "StopVm";
+/// dstack prpc method that deletes a stopped CVM.
+pub const REMOVE_VM_METHOD: &str = "RemoveVm";
+
+/// A CVM as dstack reports it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VmSummary {
+    pub id: String,
+    pub name: String,
+    /// e.g. `running`, `stopped` or `exited`.
+    pub status: String,
+    /// Uptime as dstack formats it, e.g. `3h 12m`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// PCI slots of the GPUs attached to the CVM.
+    pub gpus: Vec<String>,
+    /// Whether the CVM was given every GPU (`attach_mode = "all"`).
+    pub all_gpus: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VmsResponse {
+    pub vms: Vec<VmSummary>,
+    /// GPUs dstack reports as not free that no running CVM holds or lease
+    /// reserves.
+    pub unaccounted_gpus: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusResponse {
+    #[serde(default)]
+    vms: Vec<VmInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VmInfo {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    uptime: Option<String>,
+    #[serde(default)]
+    app_id: Option<String>,
+    #[serde(default)]
+    configuration: Option<VmConfiguration>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VmConfiguration {
+    #[serde(default)]
+    gpus: Option<GpuConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GpuConfig {
+    #[serde(default)]
+    attach_mode: String,
+    #[serde(default)]
+    gpus: Vec<GpuSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GpuSpec {
+    slot: String,
+}
+
+impl From<VmInfo> for VmSummary {
+    fn from(vm: VmInfo) -> Self {
+        let gpus = vm
+            .configuration
+            .and_then(|configuration| configuration.gpus);
+        Self {
+            id: vm.id,
+            name: vm.name,
+            status: vm.status,
+            uptime: vm.uptime.filter(|uptime| !uptime.is_empty()),
+            app_id: vm.app_id.filter(|app_id| !app_id.is_empty()),
+            all_gpus: gpus.as_ref().is_some_and(|gpus| gpus.attach_mode == "all"),
+            gpus: gpus
+                .map(|gpus| gpus.gpus.into_iter().map(|gpu| gpu.slot).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl VmSummary {
+    pub fn running(&self) -> bool {
+        self.status == "running"
+    }
+
+    /// Whether the CVM may hold its GPUs: running, or still booting.
+    fn holds_gpus(&self) -> bool {
+        !matches!(self.status.as_str(), "stopped" | "exited")
+    }
+
+    fn holds_gpu(&self, slot: &str) -> bool {
+        self.holds_gpus() && (self.all_gpus || self.gpus.iter().any(|gpu| gpu == slot))
+    }
+}
+
+/// The CVMs dstack reports.
+pub async fn list_vms(client: &dyn DStackClient) -> Result<Vec<VmSummary>, BackendError> {
+    let status: StatusResponse = client
+        .request(&format!("/prpc/{}?json", STATUS_METHOD), None)
+        .await?;
+    Ok(status.vms.into_iter().map(VmSummary::from).collect())
+}
+
+/// `GET /vms`: the CVMs on the host and the GPUs they hold.
+#[utoipa::path(
+    get,
+    path = "/vms",
+    tag = "worker",
+    responses(
+        (status = 200, description = "CVMs and the GPUs they hold", body = VmsResponse),
+        (status = 502, description = "dstack request failed", body = ErrorBody),
+    )
+)]
+pub async fn vms_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<VmsResponse>, ApiError> {
+    let vms = list_vms(state.connection.client().as_ref())
+        .await
+        .map_err(|e| {
+            error!("dstack {} failed: {}", STATUS_METHOD, e);
+            ApiError::from(e)
+        })?;
+
+    // Compare with the GPUs of the latest health check rather than asking
+    // dstack again
+    let unaccounted_gpus = if vms.iter().any(|vm| vm.running() && vm.all_gpus) {
+        Vec::new()
+    } else {
+        let held: HashSet<&str> = vms
+            .iter()
+            .filter(|vm| vm.running())
+            .flat_map(|vm| vm.gpus.iter().map(String::as_str))
+            .collect();
+        let metadata = state
+            .poller
+            .latest()
+            .and_then(|info| info.metadata)
+            .map(|metadata| metadata.to_structured(&state.node_type))
+            .unwrap_or_default();
+        metadata
+            .gpus
+            .into_iter()
+            .filter(|gpu| {
+                !gpu.is_free
+                    && !held.contains(gpu.slot.as_str())
+                    && !metadata.reserved_gpus.contains(&gpu.slot)
+            })
+            .map(|gpu| gpu.slot)
+            .collect()
+    };
+
+    Ok(Json(VmsResponse {
+        vms,
+        unaccounted_gpus,
+    }))
+}
+
+/// The app compose manifest dstack boots a CVM from, running
+/// `docker_compose_file` without KMS or gateway.
+pub fn app_compose(name: &str, docker_compose_file: &str) -> serde_json::Value {
+    serde_json::json!({
+        "manifest_version": 2,
+        "name": name,
+        "runner": "docker-compose",
+        "docker_compose_file": docker_compose_file,
+        "kms_enabled": false,
+        "gateway_enabled": false,
+        "public_logs": true,
+        "public_sysinfo": true,
+        "local_key_provider_enabled": false,
+    })
+}
+
+/// A workload to run as a CVM.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeploySpec {
+    pub name: String,
+    /// The docker-compose file of the workload.
+    pub compose_file: String,
+    /// dstack OS image to boot, the one dstack reports by default.
+    #[serde(default)]
+    pub image: Option<String>,
+    pub vcpu: u32,
+    pub memory_mb: u32,
+    pub disk_gb: u32,
+    /// PCI slots of the GPUs to attach; each must be free or leased with
+    /// one of `leases`.
+    #[serde(default)]
+    pub gpus: Vec<String>,
+    /// IDs of the caller's leases on `gpus`, which end once the CVM is
+    /// created.
+    #[serde(default)]
+    pub leases: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<PortMapping>,
+}
+
+/// A host port forwarded into the CVM.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PortMapping {
+    /// `tcp` or `udp`.
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    #[serde(default = "default_host_address")]
+    pub host_address: String,
+    pub host_port: u16,
+    pub vm_port: u16,
+}
+
+fn default_protocol() -> String {
+    "tcp".to_string()
+}
+
+fn default_host_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeployResponse {
+    /// ID of the new CVM, for `DELETE /vms/{id}`.
+    pub id: String,
+    pub name: String,
+    pub gpus: Vec<String>,
+}
+
+impl DeploySpec {
+    fn validate(&self) -> Result<(), ApiError> {
+        if self.name.trim().is_empty() {
+            return Err(ApiError::bad_request("name must not be empty"));
+        }
+        if self.compose_file.trim().is_empty() {
+            return Err(ApiError::bad_request("compose_file must not be empty"));
+        }
+        if self.vcpu == 0 || self.memory_mb == 0 || self.disk_gb == 0 {
+            return Err(ApiError::bad_request(
+                "vcpu, memory_mb and disk_gb must be greater than 0",
+            ));
+        }
+        if let Some(port) = self
+            .ports
+            .iter()
+            .find(|port| port.protocol != "tcp" && port.protocol != "udp")
+        {
+            return Err(ApiError::bad_request(format!(
+                "Unknown port protocol {}, expected tcp or udp",
+                port.protocol
+            )));
+        }
+        let mut seen = HashSet::new();
+        if let Some(slot) = self.gpus.iter().find(|slot| !seen.insert(slot.as_str())) {
+            return Err(ApiError::bad_request(format!(
+                "GPU {} is listed twice",
+                slot
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the requested GPUs against the latest health check, the CVMs
+/// dstack reports and the leases, so a deployment doesn't fail inside dstack
+/// over a GPU that's in use or take one reserved by another scheduler.
+/// Returns the caller's leases on the GPUs.
+fn check_gpus(
+    state: &AppState,
+    spec: &DeploySpec,
+    vms: &[VmSummary],
+) -> Result<Vec<Lease>, ApiError> {
+    if spec.gpus.is_empty() {
+        return Ok(Vec::new());
+    }
+    let gpus = state
+        .poller
+        .latest()
+        .and_then(|info| info.metadata)
+        .map(|metadata| metadata.to_structured(&state.node_type).gpus)
+        .unwrap_or_default();
+    let mut held = Vec::new();
+    for slot in &spec.gpus {
+        let gpu = gpus
+            .iter()
+            .find(|gpu| &gpu.slot == slot)
+            .ok_or_else(|| ApiError::not_found(format!("Unknown GPU slot {}", slot)))?;
+        // The health check may predate a CVM that was just created
+        if let Some(vm) = vms.iter().find(|vm| vm.holds_gpu(slot)) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "gpu_busy",
+                format!("GPU {} is held by CVM {}", slot, vm.name),
+            ));
+        }
+        match state.leases.lease_on(slot) {
+            Some(lease) if spec.leases.contains(&lease.id) => held.push(lease),
+            Some(lease) => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "gpu_reserved",
+                    format!("GPU {} is reserved until {}", slot, lease.expires_at),
+                ))
+            }
+            None if !gpu.is_free => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "gpu_busy",
+                    format!("GPU {} is not free", slot),
+                ))
+            }
+            None => {}
+        }
+    }
+    Ok(held)
+}
+
+/// `POST /vms`: deploys a docker-compose workload as a CVM with the
+/// requested resources and GPUs.
+#[utoipa::path(
+    post,
+    path = "/vms",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = DeploySpec,
+    responses(
+        (status = 201, description = "CVM created", body = DeployResponse),
+        (status = 400, description = "Invalid deployment spec", body = ErrorBody),
+        (status = 404, description = "Unknown GPU slot", body = ErrorBody),
+        (status = 409, description = "A GPU is not free or reserved by someone else, the name is taken, or the worker is drained", body = ErrorBody),
+        (status = 502, description = "dstack request failed", body = ErrorBody),
+    )
+)]
+pub async fn deploy_vm_handler(
+    State(state): State<Arc<AppState>>,
+    spec: Result<Json<DeploySpec>, JsonRejection>,
+) -> Result<(StatusCode, Json<DeployResponse>), ApiError> {
+    let Json(spec) = spec?;
+    let deployed = deploy(&state, spec).await?;
+    Ok((StatusCode::CREATED, Json(deployed)))
+}
+
+/// Creates a CVM for `spec` once its GPUs are checked, ending the leases it
+/// brings.
+pub async fn deploy(state: &Arc<AppState>, spec: DeploySpec) -> Result<DeployResponse, ApiError> {
+    spec.validate()?;
+    if state.maintenance.get().is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "drained",
+            "The worker is drained and takes no new workloads",
+        ));
+    }
+    // Checking the GPUs and creating the CVM must not interleave with
+    // another deployment
+    let _deploying = state.deploying.lock().await;
+    let client = state.connection.client();
+    let vms = list_vms(client.as_ref()).await.map_err(|e| {
+        error!("dstack {} failed: {}", STATUS_METHOD, e);
+        ApiError::from(e)
+    })?;
+    if vms.iter().any(|vm| vm.name == spec.name) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "vm_exists",
+            format!("A CVM named {} already exists", spec.name),
+        ));
+    }
+    let leases = check_gpus(state, &spec, &vms)?;
+
+    let image = match spec.image {
+        Some(image) => image,
+        None => fetch_dstack_info(client.as_ref())
+            .await
+            .and_then(|info| info.os_image)
+            .ok_or_else(|| {
+                ApiError::bad_request("dstack doesn't report its OS image, set image")
+            })?,
+    };
+    let vm = serde_json::json!({
+        "name": spec.name,
+        "image": image,
+        "compose_file": app_compose(&spec.name, &spec.compose_file).to_string(),
+        "vcpu": spec.vcpu,
+        "memory": spec.memory_mb,
+        "disk_size": spec.disk_gb,
+        "ports": spec.ports,
+        "gpus": {
+            "attach_mode": "listed",
+            "gpus": spec.gpus.iter().map(|slot| serde_json::json!({ "slot": slot })).collect::<Vec<_>>(),
+        },
+    });
+
+    let id = create_vm(client.as_ref(), &spec.name, &vm).await?;
+
+    info!(
+        "Deployed CVM {} ({}) with {} GPUs",
+        spec.name,
+        id,
+        spec.gpus.len()
+    );
+    // The CVM holds the GPUs now
+    for lease in &leases {
+        if let Err(e) = state.leases.release(&lease.slot, &lease.id) {
+            warn!("Failed to end lease {}: {}", lease.id, e.message);
+        }
+    }
+    refresh_status(state);
+    Ok(DeployResponse {
+        id,
+        name: spec.name,
+        gpus: spec.gpus,
+    })
+}
+
+/// Sends `CreateVm` once and returns the new CVM's ID. dstack may create the
+/// CVM even when the response is lost, and sending it again would start a
+/// second CVM on the same GPUs, so after a failure that may have reached
+/// dstack the CVM is looked up by name instead.
+async fn create_vm(
+    client: &dyn DStackClient,
+    name: &str,
+    vm: &serde_json::Value,
+) -> Result<String, ApiError> {
+    let error = match client
+        .call(&format!("/prpc/{}?json", CREATE_VM_METHOD), Some(vm))
+        .await
+    {
+        Ok(created) => {
+            return created["id"].as_str().map(str::to_string).ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::BAD_GATEWAY,
+                    "invalid_response",
+                    format!("{} response has no id", CREATE_VM_METHOD),
+                )
+            })
+        }
+        Err(e) => e,
+    };
+    error!("dstack {} for {} failed: {}", CREATE_VM_METHOD, name, error);
+    if !error.is_transient() {
+        return Err(error.into());
+    }
+
+    match list_vms(client).await {
+        Ok(vms) => match vms.into_iter().find(|vm| vm.name == name) {
+            Some(vm) => {
+                warn!(
+                    "CVM {} was created despite the failed {} ({})",
+                    name, CREATE_VM_METHOD, vm.id
+                );
+                Ok(vm.id)
+            }
+            None => Err(error.into()),
+        },
+        Err(e) => {
+            error!(
+                "Can't tell whether CVM {} was created, dstack {} failed: {}",
+                name, STATUS_METHOD, e
+            );
+            Err(error.into())
+        }
+    }
+}
+
+/// `DELETE /vms/{id}`: stops a CVM and removes it.
+#[utoipa::path(
+    delete,
+    path = "/vms/{id}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("id" = String, Path, description = "CVM ID from `GET /vms`")),
+    responses(
+        (status = 204, description = "CVM removed"),
+        (status = 502, description = "dstack request failed", body = ErrorBody),
+    )
+)]
+pub async fn remove_vm_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    remove_vm(state.connection.client().as_ref(), &id).await?;
+    refresh_status(&state);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops the CVM `id` and removes it.
+pub async fn remove_vm(client: &dyn DStackClient, id: &str) -> Result<(), BackendError> {
+    let body = serde_json::json!({ "id": id });
+
+    // A CVM that already stopped can't be stopped again, but can still be
+    // removed
+    if let Err(e) = client
+        .call(&format!("/prpc/{}?json", STOP_VM_METHOD), Some(&body))
+        .await
+    {
+        warn!("dstack {} for CVM {} failed: {}", STOP_VM_METHOD, id, e);
+    }
+    client
+        .call(&format!("/prpc/{}?json", REMOVE_VM_METHOD), Some(&body))
+        .await
+        .inspect_err(|e| error!("dstack {} for CVM {} failed: {}", REMOVE_VM_METHOD, id, e))?;
+
+    info!("Removed CVM {}", id);
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio_vsock::{VsockAddr, VsockStream};
+
+/// hyper connector that opens a vsock stream to a fixed CID and port,
+/// ignoring the request URI.
+#[derive(Debug, Clone, Copy)]
+pub struct VsockConnector {
+    pub cid: u32,
+    pub port: u32,
+    pub connect_timeout: Duration,
+}
+
+impl tower::Service<hyper::Uri> for VsockConnector {
+    type Response = VsockConnection;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<VsockConnection>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: hyper::Uri) -> Self::Future {
+        let addr = VsockAddr::new(self.cid, self.port);
+        let connect_timeout = self.connect_timeout;
+        Box::pin(async move {
+            let stream = tokio::time::timeout(connect_timeout, VsockStream::connect(addr))
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "vsock connect timed out")
+                })??;
+            Ok(VsockConnection(TokioIo::new(stream)))
+        })
+    }
+}
+
+/// A connected vsock stream usable by the hyper client.
+pub struct VsockConnection(TokioIo<VsockStream>);
+
+impl Connection for VsockConnection {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl hyper::rt::Read for VsockConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: hyper::rt::ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl hyper::rt::Write for VsockConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
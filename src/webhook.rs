@@ -0,0 +1,231 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use crate::gpu_changes::GpuChange;
+use crate::health::{BackendInfo, DephyWorkerRespondedStatus};
+use crate::history::unix_timestamp;
+use crate::server::AppState;
+
+/// Header carrying `sha256=<hex>`, the HMAC-SHA256 of the request body keyed
+/// with the webhook secret.
+pub const SIGNATURE_HEADER: &str = "X-Dstack-Signature";
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The fields whose changes trigger a webhook delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WebhookSnapshot {
+    pub status: DephyWorkerRespondedStatus,
+    pub gpu_count: usize,
+    pub free_gpu_count: usize,
+}
+
+/// What a webhook delivery reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// The worker status or GPU counts changed.
+    Status,
+    /// GPUs appeared, disappeared or changed `is_free`.
+    GpuChanges,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub timestamp: u64,
+    pub nostr_pubkey: String,
+    pub node_type: String,
+    #[serde(flatten)]
+    pub current: WebhookSnapshot,
+    pub previous: Option<WebhookSnapshot>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GpuChangesPayload {
+    pub event: WebhookEvent,
+    pub timestamp: u64,
+    pub nostr_pubkey: String,
+    pub node_type: String,
+    pub changes: Vec<GpuChange>,
+}
+
+/// Parses a comma-separated webhook list (e.g. from `WEBHOOKS`), dropping
+/// empty entries.
+pub fn parse_webhook_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|url| url.trim())
+        .filter(|url| !url.is_empty())
+        .map(|url| url.to_string())
+        .collect()
+}
+
+/// Signs a request body with HMAC-SHA256, formatted for [`SIGNATURE_HEADER`].
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs a JSON payload to every webhook whenever the worker status, GPU
+/// count or free GPU count changes, and whenever the poller sees GPUs
+/// appear, disappear or change `is_free`.
+/// The URLs and secret are read from the current settings for every
+/// delivery, so reloaded ones apply right away.
+pub async fn run_webhooks(state: Arc<AppState>) {
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create webhook HTTP client: {}", e);
+            return;
+        }
+    };
+
+    let mut changes = state.poller.subscribe();
+    let mut gpu_changes = state.gpu_changes.subscribe();
+    let mut last = state.poller.latest().map(|info| snapshot(&state, &info).0);
+
+    loop {
+        tokio::select! {
+            info = changes.recv() => {
+                let info = match info {
+                    Ok(info) => info,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhooks lagged, skipped {} updates", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let (current, errors) = snapshot(&state, &info);
+                if last == Some(current) {
+                    continue;
+                }
+
+                let payload = WebhookPayload {
+                    event: WebhookEvent::Status,
+                    timestamp: unix_timestamp(),
+                    nostr_pubkey: state.nostr_pubkey.clone(),
+                    node_type: state.node_type.clone(),
+                    current,
+                    previous: last.replace(current),
+                    errors,
+                };
+                send(&client, &state, &payload);
+            }
+            changes = gpu_changes.recv() => {
+                let changes = match changes {
+                    Ok(changes) => changes,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhooks lagged, skipped {} GPU changes", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let payload = GpuChangesPayload {
+                    event: WebhookEvent::GpuChanges,
+                    timestamp: unix_timestamp(),
+                    nostr_pubkey: state.nostr_pubkey.clone(),
+                    node_type: state.node_type.clone(),
+                    changes,
+                };
+                send(&client, &state, &payload);
+            }
+        }
+    }
+}
+
+/// Signs a payload and starts delivering it to every webhook.
+fn send(client: &reqwest::Client, state: &AppState, payload: &impl Serialize) {
+    let settings = state.settings();
+    if settings.webhooks.is_empty() {
+        return;
+    }
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to serialize webhook payload: {}", e);
+            return;
+        }
+    };
+    let signature = settings
+        .webhook_secret
+        .as_deref()
+        .map(|secret| sign_payload(secret, &body));
+
+    for url in &settings.webhooks {
+        tokio::spawn(deliver(
+            client.clone(),
+            url.clone(),
+            body.clone(),
+            signature.clone(),
+        ));
+    }
+}
+
+fn snapshot(state: &AppState, info: &BackendInfo) -> (WebhookSnapshot, Vec<String>) {
+    let metadata = info
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.to_structured(&state.node_type))
+        .unwrap_or_default();
+
+    let snapshot = WebhookSnapshot {
+        status: info.status,
+        gpu_count: metadata.gpu_count,
+        free_gpu_count: metadata.free_gpu_count,
+    };
+    (snapshot, metadata.errors)
+}
+
+/// Delivers one payload, retrying with exponential backoff on connection
+/// errors and non-2xx responses.
+async fn deliver(client: reqwest::Client, url: String, body: Vec<u8>, signature: Option<String>) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("Delivered webhook to {}", url);
+                return;
+            }
+            Ok(response) => format!("HTTP error: {}", response.status()),
+            Err(e) => format!("HTTP request failed: {}", e),
+        };
+
+        if attempt == MAX_ATTEMPTS {
+            error!(
+                "Giving up on webhook {} after {} attempts: {}",
+                url, MAX_ATTEMPTS, error
+            );
+            return;
+        }
+
+        warn!(
+            "Webhook {} failed (attempt {}/{}), retrying in {:?}: {}",
+            url, attempt, MAX_ATTEMPTS, backoff, error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
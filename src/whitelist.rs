@@ -0,0 +1,283 @@
+//! Whitelist membership of this worker, from two sources:
+//!
+//! - the whitelist the registry publishes on Nostr: a NIP-51 follow set
+//!   (kind `30000`) signed by `WHITELIST_NPUB` whose `p` tags are the
+//!   approved workers. The latest list is kept under `DATA_DIR`, so
+//!   membership is still known after a restart while no relay is reachable.
+//! - the whitelist service at `WHITELIST_URL`, queried periodically. Its
+//!   answers are cached for `WHITELIST_CACHE_TTL_SECS`.
+
+use nostr_sdk::prelude::*;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::server::AppState;
+
+/// `d` tag of the published whitelist unless configured otherwise.
+pub const DEFAULT_WHITELIST_LIST: &str = "dstack-workers";
+
+/// The latest whitelist event, kept for restarts without relays.
+pub const WHITELIST_FILE: &str = "whitelist_event.json";
+
+/// Timeout of each whitelist service request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+struct Membership {
+    whitelisted: bool,
+    /// When a whitelist service answer stops being trusted. Signed lists
+    /// don't expire.
+    expires_at: Option<Instant>,
+}
+
+/// Whether this worker is on the whitelist, from whichever source answered
+/// last.
+#[derive(Debug, Default)]
+pub struct WhitelistStatus {
+    current: RwLock<Option<Membership>>,
+}
+
+impl WhitelistStatus {
+    /// `None` until a source has answered (or when none is configured), and
+    /// again once a whitelist service answer has expired.
+    pub fn get(&self) -> Option<bool> {
+        self.current
+            .read()
+            .unwrap()
+            .filter(|membership| {
+                membership
+                    .expires_at
+                    .is_none_or(|expires_at| Instant::now() < expires_at)
+            })
+            .map(|membership| membership.whitelisted)
+    }
+
+    /// Stores the membership, valid for `ttl` if set, returning the
+    /// previous one.
+    fn set(&self, whitelisted: bool, ttl: Option<Duration>) -> Option<bool> {
+        let previous = self.get();
+        *self.current.write().unwrap() = Some(Membership {
+            whitelisted,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        });
+        previous
+    }
+}
+
+/// The published whitelist to follow.
+#[derive(Debug, Clone)]
+pub struct WhitelistSource {
+    pub publisher: PublicKey,
+    /// `d` tag of the list.
+    pub list: String,
+}
+
+impl WhitelistSource {
+    fn filter(&self) -> Filter {
+        Filter::new()
+            .kind(Kind::FollowSet)
+            .author(self.publisher)
+            .identifier(self.list.clone())
+    }
+
+    /// Whether `event` is a validly signed version of this list.
+    fn accepts(&self, event: &Event) -> bool {
+        event.kind == Kind::FollowSet
+            && event.pubkey == self.publisher
+            && event.tags.identifier() == Some(self.list.as_str())
+            && event.verify().is_ok()
+    }
+}
+
+/// Follows the published whitelist on the relays and updates the worker's
+/// membership whenever a newer version of the list arrives.
+pub async fn run_whitelist_watch(state: Arc<AppState>, client: Client, source: WhitelistSource) {
+    let worker = match PublicKey::from_hex(&state.nostr_pubkey) {
+        Ok(worker) => worker,
+        Err(e) => {
+            error!("Invalid worker public key, whitelist check disabled: {}", e);
+            return;
+        }
+    };
+    let path = state.data_dir.join(WHITELIST_FILE);
+
+    // Start from the saved list; relays only replace it with a newer one
+    let mut latest = load_list(&path, &source);
+    if let Some(event) = &latest {
+        apply(&state, &source, worker, event);
+    }
+
+    let mut notifications = client.notifications();
+    if let Err(e) = client.subscribe(vec![source.filter()], None).await {
+        error!("Failed to subscribe to the published whitelist: {}", e);
+        return;
+    }
+    info!(
+        "Following whitelist {:?} published by {}",
+        source.list,
+        source.publisher.to_bech32().unwrap_or_default()
+    );
+
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Whitelist watch lagged, skipped {} notifications", skipped);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+
+        let RelayPoolNotification::Event { event, .. } = notification else {
+            continue;
+        };
+        if !source.accepts(&event)
+            || latest
+                .as_ref()
+                .is_some_and(|current| current.created_at >= event.created_at)
+        {
+            continue;
+        }
+
+        apply(&state, &source, worker, &event);
+        save_list(&path, &event);
+        latest = Some(*event);
+    }
+}
+
+fn apply(state: &AppState, source: &WhitelistSource, worker: PublicKey, event: &Event) {
+    let whitelisted = event.tags.public_keys().any(|pubkey| *pubkey == worker);
+    let previous = state.whitelist.set(whitelisted, None);
+    log_change(previous, whitelisted, || {
+        format!(
+            "whitelist {:?} (list from {})",
+            source.list,
+            event.created_at.to_human_datetime()
+        )
+    });
+}
+
+/// Logs membership changes, loudly when the worker isn't whitelisted.
+fn log_change(previous: Option<bool>, whitelisted: bool, source: impl FnOnce() -> String) {
+    match (previous, whitelisted) {
+        (Some(previous), whitelisted) if previous == whitelisted => {}
+        (_, true) => info!("Worker is on {}", source()),
+        (Some(true), false) => warn!(
+            "Worker was REMOVED from {}, it will not receive work",
+            source()
+        ),
+        (_, false) => warn!(
+            "Worker is NOT on {}, it will not receive work until an administrator approves it",
+            source()
+        ),
+    }
+}
+
+fn load_list(path: &Path, source: &WhitelistSource) -> Option<Event> {
+    let json = fs::read_to_string(path).ok()?;
+    match Event::from_json(json) {
+        Ok(event) if source.accepts(&event) => Some(event),
+        Ok(_) => {
+            warn!(
+                "Ignoring {}, it is not a valid version of the configured whitelist",
+                path.display()
+            );
+            None
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn save_list(path: &Path, event: &Event) {
+    if let Err(e) = fs::write(path, event.as_json()) {
+        error!("Failed to save the whitelist to {}: {}", path.display(), e);
+    }
+}
+
+/// Periodic check against the whitelist service.
+#[derive(Debug, Clone)]
+pub struct WhitelistCheck {
+    /// Queried as `GET <url>?pubkey=<hex>`.
+    pub url: String,
+    pub interval: Duration,
+    /// How long an answer is trusted while the service is unreachable.
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct WhitelistResponse {
+    #[serde(alias = "is_whitelisted")]
+    whitelisted: bool,
+}
+
+/// Asks the whitelist service whether `pubkey` is on the whitelist. A `404`
+/// means it isn't.
+pub async fn query_whitelist(
+    client: &reqwest::Client,
+    url: &str,
+    pubkey: &str,
+) -> Result<bool, String> {
+    let response = client
+        .get(url)
+        .query(&[("pubkey", pubkey)])
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {}", e))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    response
+        .error_for_status()
+        .map_err(|e| format!("HTTP error: {}", e))?
+        .json::<WhitelistResponse>()
+        .await
+        .map(|response| response.whitelisted)
+        .map_err(|e| format!("Invalid response: {}", e))
+}
+
+/// Checks the worker's key against the whitelist service at startup and
+/// then every `check.interval`. Failures keep the cached answer until it
+/// expires.
+pub async fn run_whitelist_check(state: Arc<AppState>, check: WhitelistCheck) {
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create whitelist HTTP client: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Checking the whitelist at {} every {}s",
+        check.url,
+        check.interval.as_secs()
+    );
+
+    let mut ticker = tokio::time::interval(check.interval);
+    loop {
+        ticker.tick().await;
+        match query_whitelist(&client, &check.url, &state.nostr_pubkey).await {
+            Ok(whitelisted) => {
+                let previous = state.whitelist.set(whitelisted, Some(check.ttl));
+                log_change(previous, whitelisted, || {
+                    format!("the whitelist at {}", check.url)
+                });
+            }
+            Err(e) if state.whitelist.get().is_some() => warn!(
+                "Failed to check the whitelist at {}, keeping the cached result: {}",
+                check.url, e
+            ),
+            Err(e) => warn!(
+                "Failed to check the whitelist at {}, membership unknown: {}",
+                check.url, e
+            ),
+        }
+    }
+}
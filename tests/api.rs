@@ -0,0 +1,266 @@
+//! The HTTP API served by `server::router`, in-process on a `MockDStack`.
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::response::Response;
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use dstack_backend::config::Config;
+use dstack_backend::connection::DStackConnection;
+use dstack_backend::dstack::GpuInfo;
+use dstack_backend::error::BackendError;
+use dstack_backend::mock::{self, MockDStack};
+use dstack_backend::rules::HealthRules;
+use dstack_backend::server::{self, AppState};
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123";
+
+struct Backend {
+    state: Arc<AppState>,
+    dstack: Arc<MockDStack>,
+    _data_dir: TempDir,
+}
+
+impl Backend {
+    fn new(dstack: MockDStack) -> Self {
+        Self::with_config(dstack, |_| {})
+    }
+
+    fn with_config(dstack: MockDStack, configure: impl FnOnce(&mut Config)) -> Self {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            data_dir: data_dir.path().to_path_buf(),
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..Config::default()
+        };
+        configure(&mut config);
+
+        let dstack = Arc::new(dstack);
+        let connection = DStackConnection::with_client(
+            "mock://dstack",
+            dstack.clone(),
+            config.dstack_timeouts(),
+            config.dstack_retry.clone(),
+            &config.data_dir,
+        );
+        Self {
+            state: Arc::new(mock::app_state(&config, connection)),
+            dstack,
+            _data_dir: data_dir,
+        }
+    }
+
+    async fn send(&self, request: Request<Body>) -> Response {
+        server::router(self.state.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    async fn get(&self, uri: &str) -> Response {
+        self.send(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+    }
+}
+
+fn gpus(count: usize, busy: usize) -> Vec<GpuInfo> {
+    (0..count)
+        .map(|i| GpuInfo {
+            slot: format!("{:02x}:00.0", i + 1),
+            product_id: "2330".to_string(),
+            description: "NVIDIA H100".to_string(),
+            is_free: i >= busy,
+        })
+        .collect()
+}
+
+async fn body(response: Response) -> Value {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn health_is_available_when_dstack_is_up() {
+    let backend = Backend::new(MockDStack::with_gpus(gpus(2, 0), true));
+
+    let response = backend.get("/health").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body(response).await;
+    assert_eq!(body["status"], "Available");
+    assert_eq!(body["metadata"]["free_gpu_count"], 2);
+}
+
+#[tokio::test]
+async fn health_is_unavailable_when_dstack_is_down() {
+    let dstack = MockDStack::new();
+    dstack.set_error("ListGpus", BackendError::Timeout(Duration::from_secs(5)));
+    let backend = Backend::new(dstack);
+
+    let response = backend.get("/health").await;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body(response).await["status"], "Unavailable");
+}
+
+#[tokio::test]
+async fn health_is_degraded_when_a_rule_is_violated() {
+    let backend = Backend::with_config(MockDStack::with_gpus(gpus(2, 1), true), |config| {
+        config.health_rules = HealthRules {
+            min_free_gpus: Some(2),
+            ..HealthRules::default()
+        };
+    });
+
+    let response = backend.get("/health").await;
+
+    // Degraded workers still take jobs
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await["status"], "Degraded");
+}
+
+#[tokio::test]
+async fn health_serves_304_for_a_matching_etag() {
+    let backend = Backend::new(MockDStack::with_gpus(gpus(2, 0), true));
+
+    let response = backend.get("/health").await;
+    let etag = response.headers()[header::ETAG].clone();
+
+    let request = Request::get("/health")
+        .header(header::IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = backend.send(request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let request = Request::get("/health")
+        .header(header::IF_NONE_MATCH, "\"stale\"")
+        .body(Body::empty())
+        .unwrap();
+    let response = backend.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn health_serves_the_poller_snapshot() {
+    let backend = Backend::new(MockDStack::with_gpus(gpus(2, 0), true));
+
+    backend.get("/health").await;
+    backend.get("/health").await;
+
+    // Only the first request checks dstack, later ones read the snapshot
+    let list_gpus = backend
+        .dstack
+        .calls()
+        .iter()
+        .filter(|(method, _)| method == "ListGpus")
+        .count();
+    assert_eq!(list_gpus, 1);
+}
+
+#[tokio::test]
+async fn admin_routes_require_a_token() {
+    let backend = Backend::new(MockDStack::with_gpus(gpus(2, 0), true));
+
+    for (method, uri) in [
+        (Method::POST, "/admin/drain"),
+        (Method::POST, "/gpus/01:00.0/reserve"),
+        (Method::POST, "/api/v1/vms"),
+        (Method::DELETE, "/vms/abc"),
+    ] {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = backend.send(request).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        assert_eq!(body(response).await["error"]["code"], "unauthorized");
+    }
+
+    let request = Request::post("/admin/drain")
+        .header(header::AUTHORIZATION, "Bearer wrong-token")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        backend.send(request).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let request = Request::post("/admin/drain")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    assert!(backend.send(request).await.status().is_success());
+}
+
+#[tokio::test]
+async fn v1_routes_serve_the_same_body_as_the_unversioned_ones() {
+    let dstack = MockDStack::with_gpus(gpus(2, 0), true);
+    dstack.set_response("Status", json!({ "vms": [] }));
+    let backend = Backend::new(dstack);
+
+    for uri in ["/health", "/vms", "/gpus/leases", "/health/history"] {
+        let unversioned = backend.get(uri).await;
+        let versioned = backend.get(&format!("/api/v1{}", uri)).await;
+
+        assert_eq!(unversioned.status(), versioned.status(), "{}", uri);
+        assert_eq!(body(unversioned).await, body(versioned).await, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn unknown_routes_return_a_json_error() {
+    let backend = Backend::new(MockDStack::new());
+
+    for uri in ["/nope", "/api/v1/nope"] {
+        let response = backend.get(uri).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        assert_eq!(body(response).await["error"]["code"], "not_found");
+    }
+}
+
+#[tokio::test]
+async fn wrong_methods_return_a_json_error() {
+    let backend = Backend::new(MockDStack::new());
+
+    for uri in ["/health", "/api/v1/health"] {
+        let request = Request::delete(uri).body(Body::empty()).unwrap();
+        let response = backend.send(request).await;
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", uri);
+        assert_eq!(body(response).await["error"]["code"], "method_not_allowed");
+    }
+}
+
+#[tokio::test]
+async fn proxy_forwards_the_default_methods() {
+    let dstack = MockDStack::with_gpus(gpus(2, 0), true);
+    let vms = json!({ "vms": [{ "id": "vm-1", "name": "job", "status": "running" }] });
+    dstack.set_response("Status", vms.clone());
+    let backend = Backend::new(dstack);
+
+    let response = backend.get("/dstack/Status").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, vms);
+    let (method, _) = backend.dstack.calls().pop().unwrap();
+    assert_eq!(method, "Status");
+}
+
+#[tokio::test]
+async fn proxy_rejects_methods_that_are_not_exposed() {
+    let backend = Backend::new(MockDStack::new());
+
+    let response = backend.get("/dstack/RemoveVm").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(backend.dstack.calls().is_empty());
+}
@@ -0,0 +1,184 @@
+//! The backend against the `mock-dstack` binary over HTTP, checking the
+//! configured retries and circuit breaker when dstack fails.
+
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+use dstack_backend::breaker::BreakerConfig;
+use dstack_backend::config::Config;
+use dstack_backend::health::{check_dstack_health, poll_dstack_health, DephyWorkerRespondedStatus};
+use dstack_backend::mock;
+use dstack_backend::retry::RetryPolicy;
+use dstack_backend::server::AppState;
+
+/// A `mock-dstack` process on a free port, killed on drop.
+struct MockDstack {
+    child: Child,
+    url: String,
+    http: reqwest::Client,
+}
+
+impl MockDstack {
+    async fn start(args: &[&str]) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mock-dstack"))
+            .arg("--listen")
+            .arg(format!("127.0.0.1:{}", port))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mock = Self {
+            child,
+            url: format!("http://127.0.0.1:{}", port),
+            http: reqwest::Client::new(),
+        };
+
+        for _ in 0..100 {
+            if mock.http.get(mock.stats_url()).send().await.is_ok() {
+                return mock;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("mock-dstack didn't start on {}", mock.url);
+    }
+
+    fn stats_url(&self) -> String {
+        format!("{}/mock/stats", self.url)
+    }
+
+    /// prpc requests served so far.
+    async fn requests(&self) -> u64 {
+        let stats: Value = self
+            .http
+            .get(self.stats_url())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        stats["requests"].as_u64().unwrap()
+    }
+
+    async fn set_settings(&self, settings: Value) {
+        let response = self
+            .http
+            .put(format!("{}/mock/settings", self.url))
+            .json(&settings)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+}
+
+impl Drop for MockDstack {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn backend(mock: &MockDstack, data_dir: &TempDir) -> AppState {
+    let config = Config {
+        data_dir: data_dir.path().to_path_buf(),
+        dstack_url: mock.url.clone(),
+        dstack_retry: RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 10,
+            multiplier: 1.0,
+            jitter: 0.0,
+            max_elapsed_ms: 5_000,
+        },
+        dstack_breaker: BreakerConfig {
+            failure_threshold: 2,
+            reset_secs: 1,
+        },
+        ..Config::default()
+    };
+    let connection = config.dstack_connection().unwrap();
+    mock::app_state(&config, connection)
+}
+
+#[tokio::test]
+async fn failed_reads_are_retried_until_the_circuit_opens() {
+    let mock = MockDstack::start(&["--down"]).await;
+    let data_dir = TempDir::new().unwrap();
+    let state = backend(&mock, &data_dir);
+
+    // Each check makes max_attempts requests
+    let info = check_dstack_health(&state).await;
+    assert_eq!(info.status, DephyWorkerRespondedStatus::Unavailable);
+    assert_eq!(mock.requests().await, 3);
+    assert!(!state.breaker.is_open());
+
+    // The second failed check reaches failure_threshold
+    check_dstack_health(&state).await;
+    assert_eq!(mock.requests().await, 6);
+    assert!(state.breaker.is_open());
+
+    // While open, checks fail fast without reaching dstack
+    let info = check_dstack_health(&state).await;
+    assert_eq!(info.status, DephyWorkerRespondedStatus::Unavailable);
+    assert_eq!(mock.requests().await, 6);
+}
+
+#[tokio::test]
+async fn only_the_poller_probes_once_the_reset_timeout_passes() {
+    let mock = MockDstack::start(&["--down"]).await;
+    let data_dir = TempDir::new().unwrap();
+    let state = backend(&mock, &data_dir);
+
+    check_dstack_health(&state).await;
+    check_dstack_health(&state).await;
+    assert!(state.breaker.is_open());
+    assert_eq!(mock.requests().await, 6);
+
+    mock.set_settings(json!({ "down": false })).await;
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+    // Requests keep failing fast, dstack isn't called
+    check_dstack_health(&state).await;
+    assert!(state.breaker.is_open());
+    assert_eq!(mock.requests().await, 6);
+
+    // The poller's probe succeeds and closes the circuit
+    let info = poll_dstack_health(&state).await;
+    assert_eq!(info.status, DephyWorkerRespondedStatus::Available);
+    assert_eq!(mock.requests().await, 7);
+    assert!(!state.breaker.is_open());
+
+    let info = check_dstack_health(&state).await;
+    assert_eq!(info.status, DephyWorkerRespondedStatus::Available);
+    assert_eq!(mock.requests().await, 8);
+}
+
+#[tokio::test]
+async fn a_failed_probe_reopens_the_circuit() {
+    let mock = MockDstack::start(&["--down"]).await;
+    let data_dir = TempDir::new().unwrap();
+    let state = backend(&mock, &data_dir);
+
+    check_dstack_health(&state).await;
+    check_dstack_health(&state).await;
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+    // The probe is retried like any read, then opens the circuit again
+    let info = poll_dstack_health(&state).await;
+    assert_eq!(info.status, DephyWorkerRespondedStatus::Unavailable);
+    assert_eq!(mock.requests().await, 9);
+    assert!(state.breaker.is_open());
+
+    poll_dstack_health(&state).await;
+    assert_eq!(mock.requests().await, 9);
+}